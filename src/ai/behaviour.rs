@@ -0,0 +1,791 @@
+use crate::ai::senses;
+use crate::ai::senses::TankView;
+use crate::archetype::TankArchetype;
+use crate::commands::{
+    FireCommand, MovementCommand, PlayerCommands, PlayerId, RotationCommand, WeaponCommand,
+};
+use crate::components::Rotation;
+use crate::fog::Sight;
+use crate::geometry::Point;
+use crate::navigation::{Navigator, PathFollower};
+use crate::resources::Tick;
+use crate::terrain::Terrain;
+use rand::rngs::StdRng;
+use rand::Rng;
+use specs::prelude::{World, WorldExt};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The turret counts as aimed this many degrees either side of the target
+const AIM_TOLERANCE: f32 = 4.0;
+
+/// A place to drive to counts as reached this close
+const ARRIVED: f32 = 24.0;
+
+/// A route is found again once where it leads has moved this far
+const REPATH_DISTANCE: f32 = 32.0;
+
+/// A tank that hasn't got this far in `STUCK_TICKS` while driving somewhere finds another way there
+const STUCK_DISTANCE: f32 = 8.0;
+const STUCK_TICKS: u64 = 20;
+
+/// Wandering keeps this far from the edge of the arena
+const WANDER_MARGIN: i32 = 64;
+
+/// How a node got on this tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    Failure,
+    /// Still at it, it is asked again next tick
+    Running,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    /// Runs its children in order until one doesn't succeed
+    Sequence(Vec<Node>),
+    /// Runs its children in order until one doesn't fail
+    Selector(Vec<Node>),
+    /// Swaps its child's success and failure
+    Invert(Box<Node>),
+    /// Succeeds whatever its child does
+    Succeed(Box<Node>),
+    /// Only lets its child run once every `ticks`, failing in between.
+    /// `id` tells the cooldowns of a tree apart on the blackboard.
+    Cooldown {
+        ticks: u64,
+        id: usize,
+        child: Box<Node>,
+    },
+    Leaf(Leaf),
+}
+
+/// The conditions and actions trees are built from
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Leaf {
+    /// Target the nearest enemy in sight, failing if there is none
+    EnemyVisible,
+    /// Whether the target is this close
+    EnemyWithin(f32),
+    /// Whether the tank has less than this fraction of its health left
+    HealthBelow(f32),
+    /// Whether the selected weapon is reloading
+    Reloading,
+    /// Whether a shot at the target would reach it
+    ClearShot,
+    /// Turn the turret towards the target, succeeding once it points at it
+    Aim,
+    Fire,
+    HoldFire,
+    /// Switch to the weapon in this slot, counted from 1 like the keys
+    Weapon(u8),
+    /// Drive towards the target until this close to it
+    Chase(f32),
+    /// Drive away from the target until this far from it
+    Retreat(f32),
+    /// Drive to where an enemy was last seen
+    Investigate,
+    MoveTo(Point),
+    /// Drive about the arena from one random place to the next, never finishing
+    Wander,
+    Stop,
+}
+
+/// A bot's decision making, loaded from a file describing a tree of nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct BehaviourTree {
+    pub name: String,
+    /// The tank the bot drives
+    pub archetype: TankArchetype,
+    root: Node,
+}
+
+/// What a bot remembers from one tick to the next
+pub struct Blackboard {
+    /// The enemy it is after this tick and where it is
+    target: Option<(PlayerId, Point)>,
+    /// Where an enemy was last seen, until it has been looked for there
+    last_seen: Option<Point>,
+    /// Where it is driving to and the way there
+    route: Option<(Point, PathFollower)>,
+    /// Where the tank last got to and when, to tell when it is stuck
+    moved: (Point, u64),
+    wander: Option<Point>,
+    /// The tick each cooldown last let its child run
+    cooldowns: HashMap<usize, u64>,
+    rng: StdRng,
+}
+
+impl Blackboard {
+    pub fn new(rng: StdRng) -> Self {
+        Blackboard {
+            target: None,
+            last_seen: None,
+            route: None,
+            moved: (Point::new(0, 0), 0),
+            wander: None,
+            cooldowns: HashMap::new(),
+            rng,
+        }
+    }
+}
+
+/// Everything a tick of a tree looks at, and the commands it decides on
+struct Context<'w, 'n> {
+    world: &'w World,
+    player: PlayerId,
+    tick: u64,
+    me: TankView,
+    sight: Sight,
+    navigator: &'n mut Navigator,
+    commands: PlayerCommands,
+}
+
+impl BehaviourTree {
+    pub fn load(path: &Path) -> Result<BehaviourTree, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read behaviour tree {:?}: {}", path, e))?;
+        let name = path.file_stem().map_or("bot".to_string(), |stem| {
+            stem.to_string_lossy().into_owned()
+        });
+        BehaviourTree::parse(&name, &text).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    /// Read a tree written one node to a line, a node's children indented under it.
+    /// It may start with a `tank <archetype>` line; `#` starts a comment.
+    pub fn parse(name: &str, text: &str) -> Result<BehaviourTree, String> {
+        let lines: Vec<(usize, usize, Vec<&str>)> = text
+            .lines()
+            .enumerate()
+            .filter_map(|(number, line)| {
+                let line = line.split('#').next().unwrap_or("");
+                let words: Vec<&str> = line.split_whitespace().collect();
+                if words.is_empty() {
+                    return None;
+                }
+                let indent = line.len() - line.trim_start().len();
+                Some((number + 1, indent, words))
+            })
+            .collect();
+
+        let mut archetype = TankArchetype::default();
+        let mut next = 0;
+        if let Some((number, _, words)) = lines.first() {
+            if words[0] == "tank" {
+                archetype = match words.as_slice() {
+                    [_, name] => TankArchetype::from_name(name),
+                    _ => None,
+                }
+                .ok_or(format!(
+                    "line {}: expected tank scout, medium or heavy",
+                    number
+                ))?;
+                next = 1;
+            }
+        }
+        let mut parser = Parser {
+            lines: &lines,
+            next,
+            cooldowns: 0,
+        };
+        let root = parser.node()?.ok_or("the tree is empty")?;
+        if let Some((number, _, _)) = lines.get(parser.next) {
+            return Err(format!("line {}: a tree has a single root", number));
+        }
+        Ok(BehaviourTree {
+            name: name.to_string(),
+            archetype,
+            root,
+        })
+    }
+
+    /// Decide on `player`'s commands for this tick, or nothing if its tank is gone
+    pub fn tick(
+        &self,
+        world: &World,
+        player: PlayerId,
+        blackboard: &mut Blackboard,
+        navigator: &mut Navigator,
+    ) -> Option<PlayerCommands> {
+        let me = senses::own_tank(world, player)?;
+        let mut context = Context {
+            world,
+            player,
+            tick: world.read_resource::<Tick>().0,
+            me,
+            sight: Sight::of(world, player),
+            navigator,
+            // Whatever no node asks for this tick isn't done
+            commands: PlayerCommands {
+                movement: Some(MovementCommand::Stop),
+                rotation: Some(RotationCommand::Stop),
+                fire: Some(FireCommand::Stop),
+                weapon: None,
+            },
+        };
+        blackboard.target = None;
+        run(&self.root, &mut context, blackboard);
+        Some(context.commands)
+    }
+}
+
+struct Parser<'l, 'w> {
+    lines: &'l [(usize, usize, Vec<&'w str>)],
+    next: usize,
+    cooldowns: usize,
+}
+
+impl<'l, 'w> Parser<'l, 'w> {
+    /// The node on the next line along with everything indented under it
+    fn node(&mut self) -> Result<Option<Node>, String> {
+        let (number, indent, words) = match self.lines.get(self.next) {
+            Some((number, indent, words)) => (*number, *indent, words),
+            None => return Ok(None),
+        };
+        self.next += 1;
+        let error = |what: &str| format!("line {}: {}", number, what);
+        let number_arg = |what: &str| -> Result<f32, String> {
+            match words.as_slice() {
+                [_, value] => value.parse().map_err(|_| error(what)),
+                _ => Err(error(what)),
+            }
+        };
+        let no_args = || {
+            if words.len() == 1 {
+                Ok(())
+            } else {
+                Err(error(&format!("{} takes no arguments", words[0])))
+            }
+        };
+
+        let mut children = Vec::new();
+        while let Some((_, child_indent, _)) = self.lines.get(self.next) {
+            if *child_indent <= indent {
+                break;
+            }
+            children.push(self.node()?.expect("there is a next line"));
+        }
+        let leaf = |leaf: Leaf| {
+            if children.is_empty() {
+                Ok(Node::Leaf(leaf))
+            } else {
+                Err(error(&format!("{} can't have children", words[0])))
+            }
+        };
+        let only_child = |mut children: Vec<Node>| {
+            if children.len() == 1 {
+                Ok(Box::new(children.remove(0)))
+            } else {
+                Err(error(&format!("{} takes exactly one child", words[0])))
+            }
+        };
+
+        let node = match words[0] {
+            "sequence" | "selector" => {
+                no_args()?;
+                if children.is_empty() {
+                    return Err(error(&format!("{} needs children", words[0])));
+                }
+                if words[0] == "sequence" {
+                    Node::Sequence(children)
+                } else {
+                    Node::Selector(children)
+                }
+            }
+            "invert" => {
+                no_args()?;
+                Node::Invert(only_child(children)?)
+            }
+            "succeed" => {
+                no_args()?;
+                Node::Succeed(only_child(children)?)
+            }
+            "cooldown" => {
+                let ticks = number_arg("expected cooldown <ticks>")?;
+                self.cooldowns += 1;
+                Node::Cooldown {
+                    ticks: ticks.max(0.0) as u64,
+                    id: self.cooldowns,
+                    child: only_child(children)?,
+                }
+            }
+            "enemy-visible" => no_args().and_then(|_| leaf(Leaf::EnemyVisible))?,
+            "enemy-within" => leaf(Leaf::EnemyWithin(number_arg(
+                "expected enemy-within <distance>",
+            )?))?,
+            "health-below" => {
+                let percent = number_arg("expected health-below <percent>")?;
+                leaf(Leaf::HealthBelow(percent / 100.0))?
+            }
+            "reloading" => no_args().and_then(|_| leaf(Leaf::Reloading))?,
+            "clear-shot" => no_args().and_then(|_| leaf(Leaf::ClearShot))?,
+            "aim" => no_args().and_then(|_| leaf(Leaf::Aim))?,
+            "fire" => no_args().and_then(|_| leaf(Leaf::Fire))?,
+            "hold-fire" => no_args().and_then(|_| leaf(Leaf::HoldFire))?,
+            "weapon" => {
+                let slot = number_arg("expected weapon <slot>")?;
+                if !(1.0..=9.0).contains(&slot) {
+                    return Err(error("weapon slots go from 1 to 9"));
+                }
+                leaf(Leaf::Weapon(slot as u8))?
+            }
+            "chase" => leaf(Leaf::Chase(number_arg("expected chase <distance>")?))?,
+            "retreat" => leaf(Leaf::Retreat(number_arg("expected retreat <distance>")?))?,
+            "investigate" => no_args().and_then(|_| leaf(Leaf::Investigate))?,
+            "move-to" => {
+                let point = match words.as_slice() {
+                    [_, x, y] => x.parse().ok().zip(y.parse().ok()),
+                    _ => None,
+                }
+                .ok_or_else(|| error("expected move-to <x> <y>"))?;
+                leaf(Leaf::MoveTo(Point::new(point.0, point.1)))?
+            }
+            "wander" => no_args().and_then(|_| leaf(Leaf::Wander))?,
+            "stop" => no_args().and_then(|_| leaf(Leaf::Stop))?,
+            other => return Err(error(&format!("unknown node {}", other))),
+        };
+        Ok(Some(node))
+    }
+}
+
+fn run(node: &Node, context: &mut Context, blackboard: &mut Blackboard) -> Status {
+    match node {
+        Node::Sequence(children) => {
+            for child in children {
+                match run(child, context, blackboard) {
+                    Status::Success => {}
+                    status => return status,
+                }
+            }
+            Status::Success
+        }
+        Node::Selector(children) => {
+            for child in children {
+                match run(child, context, blackboard) {
+                    Status::Failure => {}
+                    status => return status,
+                }
+            }
+            Status::Failure
+        }
+        Node::Invert(child) => match run(child, context, blackboard) {
+            Status::Success => Status::Failure,
+            Status::Failure => Status::Success,
+            Status::Running => Status::Running,
+        },
+        Node::Succeed(child) => {
+            run(child, context, blackboard);
+            Status::Success
+        }
+        Node::Cooldown { ticks, id, child } => {
+            let ready = blackboard
+                .cooldowns
+                .get(id)
+                .is_none_or(|&last| context.tick >= last + ticks);
+            if !ready {
+                return Status::Failure;
+            }
+            let status = run(child, context, blackboard);
+            if status != Status::Failure {
+                blackboard.cooldowns.insert(*id, context.tick);
+            }
+            status
+        }
+        Node::Leaf(leaf) => run_leaf(*leaf, context, blackboard),
+    }
+}
+
+fn succeed_if(condition: bool) -> Status {
+    if condition {
+        Status::Success
+    } else {
+        Status::Failure
+    }
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    let offset = b - a;
+    (offset.x() as f32).hypot(offset.y() as f32)
+}
+
+fn run_leaf(leaf: Leaf, context: &mut Context, blackboard: &mut Blackboard) -> Status {
+    let me = &context.me;
+    let target = blackboard.target;
+    match leaf {
+        Leaf::EnemyVisible => {
+            // Ties are broken on player so the choice doesn't depend on storage order
+            let nearest = context
+                .sight
+                .enemies(context.world)
+                .into_iter()
+                .min_by(|a, b| {
+                    distance(me.position, a.1)
+                        .partial_cmp(&distance(me.position, b.1))
+                        .expect("distances are never NaN")
+                        .then(a.0.cmp(&b.0))
+                });
+            blackboard.target = nearest;
+            if let Some((_, position)) = nearest {
+                blackboard.last_seen = Some(position);
+            }
+            succeed_if(nearest.is_some())
+        }
+        Leaf::EnemyWithin(range) => {
+            succeed_if(target.is_some_and(|(_, position)| distance(me.position, position) <= range))
+        }
+        Leaf::HealthBelow(fraction) => {
+            succeed_if((me.health.current as f32) < fraction * me.health.max as f32)
+        }
+        Leaf::Reloading => succeed_if(me.reloading),
+        Leaf::ClearShot => succeed_if(target.is_some_and(|(enemy, position)| {
+            senses::clear_shot(context.world, context.player, me.position, enemy, position)
+        })),
+        Leaf::Aim => {
+            let (_, position) = match target {
+                Some(target) => target,
+                None => return Status::Failure,
+            };
+            let offset = position - me.position;
+            let wanted = (offset.y() as f32).atan2(offset.x() as f32).to_degrees();
+            let mut turn = (wanted - me.turret).rem_euclid(360.0);
+            if turn > 180.0 {
+                turn -= 360.0;
+            }
+            // Let go in time for the turret to coast to a stop on target
+            let coasting = if me.engine.turn_acceleration > 0.0 {
+                me.turret_speed * me.turret_speed / (2.0 * me.engine.turn_acceleration)
+            } else {
+                0.0
+            };
+            if turn.abs() <= AIM_TOLERANCE {
+                return Status::Success;
+            }
+            if turn.abs() > coasting {
+                let rotation = if turn > 0.0 {
+                    Rotation::Clockwise
+                } else {
+                    Rotation::CounterClockwise
+                };
+                context.commands.rotation = Some(RotationCommand::Move(rotation));
+            }
+            Status::Running
+        }
+        Leaf::Fire => {
+            context.commands.fire = Some(FireCommand::Fire);
+            Status::Success
+        }
+        Leaf::HoldFire => {
+            context.commands.fire = Some(FireCommand::Stop);
+            Status::Success
+        }
+        Leaf::Weapon(slot) => {
+            if slot as usize > me.weapons {
+                return Status::Failure;
+            }
+            context.commands.weapon = Some(WeaponCommand::Select(slot - 1));
+            Status::Success
+        }
+        Leaf::Chase(range) => match target {
+            Some((_, position)) if distance(me.position, position) <= range => {
+                blackboard.route = None;
+                Status::Success
+            }
+            Some((_, position)) => drive_to(context, blackboard, position),
+            None => Status::Failure,
+        },
+        Leaf::Retreat(range) => match target {
+            Some((_, position)) if distance(me.position, position) >= range => {
+                blackboard.route = None;
+                Status::Success
+            }
+            Some((_, position)) => {
+                let away = me.position - position;
+                let length = distance(position, me.position).max(1.0);
+                let goal = position
+                    + Point::new(
+                        (away.x() as f32 / length * range).round() as i32,
+                        (away.y() as f32 / length * range).round() as i32,
+                    );
+                drive_to(context, blackboard, goal)
+            }
+            None => Status::Failure,
+        },
+        Leaf::Investigate => match blackboard.last_seen {
+            Some(position) => match drive_to(context, blackboard, position) {
+                Status::Running => Status::Running,
+                status => {
+                    blackboard.last_seen = None;
+                    status
+                }
+            },
+            None => Status::Failure,
+        },
+        Leaf::MoveTo(point) => drive_to(context, blackboard, point),
+        Leaf::Wander => {
+            let goal = match blackboard.wander {
+                Some(goal) => goal,
+                None => {
+                    let bounds = context.world.read_resource::<Terrain>().bounds();
+                    let goal = Point::new(
+                        blackboard.rng.gen_range(
+                            bounds.left() + WANDER_MARGIN,
+                            bounds.right() - WANDER_MARGIN,
+                        ),
+                        blackboard.rng.gen_range(
+                            bounds.top() + WANDER_MARGIN,
+                            bounds.bottom() - WANDER_MARGIN,
+                        ),
+                    );
+                    blackboard.wander = Some(goal);
+                    goal
+                }
+            };
+            if drive_to(context, blackboard, goal) != Status::Running {
+                blackboard.wander = None;
+            }
+            Status::Running
+        }
+        Leaf::Stop => {
+            blackboard.route = None;
+            Status::Success
+        }
+    }
+}
+
+/// Drive along a route to `goal`, finding one if there is none there yet
+fn drive_to(context: &mut Context, blackboard: &mut Blackboard, goal: Point) -> Status {
+    let me = &context.me;
+    if distance(me.position, goal) <= ARRIVED {
+        blackboard.route = None;
+        return Status::Success;
+    }
+    if distance(blackboard.moved.0, me.position) > STUCK_DISTANCE {
+        blackboard.moved = (me.position, context.tick);
+    }
+    // Cutting a corner can leave it pushing against a wall, the way from where it is goes round it
+    let stuck = context.tick >= blackboard.moved.1 + STUCK_TICKS;
+    let current = blackboard.route.as_ref().is_some_and(|(to, follower)| {
+        distance(*to, goal) <= REPATH_DISTANCE && !follower.finished()
+    });
+    if !current || stuck {
+        blackboard.moved = (me.position, context.tick);
+        blackboard.route = context
+            .navigator
+            .path(context.world, me.position, goal)
+            .map(|path| (goal, PathFollower::new(path)));
+    }
+    match blackboard.route.as_mut() {
+        Some((_, follower)) => {
+            context.commands.movement = Some(follower.steer(me.position, &me.velocity, &me.engine));
+            if follower.finished() {
+                // As near as a tank can get
+                blackboard.route = None;
+                Status::Success
+            } else {
+                Status::Running
+            }
+        }
+        None => Status::Failure,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game;
+    use rand::SeedableRng;
+
+    fn tree(text: &str) -> BehaviourTree {
+        BehaviourTree::parse("test", text).expect("tree parses")
+    }
+
+    fn error(text: &str) -> String {
+        BehaviourTree::parse("test", text).expect_err("tree is rejected")
+    }
+
+    /// Commands with nothing but firing in them
+    fn fire() -> PlayerCommands {
+        PlayerCommands {
+            fire: Some(FireCommand::Fire),
+            ..PlayerCommands::default()
+        }
+    }
+
+    /// A match to run trees in, with player 1's tank and what it remembers
+    struct Bench {
+        world: World,
+        navigator: Navigator,
+        blackboard: Blackboard,
+    }
+
+    impl Bench {
+        fn new() -> Bench {
+            let mut dispatcher = game::build_dispatcher();
+            let world = game::create_match(&mut dispatcher, 1, &[1]);
+            let navigator = Navigator::new(&world, game::TANK_COLLIDER);
+            Bench {
+                world,
+                navigator,
+                blackboard: Blackboard::new(StdRng::seed_from_u64(1)),
+            }
+        }
+
+        /// How the tree in `text` gets on when run on `tick`, and what it decided
+        fn run(&mut self, text: &str, tick: u64) -> (Status, PlayerCommands) {
+            let mut context = Context {
+                world: &self.world,
+                player: 1,
+                tick,
+                me: senses::own_tank(&self.world, 1).expect("tank is alive"),
+                sight: Sight::of(&self.world, 1),
+                navigator: &mut self.navigator,
+                commands: PlayerCommands::default(),
+            };
+            let status = run(&tree(text).root, &mut context, &mut self.blackboard);
+            (status, context.commands)
+        }
+    }
+
+    #[test]
+    fn children_are_indented_under_their_parent() {
+        let parsed = tree(
+            "# A comment on its own
+tank heavy
+selector
+    sequence   # and after a node
+        fire
+        stop
+
+    wander
+",
+        );
+        assert_eq!(parsed.archetype, TankArchetype::Heavy);
+        assert_eq!(
+            parsed.root,
+            Node::Selector(vec![
+                Node::Sequence(vec![Node::Leaf(Leaf::Fire), Node::Leaf(Leaf::Stop)]),
+                Node::Leaf(Leaf::Wander),
+            ])
+        );
+        assert_eq!(tree("stop").archetype, TankArchetype::default());
+    }
+
+    #[test]
+    fn malformed_trees_are_rejected() {
+        assert_eq!(error(""), "the tree is empty");
+        assert_eq!(
+            error("tank tiger\nstop"),
+            "line 1: expected tank scout, medium or heavy"
+        );
+        assert_eq!(error("fire\n  stop"), "line 1: fire can't have children");
+        assert_eq!(
+            error("invert\n  fire\n  stop"),
+            "line 1: invert takes exactly one child"
+        );
+        assert_eq!(error("succeed"), "line 1: succeed takes exactly one child");
+        assert_eq!(error("sequence"), "line 1: sequence needs children");
+        assert_eq!(error("fire\nstop"), "line 2: a tree has a single root");
+        assert_eq!(
+            error("cooldown\n  fire"),
+            "line 1: expected cooldown <ticks>"
+        );
+        assert_eq!(error("stop now"), "line 1: stop takes no arguments");
+        assert_eq!(error("move-to 1"), "line 1: expected move-to <x> <y>");
+        assert_eq!(error("jump"), "line 1: unknown node jump");
+    }
+
+    #[test]
+    fn weapon_slots_go_from_1_to_9() {
+        assert_eq!(tree("weapon 1").root, Node::Leaf(Leaf::Weapon(1)));
+        assert_eq!(tree("weapon 9").root, Node::Leaf(Leaf::Weapon(9)));
+        for slot in ["0", "10", "-1", "x"] {
+            assert!(BehaviourTree::parse("test", &format!("weapon {}", slot)).is_err());
+        }
+
+        // A slot the tank has no weapon in fails
+        let mut bench = Bench::new();
+        let (status, commands) = bench.run("weapon 1", 0);
+        assert_eq!(status, Status::Success);
+        assert_eq!(commands.weapon, Some(WeaponCommand::Select(0)));
+        let (status, commands) = bench.run("weapon 9", 0);
+        assert_eq!(status, Status::Failure);
+        assert_eq!(commands.weapon, None);
+    }
+
+    #[test]
+    fn sequences_stop_at_the_first_child_that_doesnt_succeed() {
+        let mut bench = Bench::new();
+        let (status, commands) = bench.run("sequence\n  fire\n  weapon 1", 0);
+        assert_eq!(status, Status::Success);
+        assert_eq!(commands.weapon, Some(WeaponCommand::Select(0)));
+
+        let (status, commands) = bench.run("sequence\n  fire\n  health-below 0\n  weapon 1", 0);
+        assert_eq!(status, Status::Failure);
+        assert_eq!(commands.fire, Some(FireCommand::Fire));
+        assert_eq!(commands.weapon, None);
+
+        let (status, commands) = bench.run("sequence\n  wander\n  weapon 1", 0);
+        assert_eq!(status, Status::Running);
+        assert_eq!(commands.weapon, None);
+    }
+
+    #[test]
+    fn selectors_stop_at_the_first_child_that_doesnt_fail() {
+        let mut bench = Bench::new();
+        let (status, commands) = bench.run("selector\n  health-below 0\n  fire\n  weapon 1", 0);
+        assert_eq!(status, Status::Success);
+        assert_eq!(commands.fire, Some(FireCommand::Fire));
+        assert_eq!(commands.weapon, None);
+
+        let (status, commands) = bench.run("selector\n  wander\n  weapon 1", 0);
+        assert_eq!(status, Status::Running);
+        assert_eq!(commands.weapon, None);
+
+        let (status, _) = bench.run("selector\n  health-below 0\n  weapon 9", 0);
+        assert_eq!(status, Status::Failure);
+    }
+
+    #[test]
+    fn invert_swaps_success_and_failure_but_not_running() {
+        let mut bench = Bench::new();
+        assert_eq!(bench.run("invert\n  fire", 0).0, Status::Failure);
+        assert_eq!(bench.run("invert\n  health-below 0", 0).0, Status::Success);
+        assert_eq!(bench.run("invert\n  wander", 0).0, Status::Running);
+        assert_eq!(bench.run("succeed\n  health-below 0", 0).0, Status::Success);
+    }
+
+    #[test]
+    fn cooldowns_fail_until_their_time_is_up() {
+        let mut bench = Bench::new();
+        let cooldown = "cooldown 10\n  fire";
+        assert_eq!(bench.run(cooldown, 0), (Status::Success, fire()));
+        assert_eq!(
+            bench.run(cooldown, 9),
+            (Status::Failure, PlayerCommands::default())
+        );
+        assert_eq!(bench.run(cooldown, 10), (Status::Success, fire()));
+
+        // Only a child that didn't fail starts it
+        let mut bench = Bench::new();
+        assert_eq!(
+            bench.run("cooldown 10\n  health-below 0", 0).0,
+            Status::Failure
+        );
+        assert!(bench.blackboard.cooldowns.is_empty());
+    }
+
+    #[test]
+    fn the_shipped_trees_load() {
+        let mut loaded = 0;
+        for entry in fs::read_dir("resources/ai").expect("resources/ai is there") {
+            let path = entry.expect("entry is readable").path();
+            if path.extension().is_some_and(|extension| extension == "bt") {
+                BehaviourTree::load(&path).expect("tree loads");
+                loaded += 1;
+            }
+        }
+        assert!(loaded > 0);
+    }
+}
@@ -0,0 +1,129 @@
+//! Computer controlled tanks.
+//!
+//! A bot plays the way a player does: it only knows what its side can see and decides on the
+//! commands to send each tick as though it were at the keyboard, so bots take part in replays
+//! and network play like anyone else. How it decides is either a behaviour tree read from a file,
+//! letting different bots be given different personalities without touching the code, or a
+//! script written against a small sandboxed API.
+
+pub mod behaviour;
+pub mod script;
+pub mod senses;
+
+use crate::ai::behaviour::{BehaviourTree, Blackboard};
+use crate::ai::script::Script;
+use crate::archetype::TankArchetype;
+use crate::commands::{PlayerCommands, PlayerId};
+use crate::navigation::Navigator;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use specs::prelude::World;
+use std::path::Path;
+use std::rc::Rc;
+
+/// How a bot makes up its mind
+pub enum Mind {
+    Tree(Rc<BehaviourTree>, Box<Blackboard>),
+    Script(Box<Script>),
+}
+
+/// A player driven by a behaviour tree or a script
+pub struct Bot {
+    pub player: PlayerId,
+    pub mind: Mind,
+    /// What the tank was last told, a command is only sent again once it changes
+    sent: PlayerCommands,
+}
+
+impl Bot {
+    /// A bot for `player` deciding with `tree`. Anything it does at random is drawn from `seed`.
+    pub fn new(player: PlayerId, tree: Rc<BehaviourTree>, seed: u64) -> Self {
+        let rng = StdRng::seed_from_u64(seed ^ u64::from(player));
+        Bot {
+            player,
+            mind: Mind::Tree(tree, Box::new(Blackboard::new(rng))),
+            sent: PlayerCommands::default(),
+        }
+    }
+
+    pub fn scripted(player: PlayerId, script: Script) -> Self {
+        Bot {
+            player,
+            mind: Mind::Script(Box::new(script)),
+            sent: PlayerCommands::default(),
+        }
+    }
+
+    /// A bot for `player` from a file, a script if it ends in `.rhai` and a behaviour tree otherwise.
+    /// Scripts get `budget` operations a tick.
+    pub fn load(player: PlayerId, path: &Path, seed: u64, budget: u64) -> Result<Self, String> {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "rhai")
+        {
+            Ok(Bot::scripted(player, Script::load(path, budget)?))
+        } else {
+            Ok(Bot::new(player, Rc::new(BehaviourTree::load(path)?), seed))
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.mind {
+            Mind::Tree(tree, _) => &tree.name,
+            Mind::Script(script) => &script.name,
+        }
+    }
+
+    /// The tank the bot drives
+    pub fn archetype(&self) -> TankArchetype {
+        match &self.mind {
+            Mind::Tree(tree, _) => tree.archetype,
+            Mind::Script(script) => script.archetype,
+        }
+    }
+
+    /// Ticks a script ran out of operations on, a behaviour tree never does
+    pub fn overruns(&self) -> u32 {
+        match &self.mind {
+            Mind::Tree(..) => 0,
+            Mind::Script(script) => script.overruns,
+        }
+    }
+
+    /// This tick's commands, nothing once its tank is gone
+    pub fn think(&mut self, world: &World, navigator: &mut Navigator) -> PlayerCommands {
+        let decided = match &mut self.mind {
+            Mind::Tree(tree, blackboard) => tree.tick(world, self.player, blackboard, navigator),
+            Mind::Script(script) => script.tick(world, self.player),
+        };
+        let decided = match decided {
+            Some(decided) => decided,
+            None => {
+                // A new tank starts out knowing nothing
+                self.sent = PlayerCommands::default();
+                return PlayerCommands::default();
+            }
+        };
+        let commands = PlayerCommands {
+            movement: changed(decided.movement, self.sent.movement),
+            rotation: changed(decided.rotation, self.sent.rotation),
+            fire: changed(decided.fire, self.sent.fire),
+            weapon: changed(decided.weapon, self.sent.weapon),
+        };
+        self.sent = PlayerCommands {
+            movement: decided.movement.or(self.sent.movement),
+            rotation: decided.rotation.or(self.sent.rotation),
+            fire: decided.fire.or(self.sent.fire),
+            weapon: decided.weapon.or(self.sent.weapon),
+        };
+        commands
+    }
+}
+
+fn changed<T: PartialEq>(now: Option<T>, before: Option<T>) -> Option<T> {
+    if now == before {
+        None
+    } else {
+        now
+    }
+}
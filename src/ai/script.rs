@@ -0,0 +1,403 @@
+use crate::ai::senses;
+use crate::archetype::TankArchetype;
+use crate::commands::{
+    FireCommand, MovementCommand, PlayerCommands, PlayerId, RotationCommand, WeaponCommand,
+};
+use crate::components::{Angle, Rotation};
+use crate::fog::Sight;
+use crate::resources::Tick;
+use rhai::packages::{
+    BasicArrayPackage, BasicMapPackage, BasicMathPackage, CorePackage, LogicPackage,
+    MoreStringPackage, Package,
+};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST, FLOAT, INT};
+use specs::prelude::{World, WorldExt};
+use std::fs;
+use std::path::Path;
+
+/// How many operations a script gets each tick unless it is told otherwise
+pub const DEFAULT_BUDGET: u64 = 50_000;
+
+/// A bot's decision making written in Rhai.
+///
+/// The script defines `fn think(me, enemies)`, called once a tick with a map describing its own tank
+/// and an array of the enemy tanks its side can see, nearest first. It returns a map of the commands
+/// to send, any of `movement` (an angle in degrees or `"stop"`), `rotation` (`"cw"`, `"ccw"` or `"stop"`),
+/// `fire` (`true` or `false`) and `weapon` (a slot counted from 1); whatever is left out carries on as it was.
+/// `this` is a map kept from one tick to the next. An optional `fn tank()` returns the archetype to drive.
+///
+/// Scripts can't reach anything outside the game: there is no file access, no modules, no clock and no
+/// `eval`, and a tick that runs over its budget of operations is skipped with the tank sat still.
+pub struct Script {
+    pub name: String,
+    pub archetype: TankArchetype,
+    engine: Engine,
+    ast: AST,
+    /// What the script remembers, `this` inside `think`
+    memory: Dynamic,
+    /// Ticks the script ran out of operations on
+    pub overruns: u32,
+    /// Ticks the script failed on some other way
+    pub errors: u32,
+}
+
+impl Script {
+    pub fn load(path: &Path, budget: u64) -> Result<Script, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read script {:?}: {}", path, e))?;
+        let name = path.file_stem().map_or("bot".to_string(), |stem| {
+            stem.to_string_lossy().into_owned()
+        });
+        Script::parse(&name, &text, budget).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    /// Compile `text`, giving it `budget` operations a tick
+    pub fn parse(name: &str, text: &str, budget: u64) -> Result<Script, String> {
+        let engine = sandbox(name, budget);
+        let ast = engine.compile(text).map_err(|e| e.to_string())?;
+        let defines = |function: &str, params: usize| {
+            ast.iter_functions()
+                .any(|f| f.name == function && f.params.len() == params)
+        };
+        if !defines("think", 2) {
+            return Err("a script must define fn think(me, enemies)".to_string());
+        }
+        let archetype = if defines("tank", 0) {
+            let tank: String = engine
+                .call_fn_with_options(
+                    CallFnOptions::new().eval_ast(false),
+                    &mut Scope::new(),
+                    &ast,
+                    "tank",
+                    (),
+                )
+                .map_err(|e| format!("tank(): {}", e))?;
+            TankArchetype::from_name(&tank).ok_or(format!(
+                "tank(): expected scout, medium or heavy, not {}",
+                tank
+            ))?
+        } else {
+            TankArchetype::default()
+        };
+        Ok(Script {
+            name: name.to_string(),
+            archetype,
+            engine,
+            ast,
+            memory: Dynamic::from_map(Map::new()),
+            overruns: 0,
+            errors: 0,
+        })
+    }
+
+    /// Decide on `player`'s commands for this tick, or nothing if its tank is gone
+    pub fn tick(&mut self, world: &World, player: PlayerId) -> Option<PlayerCommands> {
+        let me = senses::own_tank(world, player)?;
+        let tick = world.read_resource::<Tick>().0;
+
+        let mut own = Map::new();
+        own.insert("x".into(), Dynamic::from_float(me.position.x().into()));
+        own.insert("y".into(), Dynamic::from_float(me.position.y().into()));
+        own.insert("turret".into(), Dynamic::from_float(me.turret.into()));
+        own.insert(
+            "turret_speed".into(),
+            Dynamic::from_float(me.turret_speed.into()),
+        );
+        own.insert(
+            "heading".into(),
+            Dynamic::from_float(me.velocity.direction.angle.into()),
+        );
+        own.insert(
+            "speed".into(),
+            Dynamic::from_float(me.velocity.speed.into()),
+        );
+        own.insert("health".into(), Dynamic::from_int(me.health.current.into()));
+        own.insert("max_health".into(), Dynamic::from_int(me.health.max.into()));
+        own.insert("weapon".into(), Dynamic::from_int(me.selected as INT + 1));
+        own.insert("weapons".into(), Dynamic::from_int(me.weapons as INT));
+        own.insert("reloading".into(), Dynamic::from_bool(me.reloading));
+        own.insert("tick".into(), Dynamic::from_int(tick as INT));
+
+        let mut seen = Sight::of(world, player).enemies(world);
+        let distance = |position: crate::geometry::Point| {
+            let offset = position - me.position;
+            (offset.x() as FLOAT).hypot(offset.y() as FLOAT)
+        };
+        // Ties are broken on player, which `enemies` is already sorted by
+        seen.sort_by(|a, b| {
+            distance(a.1)
+                .partial_cmp(&distance(b.1))
+                .expect("distances are never NaN")
+        });
+        let enemies: Array = seen
+            .into_iter()
+            .map(|(enemy, position)| {
+                let mut map = Map::new();
+                map.insert("player".into(), Dynamic::from_int(enemy.into()));
+                map.insert("x".into(), Dynamic::from_float(position.x().into()));
+                map.insert("y".into(), Dynamic::from_float(position.y().into()));
+                map.insert("distance".into(), Dynamic::from_float(distance(position)));
+                map.insert(
+                    "clear_shot".into(),
+                    Dynamic::from_bool(senses::clear_shot(
+                        world,
+                        player,
+                        me.position,
+                        enemy,
+                        position,
+                    )),
+                );
+                Dynamic::from_map(map)
+            })
+            .collect();
+
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut self.memory),
+            &mut Scope::new(),
+            &self.ast,
+            "think",
+            (Dynamic::from_map(own), Dynamic::from_array(enemies)),
+        );
+        let error = match result.map_err(|e| *e) {
+            Ok(decided) => match commands(decided) {
+                Ok(commands) => return Some(commands),
+                Err(e) => e,
+            },
+            Err(EvalAltResult::ErrorTooManyOperations(_)) => {
+                if self.overruns == 0 {
+                    eprintln!("{} ran out of operations on tick {}", self.name, tick);
+                }
+                self.overruns += 1;
+                return Some(sit_still());
+            }
+            Err(e) => e.to_string(),
+        };
+        if self.errors == 0 {
+            eprintln!("{} failed on tick {}: {}", self.name, tick, error);
+        }
+        self.errors += 1;
+        Some(sit_still())
+    }
+}
+
+/// An engine that can only run `budget` operations at a time and reach nothing but what it is given
+fn sandbox(name: &str, budget: u64) -> Engine {
+    // A raw engine has no packages, module resolver or printing to start with.
+    // The time package is left out, scripts must behave the same every time.
+    let mut engine = Engine::new_raw();
+    engine.register_global_module(CorePackage::new().as_shared_module());
+    engine.register_global_module(LogicPackage::new().as_shared_module());
+    engine.register_global_module(BasicMathPackage::new().as_shared_module());
+    engine.register_global_module(BasicArrayPackage::new().as_shared_module());
+    engine.register_global_module(BasicMapPackage::new().as_shared_module());
+    engine.register_global_module(MoreStringPackage::new().as_shared_module());
+    engine.disable_symbol("eval");
+    engine.set_max_operations(budget.max(1));
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(4096);
+    engine.set_max_array_size(4096);
+    engine.set_max_map_size(4096);
+    engine.set_max_modules(0);
+
+    let name = name.to_string();
+    // Kept off standard output, which may be carrying a protocol
+    engine.on_print(move |text| eprintln!("{}: {}", name, text));
+    // The way `dx`, `dy` points in degrees, as tanks and turrets measure it
+    engine.register_fn("heading", |dx: FLOAT, dy: FLOAT| dy.atan2(dx).to_degrees());
+    // How far to turn from `from` to face `to`, between -180 and 180 degrees, positive is clockwise
+    engine.register_fn("angle_diff", |from: FLOAT, to: FLOAT| {
+        let turn = (to - from).rem_euclid(360.0);
+        if turn > 180.0 {
+            turn - 360.0
+        } else {
+            turn
+        }
+    });
+    engine
+}
+
+/// Sent when a script couldn't decide, so a broken script doesn't leave its tank charging on
+fn sit_still() -> PlayerCommands {
+    PlayerCommands {
+        movement: Some(MovementCommand::Stop),
+        rotation: Some(RotationCommand::Stop),
+        fire: Some(FireCommand::Stop),
+        weapon: None,
+    }
+}
+
+/// The commands in a map returned by `think`
+fn commands(decided: Dynamic) -> Result<PlayerCommands, String> {
+    let mut commands = PlayerCommands::default();
+    if decided.is_unit() {
+        return Ok(commands);
+    }
+    let map = decided
+        .try_cast::<Map>()
+        .ok_or("think must return a map of commands")?;
+    for (key, value) in map {
+        match key.as_str() {
+            "movement" => {
+                commands.movement = Some(match number(&value) {
+                    Some(angle) => MovementCommand::Move(Angle {
+                        angle: angle as f32,
+                    }),
+                    None if text(&value).as_deref() == Some("stop") => MovementCommand::Stop,
+                    None => return Err("movement must be an angle or \"stop\"".to_string()),
+                })
+            }
+            "rotation" => {
+                commands.rotation = Some(match text(&value).as_deref() {
+                    Some("cw") => RotationCommand::Move(Rotation::Clockwise),
+                    Some("ccw") => RotationCommand::Move(Rotation::CounterClockwise),
+                    Some("stop") => RotationCommand::Stop,
+                    _ => return Err("rotation must be \"cw\", \"ccw\" or \"stop\"".to_string()),
+                })
+            }
+            "fire" => {
+                commands.fire = Some(match value.as_bool() {
+                    Ok(true) => FireCommand::Fire,
+                    Ok(false) => FireCommand::Stop,
+                    Err(_) => return Err("fire must be true or false".to_string()),
+                })
+            }
+            "weapon" => {
+                commands.weapon = Some(match value.as_int() {
+                    Ok(slot) if (1..=9).contains(&slot) => WeaponCommand::Select(slot as u8 - 1),
+                    _ => return Err("weapon must be a slot from 1 to 9".to_string()),
+                })
+            }
+            other => return Err(format!("unknown command {}", other)),
+        }
+    }
+    Ok(commands)
+}
+
+fn number(value: &Dynamic) -> Option<FLOAT> {
+    value
+        .as_float()
+        .ok()
+        .or_else(|| value.as_int().ok().map(|int| int as FLOAT))
+}
+
+fn text(value: &Dynamic) -> Option<String> {
+    value.clone().into_string().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game;
+
+    /// A match with tanks for players 1 and 2
+    fn world() -> World {
+        let mut dispatcher = game::build_dispatcher();
+        game::create_match(&mut dispatcher, 1, &[1, 2])
+    }
+
+    fn script(text: &str) -> Script {
+        Script::parse("test", text, 1000).expect("script compiles")
+    }
+
+    /// What `think` returning `value` sends
+    fn decided(value: &str) -> Result<PlayerCommands, String> {
+        commands(sandbox("test", 1000).eval(value).expect("value evaluates"))
+    }
+
+    #[test]
+    fn scripts_that_run_out_of_operations_sit_still() {
+        let world = world();
+        let mut script = script("fn think(me, enemies) { loop {} }");
+        assert_eq!(script.tick(&world, 1), Some(sit_still()));
+        assert_eq!(script.tick(&world, 1), Some(sit_still()));
+        assert_eq!((script.overruns, script.errors), (2, 0));
+    }
+
+    #[test]
+    fn scripts_cant_reach_outside_the_game() {
+        assert!(Script::parse("test", r#"fn think(me, enemies) { eval("1") }"#, 1000).is_err());
+
+        // The rest compile, but fail when they run
+        let world = world();
+        for text in [
+            r#"fn think(me, enemies) { import "other" as other; }"#,
+            r#"fn think(me, enemies) { open_file("secrets") }"#,
+            r#"fn think(me, enemies) { timestamp() }"#,
+        ] {
+            let mut script = script(text);
+            assert_eq!(script.tick(&world, 1), Some(sit_still()));
+            assert_eq!((script.overruns, script.errors), (0, 1));
+        }
+    }
+
+    #[test]
+    fn commands_are_read_from_the_map() {
+        assert_eq!(decided("()"), Ok(PlayerCommands::default()));
+        assert_eq!(
+            decided(r#"#{ movement: 90, rotation: "ccw", fire: true, weapon: 9 }"#),
+            Ok(PlayerCommands {
+                movement: Some(MovementCommand::Move(Angle { angle: 90.0 })),
+                rotation: Some(RotationCommand::Move(Rotation::CounterClockwise)),
+                fire: Some(FireCommand::Fire),
+                weapon: Some(WeaponCommand::Select(8)),
+            })
+        );
+        assert_eq!(
+            decided(r#"#{ movement: "stop", rotation: "stop", fire: false, weapon: 1 }"#),
+            Ok(PlayerCommands {
+                weapon: Some(WeaponCommand::Select(0)),
+                ..sit_still()
+            })
+        );
+    }
+
+    #[test]
+    fn bad_commands_are_rejected() {
+        for value in [
+            "42",
+            r#"#{ movement: "left" }"#,
+            r#"#{ movement: true }"#,
+            r#"#{ rotation: "left" }"#,
+            r#"#{ rotation: 1 }"#,
+            r#"#{ fire: 1 }"#,
+            r#"#{ weapon: 0 }"#,
+            r#"#{ weapon: 10 }"#,
+            r#"#{ weapon: "1" }"#,
+            r#"#{ jump: true }"#,
+        ] {
+            assert!(decided(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn bad_commands_count_as_errors() {
+        let world = world();
+        let mut script = script(r#"fn think(me, enemies) { #{ weapon: 0 } }"#);
+        assert_eq!(script.tick(&world, 1), Some(sit_still()));
+        assert_eq!((script.overruns, script.errors), (0, 1));
+    }
+
+    #[test]
+    fn this_is_remembered_from_one_tick_to_the_next() {
+        let world = world();
+        let text = r#"
+            fn think(me, enemies) {
+                if this.ticks == () { this.ticks = 0; }
+                this.ticks += 1;
+                #{ weapon: this.ticks }
+            }
+        "#;
+        let mut first = script(text);
+        for slot in 0..3 {
+            let commands = first.tick(&world, 1).expect("tank is alive");
+            assert_eq!(commands.weapon, Some(WeaponCommand::Select(slot)));
+        }
+        // Each script has its own
+        let mut second = script(text);
+        let commands = second.tick(&world, 2).expect("tank is alive");
+        assert_eq!(commands.weapon, Some(WeaponCommand::Select(0)));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::commands::PlayerId;
+use crate::components::Ammo;
+use crate::components::Angle;
+use crate::components::AngularVelocity;
+use crate::components::BulletSpawner;
+use crate::components::Collider;
+use crate::components::Engine;
+use crate::components::Health;
+use crate::components::KeyboardControlled;
+use crate::components::Position;
+use crate::components::Velocity;
+use crate::geometry::Point;
+use crate::raycast;
+use crate::spatial::SpatialIndex;
+use specs::join::Join;
+use specs::prelude::{World, WorldExt};
+
+/// What a bot knows about its own tank
+#[derive(Debug, Clone)]
+pub struct TankView {
+    pub position: Point,
+    pub velocity: Velocity,
+    pub engine: Engine,
+    /// The way the turret points, in degrees
+    pub turret: f32,
+    /// How fast the turret is turning, in degrees a tick
+    pub turret_speed: f32,
+    pub health: Health,
+    /// The slot of the weapon in use, counted from 0
+    pub selected: usize,
+    /// How many weapons the turret can switch between
+    pub weapons: usize,
+    pub reloading: bool,
+}
+
+/// `player`'s tank in `world`, if it is still in one piece
+pub fn own_tank(world: &World, player: PlayerId) -> Option<TankView> {
+    let positions = world.read_storage::<Position>();
+    let controlled = world.read_storage::<KeyboardControlled>();
+    let velocities = world.read_storage::<Velocity>();
+    let engines = world.read_storage::<Engine>();
+    let healths = world.read_storage::<Health>();
+    let colliders = world.read_storage::<Collider>();
+    let angles = world.read_storage::<Angle>();
+    let angular_velocities = world.read_storage::<AngularVelocity>();
+    let spawners = world.read_storage::<BulletSpawner>();
+    let ammo = world.read_storage::<Ammo>();
+
+    let (position, velocity, engine, health) = (
+        &positions,
+        &controlled,
+        &velocities,
+        &engines,
+        &healths,
+        &colliders,
+    )
+        .join()
+        .find(|(_, control, _, _, _, _)| control.player == player)
+        .map(|(pos, _, vel, engine, health, _)| (pos.0, vel.clone(), engine.clone(), *health))?;
+    let (turret, turret_speed, spawner, ammo) = (
+        &controlled,
+        &angles,
+        &angular_velocities,
+        &spawners,
+        ammo.maybe(),
+    )
+        .join()
+        .find(|(control, _, _, _, _)| control.player == player)
+        .map(|(_, angle, angular_vel, spawner, ammo)| {
+            (angle.angle, angular_vel.speed, spawner, ammo)
+        })?;
+    Some(TankView {
+        position,
+        velocity,
+        engine,
+        turret,
+        turret_speed,
+        health,
+        selected: spawner.selected,
+        weapons: spawner.weapons.len(),
+        reloading: ammo.is_some_and(|ammo| ammo.magazines[spawner.selected].is_reloading()),
+    })
+}
+
+/// Whether a shot from `from` would fly straight into `target`'s tank, rather than into a wall
+/// or some other tank first
+pub fn clear_shot(
+    world: &World,
+    player: PlayerId,
+    from: Point,
+    target: PlayerId,
+    to: Point,
+) -> bool {
+    let controlled = world.read_storage::<KeyboardControlled>();
+    let hit = raycast::raycast(
+        &world.read_resource::<SpatialIndex>(),
+        &world.read_storage::<Collider>(),
+        from,
+        to,
+        |entity| {
+            controlled
+                .get(entity)
+                .is_none_or(|control| control.player != player)
+        },
+    );
+    hit.is_some_and(|hit| {
+        controlled
+            .get(hit.entity)
+            .is_some_and(|control| control.player == target)
+    })
+}
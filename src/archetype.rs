@@ -0,0 +1,109 @@
+use crate::components::Engine;
+use crate::weapon::Weapon;
+use std::fmt;
+
+/// The kinds of tank a player can pick, trading speed for firepower
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TankArchetype {
+    Scout,
+    #[default]
+    Medium,
+    Heavy,
+}
+
+impl TankArchetype {
+    pub const ALL: [TankArchetype; 3] = [
+        TankArchetype::Scout,
+        TankArchetype::Medium,
+        TankArchetype::Heavy,
+    ];
+
+    /// How far the tank drives in a tick
+    pub fn speed(self) -> f32 {
+        match self {
+            TankArchetype::Scout => 28.0,
+            TankArchetype::Medium => 20.0,
+            TankArchetype::Heavy => 14.0,
+        }
+    }
+
+    /// How many degrees the turret turns in a tick
+    pub fn rotation_speed(self) -> f32 {
+        match self {
+            TankArchetype::Scout => 3.0,
+            TankArchetype::Medium => 2.0,
+            TankArchetype::Heavy => 1.5,
+        }
+    }
+
+    /// How quickly the tank gets up to speed and comes to a stop, with nobody at the controls
+    pub fn engine(self) -> Engine {
+        let (mass, acceleration, braking, friction, turn_acceleration) = match self {
+            TankArchetype::Scout => (1.0, 4.0, 8.0, 4.0, 1.0),
+            TankArchetype::Medium => (1.5, 4.5, 9.0, 3.0, 0.5),
+            TankArchetype::Heavy => (2.5, 5.0, 10.0, 2.0, 0.25),
+        };
+        Engine {
+            mass,
+            acceleration,
+            braking,
+            friction,
+            turn_acceleration,
+            throttle: None,
+            turn: None,
+        }
+    }
+
+    /// How much damage the tank takes to destroy
+    pub fn health(self) -> u32 {
+        match self {
+            TankArchetype::Scout => 80,
+            TankArchetype::Medium => 100,
+            TankArchetype::Heavy => 140,
+        }
+    }
+
+    /// The weapons the turret can switch between, the first is selected at spawn
+    pub fn weapons(self) -> Vec<Weapon> {
+        match self {
+            TankArchetype::Scout => vec![Weapon::MachineGun, Weapon::Spread, Weapon::Burst],
+            TankArchetype::Medium => vec![
+                Weapon::Cannon,
+                Weapon::Burst,
+                Weapon::Missile,
+                Weapon::Laser,
+            ],
+            TankArchetype::Heavy => vec![Weapon::Cannon, Weapon::Shell, Weapon::Missile],
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TankArchetype::Scout => "scout",
+            TankArchetype::Medium => "medium",
+            TankArchetype::Heavy => "heavy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TankArchetype> {
+        TankArchetype::ALL
+            .iter()
+            .cloned()
+            .find(|archetype| archetype.name() == name)
+    }
+
+    /// A stable number identifying the archetype on the wire
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_index(index: u8) -> Option<TankArchetype> {
+        TankArchetype::ALL.get(index as usize).cloned()
+    }
+}
+
+impl fmt::Display for TankArchetype {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,75 @@
+use rusty_tanks::archetype::TankArchetype;
+use rusty_tanks::{ai, gym};
+use std::path::PathBuf;
+
+/// Command line options
+struct Options {
+    /// Serve trainers over TCP on this port instead of standard input and output
+    port: Option<u16>,
+    /// The opponents, until a trainer says otherwise
+    bots: Vec<PathBuf>,
+    /// How many operations a bot's script gets each tick
+    bot_budget: u64,
+    /// The agent's tank
+    archetype: TankArchetype,
+    /// How long an episode lasts before it is over
+    max_ticks: u64,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        port: None,
+        bots: Vec::new(),
+        bot_budget: ai::script::DEFAULT_BUDGET,
+        archetype: TankArchetype::default(),
+        max_ticks: 3 * 60 * 20,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--port" => {
+                let port = value()?;
+                options.port = Some(port.parse().map_err(|_| format!("bad port {}", port))?);
+            }
+            "--bot" => options.bots.push(value()?.into()),
+            "--bot-budget" => {
+                let budget = value()?;
+                options.bot_budget = budget
+                    .parse()
+                    .map_err(|_| format!("bad bot budget {}", budget))?;
+            }
+            "--archetype" => {
+                let name = value()?;
+                options.archetype =
+                    TankArchetype::from_name(&name).ok_or(format!("unknown archetype {}", name))?;
+            }
+            "--max-ticks" => {
+                let ticks = value()?;
+                options.max_ticks = ticks
+                    .parse()
+                    .map_err(|_| format!("bad max ticks {}", ticks))?;
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Let a trainer drive a tank without a window, so it runs where SDL isn't installed
+fn main() -> Result<(), String> {
+    let options = parse_args()?;
+    let config = gym::Config {
+        archetype: options.archetype,
+        opponents: options.bots,
+        observation: gym::ObservationKind::Entities,
+        rewards: gym::Rewards::default(),
+        max_ticks: options.max_ticks,
+        ticks_per_step: 1,
+        budget: options.bot_budget,
+    };
+    match options.port {
+        Some(port) => gym::run_tcp(config, port),
+        None => gym::run_stdio(config),
+    }
+}
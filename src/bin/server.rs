@@ -0,0 +1,60 @@
+use rusty_tanks::net;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Command line options
+struct Options {
+    seed: Option<u64>,
+    port: u16,
+    /// Host a lobby with this name, serving the match once everyone is ready
+    host: Option<String>,
+    /// How many players a hosted lobby waits for
+    min_players: u8,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        seed: None,
+        port: net::DEFAULT_PORT,
+        host: None,
+        min_players: 2,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--seed" => {
+                let seed = value()?;
+                options.seed = Some(seed.parse().map_err(|_| format!("bad seed {}", seed))?);
+            }
+            "--port" => {
+                let port = value()?;
+                options.port = port.parse().map_err(|_| format!("bad port {}", port))?;
+            }
+            "--host" => options.host = Some(value()?),
+            "--min-players" => {
+                let count = value()?;
+                options.min_players = count
+                    .parse()
+                    .map_err(|_| format!("bad player count {}", count))?;
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Serve matches without a window, so it runs where SDL isn't installed
+fn main() -> Result<(), String> {
+    let options = parse_args()?;
+    let seed = options.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0)
+    });
+
+    match &options.host {
+        Some(name) => net::lobby::run_host(options.port, name, seed, options.min_players),
+        None => net::server::run(options.port, seed),
+    }
+}
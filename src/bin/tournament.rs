@@ -0,0 +1,75 @@
+use rusty_tanks::{ai, tournament};
+use std::path::PathBuf;
+
+/// Command line options
+struct Options {
+    /// The first match's seed, the rest count up from it
+    seed: u64,
+    /// The behaviour trees and scripts of the bots to play against each other
+    bots: Vec<PathBuf>,
+    /// How many operations a bot's script gets each tick
+    bot_budget: u64,
+    /// How many times each pair of bots meets
+    rounds: u32,
+    /// How long a match lasts before it is called a draw
+    max_ticks: u64,
+    /// Write the results to this file, JSON if it ends in .json and CSV otherwise
+    results: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    // Left to itself a tournament plays the same matches every time it is run
+    let mut options = Options {
+        seed: 0,
+        bots: Vec::new(),
+        bot_budget: ai::script::DEFAULT_BUDGET,
+        rounds: 2,
+        max_ticks: 3 * 60 * 20,
+        results: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--seed" => {
+                let seed = value()?;
+                options.seed = seed.parse().map_err(|_| format!("bad seed {}", seed))?;
+            }
+            "--bot" => options.bots.push(value()?.into()),
+            "--bot-budget" => {
+                let budget = value()?;
+                options.bot_budget = budget
+                    .parse()
+                    .map_err(|_| format!("bad bot budget {}", budget))?;
+            }
+            "--rounds" => {
+                let rounds = value()?;
+                options.rounds = rounds
+                    .parse()
+                    .map_err(|_| format!("bad rounds {}", rounds))?;
+            }
+            "--max-ticks" => {
+                let ticks = value()?;
+                options.max_ticks = ticks
+                    .parse()
+                    .map_err(|_| format!("bad max ticks {}", ticks))?;
+            }
+            "--results" => options.results = Some(value()?.into()),
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Play bots against each other without a window, so it runs where SDL isn't installed
+fn main() -> Result<(), String> {
+    let options = parse_args()?;
+    tournament::run(
+        &options.bots,
+        options.rounds,
+        options.seed,
+        options.max_ticks,
+        options.bot_budget,
+        options.results.as_deref(),
+    )
+}
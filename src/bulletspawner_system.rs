@@ -1,11 +1,23 @@
+use crate::components::Ammo;
 use crate::components::Angle;
 use crate::components::AngularVelocity;
 use crate::components::BulletSpawner;
+use crate::components::Collider;
+use crate::components::Health;
+use crate::components::KeyboardControlled;
 use crate::components::Position;
+use crate::components::Projectile;
 use crate::components::Rotation;
-use crate::components::Sprite;
+use crate::components::StatusEffects;
+use crate::components::Team;
 use crate::components::Velocity;
-use sdl2::rect::{Point, Rect};
+use crate::damage;
+use crate::particles::{EffectKind, Effects};
+use crate::raycast;
+use crate::score::Scoreboard;
+use crate::spatial::SpatialIndex;
+use crate::status;
+use crate::weapon::Weapon;
 use specs::join::Join;
 use specs::storage::WriteStorage;
 use specs::Entities;
@@ -13,6 +25,10 @@ use specs::LazyUpdate;
 use specs::Read;
 use specs::ReadStorage;
 use specs::System;
+use specs::Write;
+
+/// Fires the turrets whose triggers are held. Projectiles are spawned to fly on their own,
+/// beams hit the first thing in their way there and then.
 pub struct BulletSpawnerSystem;
 
 impl<'a> System<'a> for BulletSpawnerSystem {
@@ -22,56 +38,231 @@ impl<'a> System<'a> for BulletSpawnerSystem {
         WriteStorage<'a, BulletSpawner>,
         Entities<'a>,
         Read<'a, LazyUpdate>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Team>,
+        WriteStorage<'a, Ammo>,
+        ReadStorage<'a, StatusEffects>,
+        Read<'a, SpatialIndex>,
+        ReadStorage<'a, Collider>,
+        WriteStorage<'a, Health>,
+        Write<'a, Effects>,
+        Write<'a, Scoreboard>,
     );
 
-    fn run(&mut self, mut data: Self::SystemData) {
-        let entities = data.3;
-        let updater = data.4;
-        for (pos, angle, spawner) in (&data.0, &data.1, &mut data.2).join() {
-            match spawner {
-                BulletSpawner {
-                    spawning: true,
-                    cooldown_rem: 0,
-                    cooldown: _,
-                    bullet_speed: _,
-                } => {
-                    spawner.cooldown_rem = spawner.cooldown;
-
-                    let bullet = entities.create();
-                    updater.insert(
-                        bullet,
-                        Velocity {
-                            speed: spawner.bullet_speed,
-                            direction: Angle { angle: angle.angle },
-                        },
-                    );
-                    updater.insert(bullet, Position(Point::new(pos.0.x, pos.0.y)));
-                    updater.insert(bullet, Angle { angle: angle.angle });
-                    updater.insert(
-                        bullet,
-                        AngularVelocity {
-                            speed: 2.0,
-                            rotation: Rotation::Clockwise,
-                        },
-                    );
-                    updater.insert(
-                        bullet,
-                        Sprite {
-                            spritesheet: 0,
-                            region: Rect::new(0, 0, 32, 32),
-                        },
-                    );
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            positions,
+            angles,
+            mut spawners,
+            entities,
+            updater,
+            controlled,
+            teams,
+            mut ammunition,
+            statuses,
+            index,
+            colliders,
+            mut health,
+            mut fx,
+            mut scoreboard,
+        ) = data;
+        for (pos, angle, spawner, control, team, mut ammo, effects) in (
+            &positions,
+            &angles,
+            &mut spawners,
+            controlled.maybe(),
+            teams.maybe(),
+            (&mut ammunition).maybe(),
+            statuses.maybe(),
+        )
+            .join()
+        {
+            if let Some(ammo) = ammo.as_mut() {
+                for magazine in &mut ammo.magazines {
+                    magazine.tick();
                 }
-                BulletSpawner {
-                    spawning: _,
-                    cooldown_rem: 1..=u32::MAX,
-                    cooldown: _,
-                    bullet_speed: _,
-                } => {
-                    spawner.cooldown_rem -= 1;
+            }
+
+            if spawner.cooldown_rem > 0 {
+                spawner.cooldown_rem -= 1;
+                continue;
+            }
+
+            let weapon = spawner.weapon();
+            if spawner.burst_rem == 0 && spawner.spawning {
+                spawner.burst_rem = weapon.burst();
+            }
+            if spawner.burst_rem == 0 {
+                continue;
+            }
+            // Turrets without ammunition never run out
+            if let Some(ammo) = ammo.as_mut() {
+                if !ammo.magazines[spawner.selected].take_round() {
+                    spawner.burst_rem = 0;
+                    continue;
+                }
+            }
+            spawner.burst_rem -= 1;
+            spawner.cooldown_rem = if spawner.burst_rem > 0 {
+                weapon.burst_interval()
+            } else {
+                status::cooldown(effects, weapon.cooldown())
+            };
+
+            let owner = control.map(|control| control.player);
+            for direction in pellet_angles(weapon, angle.angle) {
+                if weapon.beam_length() > 0 {
+                    // Straight through its own tank and team mates
+                    let end = raycast::ray_end(pos.0, direction, weapon.beam_length() as f32);
+                    let hit = raycast::raycast(&index, &colliders, pos.0, end, |target| {
+                        let own = owner.is_some()
+                            && controlled.get(target).map(|control| control.player) == owner;
+                        !own && !damage::friendly(team, teams.get(target))
+                    });
+                    let reached = hit.map_or(end, |hit| hit.point);
+                    if let Some(hit) = hit {
+                        if let Some(health) = health.get_mut(hit.entity) {
+                            let damage =
+                                status::damage_taken(statuses.get(hit.entity), weapon.damage());
+                            let victim = controlled.get(hit.entity).map(|control| control.player);
+                            scoreboard.damage(health, owner, victim, damage);
+                        }
+                        fx.add(EffectKind::Sparks, hit.point);
+                    }
+                    fx.add(EffectKind::Beam { to: reached }, pos.0);
+                    continue;
+                }
+                let bullet = entities.create();
+                updater.insert(
+                    bullet,
+                    Velocity {
+                        speed: weapon.projectile_speed(),
+                        direction: Angle { angle: direction },
+                    },
+                );
+                updater.insert(bullet, *pos);
+                updater.insert(bullet, Angle { angle: direction });
+                updater.insert(
+                    bullet,
+                    AngularVelocity {
+                        speed: weapon.spin(),
+                        rotation: Rotation::Clockwise,
+                    },
+                );
+                updater.insert(bullet, weapon.sprite());
+                updater.insert(
+                    bullet,
+                    Projectile {
+                        weapon,
+                        owner,
+                        damage: weapon.damage(),
+                        ticks_left: weapon.range(),
+                        bounces_rem: weapon.bounces(),
+                    },
+                );
+                if let Some(team) = team {
+                    updater.insert(bullet, *team);
                 }
-                _ => {}
             }
         }
     }
 }
+
+/// The direction of each pellet of a shot fired at `angle`, fanned evenly across the weapon's spread
+fn pellet_angles(weapon: Weapon, angle: f32) -> impl Iterator<Item = f32> {
+    let pellets = weapon.pellets();
+    let step = if pellets > 1 {
+        weapon.spread() / (pellets - 1) as f32
+    } else {
+        0.0
+    };
+    let first = angle - weapon.spread() / 2.0;
+    (0..pellets).map(move |i| first + step * i as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Sprite;
+    use crate::geometry::Point;
+    use specs::prelude::{Builder, RunNow, World, WorldExt};
+    use specs::Entity;
+
+    fn arena() -> World {
+        let mut world = World::new();
+        System::setup(&mut BulletSpawnerSystem, &mut world);
+        world.register::<Velocity>();
+        world.register::<AngularVelocity>();
+        world.register::<Sprite>();
+        world.register::<Projectile>();
+        world
+    }
+
+    fn turret(world: &mut World, weapon: Weapon) -> Entity {
+        world
+            .create_entity()
+            .with(Position(Point::new(0, 0)))
+            .with(Angle { angle: 0.0 })
+            .with(BulletSpawner::new(vec![weapon], true))
+            .build()
+    }
+
+    /// Runs a tick and returns how many projectiles it fired
+    fn run(world: &mut World) -> usize {
+        let before = world.read_storage::<Projectile>().count();
+        BulletSpawnerSystem.run_now(world);
+        world.maintain();
+        world.read_storage::<Projectile>().count() - before
+    }
+
+    fn release(world: &mut World, turret: Entity) {
+        world
+            .write_storage::<BulletSpawner>()
+            .get_mut(turret)
+            .expect("turret is alive")
+            .spawning = false;
+    }
+
+    #[test]
+    fn pellets_fan_evenly_across_the_spread() {
+        let angles: Vec<f32> = pellet_angles(Weapon::Spread, 90.0).collect();
+        assert_eq!(angles, vec![70.0, 80.0, 90.0, 100.0, 110.0]);
+    }
+
+    #[test]
+    fn single_shots_go_straight() {
+        let angles: Vec<f32> = pellet_angles(Weapon::Cannon, 90.0).collect();
+        assert_eq!(angles, vec![90.0]);
+    }
+
+    #[test]
+    fn a_spread_shot_fires_every_pellet_at_once() {
+        let mut world = arena();
+        turret(&mut world, Weapon::Spread);
+        assert_eq!(run(&mut world), 5);
+    }
+
+    #[test]
+    fn a_burst_fires_its_shots_then_cools_down() {
+        let mut world = arena();
+        turret(&mut world, Weapon::Burst);
+        let cooldown = Weapon::Burst.cooldown() as usize;
+
+        let fired: Vec<usize> = (0..5 + cooldown).map(|_| run(&mut world)).collect();
+        let mut expected = vec![1, 0, 1, 0, 1];
+        expected.extend(vec![0; cooldown]);
+        assert_eq!(fired, expected);
+        // The next burst starts once the cooldown is over
+        assert_eq!(run(&mut world), 1);
+    }
+
+    #[test]
+    fn letting_go_of_the_trigger_finishes_the_burst() {
+        let mut world = arena();
+        let turret = turret(&mut world, Weapon::Burst);
+        assert_eq!(run(&mut world), 1);
+        release(&mut world, turret);
+        let fired: usize = (0..20).map(|_| run(&mut world)).sum();
+        assert_eq!(fired, 2);
+    }
+}
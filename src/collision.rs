@@ -0,0 +1,453 @@
+use crate::commands::PlayerId;
+use crate::components::Collider;
+use crate::components::Engine;
+use crate::components::KeyboardControlled;
+use crate::components::Position;
+use crate::components::Velocity;
+use crate::components::Wall;
+use crate::geometry::{Point, Rect};
+use crate::ricochet::overlap;
+use crate::spatial::SpatialIndex;
+use crate::terrain::Terrain;
+use core::f32::consts::PI;
+use specs::join::Join;
+use specs::storage::WriteStorage;
+use specs::Entities;
+use specs::Entity;
+use specs::Read;
+use specs::ReadStorage;
+use specs::System;
+use specs::Write;
+use std::collections::HashMap;
+use std::iter;
+
+/// The furthest anything moves in one go along either axis. Faster things move in several
+/// steps, checking for what they run into after each, so they can't pass through thin walls.
+const MAX_SUBSTEP: i32 = 8;
+
+/// How heavy solid things without an engine of their own are
+const UNPOWERED_MASS: f32 = 1.0;
+
+/// Split a move of `step` into substeps no longer than `MAX_SUBSTEP` along either axis.
+/// They are all the same but the first, which also takes whatever doesn't divide evenly.
+pub fn substeps(step: Point) -> impl Iterator<Item = Point> {
+    let longest = step.x().abs().max(step.y().abs());
+    let count = ((longest + MAX_SUBSTEP - 1) / MAX_SUBSTEP).max(1);
+    let each = Point::new(step.x() / count, step.y() / count);
+    let first = step - each * (count - 1);
+    iter::once(first).chain(iter::repeat_n(each, count as usize - 1))
+}
+
+/// The smallest box covering `rect` all the way along a move of `step`
+pub fn swept(rect: Rect, step: Point) -> Rect {
+    let left = rect.left().min(rect.left() + step.x());
+    let top = rect.top().min(rect.top() + step.y());
+    let right = rect.right().max(rect.right() + step.x());
+    let bottom = rect.bottom().max(rect.bottom() + step.y());
+    Rect::new(left, top, (right - left) as u32, (bottom - top) as u32)
+}
+
+/// What a moving box can't move into: a set of solid boxes and, for tanks, the ground they can't drive on.
+/// Only newly touching something counts, whatever a box already overlaps it is free to move out of.
+pub struct Obstacles<'t> {
+    solids: Vec<Rect>,
+    terrain: Option<&'t Terrain>,
+}
+
+impl<'t> Obstacles<'t> {
+    pub fn new(solids: Vec<Rect>, terrain: Option<&'t Terrain>) -> Self {
+        Obstacles { solids, terrain }
+    }
+
+    /// The walls in `area` and the ground, for a tank moving within it
+    pub fn walls(
+        index: &SpatialIndex,
+        walls: &ReadStorage<Wall>,
+        area: Rect,
+        terrain: &'t Terrain,
+    ) -> Self {
+        let solids = index
+            .in_rect(area)
+            .into_iter()
+            .filter(|entry| walls.contains(entry.entity))
+            .map(|entry| entry.bounds)
+            .collect();
+        Obstacles::new(solids, Some(terrain))
+    }
+
+    /// Everything solid in `area`, for a shot moving within it
+    pub fn colliders(index: &SpatialIndex, colliders: &ReadStorage<Collider>, area: Rect) -> Self {
+        let solids = index
+            .in_rect(area)
+            .into_iter()
+            .filter(|entry| colliders.contains(entry.entity))
+            .map(|entry| entry.bounds)
+            .collect();
+        Obstacles::new(solids, None)
+    }
+
+    /// Whether moving `from` one box to `to` runs into anything
+    fn blocks(&self, from: Rect, to: Rect) -> bool {
+        let ground = self.terrain.is_some_and(|terrain| {
+            terrain.at(from.center()).passable() && !terrain.at(to.center()).passable()
+        });
+        ground
+            || self
+                .solids
+                .iter()
+                .any(|&solid| overlap(solid, to).is_some() && overlap(solid, from).is_none())
+    }
+
+    /// Move a `width` by `height` box at `from` by `step`, stopping as soon as it touches something.
+    /// Returns where it got to and whether it was stopped.
+    pub fn sweep(&self, from: Point, step: Point, width: u32, height: u32) -> (Point, bool) {
+        let mut pos = from;
+        for substep in substeps(step) {
+            let to = pos + substep;
+            let touched = self.blocks(
+                Rect::from_center(pos, width, height),
+                Rect::from_center(to, width, height),
+            );
+            pos = to;
+            if touched {
+                return (pos, true);
+            }
+        }
+        (pos, false)
+    }
+
+    /// Move a box with `collider` at `from` by `step`. Running into something it slides along it,
+    /// carrying on along whichever axis it still can. Returns where it got to and whether it was
+    /// stopped along the x and the y axis.
+    pub fn slide(&self, collider: &Collider, from: Point, step: Point) -> (Point, (bool, bool)) {
+        let mut pos = from;
+        let (mut stopped_x, mut stopped_y) = (false, false);
+        for substep in substeps(step) {
+            let x = if stopped_x { 0 } else { substep.x() };
+            let y = if stopped_y { 0 } else { substep.y() };
+            let here = collider.rect(pos);
+            let free = |offset: Point| !self.blocks(here, collider.rect(pos + offset));
+            if free(Point::new(x, y)) {
+                pos += Point::new(x, y);
+            } else if free(Point::new(x, 0)) {
+                pos += Point::new(x, 0);
+                stopped_y |= y != 0;
+            } else if free(Point::new(0, y)) {
+                pos += Point::new(0, y);
+                stopped_x |= x != 0;
+            } else {
+                stopped_x |= x != 0;
+                stopped_y |= y != 0;
+            }
+        }
+        (pos, (stopped_x, stopped_y))
+    }
+}
+
+/// Stop `vel` moving along the axes something stopped it on, keeping the rest of its speed
+pub fn stop_along(vel: &mut Velocity, (stopped_x, stopped_y): (bool, bool)) {
+    if !stopped_x && !stopped_y {
+        return;
+    }
+    let radians = vel.direction.angle * (PI / 180.0);
+    let x = if stopped_x {
+        0.0
+    } else {
+        vel.speed * radians.cos()
+    };
+    let y = if stopped_y {
+        0.0
+    } else {
+        vel.speed * radians.sin()
+    };
+    set_velocity(vel, x, y);
+}
+
+fn set_velocity(vel: &mut Velocity, x: f32, y: f32) {
+    vel.speed = (x * x + y * y).sqrt();
+    // Something stopped keeps the direction it was last going in
+    if vel.speed > 0.0 {
+        vel.direction.angle = y.atan2(x) * (180.0 / PI);
+    }
+}
+
+/// A tank taking part in this tick's collisions
+struct Body {
+    player: PlayerId,
+    pos: Point,
+    collider: Collider,
+    mass: f32,
+    /// Without an engine nothing would slow it down again, so it is only ever pushed
+    powered: bool,
+    velocity: (f32, f32),
+    /// Whether it ran into another this tick
+    hit: bool,
+}
+
+/// Pushes tanks that have driven into each other apart, the lighter one giving way more,
+/// and leaves them moving together along the way they collided
+pub struct Collisions;
+
+impl<'a> System<'a> for Collisions {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Velocity>,
+        ReadStorage<'a, Collider>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Engine>,
+        ReadStorage<'a, Wall>,
+        Read<'a, Terrain>,
+        Write<'a, SpatialIndex>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        // Resolved in order of player so the outcome doesn't depend on storage order
+        let mut bodies: Vec<_> = (&data.0, &data.1, &data.2, &data.3, &data.4, data.5.maybe())
+            .join()
+            .map(|(entity, pos, vel, collider, control, engine)| {
+                let radians = vel.direction.angle * (PI / 180.0);
+                let body = Body {
+                    player: control.player,
+                    pos: pos.0,
+                    collider: *collider,
+                    mass: engine.map_or(UNPOWERED_MASS, |engine| engine.mass),
+                    powered: engine.is_some(),
+                    velocity: (vel.speed * radians.cos(), vel.speed * radians.sin()),
+                    hit: false,
+                };
+                (entity, body)
+            })
+            .collect();
+        bodies.sort_by_key(|(_, body)| body.player);
+        let order: HashMap<Entity, usize> = bodies
+            .iter()
+            .enumerate()
+            .map(|(i, (entity, _))| (*entity, i))
+            .collect();
+
+        // Every pair is tried in turn, a body pushed out of one collision can end up in another
+        let (walls, terrain, index) = (&data.6, &data.7, &mut data.8);
+        for i in 0..bodies.len() {
+            let mut others: Vec<usize> = index
+                .in_rect(bodies[i].1.collider.rect(bodies[i].1.pos))
+                .into_iter()
+                .filter_map(|entry| order.get(&entry.entity).cloned())
+                .filter(|&j| j > i)
+                .collect();
+            others.sort_unstable();
+            for j in others {
+                let (left, right) = bodies.split_at_mut(j);
+                let ((a_entity, a), (b_entity, b)) = (&mut left[i], &mut right[0]);
+                let near = |area| Obstacles::walls(index, walls, area, terrain);
+                if collide(near, a, b) {
+                    index.insert(*a_entity, a.collider.rect(a.pos));
+                    index.insert(*b_entity, b.collider.rect(b.pos));
+                }
+            }
+        }
+
+        for (entity, body) in bodies.into_iter().filter(|(_, body)| body.hit) {
+            data.1.get_mut(entity).expect("body has a position").0 = body.pos;
+            if body.powered {
+                let vel = data.2.get_mut(entity).expect("body has a velocity");
+                set_velocity(vel, body.velocity.0, body.velocity.1);
+            }
+        }
+    }
+}
+
+/// Separate `a` and `b` if they overlap, along whichever axis they overlap least on, returning
+/// whether they did. `near` gives what is in the way of pushing either of them about an area.
+fn collide<'t>(near: impl Fn(Rect) -> Obstacles<'t>, a: &mut Body, b: &mut Body) -> bool {
+    let (width, height) = match overlap(a.collider.rect(a.pos), b.collider.rect(b.pos)) {
+        Some(overlap) => overlap,
+        None => return false,
+    };
+    a.hit = true;
+    b.hit = true;
+    // The direction `a` is pushed in, a tank right on top of another goes the negative way
+    let along_x = width < height;
+    let (depth, normal) = if along_x {
+        let sign = if a.pos.x() <= b.pos.x() { -1 } else { 1 };
+        (width, Point::new(sign, 0))
+    } else {
+        let sign = if a.pos.y() <= b.pos.y() { -1 } else { 1 };
+        (height, Point::new(0, sign))
+    };
+
+    // Each gives way in proportion to the other's mass, whatever one can't move the other takes up
+    let share = (depth as f32 * b.mass / (a.mass + b.mass)).round() as i32;
+    let push = normal * share;
+    let (moved, _) = near(swept(a.collider.rect(a.pos), push)).slide(&a.collider, a.pos, push);
+    let pushed = (moved - a.pos).x().abs() + (moved - a.pos).y().abs();
+    a.pos = moved;
+    let push = normal * -(depth - pushed);
+    b.pos = near(swept(b.collider.rect(b.pos), push))
+        .slide(&b.collider, b.pos, push)
+        .0;
+
+    // Moving towards each other, they carry on at their combined momentum along the normal
+    let (nx, ny) = (normal.x() as f32, normal.y() as f32);
+    let speed_a = a.velocity.0 * nx + a.velocity.1 * ny;
+    let speed_b = b.velocity.0 * nx + b.velocity.1 * ny;
+    if speed_a >= speed_b {
+        return true;
+    }
+    let shared = (a.mass * speed_a + b.mass * speed_b) / (a.mass + b.mass);
+    a.velocity.0 += (shared - speed_a) * nx;
+    a.velocity.1 += (shared - speed_a) * ny;
+    b.velocity.0 += (shared - speed_b) * nx;
+    b.velocity.1 += (shared - speed_b) * ny;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Angle;
+    use crate::spatial::Indexing;
+    use specs::prelude::{Builder, RunNow, World, WorldExt};
+
+    const TANK: Collider = Collider {
+        width: 32,
+        height: 32,
+    };
+
+    fn arena() -> World {
+        let mut world = World::new();
+        System::setup(&mut Collisions, &mut world);
+        System::setup(&mut Indexing, &mut world);
+        world
+    }
+
+    /// A tank of `mass` at `x` going `speed` to the right
+    fn tank(world: &mut World, player: PlayerId, mass: f32, x: i32, speed: f32) -> Entity {
+        world
+            .create_entity()
+            .with(Position(Point::new(x, 0)))
+            .with(Velocity {
+                speed,
+                direction: Angle { angle: 0.0 },
+            })
+            .with(TANK)
+            .with(KeyboardControlled {
+                player,
+                speed: 4.0,
+                rotation_speed: 1.0,
+            })
+            .with(Engine {
+                mass,
+                acceleration: 1.0,
+                braking: 1.0,
+                friction: 1.0,
+                turn_acceleration: 1.0,
+                throttle: None,
+                turn: None,
+            })
+            .build()
+    }
+
+    fn wall(world: &mut World, x: i32) {
+        world
+            .create_entity()
+            .with(Position(Point::new(x, 0)))
+            .with(TANK)
+            .with(Wall)
+            .build();
+    }
+
+    fn run(world: &mut World) {
+        Indexing.run_now(world);
+        Collisions.run_now(world);
+        world.maintain();
+    }
+
+    fn x(world: &World, entity: Entity) -> i32 {
+        world
+            .read_storage::<Position>()
+            .get(entity)
+            .expect("tank is alive")
+            .0
+            .x()
+    }
+
+    fn velocity(world: &World, entity: Entity) -> (f32, f32) {
+        let vel = world.read_storage::<Velocity>();
+        let vel = vel.get(entity).expect("tank is alive");
+        let radians = vel.direction.angle * (PI / 180.0);
+        (vel.speed * radians.cos(), vel.speed * radians.sin())
+    }
+
+    #[test]
+    fn substeps_add_up_to_the_whole_move() {
+        let steps: Vec<Point> = substeps(Point::new(20, -3)).collect();
+        assert_eq!(
+            steps,
+            vec![Point::new(8, -1), Point::new(6, -1), Point::new(6, -1)]
+        );
+        let steps: Vec<Point> = substeps(Point::new(0, 0)).collect();
+        assert_eq!(steps, vec![Point::new(0, 0)]);
+    }
+
+    #[test]
+    fn fast_moves_dont_pass_through_thin_walls() {
+        let obstacles = Obstacles::new(vec![Rect::new(20, -10, 4, 20)], None);
+        let (moved, stopped) = obstacles.sweep(Point::new(0, 0), Point::new(40, 0), 4, 4);
+        assert_eq!((moved, stopped), (Point::new(24, 0), true));
+        // Whatever it is already in, it is free to move out of
+        let (moved, stopped) = obstacles.sweep(Point::new(22, 0), Point::new(-20, 0), 4, 4);
+        assert_eq!((moved, stopped), (Point::new(2, 0), false));
+    }
+
+    #[test]
+    fn tanks_slide_along_walls() {
+        let obstacles = Obstacles::new(vec![Rect::new(40, -100, 8, 200)], None);
+        let (moved, stopped) = obstacles.slide(&TANK, Point::new(0, 0), Point::new(40, 40));
+        assert_eq!(moved, Point::new(24, 40));
+        assert_eq!(stopped, (true, false));
+
+        let mut vel = Velocity {
+            speed: 2.0,
+            direction: Angle { angle: 45.0 },
+        };
+        stop_along(&mut vel, stopped);
+        assert!((vel.speed - 2.0f32.sqrt()).abs() < 1e-4);
+        assert!((vel.direction.angle - 90.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn the_lighter_tank_gives_way_more() {
+        let mut world = arena();
+        let light = tank(&mut world, 1, 1.0, 0, 4.0);
+        let heavy = tank(&mut world, 2, 3.0, 24, 0.0);
+        run(&mut world);
+        assert_eq!((x(&world, light), x(&world, heavy)), (-6, 26));
+
+        // Carrying on together at their combined momentum
+        let (light_x, _) = velocity(&world, light);
+        let (heavy_x, _) = velocity(&world, heavy);
+        assert!((light_x - 1.0).abs() < 1e-4);
+        assert!((heavy_x - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn a_tank_against_a_wall_pushes_the_other_all_the_way() {
+        let mut world = arena();
+        wall(&mut world, -36);
+        let pinned = tank(&mut world, 1, 1.0, 0, 0.0);
+        let pushed = tank(&mut world, 2, 3.0, 24, -4.0);
+        run(&mut world);
+        assert_eq!((x(&world, pinned), x(&world, pushed)), (0, 32));
+    }
+
+    #[test]
+    fn tanks_apart_are_left_alone() {
+        let mut world = arena();
+        let a = tank(&mut world, 1, 1.0, 0, 4.0);
+        let b = tank(&mut world, 2, 1.0, 32, -4.0);
+        run(&mut world);
+        assert_eq!((x(&world, a), x(&world, b)), (0, 32));
+        assert!((velocity(&world, a).0 - 4.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::components::Angle;
+use crate::components::Rotation;
+use std::collections::BTreeMap;
+
+/// Identifies which player's commands drive a `KeyboardControlled` entity
+pub type PlayerId = u8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RotationCommand {
+    Stop,
+    Move(Rotation),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MovementCommand {
+    Stop,
+    Move(Angle),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FireCommand {
+    Stop,
+    Fire,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeaponCommand {
+    /// Switch to the next weapon in the turret's list, wrapping around
+    Next,
+    /// Switch to the weapon in this slot of the list, if there is one
+    Select(u8),
+}
+
+/// The commands issued by a single player during one tick.
+/// None - no change, Some(command) - apply the command
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PlayerCommands {
+    pub movement: Option<MovementCommand>,
+    pub rotation: Option<RotationCommand>,
+    pub fire: Option<FireCommand>,
+    pub weapon: Option<WeaponCommand>,
+}
+
+impl PlayerCommands {
+    pub fn is_empty(&self) -> bool {
+        self.movement.is_none()
+            && self.rotation.is_none()
+            && self.fire.is_none()
+            && self.weapon.is_none()
+    }
+
+    /// Combine with commands issued after these, the later command wins wherever both have one
+    pub fn merge(&mut self, later: &PlayerCommands) {
+        self.movement = later.movement.or(self.movement);
+        self.rotation = later.rotation.or(self.rotation);
+        self.fire = later.fire.or(self.fire);
+        self.weapon = later.weapon.or(self.weapon);
+    }
+}
+
+/// Resource holding this tick's commands for every player.
+/// A BTreeMap keeps iteration order stable so the simulation stays deterministic.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Commands(pub BTreeMap<PlayerId, PlayerCommands>);
+
+impl Commands {
+    pub fn get(&self, player: PlayerId) -> Option<&PlayerCommands> {
+        self.0.get(&player)
+    }
+}
@@ -1,16 +1,21 @@
-use sdl2::rect::{Point, Rect};
+use crate::commands::PlayerId;
+use crate::geometry::{Point, Rect};
+use crate::particles::EffectKind;
+use crate::pickup::PickupKind;
+use crate::status::StatusKind;
+use crate::weapon::Weapon;
 use specs::Component;
-use specs::HashMapStorage;
+use specs::NullStorage;
 use specs::VecStorage;
 use specs_derive::Component;
 
 /// The current position of a given entity
 /// https://docs.rs/specs/0.16.1/specs/storage/index.html
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
 #[storage(VecStorage)]
 pub struct Position(pub Point);
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, Debug, Clone, PartialEq)]
 #[storage(VecStorage)]
 pub struct Sprite {
     /// The specific spritesheet to render from
@@ -19,15 +24,36 @@ pub struct Sprite {
     pub region: Rect,
 }
 
-#[derive(Component, Debug)]
-#[storage(HashMapStorage)]
+#[derive(Component, Debug, Clone)]
+#[storage(VecStorage)]
 pub struct BulletSpawner {
     pub spawning: bool,
-    pub cooldown: u32,
+    /// The weapons that can be switched between
+    pub weapons: Vec<Weapon>,
+    /// Index into `weapons` of the one that fires
+    pub selected: usize,
     pub cooldown_rem: u32,
-    pub bullet_speed: f32,
+    /// Shots left to fire in the current burst
+    pub burst_rem: u32,
 }
 
+impl BulletSpawner {
+    pub fn new(weapons: Vec<Weapon>, spawning: bool) -> Self {
+        BulletSpawner {
+            spawning,
+            weapons,
+            selected: 0,
+            cooldown_rem: 0,
+            burst_rem: 0,
+        }
+    }
+
+    pub fn weapon(&self) -> Weapon {
+        self.weapons[self.selected]
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Component, Debug, Clone)]
 #[storage(VecStorage)]
 pub struct LayeredSprite {
@@ -40,21 +66,23 @@ pub enum Rotation {
     CounterClockwise,
 }
 
-#[derive(Component, Debug, Default)]
+#[derive(Component, Debug, Default, Clone)]
 #[storage(VecStorage)]
 pub struct KeyboardControlled {
+    /// The player whose commands drive this entity
+    pub player: PlayerId,
     pub speed: f32,
     pub rotation_speed: f32,
 }
 
-#[derive(Component, Debug, Default, Clone, Copy)]
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
 #[storage(VecStorage)]
 pub struct Angle {
     pub angle: f32,
 }
 
 /// The current speed and Direction of a given entity
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, PartialEq)]
 #[storage(VecStorage)]
 pub struct Velocity {
     pub speed: f32,
@@ -62,9 +90,328 @@ pub struct Velocity {
 }
 
 /// The current speed and Rotation of a given entity
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, PartialEq)]
 #[storage(VecStorage)]
 pub struct AngularVelocity {
     pub speed: f32,
     pub rotation: Rotation,
 }
+
+/// The team an entity fights for, entities without one fight for themselves
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+#[storage(VecStorage)]
+pub struct Team(pub u8);
+
+/// The ammunition a turret carries for each of its weapons
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(VecStorage)]
+pub struct Ammo {
+    /// One per weapon, in the same order as the `BulletSpawner`'s
+    pub magazines: Vec<Magazine>,
+}
+
+impl Ammo {
+    /// Full magazines and reserves for `weapons`
+    pub fn new(weapons: &[Weapon]) -> Self {
+        Ammo {
+            magazines: weapons
+                .iter()
+                .map(|&weapon| Magazine {
+                    size: weapon.magazine_size(),
+                    loaded: weapon.magazine_size(),
+                    reserve: weapon.max_reserve(),
+                    max_reserve: weapon.max_reserve(),
+                    reload_time: weapon.reload_time(),
+                    reload_rem: 0,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Magazine {
+    pub size: u32,
+    /// Rounds ready to fire
+    pub loaded: u32,
+    /// Rounds carried to reload with
+    pub reserve: u32,
+    pub max_reserve: u32,
+    pub reload_time: u32,
+    /// Ticks until the reload in progress finishes, 0 when not reloading
+    pub reload_rem: u32,
+}
+
+impl Magazine {
+    pub fn is_reloading(&self) -> bool {
+        self.reload_rem > 0
+    }
+
+    /// Take a round to fire, false if there is none loaded.
+    /// Emptying the magazine, or trying to fire from an empty one, starts a reload.
+    pub fn take_round(&mut self) -> bool {
+        let fired = self.loaded > 0;
+        if fired {
+            self.loaded -= 1;
+        }
+        if self.loaded == 0 && !self.is_reloading() && self.reserve > 0 {
+            self.reload_rem = self.reload_time;
+        }
+        fired
+    }
+
+    /// Advance a reload in progress by a tick, filling the magazine from the reserve when done
+    pub fn tick(&mut self) {
+        if self.reload_rem == 0 {
+            return;
+        }
+        self.reload_rem -= 1;
+        if self.reload_rem == 0 {
+            let rounds = (self.size - self.loaded).min(self.reserve);
+            self.loaded += rounds;
+            self.reserve -= rounds;
+        }
+    }
+}
+
+/// Something for the first tank that drives over it, which reappears after a while
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(VecStorage)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    /// Ticks until it can be picked up again, 0 when it is there to be picked up
+    pub respawn_rem: u32,
+}
+
+/// A team's flag, which the other team tries to carry back to its own base
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(VecStorage)]
+pub struct Flag {
+    pub team: Team,
+    /// Where it stands when it is at home
+    pub base: Point,
+    /// The player whose tank is carrying it
+    pub carrier: Option<PlayerId>,
+}
+
+/// A shot in flight
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(VecStorage)]
+pub struct Projectile {
+    pub weapon: Weapon,
+    /// The player who fired it, if a player did
+    pub owner: Option<PlayerId>,
+    pub damage: u32,
+    /// Ticks until it expires
+    pub ticks_left: u32,
+    /// Walls it can still bounce off, it is destroyed by the next wall it hits once this is 0
+    pub bounces_rem: u32,
+}
+
+/// A solid box centred on the entity's position
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+#[storage(VecStorage)]
+pub struct Collider {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Collider {
+    /// The box around `position`
+    pub fn rect(&self, position: Point) -> Rect {
+        Rect::from_center(position, self.width, self.height)
+    }
+}
+
+/// Marks an immovable obstacle that projectiles bounce off
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+#[storage(NullStorage)]
+pub struct Wall;
+
+/// How much more damage an entity can take, it is destroyed when this reaches 0
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+#[storage(VecStorage)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Health {
+    pub fn new(max: u32) -> Self {
+        Health { current: max, max }
+    }
+}
+
+/// A push from an explosion, moving the entity on top of its own velocity until it dies away
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(VecStorage)]
+pub struct Knockback(pub Velocity);
+
+/// A blast that damages and pushes away everything around it when it goes off, then plays out
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(VecStorage)]
+pub struct Explosion {
+    pub radius: i32,
+    /// Damage dealt at the very centre, falling off to nothing at the edge
+    pub damage: u32,
+    /// Ticks since it went off
+    pub age: u32,
+    /// Who fired the shot that set it off
+    pub owner: Option<PlayerId>,
+}
+
+/// Gives off particles every `interval` ticks while the entity is moving
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+#[storage(VecStorage)]
+pub struct Emitter {
+    pub kind: EffectKind,
+    pub interval: u32,
+}
+
+impl Emitter {
+    pub fn exhaust() -> Self {
+        Emitter {
+            kind: EffectKind::Smoke,
+            interval: 2,
+        }
+    }
+}
+
+/// The timed boosts an entity is under, every entity of a tank gets the same ones
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+#[storage(VecStorage)]
+pub struct StatusEffects {
+    pub active: Vec<StatusEffect>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    /// Ticks until it wears off
+    pub ticks_left: u32,
+}
+
+impl StatusEffects {
+    pub fn has(&self, kind: StatusKind) -> bool {
+        self.active.iter().any(|effect| effect.kind == kind)
+    }
+
+    /// Start `kind`, or restart it if it is already running. Returns whether it is new.
+    pub fn grant(&mut self, kind: StatusKind) -> bool {
+        let ticks_left = kind.duration();
+        match self.active.iter_mut().find(|effect| effect.kind == kind) {
+            Some(effect) => {
+                effect.ticks_left = ticks_left;
+                false
+            }
+            None => {
+                self.active.push(StatusEffect { kind, ticks_left });
+                true
+            }
+        }
+    }
+}
+
+/// How a tank gets up to speed and slows down again, along with what its driver is asking of it.
+/// Its top speeds are those of its `KeyboardControlled`.
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(VecStorage)]
+pub struct Engine {
+    /// Heavier tanks take longer to get going and to change direction
+    pub mass: f32,
+    /// The force driving the tank, it gains this over its mass in speed a tick
+    pub acceleration: f32,
+    /// The force slowing it down when driven against the way it is going
+    pub braking: f32,
+    /// Speed lost a tick while coasting
+    pub friction: f32,
+    /// Degrees a tick the turret's turning speeds up or slows down by
+    pub turn_acceleration: f32,
+    /// The direction being driven in, none when coasting
+    pub throttle: Option<Angle>,
+    /// The way the turret is being turned, none when it is left to stop
+    pub turn: Option<Rotation>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn magazine(loaded: u32, reserve: u32) -> Magazine {
+        Magazine {
+            size: 3,
+            loaded,
+            reserve,
+            max_reserve: 10,
+            reload_time: 4,
+            reload_rem: 0,
+        }
+    }
+
+    fn tick(magazine: &mut Magazine, ticks: u32) {
+        for _ in 0..ticks {
+            magazine.tick();
+        }
+    }
+
+    #[test]
+    fn firing_the_last_round_starts_a_reload() {
+        let mut magazine = magazine(2, 5);
+        assert!(magazine.take_round());
+        assert!(!magazine.is_reloading());
+        assert!(magazine.take_round());
+        assert_eq!(magazine.loaded, 0);
+        assert_eq!(magazine.reload_rem, 4);
+        assert!(!magazine.take_round());
+    }
+
+    #[test]
+    fn reloads_fill_the_magazine_from_the_reserve_once_done() {
+        let mut magazine = magazine(1, 5);
+        magazine.take_round();
+        tick(&mut magazine, 3);
+        assert_eq!((magazine.loaded, magazine.reserve), (0, 5));
+        tick(&mut magazine, 1);
+        assert!(!magazine.is_reloading());
+        assert_eq!((magazine.loaded, magazine.reserve), (3, 2));
+    }
+
+    #[test]
+    fn reloads_take_what_is_left_in_the_reserve() {
+        let mut magazine = magazine(1, 2);
+        magazine.take_round();
+        tick(&mut magazine, 4);
+        assert_eq!((magazine.loaded, magazine.reserve), (2, 0));
+    }
+
+    #[test]
+    fn an_empty_reserve_never_reloads() {
+        let mut magazine = magazine(1, 0);
+        magazine.take_round();
+        assert!(!magazine.is_reloading());
+        assert!(!magazine.take_round());
+        assert!(!magazine.is_reloading());
+    }
+
+    #[test]
+    fn an_empty_magazine_reloads_once_there_is_a_reserve() {
+        let mut magazine = magazine(0, 0);
+        magazine.reserve = 4;
+        assert!(!magazine.take_round());
+        assert!(magazine.is_reloading());
+        // Trying again doesn't start the reload over
+        tick(&mut magazine, 2);
+        magazine.take_round();
+        assert_eq!(magazine.reload_rem, 2);
+    }
+
+    #[test]
+    fn ammo_starts_full() {
+        let ammo = Ammo::new(&[Weapon::Cannon, Weapon::Burst]);
+        for (magazine, weapon) in ammo.magazines.iter().zip(&[Weapon::Cannon, Weapon::Burst]) {
+            assert_eq!(magazine.loaded, weapon.magazine_size());
+            assert_eq!(magazine.reserve, weapon.max_reserve());
+            assert!(!magazine.is_reloading());
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use crate::components::Angle;
+use crate::components::Collider;
+use crate::components::Explosion;
+use crate::components::Health;
+use crate::components::KeyboardControlled;
+use crate::components::Knockback;
+use crate::components::Position;
+use crate::components::Projectile;
+use crate::components::Sprite;
+use crate::components::StatusEffects;
+use crate::components::Team;
+use crate::components::Velocity;
+use crate::components::Wall;
+use crate::geometry::{Point, Rect};
+use crate::particles::{EffectKind, Effects};
+use crate::ricochet::{overlap, PROJECTILE_SIZE};
+use crate::score::Scoreboard;
+use crate::spatial::SpatialIndex;
+use crate::status;
+use specs::join::Join;
+use specs::storage::WriteStorage;
+use specs::Entities;
+use specs::Entity;
+use specs::LazyUpdate;
+use specs::Read;
+use specs::ReadStorage;
+use specs::System;
+use specs::Write;
+use std::collections::HashSet;
+
+/// Index of the explosion spritesheet loaded by the renderer
+pub const EXPLOSION_SPRITE: usize = 6;
+
+/// Frames in the explosion animation, and the ticks each is shown for
+const EXPLOSION_FRAMES: u32 = 6;
+const EXPLOSION_FRAME_TICKS: u32 = 2;
+
+/// How fast an explosion throws something at its very centre
+const KNOCKBACK_SPEED: f32 = 12.0;
+
+/// The fraction of an explosion's damage and push felt `distance` from its centre:
+/// all of it at the centre, falling off linearly to none at `radius` and beyond
+pub fn falloff(distance: f32, radius: f32) -> f32 {
+    if radius <= 0.0 || distance >= radius {
+        0.0
+    } else {
+        1.0 - distance / radius
+    }
+}
+
+/// Remove a projectile from play where it is, setting off its explosion if it has one
+pub fn detonate(
+    entities: &Entities,
+    updater: &LazyUpdate,
+    entity: Entity,
+    position: Point,
+    projectile: &Projectile,
+    team: Option<Team>,
+) {
+    entities.delete(entity).expect("entity is alive");
+
+    let radius = projectile.weapon.blast_radius();
+    if radius == 0 {
+        return;
+    }
+    let explosion = entities.create();
+    updater.insert(explosion, Position(position));
+    updater.insert(explosion, Angle { angle: 0.0 });
+    updater.insert(explosion, explosion_sprite(0));
+    updater.insert(
+        explosion,
+        Explosion {
+            radius,
+            damage: projectile.damage,
+            age: 0,
+            owner: projectile.owner,
+        },
+    );
+    if let Some(team) = team {
+        updater.insert(explosion, team);
+    }
+}
+
+fn explosion_sprite(frame: u32) -> Sprite {
+    Sprite {
+        spritesheet: EXPLOSION_SPRITE,
+        region: Rect::new(frame as i32 * 64, 0, 64, 64),
+    }
+}
+
+/// Whether something on `team` is on the same side as `other`, players without a team have no friends
+pub fn friendly(team: Option<&Team>, other: Option<&Team>) -> bool {
+    team.is_some() && team == other
+}
+
+/// Damages whatever projectiles fly into, other than their own tank and its team mates
+pub struct ProjectileHits;
+
+impl<'a> System<'a> for ProjectileHits {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Projectile>,
+        ReadStorage<'a, Collider>,
+        WriteStorage<'a, Health>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Team>,
+        Read<'a, LazyUpdate>,
+        Write<'a, Effects>,
+        ReadStorage<'a, StatusEffects>,
+        Read<'a, SpatialIndex>,
+        Write<'a, Scoreboard>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let entities = data.0;
+        let updater = data.7;
+        let mut spent = Vec::new();
+        for (entity, pos, projectile, team) in (&entities, &data.1, &data.2, data.6.maybe()).join()
+        {
+            let shot = Rect::from_center(pos.0, PROJECTILE_SIZE, PROJECTILE_SIZE);
+            // Ties are broken on position so the choice doesn't depend on storage order
+            let (positions, colliders, health, controlled, teams) =
+                (&data.1, &data.3, &data.4, &data.5, &data.6);
+            let target = data
+                .10
+                .in_rect(shot)
+                .into_iter()
+                .filter_map(|entry| {
+                    let target = entry.entity;
+                    let target_pos = positions.get(target)?.0;
+                    let collider = colliders.get(target)?;
+                    health.get(target)?;
+                    let own = projectile.owner.is_some()
+                        && controlled.get(target).map(|control| control.player) == projectile.owner;
+                    let hit = !own
+                        && !friendly(team, teams.get(target))
+                        && overlap(collider.rect(target_pos), shot).is_some();
+                    if hit {
+                        Some((target, target_pos))
+                    } else {
+                        None
+                    }
+                })
+                .min_by_key(|(_, target_pos)| {
+                    let offset = *target_pos - pos.0;
+                    (
+                        offset.x().pow(2) + offset.y().pow(2),
+                        target_pos.x(),
+                        target_pos.y(),
+                    )
+                });
+            if let Some((target, _)) = target {
+                // Explosive shots do their damage by exploding instead
+                if projectile.weapon.blast_radius() == 0 {
+                    let damage = status::damage_taken(data.9.get(target), projectile.damage);
+                    let health = data.4.get_mut(target).expect("target has health");
+                    let victim = data.5.get(target).map(|control| control.player);
+                    data.11.damage(health, projectile.owner, victim, damage);
+                }
+                detonate(
+                    &entities,
+                    &updater,
+                    entity,
+                    pos.0,
+                    projectile,
+                    team.cloned(),
+                );
+                data.8.add(EffectKind::Sparks, pos.0);
+                spent.push(entity);
+            }
+        }
+        // Later systems this tick must not see them
+        for entity in spent {
+            data.2.remove(entity);
+        }
+    }
+}
+
+/// Sets explosions off, damaging and pushing away everything in range, then animates them
+pub struct Explosions;
+
+impl<'a> System<'a> for Explosions {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Explosion>,
+        WriteStorage<'a, Sprite>,
+        WriteStorage<'a, Health>,
+        WriteStorage<'a, Knockback>,
+        ReadStorage<'a, Team>,
+        ReadStorage<'a, StatusEffects>,
+        ReadStorage<'a, Wall>,
+        Read<'a, SpatialIndex>,
+        ReadStorage<'a, KeyboardControlled>,
+        Write<'a, Scoreboard>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let entities = data.0;
+        for (entity, pos, explosion, sprite, team) in
+            (&entities, &data.1, &mut data.2, &mut data.3, data.6.maybe()).join()
+        {
+            if explosion.age == 0 {
+                let radius = explosion.radius as f32;
+                let mut pushed = Vec::new();
+                for entry in data.9.in_radius(pos.0, explosion.radius) {
+                    let target = entry.entity;
+                    let (target_pos, health) = match (data.1.get(target), data.4.get_mut(target)) {
+                        (Some(target_pos), Some(health)) => (target_pos, health),
+                        _ => continue,
+                    };
+                    if friendly(team, data.6.get(target)) {
+                        continue;
+                    }
+                    let (effects, wall) = (data.7.get(target), data.8.get(target));
+                    let offset = target_pos.0 - pos.0;
+                    let distance = ((offset.x().pow(2) + offset.y().pow(2)) as f32).sqrt();
+                    let strength = falloff(distance, radius);
+                    if strength <= 0.0 {
+                        continue;
+                    }
+                    let damage = (explosion.damage as f32 * strength).round() as u32;
+                    let damage = status::damage_taken(effects, damage);
+                    let victim = data.10.get(target).map(|control| control.player);
+                    data.11.damage(health, explosion.owner, victim, damage);
+                    // Walls don't budge
+                    if wall.is_some() {
+                        continue;
+                    }
+                    // Something right at the centre is thrown straight up the screen
+                    let direction = if distance > 0.0 {
+                        (offset.y() as f32).atan2(offset.x() as f32).to_degrees()
+                    } else {
+                        270.0
+                    };
+                    pushed.push((target, KNOCKBACK_SPEED * strength, direction));
+                }
+                for (target, speed, direction) in pushed {
+                    data.5
+                        .insert(
+                            target,
+                            Knockback(Velocity {
+                                speed,
+                                direction: Angle { angle: direction },
+                            }),
+                        )
+                        .expect("entity is alive");
+                }
+            }
+
+            explosion.age += 1;
+            let frame = explosion.age / EXPLOSION_FRAME_TICKS;
+            if frame >= EXPLOSION_FRAMES {
+                entities.delete(entity).expect("entity is alive");
+            } else {
+                *sprite = explosion_sprite(frame);
+            }
+        }
+    }
+}
+
+/// Removes everything that has run out of health, a tank's turret going with its hull,
+/// and keeps score of the tanks destroyed
+pub struct Destruction;
+
+impl<'a> System<'a> for Destruction {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Health>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Position>,
+        Write<'a, Effects>,
+        Write<'a, Scoreboard>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, health, controlled, positions, mut effects, mut scoreboard) = data;
+        scoreboard.end_tick();
+        let mut destroyed_players = HashSet::new();
+        for (entity, health, control, pos) in
+            (&entities, &health, controlled.maybe(), &positions).join()
+        {
+            if health.current == 0 {
+                entities.delete(entity).expect("entity is alive");
+                effects.add(EffectKind::Debris, pos.0);
+                if let Some(control) = control {
+                    destroyed_players.insert(control.player);
+                }
+            }
+        }
+        for (entity, control) in (&entities, &controlled).join() {
+            if destroyed_players.contains(&control.player) {
+                entities.delete(entity).expect("entity is alive");
+            }
+        }
+        for player in destroyed_players {
+            scoreboard.destroyed(player);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spatial::Indexing;
+    use specs::prelude::{Builder, RunNow, World, WorldExt};
+
+    #[test]
+    fn falloff_is_full_at_the_centre() {
+        assert_eq!(falloff(0.0, 60.0), 1.0);
+    }
+
+    #[test]
+    fn falloff_is_half_halfway_out() {
+        assert_eq!(falloff(30.0, 60.0), 0.5);
+    }
+
+    #[test]
+    fn falloff_is_nothing_at_the_edge_and_beyond() {
+        assert_eq!(falloff(60.0, 60.0), 0.0);
+        assert_eq!(falloff(61.0, 60.0), 0.0);
+        assert_eq!(falloff(1000.0, 60.0), 0.0);
+    }
+
+    #[test]
+    fn falloff_is_nothing_without_a_radius() {
+        assert_eq!(falloff(0.0, 0.0), 0.0);
+        assert_eq!(falloff(0.0, -10.0), 0.0);
+    }
+
+    fn blast_world() -> World {
+        let mut world = World::new();
+        System::setup(&mut Indexing, &mut world);
+        System::setup(&mut Explosions, &mut world);
+        world
+    }
+
+    fn target(world: &mut World, x: i32, y: i32) -> Entity {
+        world
+            .create_entity()
+            .with(Position(Point::new(x, y)))
+            .with(Collider {
+                width: 28,
+                height: 28,
+            })
+            .with(Health::new(100))
+            .build()
+    }
+
+    /// Set off an explosion at the origin and return what each target was left with
+    fn explode(world: &mut World, damage: u32, targets: &[Entity]) -> Vec<(u32, Option<Velocity>)> {
+        world
+            .create_entity()
+            .with(Position(Point::new(0, 0)))
+            .with(explosion_sprite(0))
+            .with(Explosion {
+                radius: 60,
+                damage,
+                age: 0,
+                owner: None,
+            })
+            .build();
+        Indexing.run_now(world);
+        Explosions.run_now(world);
+
+        let health = world.read_storage::<Health>();
+        let knockback = world.read_storage::<Knockback>();
+        targets
+            .iter()
+            .map(|&target| {
+                (
+                    health.get(target).unwrap().current,
+                    knockback.get(target).map(|knockback| knockback.0.clone()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn damage_is_rounded_to_the_nearest_point() {
+        // Halfway out, 5 damage is 2.5, rounded up
+        let mut world = blast_world();
+        let halfway = target(&mut world, 30, 0);
+        assert_eq!(explode(&mut world, 5, &[halfway])[0].0, 97);
+
+        // A third of the way out 10 damage is 6.67, and nearly at the edge it is 0.17 which does nothing
+        let mut world = blast_world();
+        let third = target(&mut world, 0, 20);
+        let edge = target(&mut world, -59, 0);
+        let left = explode(&mut world, 10, &[third, edge]);
+        assert_eq!(left[0].0, 93);
+        assert_eq!(left[1].0, 100);
+    }
+
+    #[test]
+    fn knockback_pushes_away_from_the_blast() {
+        let mut world = blast_world();
+        let right = target(&mut world, 30, 0);
+        let above = target(&mut world, 0, -45);
+        let below_left = target(&mut world, -20, 20);
+        let left = explode(&mut world, 40, &[right, above, below_left]);
+
+        let right = left[0].1.clone().expect("pushed");
+        assert_eq!(right.direction.angle, 0.0);
+        assert_eq!(right.speed, KNOCKBACK_SPEED * 0.5);
+        // Up the screen is towards negative y
+        let above = left[1].1.clone().expect("pushed");
+        assert_eq!(above.direction.angle, -90.0);
+        assert_eq!(above.speed, KNOCKBACK_SPEED * 0.25);
+        let below_left = left[2].1.clone().expect("pushed");
+        assert!((below_left.direction.angle - 135.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn something_at_the_centre_is_thrown_up_the_screen() {
+        let mut world = blast_world();
+        let centre = target(&mut world, 0, 0);
+        let left = explode(&mut world, 40, &[centre]);
+        assert_eq!(left[0].0, 60);
+        let pushed = left[0].1.clone().expect("pushed");
+        assert_eq!(pushed.direction.angle, 270.0);
+        assert_eq!(pushed.speed, KNOCKBACK_SPEED);
+    }
+
+    #[test]
+    fn team_mates_and_walls_are_not_pushed() {
+        let mut world = blast_world();
+        let mate = target(&mut world, 10, 0);
+        world.write_storage().insert(mate, Team(1)).unwrap();
+        let wall = target(&mut world, -10, 0);
+        world.write_storage().insert(wall, Wall).unwrap();
+        world
+            .create_entity()
+            .with(Position(Point::new(0, 0)))
+            .with(explosion_sprite(0))
+            .with(Team(1))
+            .with(Explosion {
+                radius: 60,
+                damage: 60,
+                age: 0,
+                owner: None,
+            })
+            .build();
+        Indexing.run_now(&world);
+        Explosions.run_now(&world);
+
+        let health = world.read_storage::<Health>();
+        let knockback = world.read_storage::<Knockback>();
+        assert_eq!(health.get(mate).unwrap().current, 100);
+        assert!(knockback.get(mate).is_none());
+        // Walls take damage but don't budge
+        assert_eq!(health.get(wall).unwrap().current, 50);
+        assert!(knockback.get(wall).is_none());
+    }
+}
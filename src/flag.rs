@@ -0,0 +1,365 @@
+use crate::commands::PlayerId;
+use crate::components::{Angle, Flag, Health, KeyboardControlled, Position, Sprite, Team};
+use crate::geometry::{Point, Rect};
+use crate::score::Scoreboard;
+use specs::join::Join;
+use specs::prelude::{Builder, World, WorldExt};
+use specs::storage::WriteStorage;
+use specs::{Entities, Entity, ReadStorage, System, Write};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Index of the flag spritesheet loaded by the renderer
+pub const FLAG_SPRITE: usize = 8;
+
+/// Where the two teams' flags stand, at either end of the road
+pub const BASES: [(i32, i32); 2] = [(-340, 0), (340, 0)];
+
+/// Where a team's tanks start and come back, as offsets from its flag towards the middle of the arena
+const BASE_SPAWNS: [(i32, i32); 3] = [(50, 0), (50, -70), (50, 70)];
+
+/// How close a tank has to drive to a flag to touch it
+const FLAG_RADIUS: i32 = 24;
+
+/// How much of its speed a tank keeps while it carries a flag
+const CARRIER_SPEED: f32 = 0.75;
+
+/// The base of each of the first two of `teams`, in the order given
+pub fn bases(teams: &[u8]) -> BTreeMap<u8, Point> {
+    let mut bases = BTreeMap::new();
+    for &team in teams {
+        if bases.len() < BASES.len() && !bases.contains_key(&team) {
+            let (x, y) = BASES[bases.len()];
+            bases.insert(team, Point::new(x, y));
+        }
+    }
+    bases
+}
+
+/// The points in front of the flag at `base` where its team's tanks spawn
+pub fn spawn_points(base: Point) -> Vec<Point> {
+    let towards_middle = if base.x() < 0 { 1 } else { -1 };
+    BASE_SPAWNS
+        .iter()
+        .map(|&(x, y)| base + Point::new(x * towards_middle, y))
+        .collect()
+}
+
+/// Stand a flag at each team's base
+pub fn spawn_flags(world: &mut World, bases: &BTreeMap<u8, Point>) {
+    for (&team, &base) in bases {
+        let frame = BASES
+            .iter()
+            .position(|&(x, y)| Point::new(x, y) == base)
+            .unwrap_or(0) as i32;
+        world
+            .create_entity()
+            .with(Position(base))
+            .with(Angle { angle: 0.0 })
+            .with(Sprite {
+                spritesheet: FLAG_SPRITE,
+                region: Rect::new(frame * 32, 0, 32, 32),
+            })
+            .with(Flag {
+                team: Team(team),
+                base,
+                carrier: None,
+            })
+            .build();
+    }
+}
+
+/// Lets tanks pick up the other team's flag by driving over it and carry it home to score,
+/// dropping it where they are destroyed. A dropped flag goes back to its base when one of its team touches it.
+pub struct FlagSystem;
+
+impl<'a> System<'a> for FlagSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Flag>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Health>,
+        ReadStorage<'a, Team>,
+        Write<'a, Scoreboard>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut flags, mut positions, mut controlled, health, teams, mut scoreboard) =
+            data;
+
+        // Each tank's team, position and whether it is still in one piece, by player.
+        // Tanks destroyed this tick are only removed once the world is maintained.
+        let tanks: BTreeMap<PlayerId, (Team, Point, bool)> =
+            (&controlled, &health, &positions, &teams)
+                .join()
+                .map(|(control, health, pos, &team)| {
+                    (control.player, (team, pos.0, health.current > 0))
+                })
+                .collect();
+        // Dealt with in team order so two flags changing hands in the same tick doesn't depend on storage order
+        let mut state: Vec<(Entity, Flag, Point)> = (&entities, &flags, &positions)
+            .join()
+            .map(|(entity, flag, pos)| (entity, flag.clone(), pos.0))
+            .collect();
+        state.sort_by_key(|(_, flag, _)| flag.team.0);
+        let bases: BTreeMap<u8, Point> = state
+            .iter()
+            .map(|(_, flag, _)| (flag.team.0, flag.base))
+            .collect();
+
+        // Carried flags go where their carrier went, or stay where it fell
+        for (_, flag, position) in &mut state {
+            if let Some(carrier) = flag.carrier {
+                match tanks.get(&carrier) {
+                    Some(&(_, at, alive)) => {
+                        *position = at;
+                        if !alive {
+                            flag.carrier = None;
+                        }
+                    }
+                    None => flag.carrier = None,
+                }
+            }
+        }
+        let home: BTreeSet<u8> = state
+            .iter()
+            .filter(|(_, flag, position)| flag.carrier.is_none() && *position == flag.base)
+            .map(|(_, flag, _)| flag.team.0)
+            .collect();
+        let mut carrying: BTreeSet<PlayerId> = state
+            .iter()
+            .filter_map(|(_, flag, _)| flag.carrier)
+            .collect();
+        let touching = |a: Point, b: Point| {
+            let offset = a - b;
+            offset.x().abs() <= FLAG_RADIUS && offset.y().abs() <= FLAG_RADIUS
+        };
+
+        // Players who picked up a flag, and who got rid of one
+        let mut slowed = Vec::new();
+        for (_, flag, position) in &mut state {
+            match flag.carrier {
+                Some(carrier) => {
+                    // Brought to its own base while its own flag is there to score
+                    let (team, at, _) = tanks[&carrier];
+                    let base = bases.get(&team.0);
+                    if home.contains(&team.0) && base.is_some_and(|&base| touching(base, at)) {
+                        scoreboard.captured(carrier);
+                        flag.carrier = None;
+                        *position = flag.base;
+                        carrying.remove(&carrier);
+                        slowed.push((carrier, false));
+                    }
+                }
+                None => {
+                    let mut near = tanks
+                        .iter()
+                        .filter(|(_, &(_, at, alive))| alive && touching(*position, at));
+                    if near.clone().any(|(_, &(team, _, _))| team == flag.team) {
+                        *position = flag.base;
+                    } else if let Some((&player, &(_, at, _))) =
+                        near.find(|(player, _)| !carrying.contains(player))
+                    {
+                        flag.carrier = Some(player);
+                        *position = at;
+                        carrying.insert(player);
+                        slowed.push((player, true));
+                    }
+                }
+            }
+        }
+
+        for (entity, flag, position) in state {
+            positions
+                .insert(entity, Position(position))
+                .expect("entity is alive");
+            flags.insert(entity, flag).expect("entity is alive");
+        }
+        // Like a speed boost, the engine takes the tank down to or back up from its new speed
+        for (player, carrying) in slowed {
+            for control in (&mut controlled).join() {
+                if control.player != player {
+                    continue;
+                }
+                if carrying {
+                    control.speed *= CARRIER_SPEED;
+                } else {
+                    control.speed /= CARRIER_SPEED;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::prelude::RunNow;
+
+    const SPEED: f32 = 4.0;
+
+    /// Two teams' flags standing at their bases, team 0 on the left and team 1 on the right
+    fn arena() -> World {
+        let mut world = World::new();
+        System::setup(&mut FlagSystem, &mut world);
+        world.register::<Angle>();
+        world.register::<Sprite>();
+        spawn_flags(&mut world, &bases(&[0, 1]));
+        world
+    }
+
+    fn tank(world: &mut World, player: PlayerId, team: u8, x: i32, y: i32) -> Entity {
+        world
+            .create_entity()
+            .with(KeyboardControlled {
+                player,
+                speed: SPEED,
+                rotation_speed: 1.0,
+            })
+            .with(Health::new(10))
+            .with(Position(Point::new(x, y)))
+            .with(Team(team))
+            .build()
+    }
+
+    fn drive(world: &mut World, tank: Entity, x: i32, y: i32) {
+        world
+            .write_storage::<Position>()
+            .insert(tank, Position(Point::new(x, y)))
+            .expect("tank is alive");
+    }
+
+    fn run(world: &mut World) {
+        FlagSystem.run_now(world);
+        world.maintain();
+    }
+
+    /// Where `team`'s flag is and who carries it
+    fn flag(world: &World, team: u8) -> (Point, Option<PlayerId>) {
+        let flags = world.read_storage::<Flag>();
+        let positions = world.read_storage::<Position>();
+        (&flags, &positions)
+            .join()
+            .find(|(flag, _)| flag.team == Team(team))
+            .map(|(flag, pos)| (pos.0, flag.carrier))
+            .expect("the team has a flag")
+    }
+
+    fn speed(world: &World, tank: Entity) -> f32 {
+        world
+            .read_storage::<KeyboardControlled>()
+            .get(tank)
+            .expect("tank is alive")
+            .speed
+    }
+
+    fn base(team: u8) -> Point {
+        let (x, y) = BASES[usize::from(team)];
+        Point::new(x, y)
+    }
+
+    #[test]
+    fn only_enemies_pick_up_a_flag() {
+        let mut world = arena();
+        let defender = tank(&mut world, 1, 1, 340, 10);
+        run(&mut world);
+        assert_eq!(flag(&world, 1), (base(1), None));
+        assert_eq!(speed(&world, defender), SPEED);
+
+        drive(&mut world, defender, 200, 0);
+        let attacker = tank(&mut world, 2, 0, 330, 0);
+        run(&mut world);
+        assert_eq!(flag(&world, 1), (Point::new(330, 0), Some(2)));
+        assert_eq!(speed(&world, attacker), SPEED * CARRIER_SPEED);
+    }
+
+    #[test]
+    fn a_flag_drops_where_its_carrier_is_destroyed() {
+        let mut world = arena();
+        let attacker = tank(&mut world, 1, 0, 340, 0);
+        run(&mut world);
+        drive(&mut world, attacker, 0, 50);
+        run(&mut world);
+        assert_eq!(flag(&world, 1), (Point::new(0, 50), Some(1)));
+
+        drive(&mut world, attacker, 10, 60);
+        world
+            .write_storage::<Health>()
+            .get_mut(attacker)
+            .expect("tank is alive")
+            .current = 0;
+        run(&mut world);
+        assert_eq!(flag(&world, 1), (Point::new(10, 60), None));
+        world.delete_entity(attacker).expect("tank is alive");
+        run(&mut world);
+        assert_eq!(flag(&world, 1), (Point::new(10, 60), None));
+    }
+
+    #[test]
+    fn a_dropped_flag_goes_home_when_a_team_mate_touches_it() {
+        let mut world = arena();
+        let attacker = tank(&mut world, 1, 0, 340, 0);
+        run(&mut world);
+        drive(&mut world, attacker, 0, 50);
+        world
+            .write_storage::<Health>()
+            .get_mut(attacker)
+            .expect("tank is alive")
+            .current = 0;
+        run(&mut world);
+        assert_eq!(flag(&world, 1), (Point::new(0, 50), None));
+
+        tank(&mut world, 2, 1, 20, 40);
+        run(&mut world);
+        assert_eq!(flag(&world, 1), (base(1), None));
+    }
+
+    #[test]
+    fn flags_are_only_captured_while_the_team_has_its_own_at_home() {
+        let mut world = arena();
+        let attacker = tank(&mut world, 1, 0, 340, 0);
+        let raider = tank(&mut world, 2, 1, -340, 0);
+        run(&mut world);
+        assert_eq!(flag(&world, 0).1, Some(2));
+        assert_eq!(flag(&world, 1).1, Some(1));
+
+        // The raider has its flag, so bringing theirs home scores nothing
+        drive(&mut world, raider, 0, -100);
+        drive(&mut world, attacker, -330, 0);
+        run(&mut world);
+        assert_eq!(world.read_resource::<Scoreboard>().of(1).captures, 0);
+        assert_eq!(flag(&world, 1), (Point::new(-330, 0), Some(1)));
+
+        // Until it is taken back
+        world
+            .write_storage::<Health>()
+            .get_mut(raider)
+            .expect("tank is alive")
+            .current = 0;
+        run(&mut world);
+        drive(&mut world, attacker, 10, -90);
+        run(&mut world);
+        assert_eq!(flag(&world, 0), (base(0), None));
+        assert_eq!(world.read_resource::<Scoreboard>().of(1).captures, 0);
+
+        drive(&mut world, attacker, -330, 0);
+        run(&mut world);
+        assert_eq!(world.read_resource::<Scoreboard>().of(1).captures, 1);
+        assert_eq!(flag(&world, 1), (base(1), None));
+    }
+
+    #[test]
+    fn carriers_are_slowed_once_until_they_score() {
+        let mut world = arena();
+        let attacker = tank(&mut world, 1, 0, 340, 0);
+        for _ in 0..3 {
+            run(&mut world);
+            assert_eq!(speed(&world, attacker), SPEED * CARRIER_SPEED);
+        }
+        drive(&mut world, attacker, -340, 0);
+        run(&mut world);
+        assert_eq!(speed(&world, attacker), SPEED);
+        run(&mut world);
+        assert_eq!(speed(&world, attacker), SPEED);
+    }
+}
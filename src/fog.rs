@@ -0,0 +1,327 @@
+use crate::commands::PlayerId;
+use crate::components::Health;
+use crate::components::KeyboardControlled;
+use crate::components::Position;
+use crate::components::Projectile;
+use crate::components::Team;
+use crate::components::Wall;
+use crate::damage;
+use crate::geometry::Point;
+use crate::mode::Match;
+use crate::raycast;
+use crate::spatial::SpatialIndex;
+use specs::join::Join;
+use specs::prelude::{World, WorldExt};
+use specs::Entity;
+use specs::ReadStorage;
+use std::collections::HashSet;
+
+/// How far a tank can see when no wall is in the way
+pub const VIEW_RANGE: i32 = 320;
+
+/// The side of a square of the fog, it is lifted a square at a time
+pub const FOG_CELL: i32 = 16;
+
+/// What one player's side can see: whatever is in range of one of its tanks without a wall in between
+pub struct Sight {
+    player: PlayerId,
+    team: Option<Team>,
+    eyes: Vec<Point>,
+}
+
+impl Sight {
+    /// Where `player` and its team mates look out from in `world`
+    pub fn of(world: &World, player: PlayerId) -> Self {
+        let positions = world.read_storage::<Position>();
+        let controlled = world.read_storage::<KeyboardControlled>();
+        let teams = world.read_storage::<Team>();
+        // A player waiting to come back has no tank to tell its team by, but the match knows it
+        let entrant = world
+            .try_fetch::<Match>()
+            .and_then(|game| game.entrants.get(&player).map(|entrant| entrant.team));
+        let team = match entrant {
+            Some(team) => team,
+            None => (&controlled, teams.maybe())
+                .join()
+                .find(|(control, _)| control.player == player)
+                .and_then(|(_, team)| team.cloned()),
+        };
+        let mut sight = Sight {
+            player,
+            team,
+            eyes: Vec::new(),
+        };
+        // A turret sits on its hull, looking out from both would only do the work twice
+        let mut eyes: Vec<Point> = (&positions, &controlled, teams.maybe())
+            .join()
+            .filter(|(_, control, team)| sight.on_side(Some(control.player), *team))
+            .map(|(pos, _, _)| pos.0)
+            .collect();
+        eyes.sort_by_key(|eye| (eye.x(), eye.y()));
+        eyes.dedup();
+        sight.eyes = eyes;
+        sight
+    }
+
+    /// Whether something belonging to `player` on `team` fights on this side
+    fn on_side(&self, player: Option<PlayerId>, team: Option<&Team>) -> bool {
+        player == Some(self.player) || damage::friendly(self.team.as_ref(), team)
+    }
+
+    pub fn sees(&self, index: &SpatialIndex, walls: &ReadStorage<Wall>, point: Point) -> bool {
+        self.eyes.iter().any(|&eye| {
+            let offset = point - eye;
+            i64::from(offset.x()).pow(2) + i64::from(offset.y()).pow(2)
+                <= i64::from(VIEW_RANGE).pow(2)
+                && raycast::line_of_sight(index, walls, eye, point)
+        })
+    }
+
+    /// The enemy tanks and shots in `world` out of sight. Everything else is always in the open.
+    pub fn hidden(&self, world: &World) -> HashSet<Entity> {
+        let entities = world.entities();
+        let positions = world.read_storage::<Position>();
+        let controlled = world.read_storage::<KeyboardControlled>();
+        let projectiles = world.read_storage::<Projectile>();
+        let teams = world.read_storage::<Team>();
+        let index = world.read_resource::<SpatialIndex>();
+        let walls = world.read_storage::<Wall>();
+        (
+            &entities,
+            &positions,
+            controlled.maybe(),
+            projectiles.maybe(),
+            teams.maybe(),
+        )
+            .join()
+            .filter(|(_, pos, control, projectile, team)| {
+                let player = match (control, projectile) {
+                    (Some(control), _) => Some(control.player),
+                    (None, Some(projectile)) => projectile.owner,
+                    (None, None) => return false,
+                };
+                !self.on_side(player, *team) && !self.sees(&index, &walls, pos.0)
+            })
+            .map(|(entity, _, _, _, _)| entity)
+            .collect()
+    }
+
+    /// Where the enemy tanks in sight are, and who drives them, in order of player
+    pub fn enemies(&self, world: &World) -> Vec<(PlayerId, Point)> {
+        let positions = world.read_storage::<Position>();
+        let controlled = world.read_storage::<KeyboardControlled>();
+        let healths = world.read_storage::<Health>();
+        let teams = world.read_storage::<Team>();
+        let index = world.read_resource::<SpatialIndex>();
+        let walls = world.read_storage::<Wall>();
+        let mut enemies: Vec<(PlayerId, Point)> =
+            (&positions, &controlled, &healths, teams.maybe())
+                .join()
+                .filter(|(pos, control, _, team)| {
+                    !self.on_side(Some(control.player), *team) && self.sees(&index, &walls, pos.0)
+                })
+                .map(|(pos, control, _, _)| (control.player, pos.0))
+                .collect();
+        enemies.sort_by_key(|&(player, _)| player);
+        enemies
+    }
+
+    /// The squares of the fog this side can see into
+    fn cells(&self, index: &SpatialIndex, walls: &ReadStorage<Wall>) -> HashSet<(i32, i32)> {
+        let reach = VIEW_RANGE.div_euclid(FOG_CELL) + 1;
+        let mut cells = HashSet::new();
+        for eye in &self.eyes {
+            let (x, y) = cell_of(*eye);
+            for cell in
+                (y - reach..=y + reach).flat_map(|y| (x - reach..=x + reach).map(move |x| (x, y)))
+            {
+                if !cells.contains(&cell) && self.sees(index, walls, cell_centre(cell)) {
+                    cells.insert(cell);
+                }
+            }
+        }
+        cells
+    }
+}
+
+fn cell_of(point: Point) -> (i32, i32) {
+    (
+        point.x().div_euclid(FOG_CELL),
+        point.y().div_euclid(FOG_CELL),
+    )
+}
+
+fn cell_centre((x, y): (i32, i32)) -> Point {
+    Point::new(x * FOG_CELL + FOG_CELL / 2, y * FOG_CELL + FOG_CELL / 2)
+}
+
+/// One player's fog of war as it is drawn: what they can see now, where they have been
+/// able to see before and which enemies to leave out. Like particles, it plays no part in the simulation.
+pub struct Fog {
+    player: PlayerId,
+    visible: HashSet<(i32, i32)>,
+    explored: HashSet<(i32, i32)>,
+    hidden: HashSet<Entity>,
+}
+
+impl Fog {
+    pub fn new(player: PlayerId) -> Self {
+        Fog {
+            player,
+            visible: HashSet::new(),
+            explored: HashSet::new(),
+            hidden: HashSet::new(),
+        }
+    }
+
+    /// Look around from wherever the player's side is in `world` and hide the enemies it can't see
+    pub fn update(&mut self, world: &World) {
+        self.look(world);
+        self.hidden = Sight::of(world, self.player).hidden(world);
+    }
+
+    /// Look around from wherever the player's side is in `world` without hiding anything,
+    /// for when what can't be seen has been left out already
+    pub fn look(&mut self, world: &World) {
+        let index = world.read_resource::<SpatialIndex>();
+        let walls = world.read_storage::<Wall>();
+        self.visible = Sight::of(world, self.player).cells(&index, &walls);
+        self.explored.extend(self.visible.iter().cloned());
+    }
+
+    pub fn hides(&self, entity: Entity) -> bool {
+        self.hidden.contains(&entity)
+    }
+
+    pub fn in_view(&self, point: Point) -> bool {
+        self.visible.contains(&cell_of(point))
+    }
+
+    pub fn explored(&self, point: Point) -> bool {
+        self.explored.contains(&cell_of(point))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archetype::TankArchetype;
+    use crate::components::Collider;
+    use crate::mode::{GameMode, Rules};
+    use crate::spatial::Indexing;
+    use specs::prelude::{Builder, RunNow, System};
+
+    fn arena() -> World {
+        let mut world = World::new();
+        System::setup(&mut Indexing, &mut world);
+        world.register::<KeyboardControlled>();
+        world.register::<Health>();
+        world.register::<Team>();
+        world.register::<Wall>();
+        world.register::<Projectile>();
+        world
+    }
+
+    fn tank(world: &mut World, player: PlayerId, team: Option<u8>, x: i32, y: i32) -> Entity {
+        let mut builder = world
+            .create_entity()
+            .with(Position(Point::new(x, y)))
+            .with(Collider {
+                width: 28,
+                height: 28,
+            })
+            .with(KeyboardControlled {
+                player,
+                speed: 1.0,
+                rotation_speed: 1.0,
+            })
+            .with(Health::new(10));
+        if let Some(team) = team {
+            builder = builder.with(Team(team));
+        }
+        builder.build()
+    }
+
+    fn wall(world: &mut World, x: i32, y: i32) {
+        world
+            .create_entity()
+            .with(Position(Point::new(x, y)))
+            .with(Collider {
+                width: 32,
+                height: 32,
+            })
+            .with(Wall)
+            .build();
+    }
+
+    /// The enemies `player` sees, and whether each of `tanks` is hidden from it
+    fn look(world: &World, player: PlayerId, tanks: &[Entity]) -> (Vec<PlayerId>, Vec<bool>) {
+        Indexing.run_now(world);
+        let sight = Sight::of(world, player);
+        let hidden = sight.hidden(world);
+        let enemies = sight.enemies(world).into_iter().map(|(enemy, _)| enemy);
+        (
+            enemies.collect(),
+            tanks.iter().map(|tank| hidden.contains(tank)).collect(),
+        )
+    }
+
+    #[test]
+    fn enemies_in_the_open_are_seen() {
+        let mut world = arena();
+        let me = tank(&mut world, 1, None, 0, 0);
+        let enemy = tank(&mut world, 2, None, 100, 50);
+        assert_eq!(look(&world, 1, &[me, enemy]), (vec![2], vec![false, false]));
+    }
+
+    #[test]
+    fn walls_hide_enemies_behind_them() {
+        let mut world = arena();
+        let me = tank(&mut world, 1, None, 0, 0);
+        let enemy = tank(&mut world, 2, None, 100, 0);
+        wall(&mut world, 50, 0);
+        assert_eq!(look(&world, 1, &[me, enemy]), (vec![], vec![false, true]));
+    }
+
+    #[test]
+    fn enemies_out_of_range_are_hidden() {
+        let mut world = arena();
+        let me = tank(&mut world, 1, None, 0, 0);
+        let near = tank(&mut world, 2, None, VIEW_RANGE, 0);
+        let far = tank(&mut world, 3, None, 0, VIEW_RANGE + 1);
+        assert_eq!(
+            look(&world, 1, &[me, near, far]),
+            (vec![2], vec![false, false, true])
+        );
+    }
+
+    #[test]
+    fn team_mates_see_for_each_other() {
+        let mut world = arena();
+        let me = tank(&mut world, 1, Some(0), 0, 0);
+        let mate = tank(&mut world, 2, Some(0), 0, 200);
+        let enemy = tank(&mut world, 3, Some(1), 0, 400);
+        // Its team mate is never hidden, even behind a wall
+        wall(&mut world, 0, 100);
+        assert_eq!(
+            look(&world, 1, &[me, mate, enemy]),
+            (vec![3], vec![false, false, false])
+        );
+    }
+
+    #[test]
+    fn players_waiting_to_come_back_see_what_their_team_sees() {
+        let mut world = arena();
+        let mate = tank(&mut world, 2, Some(0), 0, 0);
+        let enemy = tank(&mut world, 3, Some(1), 100, 0);
+        let entrants: Vec<(PlayerId, TankArchetype, Option<Team>)> = [(1, 0), (2, 0), (3, 1)]
+            .iter()
+            .map(|&(player, team)| (player, TankArchetype::default(), Some(Team(team))))
+            .collect();
+        world.insert(Match::new(Rules::new(GameMode::TeamDeathmatch), &entrants));
+        assert_eq!(
+            look(&world, 1, &[mate, enemy]),
+            (vec![3], vec![false, false])
+        );
+    }
+}
@@ -0,0 +1,560 @@
+use crate::archetype::TankArchetype;
+use crate::bulletspawner_system;
+use crate::collision;
+use crate::commands::Commands;
+use crate::commands::PlayerId;
+use crate::components::Ammo;
+use crate::components::Angle;
+use crate::components::AngularVelocity;
+use crate::components::BulletSpawner;
+use crate::components::Collider;
+use crate::components::Emitter;
+use crate::components::Health;
+use crate::components::Pickup;
+use crate::components::Rotation;
+use crate::components::StatusEffects;
+use crate::components::Team;
+use crate::components::Wall;
+use crate::components::{KeyboardControlled, Position, Sprite, Velocity};
+use crate::damage;
+use crate::flag;
+use crate::geometry::{Point, Rect};
+use crate::keyboard;
+use crate::mode;
+use crate::mode::{GameMode, Match, Rules};
+use crate::particles::Effects;
+use crate::physics;
+use crate::pickup;
+use crate::pickup::PickupKind;
+use crate::projectile;
+use crate::resources::GameRng;
+use crate::resources::Tick;
+use crate::ricochet;
+use crate::score::Scoreboard;
+use crate::spatial;
+use crate::status;
+use crate::terrain::{Terrain, TerrainKind};
+use crate::weapon::Weapon;
+use rand::Rng;
+use specs::join::Join;
+use specs::prelude::{
+    Dispatcher, DispatcherBuilder, Read, ReadStorage, SystemData, World, WorldExt,
+};
+use specs::world::{Builder, EntityBuilder};
+use specs::Entities;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// Type alias for the data needed to draw a world
+pub type DrawData<'a> = (
+    ReadStorage<'a, Position>,
+    ReadStorage<'a, Sprite>,
+    ReadStorage<'a, Angle>,
+    Read<'a, Terrain>,
+    Entities<'a>,
+);
+
+/// The player driven by the local keyboard
+pub const LOCAL_PLAYER: PlayerId = 0;
+
+/// Drives the idle target tank, which never receives any commands
+const TARGET_PLAYER: PlayerId = PlayerId::MAX;
+
+/// The box tanks are hit in
+pub const TANK_COLLIDER: Collider = Collider {
+    width: 28,
+    height: 28,
+};
+
+/// Indices of the tank textures loaded by the renderer
+pub const TANK_BASE_SPRITE: usize = 1;
+pub const TANK_TURRET_SPRITE: usize = 2;
+
+/// Where the tanks of a match start
+pub const SPAWN_POINTS: [(i32, i32); 4] = [(-300, 0), (300, 0), (0, -200), (0, 200)];
+
+/// Which way more tanks line up either side of each of the spawn points, along the wall behind it
+const SPAWN_LINES: [(i32, i32); 4] = [(0, 1), (0, 1), (1, 0), (1, 0)];
+
+/// How far apart tanks lined up at a spawn point start
+const SPAWN_SPACING: i32 = 48;
+
+/// How many tanks line up at each spawn point, as many as fit clear of the water and walls
+const SPAWN_LINE_LENGTH: usize = 5;
+
+/// How many tanks a match can start without any of them on top of another
+pub const MAX_PLAYERS: usize = SPAWN_POINTS.len() * SPAWN_LINE_LENGTH;
+
+pub const WALL_SPRITE: usize = 5;
+
+/// The arena fills the window, walls included
+pub const ARENA_SIZE: (u32, u32) = (800, 600);
+
+/// Walls are built of square tiles this wide
+const WALL_TILE: u32 = 32;
+
+/// Wooden barricades shielding the north and south spawn points, which can be shot through,
+/// in tiles from the centre
+const BARRICADE_TILES: [(i32, i32); 6] = [(-1, -3), (0, -3), (1, -3), (-1, 3), (0, 3), (1, 3)];
+
+/// How much damage a barricade tile takes to break
+const BARRICADE_HEALTH: u32 = 60;
+
+/// The stone obstacles inside the arena, in tiles from the centre
+const OBSTACLE_TILES: [(i32, i32); 8] = [
+    // Corner pillars to bank shots off
+    (-7, -5),
+    (-7, -4),
+    (7, -5),
+    (7, -4),
+    (-7, 5),
+    (-7, 4),
+    (7, 5),
+    (7, 4),
+];
+
+/// Patches of ground other than grass, as the kind and the x and y of opposite corners in tiles from
+/// the centre. Later patches are painted over earlier ones.
+const TERRAIN_PATCHES: [(TerrainKind, i32, i32, i32, i32); 7] = [
+    // A road across the middle, between the east and west spawn points
+    (TerrainKind::Road, -11, 0, 11, 0),
+    (TerrainKind::Water, 4, -7, 6, -5),
+    (TerrainKind::Water, -6, 5, -4, 7),
+    (TerrainKind::Mud, -5, -7, -3, -5),
+    (TerrainKind::Mud, 3, 5, 5, 7),
+    (TerrainKind::Ice, 8, -4, 10, -2),
+    (TerrainKind::Ice, -10, 2, -8, 4),
+];
+
+/// Where ammo crates sit, between the spawn points
+const PICKUP_POINTS: [(i32, i32, PickupKind); 9] = [
+    (-150, -100, PickupKind::Ammo),
+    (150, 100, PickupKind::Ammo),
+    (-150, 100, PickupKind::Ammo),
+    (150, -100, PickupKind::Ammo),
+    (0, 0, PickupKind::Repair),
+    (-300, -200, PickupKind::SpeedBoost),
+    (300, 200, PickupKind::SpeedBoost),
+    (300, -200, PickupKind::Shield),
+    (-300, 200, PickupKind::RapidFire),
+];
+
+/// Length of a single simulation step
+pub const TICK_DURATION: Duration = Duration::from_millis(1000 / 20);
+
+pub fn build_dispatcher<'a, 'b>() -> Dispatcher<'a, 'b> {
+    DispatcherBuilder::new()
+        .with(spatial::Indexing, "Indexing", &[])
+        .with(keyboard::KeyboardShoot, "KeyboardShoot", &[])
+        .with(keyboard::KeyboardMove, "KeyboardMove", &[])
+        .with(keyboard::KeyboardRotate, "KeyboardRotate", &[])
+        .with(
+            bulletspawner_system::BulletSpawnerSystem,
+            "BulletSpawnerSystem",
+            &["KeyboardShoot", "KeyboardMove", "KeyboardRotate"],
+        )
+        .with(pickup::PickupSystem, "PickupSystem", &["KeyboardMove"])
+        .with(status::StatusSystem, "StatusSystem", &["PickupSystem"])
+        .with(physics::Drive, "Drive", &["StatusSystem"])
+        .with(
+            projectile::ProjectileSystem,
+            "ProjectileSystem",
+            &["BulletSpawnerSystem", "Indexing"],
+        )
+        .with(
+            physics::Physics,
+            "Physics",
+            &[
+                "BulletSpawnerSystem",
+                "ProjectileSystem",
+                "KeyboardShoot",
+                "KeyboardMove",
+                "KeyboardRotate",
+                "Drive",
+                "Indexing",
+            ],
+        )
+        .with(collision::Collisions, "Collisions", &["Physics"])
+        .with(damage::ProjectileHits, "ProjectileHits", &["Collisions"])
+        .with(ricochet::Ricochet, "Ricochet", &["ProjectileHits"])
+        .with(damage::Explosions, "Explosions", &["Ricochet"])
+        .with(damage::Destruction, "Destruction", &["Explosions"])
+        .with(physics::TurretMount, "TurretMount", &["Destruction"])
+        .with(flag::FlagSystem, "FlagSystem", &["Destruction"])
+        .with(mode::MatchRules, "MatchRules", &["FlagSystem"])
+        .build()
+}
+
+/// The systems a client runs to predict its own tank's movement ahead of the server.
+/// Firing is left to the server, predicted bullets would only have to be taken back.
+pub fn build_prediction_dispatcher<'a, 'b>() -> Dispatcher<'a, 'b> {
+    DispatcherBuilder::new()
+        .with(spatial::Indexing, "Indexing", &[])
+        .with(keyboard::KeyboardMove, "KeyboardMove", &[])
+        .with(keyboard::KeyboardRotate, "KeyboardRotate", &[])
+        .with(physics::Drive, "Drive", &["KeyboardMove", "KeyboardRotate"])
+        .with(physics::Physics, "Physics", &["Drive", "Indexing"])
+        .with(collision::Collisions, "Collisions", &["Physics"])
+        .build()
+}
+
+/// Create a world with every storage and resource the simulation needs, seeded with `seed`,
+/// containing the local player's tank and an idle target
+pub fn create_world(dispatcher: &mut Dispatcher, seed: u64) -> World {
+    let mut world = create_arena(dispatcher, seed);
+
+    initialize_tank(&mut world, TANK_BASE_SPRITE, TANK_TURRET_SPRITE);
+    spawn_scenery(&mut world);
+
+    world
+}
+
+/// Create a world with a tank for each of `players`, at the spawn point matching their position in the list.
+/// Peers creating a match from the same seed and players get identical worlds.
+pub fn create_match(dispatcher: &mut Dispatcher, seed: u64, players: &[PlayerId]) -> World {
+    let mut world = create_arena(dispatcher, seed);
+
+    for (i, &player) in players.iter().enumerate() {
+        spawn_player_tank(
+            &mut world,
+            player,
+            spawn_slot(i),
+            TankArchetype::default(),
+            None,
+        );
+    }
+    spawn_scenery(&mut world);
+
+    world
+}
+
+/// Create a world for a match played to `rules` between `entrants`, each starting at the spawn point
+/// matching its player. In capture the flag the first two teams get a flag and start at its base instead.
+pub fn create_game(
+    dispatcher: &mut Dispatcher,
+    seed: u64,
+    rules: Rules,
+    entrants: &[(PlayerId, TankArchetype, Option<Team>)],
+) -> World {
+    let mut world = create_arena(dispatcher, seed);
+
+    if rules.mode == GameMode::CaptureTheFlag {
+        let teams: Vec<u8> = entrants
+            .iter()
+            .filter_map(|(_, _, team)| team.map(|team| team.0))
+            .collect();
+        let bases = flag::bases(&teams);
+        // Team mates spread out over the spawn points in front of their base
+        let mut spawned: BTreeMap<u8, usize> = BTreeMap::new();
+        for &(player, archetype, team) in entrants {
+            let position = match team.and_then(|team| bases.get(&team.0).map(|base| (team, base))) {
+                Some((team, &base)) => {
+                    let points = flag::spawn_points(base);
+                    let count = spawned.entry(team.0).or_default();
+                    *count += 1;
+                    points[(*count - 1) % points.len()]
+                }
+                None => spawn_point(player),
+            };
+            spawn_player_tank(&mut world, player, position, archetype, team);
+        }
+        flag::spawn_flags(&mut world, &bases);
+    } else {
+        spawn_tanks(&mut world, entrants);
+    }
+    spawn_scenery(&mut world);
+    world.insert(Match::new(rules, entrants));
+
+    world
+}
+
+/// Add a tank for each of `tanks` to a world, at the spawn point matching its player
+pub fn spawn_tanks(world: &mut World, tanks: &[(PlayerId, TankArchetype, Option<Team>)]) {
+    for &(player, archetype, team) in tanks {
+        spawn_player_tank(world, player, spawn_point(player), archetype, team);
+    }
+}
+
+/// Where `player`'s tank starts a match
+pub fn spawn_point(player: PlayerId) -> Point {
+    spawn_slot(usize::from(player))
+}
+
+/// The `slot`th place a tank can start. The first of them are the spawn points, then the tanks after
+/// line up either side of them in turn. They only come round again after `MAX_PLAYERS`.
+fn spawn_slot(slot: usize) -> Point {
+    let slot = slot % MAX_PLAYERS;
+    let (x, y) = SPAWN_POINTS[slot % SPAWN_POINTS.len()];
+    let (dx, dy) = SPAWN_LINES[slot % SPAWN_POINTS.len()];
+    // 0, 1, -1, 2, -2 places along
+    let lap = slot / SPAWN_POINTS.len();
+    let along = (lap as i32 + 1) / 2 * if lap.is_multiple_of(2) { -1 } else { 1 };
+    Point::new(
+        x + dx * along * SPAWN_SPACING,
+        y + dy * along * SPAWN_SPACING,
+    )
+}
+
+/// Create a world with every storage and resource the simulation needs but no entities
+pub fn create_arena(dispatcher: &mut Dispatcher, seed: u64) -> World {
+    let mut world = World::new();
+    dispatcher.setup(&mut world);
+    DrawData::setup(&mut world);
+    // Only read when drawing particles, which no system does
+    world.register::<Emitter>();
+
+    // Initialize resource
+    world.insert(Commands::default());
+    world.insert(Tick::default());
+    world.insert(GameRng::new(seed));
+    world.insert(Effects::default());
+    world.insert(Scoreboard::default());
+    world.insert(arena_terrain());
+
+    world
+}
+
+/// Advance the simulation by exactly one tick using `commands` as this tick's input
+pub fn step(world: &mut World, dispatcher: &mut Dispatcher, commands: Commands) {
+    *world.write_resource() = commands;
+    let tick = world.read_resource::<Tick>().0;
+    world.write_resource::<Effects>().start_tick(tick);
+
+    dispatcher.dispatch(world);
+    world.maintain();
+
+    world.write_resource::<Tick>().0 += 1;
+}
+
+fn initialize_tank(world: &mut World, tank_base_sprite: usize, tank_turret_sprite: usize) {
+    spawn_player_tank(
+        world,
+        LOCAL_PLAYER,
+        Point::new(0, 0),
+        TankArchetype::default(),
+        None,
+    );
+
+    // Nobody sends the target's commands, the control only ties its turret to its base
+    let control = KeyboardControlled {
+        player: TARGET_PLAYER,
+        speed: 0.0,
+        rotation_speed: 0.0,
+    };
+
+    // Init the base
+    world
+        .create_entity()
+        .with(Position(Point::new(150, 0)))
+        .with(Angle { angle: 0.0 })
+        .with(Sprite {
+            spritesheet: tank_base_sprite,
+            region: Rect::new(0, 0, 32, 32),
+        })
+        .with(control.clone())
+        .with(Velocity {
+            speed: 0.0,
+            direction: Angle { angle: 0.0 },
+        })
+        .with(AngularVelocity {
+            speed: 2.0,
+            rotation: Rotation::Clockwise,
+        })
+        .with(Health::new(TankArchetype::default().health()))
+        .with(TANK_COLLIDER)
+        .build();
+
+    // The idle turret starts at a random angle, drawn from the seeded rng
+    let turret_angle = world.write_resource::<GameRng>().rng.gen_range(0.0, 360.0);
+
+    // Init the turret
+    world
+        .create_entity()
+        .with(Position(Point::new(150, 0)))
+        .with(Angle {
+            angle: turret_angle,
+        })
+        .with(Sprite {
+            spritesheet: tank_turret_sprite,
+            region: Rect::new(0, 0, 32, 32),
+        })
+        .with(control)
+        .with(AngularVelocity {
+            speed: 3.0,
+            rotation: Rotation::Clockwise,
+        })
+        .with(BulletSpawner::new(vec![Weapon::Cannon], false))
+        .build();
+}
+
+/// Spawn a tank (a base and a turret) driven by `player`'s commands
+pub fn spawn_player_tank(
+    world: &mut World,
+    player: PlayerId,
+    position: Point,
+    archetype: TankArchetype,
+    team: Option<Team>,
+) {
+    let control = KeyboardControlled {
+        player,
+        speed: archetype.speed(),
+        rotation_speed: archetype.rotation_speed(),
+    };
+
+    // Init the base
+    let mut base = world
+        .create_entity()
+        .with(Position(position))
+        .with(Angle { angle: 0.0 })
+        .with(Sprite {
+            spritesheet: TANK_BASE_SPRITE,
+            region: Rect::new(0, 0, 32, 32),
+        })
+        .with(control.clone())
+        .with(Velocity {
+            speed: 0.0,
+            direction: Angle { angle: 0.0 },
+        })
+        .with(Health::new(archetype.health()))
+        .with(TANK_COLLIDER)
+        .with(Emitter::exhaust())
+        .with(archetype.engine());
+    if let Some(team) = team {
+        base = base.with(team);
+    }
+    base.build();
+
+    // Init the turret
+    let mut turret = world
+        .create_entity()
+        .with(Position(position))
+        .with(Angle { angle: 0.0 })
+        .with(Sprite {
+            spritesheet: TANK_TURRET_SPRITE,
+            region: Rect::new(0, 0, 32, 32),
+        })
+        .with(control)
+        .with(Velocity {
+            speed: 0.0,
+            direction: Angle { angle: 0.0 },
+        })
+        .with(AngularVelocity {
+            speed: 0.0,
+            rotation: Rotation::Clockwise,
+        })
+        .with(archetype.engine())
+        .with(Ammo::new(&archetype.weapons()))
+        .with(BulletSpawner::new(archetype.weapons(), false));
+    if let Some(team) = team {
+        turret = turret.with(team);
+    }
+    turret.build();
+}
+
+/// The ground of the arena, the same for every world so it never has to be sent anywhere
+pub fn arena_terrain() -> Terrain {
+    let mut terrain = Terrain::new(WALL_TILE, ARENA_SIZE.0, ARENA_SIZE.1);
+    for &(kind, x0, y0, x1, y1) in TERRAIN_PATCHES.iter() {
+        terrain.paint(kind, (x0, y0), (x1, y1));
+    }
+    terrain
+}
+
+/// Build the arena's walls and place its pickups
+pub fn spawn_scenery(world: &mut World) {
+    spawn_walls(world);
+    spawn_pickups(world);
+}
+
+/// Ring the arena with walls and put up the obstacles inside it
+pub fn spawn_walls(world: &mut World) {
+    let (half_width, half_height) = (ARENA_SIZE.0 as i32 / 2, ARENA_SIZE.1 as i32 / 2);
+    let tile = WALL_TILE as i32;
+    let mut tiles = Vec::new();
+    for x in (-half_width + tile / 2..half_width).step_by(WALL_TILE as usize) {
+        tiles.push((x, -half_height + tile / 2));
+        tiles.push((x, half_height - tile / 2));
+    }
+    for y in (-half_height + tile * 3 / 2..half_height - tile).step_by(WALL_TILE as usize) {
+        tiles.push((-half_width + tile / 2, y));
+        tiles.push((half_width - tile / 2, y));
+    }
+    tiles.extend(OBSTACLE_TILES.iter().map(|&(x, y)| (x * tile, y * tile)));
+
+    for (x, y) in tiles {
+        spawn_wall_tile(world, Point::new(x, y), 0).build();
+    }
+    for &(x, y) in BARRICADE_TILES.iter() {
+        spawn_wall_tile(world, Point::new(x * tile, y * tile), 1)
+            .with(Health::new(BARRICADE_HEALTH))
+            .build();
+    }
+}
+
+/// Start building a wall tile drawn with `frame` of the wall spritesheet
+fn spawn_wall_tile(world: &mut World, position: Point, frame: i32) -> EntityBuilder<'_> {
+    world
+        .create_entity()
+        .with(Position(position))
+        .with(Angle { angle: 0.0 })
+        .with(Sprite {
+            spritesheet: WALL_SPRITE,
+            region: Rect::new(frame * WALL_TILE as i32, 0, WALL_TILE, WALL_TILE),
+        })
+        .with(Collider {
+            width: WALL_TILE,
+            height: WALL_TILE,
+        })
+        .with(Wall)
+}
+
+/// Place an ammo crate at each of the arena's crate points
+fn spawn_pickups(world: &mut World) {
+    for &(x, y, kind) in PICKUP_POINTS.iter() {
+        let mut pickup = world
+            .create_entity()
+            .with(Position(Point::new(x, y)))
+            .with(Angle { angle: 0.0 })
+            .with(Pickup {
+                kind,
+                respawn_rem: kind.first_spawn(),
+            });
+        // It is drawn once it appears
+        if kind.first_spawn() == 0 {
+            pickup = pickup.with(kind.sprite());
+        }
+        pickup.build();
+    }
+}
+
+/// A line describing `player`'s selected weapon and its ammunition, for the window title
+pub fn status(world: &World, player: PlayerId) -> String {
+    let controlled = world.read_storage::<KeyboardControlled>();
+    let spawners = world.read_storage::<BulletSpawner>();
+    let ammo = world.read_storage::<Ammo>();
+    let effects = world.read_storage::<StatusEffects>();
+    let turret = (&controlled, &spawners, ammo.maybe(), effects.maybe())
+        .join()
+        .find(|(control, _, _, _)| control.player == player);
+    match turret {
+        Some((_, spawner, ammo, effects)) => {
+            let weapon = spawner.weapon();
+            let mut status = match ammo.map(|ammo| &ammo.magazines[spawner.selected]) {
+                Some(magazine) if magazine.is_reloading() => {
+                    format!("{} reloading, {} spare", weapon, magazine.reserve)
+                }
+                Some(magazine) => format!(
+                    "{} {}/{}, {} spare",
+                    weapon, magazine.loaded, magazine.size, magazine.reserve
+                ),
+                None => weapon.to_string(),
+            };
+            for effect in effects.iter().flat_map(|effects| &effects.active) {
+                let seconds = effect.ticks_left as f32 * TICK_DURATION.as_secs_f32();
+                status.push_str(&format!(", {} {:.0}s", effect.kind, seconds.ceil()));
+            }
+            status
+        }
+        None => "destroyed".to_string(),
+    }
+}
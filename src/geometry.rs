@@ -0,0 +1,237 @@
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+/// A point in the arena, with (0, 0) at its centre and y growing downwards like the screen's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+impl Point {
+    pub fn new(x: i32, y: i32) -> Point {
+        Point { x, y }
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    /// This point moved by `x` and `y`
+    pub fn offset(&self, x: i32, y: i32) -> Point {
+        Point::new(self.x + x, self.y + y)
+    }
+}
+
+impl From<(i32, i32)> for Point {
+    fn from((x, y): (i32, i32)) -> Point {
+        Point::new(x, y)
+    }
+}
+
+impl From<Point> for (i32, i32) {
+    fn from(point: Point) -> (i32, i32) {
+        (point.x, point.y)
+    }
+}
+
+impl Add for Point {
+    type Output = Point;
+
+    fn add(self, rhs: Point) -> Point {
+        self.offset(rhs.x, rhs.y)
+    }
+}
+
+impl AddAssign for Point {
+    fn add_assign(&mut self, rhs: Point) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Point {
+    type Output = Point;
+
+    fn sub(self, rhs: Point) -> Point {
+        self.offset(-rhs.x, -rhs.y)
+    }
+}
+
+impl SubAssign for Point {
+    fn sub_assign(&mut self, rhs: Point) {
+        *self = *self - rhs;
+    }
+}
+
+impl Neg for Point {
+    type Output = Point;
+
+    fn neg(self) -> Point {
+        Point::new(-self.x, -self.y)
+    }
+}
+
+impl Mul<i32> for Point {
+    type Output = Point;
+
+    fn mul(self, rhs: i32) -> Point {
+        Point::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+impl Div<i32> for Point {
+    type Output = Point;
+
+    fn div(self, rhs: i32) -> Point {
+        Point::new(self.x / rhs, self.y / rhs)
+    }
+}
+
+/// A rectangle with its top left corner at `x` and `y`. Like SDL's, it is never empty:
+/// a width or height of 0 is taken to be 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rect {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width: width.max(1),
+            height: height.max(1),
+        }
+    }
+
+    /// A rectangle centred on `center`, rounded up and to the left when its size is odd
+    pub fn from_center<P: Into<Point>>(center: P, width: u32, height: u32) -> Rect {
+        let center = center.into();
+        let width = width.max(1);
+        let height = height.max(1);
+        Rect::new(
+            center.x - width as i32 / 2,
+            center.y - height as i32 / 2,
+            width,
+            height,
+        )
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn left(&self) -> i32 {
+        self.x
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.width as i32
+    }
+
+    pub fn top(&self) -> i32 {
+        self.y
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height as i32
+    }
+
+    pub fn top_left(&self) -> Point {
+        Point::new(self.left(), self.top())
+    }
+
+    /// The middle of the rectangle, rounded up and to the left when its size is odd
+    pub fn center(&self) -> Point {
+        Point::new(
+            self.x + self.width as i32 / 2,
+            self.y + self.height as i32 / 2,
+        )
+    }
+
+    /// Whether `point` is inside the rectangle. Its right and bottom edges are just outside it.
+    pub fn contains_point<P: Into<(i32, i32)>>(&self, point: P) -> bool {
+        let (x, y) = point.into();
+        x >= self.left() && x < self.right() && y >= self.top() && y < self.bottom()
+    }
+
+    /// Where the two rectangles overlap, if they do. Rectangles that only share an edge don't.
+    pub fn intersection(&self, other: Rect) -> Option<Rect> {
+        let left = self.left().max(other.left());
+        let right = self.right().min(other.right());
+        let top = self.top().max(other.top());
+        let bottom = self.bottom().min(other.bottom());
+        if right > left && bottom > top {
+            Some(Rect::new(
+                left,
+                top,
+                (right - left) as u32,
+                (bottom - top) as u32,
+            ))
+        } else {
+            None
+        }
+    }
+
+    pub fn has_intersection(&self, other: Rect) -> bool {
+        self.intersection(other).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rects_are_never_empty() {
+        let rect = Rect::new(4, 5, 0, 0);
+        assert_eq!((rect.width(), rect.height()), (1, 1));
+        assert!(rect.contains_point(Point::new(4, 5)));
+    }
+
+    #[test]
+    fn centres_round_up_and_left() {
+        let rect = Rect::from_center(Point::new(10, 10), 5, 4);
+        assert_eq!(rect, Rect::new(8, 8, 5, 4));
+        assert_eq!(rect.center(), Point::new(10, 10));
+    }
+
+    #[test]
+    fn right_and_bottom_edges_are_outside() {
+        let rect = Rect::new(-2, -2, 4, 4);
+        assert!(rect.contains_point(Point::new(-2, -2)));
+        assert!(rect.contains_point(Point::new(1, 1)));
+        assert!(!rect.contains_point(Point::new(2, 0)));
+        assert!(!rect.contains_point(Point::new(0, 2)));
+    }
+
+    #[test]
+    fn rects_sharing_an_edge_dont_intersect() {
+        let rect = Rect::new(0, 0, 5, 5);
+        assert_eq!(rect.intersection(rect), Some(rect));
+        assert_eq!(
+            rect.intersection(Rect::new(2, -2, 5, 5)),
+            Some(Rect::new(2, 0, 3, 3))
+        );
+        assert!(!rect.has_intersection(Rect::new(5, 0, 5, 5)));
+        assert!(!rect.has_intersection(Rect::new(0, -5, 5, 5)));
+    }
+}
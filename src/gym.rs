@@ -0,0 +1,902 @@
+use crate::ai::senses;
+use crate::ai::Bot;
+use crate::archetype::TankArchetype;
+use crate::commands::{
+    Commands, FireCommand, MovementCommand, PlayerCommands, PlayerId, RotationCommand,
+    WeaponCommand,
+};
+use crate::components::{
+    Angle, Collider, Health, KeyboardControlled, Pickup, Position, Projectile, Rotation, Velocity,
+    Wall,
+};
+use crate::fog::Sight;
+use crate::game;
+use crate::geometry::{Point, Rect};
+use crate::json::Json;
+use crate::navigation::Navigator;
+use crate::resources::Tick;
+use crate::ricochet;
+use crate::score::{PlayerScore, Scoreboard};
+use crate::spatial;
+use crate::spatial::SpatialIndex;
+use crate::terrain::Terrain;
+use specs::join::Join;
+use specs::prelude::{Dispatcher, RunNow, World, WorldExt};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+/// The player the trainer drives, the opponents take the players after it
+const AGENT: PlayerId = 0;
+
+/// What the agent is shown after each step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObservationKind {
+    /// Its own tank and lists of what it can see
+    Entities,
+    /// Its own tank and the arena drawn as layers of squares `cell` wide
+    Grid { cell: i32 },
+}
+
+/// How much each thing that happens in a step is worth to the agent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rewards {
+    /// For each point of damage
+    pub damage_dealt: f64,
+    pub damage_taken: f64,
+    pub kill: f64,
+    pub death: f64,
+    /// For each tick its tank is still in one piece
+    pub tick: f64,
+    /// For being the last tank standing
+    pub win: f64,
+}
+
+impl Default for Rewards {
+    fn default() -> Self {
+        Rewards {
+            damage_dealt: 1.0,
+            damage_taken: -1.0,
+            kill: 100.0,
+            death: -100.0,
+            tick: 0.0,
+            win: 0.0,
+        }
+    }
+}
+
+/// How episodes are set up, kept from one reset to the next until changed
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub archetype: TankArchetype,
+    /// A bot, behaviour tree or script, for each opponent
+    pub opponents: Vec<PathBuf>,
+    pub observation: ObservationKind,
+    pub rewards: Rewards,
+    /// An episode ends after this many ticks whatever has happened
+    pub max_ticks: u64,
+    /// Ticks simulated for each step, the action being held for all of them
+    pub ticks_per_step: u64,
+    /// Operations each tick for opponents that are scripts
+    pub budget: u64,
+}
+
+impl Config {
+    /// Change whatever `changes` mentions, leaving the rest as it was
+    fn update(&mut self, changes: &Json) -> Result<(), String> {
+        let fields = match changes {
+            Json::Object(fields) => fields,
+            _ => return Err("config must be an object".to_string()),
+        };
+        let count = |value: &Json, what: &str| {
+            value
+                .as_f64()
+                .filter(|count| *count >= 1.0)
+                .map(|count| count as u64)
+                .ok_or(format!("{} must be a number from 1", what))
+        };
+        for (key, value) in fields {
+            match key.as_str() {
+                "archetype" => {
+                    self.archetype = value
+                        .as_str()
+                        .and_then(TankArchetype::from_name)
+                        .ok_or("archetype must be scout, medium or heavy")?
+                }
+                "opponents" => {
+                    self.opponents = value
+                        .as_array()
+                        .and_then(|paths| {
+                            paths
+                                .iter()
+                                .map(|path| path.as_str().map(PathBuf::from))
+                                .collect()
+                        })
+                        .ok_or("opponents must be a list of bot files")?
+                }
+                "observation" => {
+                    self.observation = match value.as_str() {
+                        Some("entities") => ObservationKind::Entities,
+                        Some("grid") => ObservationKind::Grid { cell: 32 },
+                        _ => return Err("observation must be entities or grid".to_string()),
+                    }
+                }
+                "grid_cell" => {
+                    // Any wider and the whole arena is a single square
+                    let widest = game::ARENA_SIZE.0.max(game::ARENA_SIZE.1);
+                    let cell = count(value, "grid_cell")?;
+                    if cell > u64::from(widest) {
+                        return Err(format!("grid_cell must be a number from 1 to {}", widest));
+                    }
+                    self.observation = ObservationKind::Grid { cell: cell as i32 };
+                }
+                "rewards" => self.rewards.update(value)?,
+                "max_ticks" => self.max_ticks = count(value, "max_ticks")?,
+                "ticks_per_step" => self.ticks_per_step = count(value, "ticks_per_step")?,
+                "budget" => self.budget = count(value, "budget")?,
+                other => return Err(format!("unknown config {}", other)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Rewards {
+    fn update(&mut self, changes: &Json) -> Result<(), String> {
+        let fields = match changes {
+            Json::Object(fields) => fields,
+            _ => return Err("rewards must be an object".to_string()),
+        };
+        for (key, value) in fields {
+            let weight = value
+                .as_f64()
+                .ok_or(format!("reward {} must be a number", key))?;
+            match key.as_str() {
+                "damage_dealt" => self.damage_dealt = weight,
+                "damage_taken" => self.damage_taken = weight,
+                "kill" => self.kill = weight,
+                "death" => self.death = weight,
+                "tick" => self.tick = weight,
+                "win" => self.win = weight,
+                other => return Err(format!("unknown reward {}", other)),
+            }
+        }
+        Ok(())
+    }
+
+    /// What getting from `before` to `after` is worth
+    fn between(&self, before: PlayerScore, after: PlayerScore) -> f64 {
+        self.damage_dealt * f64::from(after.damage_dealt - before.damage_dealt)
+            + self.damage_taken * f64::from(after.damage_taken - before.damage_taken)
+            + self.kill * f64::from(after.kills - before.kills)
+            + self.death * f64::from(after.deaths - before.deaths)
+    }
+}
+
+/// What a step came to
+pub struct Step {
+    pub observation: Json,
+    pub reward: f64,
+    pub done: bool,
+    pub info: Json,
+}
+
+/// A match between a trainer's agent and bots, advanced a step at a time, in the manner of a gym
+/// environment. It runs headless and deterministically: the same seed and actions give the same episode.
+pub struct Environment {
+    config: Config,
+    dispatcher: Dispatcher<'static, 'static>,
+    world: World,
+    navigator: Navigator,
+    opponents: Vec<Bot>,
+    /// The agent's score when the last step ended
+    score: PlayerScore,
+    /// Why the episode is over, if it is
+    ended: Option<&'static str>,
+}
+
+impl Environment {
+    /// Start a new episode from `seed`
+    pub fn reset(config: Config, seed: u64) -> Result<(Environment, Json), String> {
+        let mut opponents = Vec::new();
+        for (player, path) in (AGENT + 1..).zip(&config.opponents) {
+            opponents.push(Bot::load(player, path, seed, config.budget)?);
+        }
+        let mut tanks = vec![(AGENT, config.archetype, None)];
+        tanks.extend(
+            opponents
+                .iter()
+                .map(|bot| (bot.player, bot.archetype(), None)),
+        );
+
+        let mut dispatcher = game::build_dispatcher();
+        let mut world = game::create_arena(&mut dispatcher, seed);
+        game::spawn_tanks(&mut world, &tanks);
+        game::spawn_scenery(&mut world);
+        // Fill the spatial index so the first observation can tell what is in sight
+        spatial::Indexing.run_now(&world);
+        let navigator = Navigator::new(&world, game::TANK_COLLIDER);
+        let environment = Environment {
+            config,
+            dispatcher,
+            world,
+            navigator,
+            opponents,
+            score: PlayerScore::default(),
+            ended: None,
+        };
+        let observation = environment.observe();
+        Ok((environment, observation))
+    }
+
+    /// Hold `action` for a step's worth of ticks
+    pub fn step(&mut self, action: PlayerCommands) -> Result<Step, String> {
+        if self.ended.is_some() {
+            return Err("the episode is over, reset to start another".to_string());
+        }
+        let mut reward = 0.0;
+        let mut action = Some(action);
+        for _ in 0..self.config.ticks_per_step {
+            let mut commands = Commands::default();
+            // Commands last until they are changed, so the action only needs sending once
+            commands.0.insert(AGENT, action.take().unwrap_or_default());
+            for bot in &mut self.opponents {
+                commands
+                    .0
+                    .insert(bot.player, bot.think(&self.world, &mut self.navigator));
+            }
+            game::step(&mut self.world, &mut self.dispatcher, commands);
+
+            let score = self.world.read_resource::<Scoreboard>().of(AGENT);
+            reward += self.config.rewards.between(self.score, score);
+            self.score = score;
+            let alive = senses::own_tank(&self.world, AGENT).is_some();
+            let opponents_left = self
+                .opponents
+                .iter()
+                .filter(|bot| senses::own_tank(&self.world, bot.player).is_some())
+                .count();
+            if alive {
+                reward += self.config.rewards.tick;
+            }
+            self.ended = if !alive {
+                Some("destroyed")
+            } else if !self.opponents.is_empty() && opponents_left == 0 {
+                reward += self.config.rewards.win;
+                Some("won")
+            } else if self.tick() >= self.config.max_ticks {
+                Some("time")
+            } else {
+                None
+            };
+            if self.ended.is_some() {
+                break;
+            }
+        }
+
+        let info = Json::object(vec![
+            ("tick", self.tick().into()),
+            ("kills", self.score.kills.into()),
+            ("deaths", self.score.deaths.into()),
+            ("damage_dealt", self.score.damage_dealt.into()),
+            ("damage_taken", self.score.damage_taken.into()),
+            ("ended", self.ended.into()),
+        ]);
+        Ok(Step {
+            observation: self.observe(),
+            reward,
+            done: self.ended.is_some(),
+            info,
+        })
+    }
+
+    fn tick(&self) -> u64 {
+        self.world.read_resource::<Tick>().0
+    }
+
+    fn observe(&self) -> Json {
+        let world = &self.world;
+        let me = senses::own_tank(world, AGENT).map(|me| {
+            Json::object(vec![
+                ("x", me.position.x().into()),
+                ("y", me.position.y().into()),
+                ("heading", me.velocity.direction.angle.into()),
+                ("speed", me.velocity.speed.into()),
+                ("turret", me.turret.into()),
+                ("turret_speed", me.turret_speed.into()),
+                ("health", me.health.current.into()),
+                ("max_health", me.health.max.into()),
+                ("weapon", (me.selected as u32 + 1).into()),
+                ("weapons", (me.weapons as u32).into()),
+                ("reloading", me.reloading.into()),
+            ])
+        });
+        let sight = Sight::of(world, AGENT);
+        let enemies = sight.enemies(world);
+        let rest = match self.config.observation {
+            ObservationKind::Entities => self.entities(&sight, &enemies),
+            ObservationKind::Grid { cell } => self.grid(&sight, &enemies, cell),
+        };
+        let mut fields = vec![("tick", self.tick().into()), ("self", me.into())];
+        fields.extend(rest);
+        Json::object(fields)
+    }
+
+    /// The shots in flight the agent can see: where they are, which way they fly and whether they are its own
+    fn projectiles(&self, sight: &Sight) -> Vec<(Point, f32, bool)> {
+        let world = &self.world;
+        let positions = world.read_storage::<Position>();
+        let velocities = world.read_storage::<Velocity>();
+        let projectiles = world.read_storage::<Projectile>();
+        let index = world.read_resource::<SpatialIndex>();
+        let walls = world.read_storage::<Wall>();
+        let mut seen: Vec<(Point, f32, bool)> = (&positions, &velocities, &projectiles)
+            .join()
+            .filter(|(pos, _, projectile)| {
+                projectile.owner == Some(AGENT) || sight.sees(&index, &walls, pos.0)
+            })
+            .map(|(pos, vel, projectile)| {
+                (pos.0, vel.direction.angle, projectile.owner == Some(AGENT))
+            })
+            .collect();
+        // Storage order isn't something the agent should learn from
+        seen.sort_by_key(|(pos, _, _)| (pos.x(), pos.y()));
+        seen
+    }
+
+    /// The pickups waiting to be taken, which are never hidden
+    fn pickups(&self) -> Vec<(Point, &'static str)> {
+        let positions = self.world.read_storage::<Position>();
+        let pickups = self.world.read_storage::<Pickup>();
+        let mut waiting: Vec<(Point, &'static str)> = (&positions, &pickups)
+            .join()
+            .filter(|(_, pickup)| pickup.respawn_rem == 0)
+            .map(|(pos, pickup)| (pos.0, pickup.kind.name()))
+            .collect();
+        waiting.sort_by_key(|(pos, _)| (pos.x(), pos.y()));
+        waiting
+    }
+
+    fn entities(&self, sight: &Sight, enemies: &[(PlayerId, Point)]) -> Vec<(&'static str, Json)> {
+        let controlled = self.world.read_storage::<KeyboardControlled>();
+        let healths = self.world.read_storage::<Health>();
+        let enemies: Vec<Json> = enemies
+            .iter()
+            .map(|&(player, position)| {
+                let health = (&controlled, &healths)
+                    .join()
+                    .find(|(control, _)| control.player == player)
+                    .map(|(_, health)| health.current);
+                Json::object(vec![
+                    ("player", u32::from(player).into()),
+                    ("x", position.x().into()),
+                    ("y", position.y().into()),
+                    ("health", health.into()),
+                ])
+            })
+            .collect();
+        let projectiles: Vec<Json> = self
+            .projectiles(sight)
+            .into_iter()
+            .map(|(position, angle, own)| {
+                Json::object(vec![
+                    ("x", position.x().into()),
+                    ("y", position.y().into()),
+                    ("angle", angle.into()),
+                    ("own", own.into()),
+                ])
+            })
+            .collect();
+        let pickups: Vec<Json> = self
+            .pickups()
+            .into_iter()
+            .map(|(position, kind)| {
+                Json::object(vec![
+                    ("x", position.x().into()),
+                    ("y", position.y().into()),
+                    ("kind", kind.into()),
+                ])
+            })
+            .collect();
+        vec![
+            ("enemies", enemies.into()),
+            ("projectiles", projectiles.into()),
+            ("pickups", pickups.into()),
+        ]
+    }
+
+    /// The arena as layers of squares, each a row after row list of 0s and 1s
+    fn grid(
+        &self,
+        sight: &Sight,
+        enemies: &[(PlayerId, Point)],
+        cell: i32,
+    ) -> Vec<(&'static str, Json)> {
+        let world = &self.world;
+        let terrain = world.read_resource::<Terrain>();
+        let bounds = terrain.bounds();
+        let width = (bounds.width() as i32 + cell - 1) / cell;
+        let height = (bounds.height() as i32 + cell - 1) / cell;
+        let square = |x: i32, y: i32| {
+            Rect::new(
+                bounds.left() + x * cell,
+                bounds.top() + y * cell,
+                cell as u32,
+                cell as u32,
+            )
+        };
+        let layer = |filled: &dyn Fn(Rect) -> bool| -> Json {
+            let cells: Vec<u32> = (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| u32::from(filled(square(x, y))))
+                .collect();
+            cells.into()
+        };
+
+        let positions = world.read_storage::<Position>();
+        let colliders = world.read_storage::<Collider>();
+        let wall_storage = world.read_storage::<Wall>();
+        let walls: Vec<Rect> = (&positions, &colliders, &wall_storage)
+            .join()
+            .map(|(pos, collider, _)| collider.rect(pos.0))
+            .collect();
+        let own: Vec<Point> = senses::own_tank(world, AGENT)
+            .map(|me| me.position)
+            .into_iter()
+            .collect();
+        let enemies: Vec<Point> = enemies.iter().map(|&(_, position)| position).collect();
+        let projectiles: Vec<Point> = self
+            .projectiles(sight)
+            .into_iter()
+            .map(|(position, _, _)| position)
+            .collect();
+        let pickups: Vec<Point> = self
+            .pickups()
+            .into_iter()
+            .map(|(position, _)| position)
+            .collect();
+
+        let layers = Json::object(vec![
+            (
+                "walls",
+                layer(&|square| {
+                    walls
+                        .iter()
+                        .any(|&wall| ricochet::overlap(wall, square).is_some())
+                }),
+            ),
+            (
+                "water",
+                layer(&|square| !terrain.at(square.center()).passable()),
+            ),
+            ("self", layer(&|square| covers(&own, square))),
+            ("enemies", layer(&|square| covers(&enemies, square))),
+            ("projectiles", layer(&|square| covers(&projectiles, square))),
+            ("pickups", layer(&|square| covers(&pickups, square))),
+        ]);
+        vec![(
+            "grid",
+            Json::object(vec![
+                ("width", width.into()),
+                ("height", height.into()),
+                ("cell", cell.into()),
+                ("left", bounds.left().into()),
+                ("top", bounds.top().into()),
+                ("layers", layers),
+            ]),
+        )]
+    }
+}
+
+fn covers(points: &[Point], square: Rect) -> bool {
+    points.iter().any(|&point| square.contains_point(point))
+}
+
+/// An action in the same shape scripts return their commands in
+fn action(value: &Json) -> Result<PlayerCommands, String> {
+    let fields = match value {
+        Json::Null => return Ok(PlayerCommands::default()),
+        Json::Object(fields) => fields,
+        _ => return Err("action must be an object".to_string()),
+    };
+    let mut commands = PlayerCommands::default();
+    for (key, value) in fields {
+        if *value == Json::Null {
+            continue;
+        }
+        match key.as_str() {
+            "movement" => {
+                commands.movement = Some(match (value.as_f64(), value.as_str()) {
+                    (Some(angle), _) => MovementCommand::Move(Angle {
+                        angle: angle as f32,
+                    }),
+                    (_, Some("stop")) => MovementCommand::Stop,
+                    _ => return Err("movement must be an angle or \"stop\"".to_string()),
+                })
+            }
+            "rotation" => {
+                commands.rotation = Some(match value.as_str() {
+                    Some("cw") => RotationCommand::Move(Rotation::Clockwise),
+                    Some("ccw") => RotationCommand::Move(Rotation::CounterClockwise),
+                    Some("stop") => RotationCommand::Stop,
+                    _ => return Err("rotation must be \"cw\", \"ccw\" or \"stop\"".to_string()),
+                })
+            }
+            "fire" => {
+                commands.fire = Some(match value.as_bool() {
+                    Some(true) => FireCommand::Fire,
+                    Some(false) => FireCommand::Stop,
+                    None => return Err("fire must be true or false".to_string()),
+                })
+            }
+            "weapon" => {
+                commands.weapon = Some(match value.as_f64() {
+                    Some(slot) if (1.0..=9.0).contains(&slot) => {
+                        WeaponCommand::Select(slot as u8 - 1)
+                    }
+                    _ => return Err("weapon must be a slot from 1 to 9".to_string()),
+                })
+            }
+            other => return Err(format!("unknown command {}", other)),
+        }
+    }
+    Ok(commands)
+}
+
+/// A trainer's session: requests in, one JSON object to a line, and a reply to each
+fn serve(config: Config, input: impl BufRead, mut output: impl Write) -> Result<(), String> {
+    let mut config = config;
+    let mut environment: Option<Environment> = None;
+    for line in input.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = Json::parse(&line);
+        if request
+            .as_ref()
+            .is_ok_and(|request| request.get("cmd").and_then(Json::as_str) == Some("close"))
+        {
+            break;
+        }
+        let reply = request
+            .and_then(|request| handle(&request, &mut config, &mut environment))
+            .unwrap_or_else(|error| Json::object(vec![("error", error.into())]));
+        writeln!(output, "{}", reply)
+            .and_then(|_| output.flush())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn handle(
+    request: &Json,
+    config: &mut Config,
+    environment: &mut Option<Environment>,
+) -> Result<Json, String> {
+    match request.get("cmd").and_then(Json::as_str) {
+        Some("reset") => {
+            if let Some(changes) = request.get("config") {
+                config.update(changes)?;
+            }
+            let seed = match request.get("seed") {
+                Some(seed) => seed
+                    .as_f64()
+                    .filter(|seed| *seed >= 0.0)
+                    .ok_or("seed must be a number from 0")? as u64,
+                None => 0,
+            };
+            let (started, observation) = Environment::reset(config.clone(), seed)?;
+            *environment = Some(started);
+            Ok(Json::object(vec![("observation", observation)]))
+        }
+        Some("step") => {
+            let environment = environment.as_mut().ok_or("reset before stepping")?;
+            let action = action(request.get("action").unwrap_or(&Json::Null))?;
+            let step = environment.step(action)?;
+            Ok(Json::object(vec![
+                ("observation", step.observation),
+                ("reward", step.reward.into()),
+                ("done", step.done.into()),
+                ("info", step.info),
+            ]))
+        }
+        _ => Err("cmd must be reset, step or close".to_string()),
+    }
+}
+
+/// Serve a trainer over standard input and output
+pub fn run_stdio(config: Config) -> Result<(), String> {
+    let stdin = std::io::stdin();
+    serve(config, stdin.lock(), std::io::stdout())
+}
+
+/// Serve trainers that connect on `port` of this machine, one after another
+pub fn run_tcp(config: Config, port: u16) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+    println!("waiting for trainers on 127.0.0.1:{}", port);
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| e.to_string())?;
+        let peer = stream.peer_addr().map_err(|e| e.to_string())?;
+        println!("{} connected", peer);
+        let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        match serve(config.clone(), reader, stream) {
+            Ok(()) => println!("{} is done", peer),
+            Err(e) => println!("{} dropped: {}", peer, e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn config(opponents: &[&str]) -> Config {
+        Config {
+            archetype: TankArchetype::default(),
+            opponents: opponents.iter().map(PathBuf::from).collect(),
+            observation: ObservationKind::Entities,
+            rewards: Rewards::default(),
+            max_ticks: 600,
+            ticks_per_step: 1,
+            budget: crate::ai::script::DEFAULT_BUDGET,
+        }
+    }
+
+    fn parse(text: &str) -> Json {
+        Json::parse(text).expect("test JSON is well formed")
+    }
+
+    /// Play an episode from `seed` with the actions going round in turn, returning every observation and reward
+    fn episode(config: Config, seed: u64, actions: &[&str]) -> Vec<(Json, f64)> {
+        let (mut environment, observation) =
+            Environment::reset(config, seed).expect("the episode starts");
+        let mut steps = vec![(observation, 0.0)];
+        for text in actions.iter().cycle().take(300) {
+            let step = environment
+                .step(action(&parse(text)).expect("the action is good"))
+                .expect("the episode is going");
+            steps.push((step.observation, step.reward));
+            if step.done {
+                break;
+            }
+        }
+        steps
+    }
+
+    /// Destroy `player`'s tank the next time the world is stepped
+    fn wreck(environment: &mut Environment, player: PlayerId) {
+        let controlled = environment.world.read_storage::<KeyboardControlled>();
+        let mut healths = environment.world.write_storage::<Health>();
+        for (control, health) in (&controlled, &mut healths).join() {
+            if control.player == player {
+                health.current = 0;
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_seed_and_actions_give_the_same_episode() {
+        let actions = [
+            r#"{"movement": 0, "rotation": "cw", "fire": true}"#,
+            r#"{"movement": 90, "weapon": 2}"#,
+            r#"{"movement": "stop", "rotation": "ccw", "fire": false}"#,
+        ];
+        let mut grid = config(&["resources/ai/hunter.bt"]);
+        grid.observation = ObservationKind::Grid { cell: 40 };
+        for config in [config(&["resources/ai/hunter.bt"]), grid] {
+            let first = episode(config.clone(), 3, &actions);
+            assert_eq!(first, episode(config.clone(), 3, &actions));
+            assert_ne!(first, episode(config, 4, &actions));
+        }
+    }
+
+    #[test]
+    fn rewards_weigh_what_changed() {
+        let rewards = Rewards {
+            damage_dealt: 1.0,
+            damage_taken: -2.0,
+            kill: 100.0,
+            death: -50.0,
+            tick: 0.5,
+            win: 1000.0,
+        };
+        let before = PlayerScore {
+            kills: 1,
+            deaths: 1,
+            damage_dealt: 30,
+            damage_taken: 40,
+            captures: 0,
+        };
+        assert_eq!(rewards.between(before, before), 0.0);
+        let after = PlayerScore {
+            kills: 2,
+            deaths: 2,
+            damage_dealt: 55,
+            damage_taken: 50,
+            captures: 0,
+        };
+        assert_eq!(rewards.between(before, after), 25.0 - 20.0 + 100.0 - 50.0);
+    }
+
+    #[test]
+    fn episodes_end_when_time_runs_out() {
+        let mut config = config(&["resources/ai/hunter.bt"]);
+        config.max_ticks = 5;
+        config.ticks_per_step = 2;
+        config.rewards.tick = 1.0;
+        let (mut environment, _) = Environment::reset(config, 1).expect("the episode starts");
+        let mut rewards = Vec::new();
+        loop {
+            let step = environment
+                .step(PlayerCommands::default())
+                .expect("the episode is going");
+            rewards.push(step.reward);
+            if step.done {
+                assert_eq!(step.info.get("ended").and_then(Json::as_str), Some("time"));
+                assert_eq!(step.info.get("tick").and_then(Json::as_f64), Some(5.0));
+                break;
+            }
+        }
+        // Two ticks a step, cut short by the time limit
+        assert_eq!(rewards, vec![2.0, 2.0, 1.0]);
+        assert!(environment.step(PlayerCommands::default()).is_err());
+    }
+
+    #[test]
+    fn episodes_end_when_the_agent_is_destroyed() {
+        let (mut environment, _) =
+            Environment::reset(config(&["resources/ai/hunter.bt"]), 1).expect("the episode starts");
+        wreck(&mut environment, AGENT);
+        let step = environment
+            .step(PlayerCommands::default())
+            .expect("the episode is going");
+        assert!(step.done);
+        assert_eq!(
+            step.info.get("ended").and_then(Json::as_str),
+            Some("destroyed")
+        );
+        assert_eq!(step.reward, Rewards::default().death);
+        assert_eq!(step.observation.get("self"), Some(&Json::Null));
+    }
+
+    #[test]
+    fn episodes_are_won_once_the_opponents_are_destroyed() {
+        let mut config = config(&["resources/ai/hunter.bt", "resources/ai/sniper.bt"]);
+        config.rewards.win = 500.0;
+        let (mut environment, _) = Environment::reset(config, 1).expect("the episode starts");
+        wreck(&mut environment, AGENT + 1);
+        let step = environment
+            .step(PlayerCommands::default())
+            .expect("the episode is going");
+        assert!(!step.done);
+        wreck(&mut environment, AGENT + 2);
+        let step = environment
+            .step(PlayerCommands::default())
+            .expect("the episode is going");
+        assert!(step.done);
+        assert_eq!(step.info.get("ended").and_then(Json::as_str), Some("won"));
+        assert_eq!(step.reward, 500.0);
+    }
+
+    #[test]
+    fn bad_actions_are_rejected() {
+        assert_eq!(action(&Json::Null), Ok(PlayerCommands::default()));
+        assert_eq!(
+            action(&parse(r#"{"movement": "stop", "fire": null}"#)),
+            Ok(PlayerCommands {
+                movement: Some(MovementCommand::Stop),
+                ..PlayerCommands::default()
+            })
+        );
+        for text in [
+            "[]",
+            "7",
+            r#"{"movement": "north"}"#,
+            r#"{"rotation": 90}"#,
+            r#"{"rotation": "left"}"#,
+            r#"{"fire": 1}"#,
+            r#"{"weapon": 0}"#,
+            r#"{"weapon": 10}"#,
+            r#"{"weapon": "cannon"}"#,
+            r#"{"jump": true}"#,
+        ] {
+            assert!(action(&parse(text)).is_err(), "{} was taken", text);
+        }
+    }
+
+    #[test]
+    fn bad_config_is_rejected() {
+        let mut changed = config(&[]);
+        changed
+            .update(&parse(
+                r#"{"archetype": "heavy", "grid_cell": 800, "max_ticks": 9, "rewards": {"win": 5}}"#,
+            ))
+            .expect("the config is good");
+        assert_eq!(
+            changed.archetype,
+            TankArchetype::from_name("heavy").unwrap()
+        );
+        assert_eq!(changed.observation, ObservationKind::Grid { cell: 800 });
+        assert_eq!(changed.max_ticks, 9);
+        assert_eq!(changed.rewards.win, 5.0);
+
+        for text in [
+            "[]",
+            r#"{"archetype": "tiny"}"#,
+            r#"{"opponents": "hunter.bt"}"#,
+            r#"{"opponents": [1]}"#,
+            r#"{"observation": "pixels"}"#,
+            r#"{"grid_cell": 0}"#,
+            r#"{"grid_cell": 801}"#,
+            r#"{"grid_cell": 2147483648}"#,
+            r#"{"grid_cell": 4294967296}"#,
+            r#"{"max_ticks": 0}"#,
+            r#"{"ticks_per_step": -1}"#,
+            r#"{"budget": "lots"}"#,
+            r#"{"rewards": {"win": "yes"}}"#,
+            r#"{"rewards": {"style": 1}}"#,
+            r#"{"speed": 2}"#,
+        ] {
+            assert!(
+                config(&[]).update(&parse(text)).is_err(),
+                "{} was taken",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn trainers_are_served_a_line_at_a_time() {
+        let requests = [
+            r#"{"cmd": "step"}"#,
+            r#"{"cmd": "reset", "seed": 2, "config": {"observation": "grid", "grid_cell": 100}}"#,
+            r#"{"cmd": "step", "action": {"movement": 0}}"#,
+            "",
+            "not json",
+            r#"{"cmd": "step", "action": {"movement": "north"}}"#,
+            r#"{"cmd": "dance"}"#,
+            r#"{"cmd": "close"}"#,
+            r#"{"cmd": "reset"}"#,
+        ];
+        let mut output = Vec::new();
+        serve(
+            config(&["resources/ai/hunter.bt"]),
+            Cursor::new(requests.join("\n")),
+            &mut output,
+        )
+        .expect("the session runs to the end");
+
+        let replies: Vec<Json> = String::from_utf8(output)
+            .expect("replies are text")
+            .lines()
+            .map(parse)
+            .collect();
+        // Nothing for the blank line or after closing
+        assert_eq!(replies.len(), 6);
+        let error = |reply: &Json| {
+            reply
+                .get("error")
+                .and_then(Json::as_str)
+                .map(str::to_string)
+        };
+        assert_eq!(error(&replies[0]).as_deref(), Some("reset before stepping"));
+        let grid = replies[1]
+            .get("observation")
+            .and_then(|observation| observation.get("grid"))
+            .expect("the config asked for a grid");
+        assert_eq!(grid.get("width").and_then(Json::as_f64), Some(8.0));
+        let step = &replies[2];
+        assert_eq!(step.get("done").and_then(Json::as_bool), Some(false));
+        assert!(step.get("reward").and_then(Json::as_f64).is_some());
+        assert_eq!(
+            step.get("info")
+                .and_then(|info| info.get("tick"))
+                .and_then(Json::as_f64),
+            Some(1.0)
+        );
+        assert!(replies[3..].iter().all(|reply| error(reply).is_some()));
+    }
+}
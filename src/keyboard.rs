@@ -1,41 +1,49 @@
+use crate::commands::Commands;
+use crate::commands::FireCommand;
+use crate::commands::MovementCommand;
+use crate::commands::RotationCommand;
+use crate::commands::WeaponCommand;
 use crate::components::AngularVelocity;
 use crate::components::BulletSpawner;
+use crate::components::Engine;
 use crate::components::KeyboardControlled;
 use crate::components::Velocity;
-use crate::FireCommand;
 use specs::join::Join;
 use specs::storage::WriteStorage;
 use specs::ReadExpect;
 use specs::ReadStorage;
 use specs::System;
 
-use super::MovementCommand;
-use super::RotationCommand;
-
 pub struct KeyboardMove;
 
 impl<'a> System<'a> for KeyboardMove {
     type SystemData = (
-        ReadExpect<'a, Option<MovementCommand>>,
+        ReadExpect<'a, Commands>,
         ReadStorage<'a, KeyboardControlled>,
         WriteStorage<'a, Velocity>,
+        WriteStorage<'a, Engine>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
         //TODO: This code can be made nicer and more idiomatic using more pattern matching.
         // Look up "rust irrefutable patterns" and use them here.
-        let movement_command = match &*data.0 {
-            Some(movement_command) => movement_command,
-            None => return, // no change
-        };
-
-        for (control, vel) in (&data.1, &mut data.2).join() {
-            match movement_command {
-                &MovementCommand::Move(direction) => {
+        for (control, vel, engine) in (&data.1, &mut data.2, (&mut data.3).maybe()).join() {
+            let movement_command = match data.0.get(control.player) {
+                Some(commands) => commands.movement,
+                None => continue, // no change
+            };
+            // With an engine the tank is driven there by `Drive`, otherwise it gets there at once
+            match (movement_command, engine) {
+                (Some(MovementCommand::Move(direction)), Some(engine)) => {
+                    engine.throttle = Some(direction)
+                }
+                (Some(MovementCommand::Stop), Some(engine)) => engine.throttle = None,
+                (Some(MovementCommand::Move(direction)), None) => {
                     vel.speed = control.speed;
                     vel.direction = direction;
                 }
-                MovementCommand::Stop => vel.speed = 0.0,
+                (Some(MovementCommand::Stop), None) => vel.speed = 0.0,
+                (None, _) => {}
             }
         }
     }
@@ -45,25 +53,33 @@ pub struct KeyboardRotate;
 
 impl<'a> System<'a> for KeyboardRotate {
     type SystemData = (
-        ReadExpect<'a, Option<RotationCommand>>,
+        ReadExpect<'a, Commands>,
         ReadStorage<'a, KeyboardControlled>,
         WriteStorage<'a, AngularVelocity>,
+        WriteStorage<'a, Engine>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
         //TODO: This code can be made nicer and more idiomatic using more pattern matching.
         // Look up "rust irrefutable patterns" and use them here.
-        let rotation_command = match &*data.0 {
-            Some(rotation_command) => rotation_command,
-            None => return, // no change
-        };
-        for (control, agular_velocity) in (&data.1, &mut data.2).join() {
-            match rotation_command {
-                &RotationCommand::Move(rotation) => {
+        for (control, agular_velocity, engine) in
+            (&data.1, &mut data.2, (&mut data.3).maybe()).join()
+        {
+            let rotation_command = match data.0.get(control.player) {
+                Some(commands) => commands.rotation,
+                None => continue, // no change
+            };
+            match (rotation_command, engine) {
+                (Some(RotationCommand::Move(rotation)), Some(engine)) => {
+                    engine.turn = Some(rotation)
+                }
+                (Some(RotationCommand::Stop), Some(engine)) => engine.turn = None,
+                (Some(RotationCommand::Move(rotation)), None) => {
                     agular_velocity.speed = control.rotation_speed;
                     agular_velocity.rotation = rotation;
                 }
-                RotationCommand::Stop => agular_velocity.speed = 0.0,
+                (Some(RotationCommand::Stop), None) => agular_velocity.speed = 0.0,
+                (None, _) => {}
             }
         }
     }
@@ -73,7 +89,7 @@ pub struct KeyboardShoot;
 
 impl<'a> System<'a> for KeyboardShoot {
     type SystemData = (
-        ReadExpect<'a, Option<FireCommand>>,
+        ReadExpect<'a, Commands>,
         ReadStorage<'a, KeyboardControlled>,
         WriteStorage<'a, BulletSpawner>,
     );
@@ -81,19 +97,31 @@ impl<'a> System<'a> for KeyboardShoot {
     fn run(&mut self, mut data: Self::SystemData) {
         //TODO: This code can be made nicer and more idiomatic using more pattern matching.
         // Look up "rust irrefutable patterns" and use them here.
-        let fire_command = match &*data.0 {
-            Some(fire_command) => fire_command,
-            None => return, // no change
-        };
-
-        for (spawner, _kc) in (&mut data.2, &data.1).join() {
+        for (spawner, control) in (&mut data.2, &data.1).join() {
+            let (fire_command, weapon_command) = match data.0.get(control.player) {
+                Some(commands) => (commands.fire, commands.weapon),
+                None => continue, // no change
+            };
+            let selected = match weapon_command {
+                Some(WeaponCommand::Next) => (spawner.selected + 1) % spawner.weapons.len(),
+                Some(WeaponCommand::Select(slot)) if (slot as usize) < spawner.weapons.len() => {
+                    slot as usize
+                }
+                _ => spawner.selected,
+            };
+            if selected != spawner.selected {
+                // A burst in progress is abandoned, the cooldown still has to run out
+                spawner.selected = selected;
+                spawner.burst_rem = 0;
+            }
             match fire_command {
-                &FireCommand::Fire => {
+                Some(FireCommand::Fire) => {
                     spawner.spawning = true;
                 }
-                FireCommand::Stop => {
+                Some(FireCommand::Stop) => {
                     spawner.spawning = false;
                 }
+                None => {}
             }
         }
     }
@@ -0,0 +1,37 @@
+//! The simulation and everything run on top of it that doesn't need a window:
+//! networking, bots, replays, tournaments and training. The game itself is in `main.rs`
+//! and the headless server in `bin/server.rs`.
+
+pub mod ai;
+pub mod archetype;
+pub mod bulletspawner_system;
+pub mod collision;
+pub mod commands;
+pub mod components;
+pub mod damage;
+pub mod flag;
+pub mod fog;
+pub mod game;
+pub mod geometry;
+pub mod gym;
+pub mod json;
+pub mod keyboard;
+pub mod mode;
+pub mod navigation;
+pub mod net;
+pub mod particles;
+pub mod physics;
+pub mod pickup;
+pub mod playback;
+pub mod projectile;
+pub mod raycast;
+pub mod replay;
+pub mod resources;
+pub mod ricochet;
+pub mod score;
+pub mod snapshot;
+pub mod spatial;
+pub mod status;
+pub mod terrain;
+pub mod tournament;
+pub mod weapon;
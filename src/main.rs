@@ -1,134 +1,319 @@
-mod bulletspawner_system;
-mod components;
-mod keyboard;
-mod physics;
 mod renderer;
-mod resources;
-use crate::components::BulletSpawner;
-use specs::prelude::*;
-
-use crate::components::Angle;
-use crate::components::AngularVelocity;
-use crate::components::Rotation;
+mod viewer;
+
+use rusty_tanks::ai::Bot;
+use rusty_tanks::archetype::TankArchetype;
+use rusty_tanks::commands::{
+    Commands, FireCommand, MovementCommand, PlayerCommands, PlayerId, RotationCommand,
+    WeaponCommand,
+};
+use rusty_tanks::components::Angle;
+use rusty_tanks::components::Rotation;
+use rusty_tanks::components::Team;
+use rusty_tanks::fog::Fog;
+use rusty_tanks::mode::{GameMode, Match, Rules};
+use rusty_tanks::navigation::Navigator;
+use rusty_tanks::net::client::Client;
+use rusty_tanks::net::lobby::Choices;
+use rusty_tanks::net::rollback::{RollbackSession, UdpTransport};
+use rusty_tanks::particles::Particles;
+use rusty_tanks::playback::Playback;
+use rusty_tanks::replay::Replay;
+use rusty_tanks::resources::Tick;
+use rusty_tanks::{ai, game, mode, net};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use specs::world::Builder;
-use std::time::Duration;
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::EventPump;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use sdl2::image::LoadTexture;
 use sdl2::pixels::Color;
-use sdl2::rect::{Point, Rect};
-
-use specs::prelude::{DispatcherBuilder, SystemData, World};
-
-use crate::components::{KeyboardControlled, Position, Sprite, Velocity};
 
-pub enum RotationCommand {
-    Stop,
-    Move(Rotation),
+use specs::prelude::WorldExt;
+
+/// Command line options
+struct Options {
+    seed: Option<u64>,
+    /// Write the match's commands to this file on exit
+    record: Option<PathBuf>,
+    /// Play back the commands in this file instead of reading the keyboard
+    replay: Option<PathBuf>,
+    /// Add a bot driven by each of these behaviour trees or scripts to a local game
+    bots: Vec<PathBuf>,
+    /// How many operations a bot's script gets each tick
+    bot_budget: u64,
+    /// Play a local game to this mode's rules instead of until the window is closed
+    mode: Option<GameMode>,
+    /// The match's time limit in seconds instead of the mode's, 0 for none
+    time_limit: Option<u64>,
+    /// The match's score limit instead of the mode's, 0 for none
+    score_limit: Option<u32>,
+    port: u16,
+    /// Play on the server at this address
+    connect: Option<String>,
+    /// Play a rollback match against the peer at this address
+    peer: Option<String>,
+    /// Which player we are in a rollback match, the peer must be the other one
+    player: PlayerId,
+    /// Search for a lobby to join, with our choices for it
+    lobby: bool,
+    /// Which of the lobbies found to join, by its number in the list
+    join: Option<usize>,
+    name: String,
+    archetype: TankArchetype,
+    team: u8,
+    ready: bool,
 }
 
-pub enum MovementCommand {
-    Stop,
-    Move(Angle),
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        seed: None,
+        record: None,
+        replay: None,
+        bots: Vec::new(),
+        bot_budget: ai::script::DEFAULT_BUDGET,
+        mode: None,
+        time_limit: None,
+        score_limit: None,
+        port: net::DEFAULT_PORT,
+        connect: None,
+        peer: None,
+        player: 0,
+        lobby: false,
+        join: None,
+        name: "player".to_string(),
+        archetype: TankArchetype::default(),
+        team: 0,
+        ready: false,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--seed" => {
+                let seed = value()?;
+                options.seed = Some(seed.parse().map_err(|_| format!("bad seed {}", seed))?);
+            }
+            "--record" => options.record = Some(value()?.into()),
+            "--replay" => options.replay = Some(value()?.into()),
+            "--bot" => options.bots.push(value()?.into()),
+            "--bot-budget" => {
+                let budget = value()?;
+                options.bot_budget = budget
+                    .parse()
+                    .map_err(|_| format!("bad bot budget {}", budget))?;
+            }
+            "--mode" => {
+                let name = value()?;
+                options.mode =
+                    Some(GameMode::from_name(&name).ok_or(format!("unknown mode {}", name))?);
+            }
+            "--time-limit" => {
+                let seconds = value()?;
+                options.time_limit = Some(
+                    seconds
+                        .parse()
+                        .map_err(|_| format!("bad time limit {}", seconds))?,
+                );
+            }
+            "--score-limit" => {
+                let score = value()?;
+                options.score_limit = Some(
+                    score
+                        .parse()
+                        .map_err(|_| format!("bad score limit {}", score))?,
+                );
+            }
+            "--port" => {
+                let port = value()?;
+                options.port = port.parse().map_err(|_| format!("bad port {}", port))?;
+            }
+            "--connect" => options.connect = Some(value()?),
+            "--peer" => options.peer = Some(value()?),
+            "--player" => {
+                let player = value()?;
+                options.player = player
+                    .parse()
+                    .map_err(|_| format!("bad player {}", player))?;
+            }
+            "--lobby" => options.lobby = true,
+            "--join" => {
+                let index = value()?;
+                options.join = Some(index.parse().map_err(|_| format!("bad lobby {}", index))?);
+            }
+            "--name" => options.name = value()?,
+            "--archetype" => {
+                let name = value()?;
+                options.archetype =
+                    TankArchetype::from_name(&name).ok_or(format!("unknown archetype {}", name))?;
+            }
+            "--team" => {
+                let team = value()?;
+                options.team = team.parse().map_err(|_| format!("bad team {}", team))?;
+            }
+            "--ready" => options.ready = true,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    if options.bots.len() >= game::MAX_PLAYERS {
+        return Err(format!(
+            "at most {} bots fit in the arena",
+            game::MAX_PLAYERS - 1
+        ));
+    }
+    Ok(options)
 }
 
-pub enum FireCommand {
-    Stop,
-    Fire,
-}
+/// Translate a keyboard event into commands for the local player
+fn handle_keyboard_event(event: &Event, commands: &mut PlayerCommands) {
+    match event {
+        // move
+        Event::KeyDown {
+            keycode: Some(Keycode::Left),
+            repeat: false,
+            ..
+        } => {
+            commands.movement = Some(MovementCommand::Move(Angle { angle: 180.0 }));
+        }
+        Event::KeyDown {
+            keycode: Some(Keycode::Right),
+            repeat: false,
+            ..
+        } => {
+            commands.movement = Some(MovementCommand::Move(Angle { angle: 0.0 }));
+        }
+        Event::KeyDown {
+            keycode: Some(Keycode::Up),
+            repeat: false,
+            ..
+        } => {
+            commands.movement = Some(MovementCommand::Move(Angle { angle: 270.0 }));
+        }
+        Event::KeyDown {
+            keycode: Some(Keycode::Down),
+            repeat: false,
+            ..
+        } => {
+            commands.movement = Some(MovementCommand::Move(Angle { angle: 90.0 }));
+        }
+
+        // rotate
+        Event::KeyDown {
+            keycode: Some(Keycode::Q),
+            repeat: false,
+            ..
+        } => {
+            commands.rotation = Some(RotationCommand::Move(Rotation::CounterClockwise));
+        }
+        Event::KeyDown {
+            keycode: Some(Keycode::E),
+            repeat: false,
+            ..
+        } => {
+            commands.rotation = Some(RotationCommand::Move(Rotation::Clockwise));
+        }
+
+        // fire
+        Event::KeyDown {
+            keycode: Some(Keycode::Space),
+            repeat: false,
+            ..
+        } => {
+            commands.fire = Some(FireCommand::Fire);
+        }
+
+        // switch weapon
+        Event::KeyDown {
+            keycode: Some(Keycode::Tab),
+            repeat: false,
+            ..
+        } => {
+            commands.weapon = Some(WeaponCommand::Next);
+        }
+        Event::KeyDown {
+            keycode: Some(keycode),
+            repeat: false,
+            ..
+        } if (Keycode::Num1 as i32..=Keycode::Num9 as i32).contains(&(*keycode as i32)) => {
+            let slot = *keycode as i32 - Keycode::Num1 as i32;
+            commands.weapon = Some(WeaponCommand::Select(slot as u8));
+        }
+
+        // stop move
+        Event::KeyUp {
+            keycode: Some(Keycode::Left),
+            repeat: false,
+            ..
+        }
+        | Event::KeyUp {
+            keycode: Some(Keycode::Right),
+            repeat: false,
+            ..
+        }
+        | Event::KeyUp {
+            keycode: Some(Keycode::Up),
+            repeat: false,
+            ..
+        }
+        | Event::KeyUp {
+            keycode: Some(Keycode::Down),
+            repeat: false,
+            ..
+        } => {
+            commands.movement = Some(MovementCommand::Stop);
+        }
+
+        // stop rotate
+        Event::KeyUp {
+            keycode: Some(Keycode::Q),
+            repeat: false,
+            ..
+        }
+        | Event::KeyUp {
+            keycode: Some(Keycode::E),
+            repeat: false,
+            ..
+        } => {
+            commands.rotation = Some(RotationCommand::Stop);
+        }
 
-fn initialize_tank(world: &mut World, tank_base_sprite: usize, tank_turret_sprite: usize) {
-    // Init the base
-    world
-        .create_entity()
-        .with(Position(Point::new(0, 0)))
-        .with(Angle { angle: 0.0 })
-        .with(Sprite {
-            spritesheet: tank_base_sprite,
-            region: Rect::new(0, 0, 32, 32),
-        })
-        .with(KeyboardControlled {
-            speed: 20.0,
-            rotation_speed: 2.0,
-        })
-        .with(Velocity {
-            speed: 0.0,
-            direction: Angle { angle: 0.0 },
-        })
-        .build();
-
-    // Init the turret
-    world
-        .create_entity()
-        .with(Position(Point::new(0, 0)))
-        .with(Angle { angle: 0.0 })
-        .with(Sprite {
-            spritesheet: tank_turret_sprite,
-            region: Rect::new(0, 0, 32, 32),
-        })
-        .with(KeyboardControlled {
-            speed: 20.0,
-            rotation_speed: 2.0,
-        })
-        .with(Velocity {
-            speed: 0.0,
-            direction: Angle { angle: 0.0 },
-        })
-        .with(AngularVelocity {
-            speed: 0.0,
-            rotation: Rotation::Clockwise,
-        })
-        .with(BulletSpawner {
-            spawning: false,
-            cooldown: 2,
-            cooldown_rem: 0,
-            bullet_speed: 15.0,
-        })
-        .build();
-
-    // Init the base
-    world
-        .create_entity()
-        .with(Position(Point::new(10, 0)))
-        .with(Angle { angle: 0.0 })
-        .with(Sprite {
-            spritesheet: tank_base_sprite,
-            region: Rect::new(0, 0, 32, 32),
-        })
-        .with(Velocity {
-            speed: 0.0,
-            direction: Angle { angle: 0.0 },
-        })
-        .with(AngularVelocity {
-            speed: 2.0,
-            rotation: Rotation::Clockwise,
-        })
-        .build();
-
-    // Init the turret
-    world
-        .create_entity()
-        .with(Position(Point::new(10, 0)))
-        .with(Angle { angle: 10.0 })
-        .with(Sprite {
-            spritesheet: tank_turret_sprite,
-            region: Rect::new(0, 0, 32, 32),
-        })
-        .with(AngularVelocity {
-            speed: 3.0,
-            rotation: Rotation::Clockwise,
-        })
-        .with(BulletSpawner {
-            spawning: true,
-            cooldown: 15,
-            cooldown_rem: 0,
-            bullet_speed: 8.0,
-        })
-        .build();
+        // stop fire
+        Event::KeyUp {
+            keycode: Some(Keycode::Space),
+            repeat: false,
+            ..
+        } => {
+            commands.fire = Some(FireCommand::Stop);
+        }
+
+        _ => {}
+    }
 }
 
 fn main() -> Result<(), String> {
+    let options = parse_args()?;
+
+    // The lobby is run from the console before there is a window
+    let server = if options.lobby {
+        let choices = Choices {
+            name: options.name.clone(),
+            archetype: options.archetype,
+            team: options.team,
+            ready: options.ready,
+        };
+        let (addr, seat) = net::lobby::join(choices, options.join)?;
+        Some((addr.to_string(), Some(seat)))
+    } else {
+        options.connect.clone().map(|addr| (addr, None))
+    };
+
+    let playback = match &options.replay {
+        Some(path) => Some(Replay::load(path)?),
+        None => None,
+    };
+
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
@@ -145,56 +330,114 @@ fn main() -> Result<(), String> {
 
     let texture_creator = canvas.texture_creator();
 
-    let mut dispatcher = DispatcherBuilder::new()
-        .with(keyboard::KeyboardShoot, "KeyboardShoot", &[])
-        .with(keyboard::KeyboardMove, "KeyboardMove", &[])
-        .with(keyboard::KeyboardRotate, "KeyboardRotate", &[])
-        .with(
-            bulletspawner_system::BulletSpawnerSystem,
-            "BulletSpawnerSystem",
-            &["KeyboardShoot", "KeyboardMove", "KeyboardRotate"],
-        )
-        .with(
-            physics::Physics,
-            "Physics",
-            &[
-                "BulletSpawnerSystem",
-                "KeyboardShoot",
-                "KeyboardMove",
-                "KeyboardRotate",
-            ],
-        )
-        .build();
-
-    let mut world = World::new();
-    dispatcher.setup(&mut world);
-    renderer::SystemData::setup(&mut world);
-
-    // Initialize resource
-    let movement_command: Option<MovementCommand> = None;
-    world.insert(movement_command);
-
-    let fire_command: Option<FireCommand> = None;
-    world.insert(fire_command);
-
-    let rotation_command: Option<RotationCommand> = None;
-    world.insert(rotation_command);
-
     let textures = [
         texture_creator.load_texture("resources/assets/tank/bullet.png")?,
         texture_creator.load_texture("resources/assets/tank/tankBase.png")?,
         texture_creator.load_texture("resources/assets/tank/tankTurret.png")?,
+        texture_creator.load_texture("resources/assets/tank/projectiles.png")?,
+        texture_creator.load_texture("resources/assets/tank/pickups.png")?,
+        texture_creator.load_texture("resources/assets/tank/wall.png")?,
+        texture_creator.load_texture("resources/assets/tank/explosion.png")?,
+        texture_creator.load_texture("resources/assets/tank/terrain.png")?,
+        texture_creator.load_texture("resources/assets/tank/flags.png")?,
     ];
 
-    initialize_tank(&mut world, 1, 2);
-
     let mut event_pump = sdl_context.event_pump()?;
+
+    if let Some(replay) = playback {
+        return run_replay_viewer(replay, &mut canvas, &textures, &mut event_pump);
+    }
+    if let Some((addr, seat)) = server {
+        return run_client(&addr, seat, &mut canvas, &textures, &mut event_pump);
+    }
+    if let Some(peer) = &options.peer {
+        return run_peer(&options, peer, &mut canvas, &textures, &mut event_pump);
+    }
+    run_game(&options, &mut canvas, &textures, &mut event_pump)
+}
+
+/// The seed given on the command line, otherwise one based on the time
+fn seed(options: &Options) -> u64 {
+    options.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0)
+    })
+}
+
+/// The rules chosen on the command line, if a mode was
+fn rules(options: &Options) -> Option<Rules> {
+    let mut rules = Rules::new(options.mode?);
+    if let Some(seconds) = options.time_limit {
+        let ticks = Duration::from_secs(seconds).as_millis() / game::TICK_DURATION.as_millis();
+        rules.time_limit = Some(ticks as u64).filter(|&ticks| ticks > 0);
+    }
+    if let Some(score) = options.score_limit {
+        rules.score_limit = Some(score).filter(|&score| score > 0);
+    }
+    Some(rules)
+}
+
+/// Play a match from the keyboard, recording it if asked to
+fn run_game(
+    options: &Options,
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    event_pump: &mut EventPump,
+) -> Result<(), String> {
+    let seed = seed(options);
+    let rules = rules(options);
+    let mut particles = Particles::new();
+    let mut fog = Fog::new(game::LOCAL_PLAYER);
+
+    // In a match our tank is an entrant like the bots', otherwise it is the usual one
+    let teams = rules.is_some_and(|rules| rules.mode.teams());
+    let mut tanks = Vec::new();
+    if rules.is_some() {
+        let team = Some(Team(options.team)).filter(|_| teams);
+        tanks.push((game::LOCAL_PLAYER, options.archetype, team));
+    }
+    // Bots take the players after ours. In a team match they alternate between the other team and ours.
+    let mut bots = Vec::new();
+    for (i, (player, path)) in (game::LOCAL_PLAYER + 1..).zip(&options.bots).enumerate() {
+        let bot = Bot::load(player, path, seed, options.bot_budget)?;
+        let team = if i % 2 == 0 {
+            options.team ^ 1
+        } else {
+            options.team
+        };
+        let team = Some(Team(team)).filter(|_| teams);
+        match team {
+            Some(Team(team)) => println!(
+                "player {} is a {} bot in a {} on team {}",
+                player,
+                bot.name(),
+                bot.archetype(),
+                team
+            ),
+            None => println!(
+                "player {} is a {} bot in a {}",
+                player,
+                bot.name(),
+                bot.archetype()
+            ),
+        }
+        tanks.push((player, bot.archetype(), team));
+        bots.push(bot);
+    }
+
+    let mut recording = Replay::new(seed);
+    recording.rules = rules;
+    recording.tanks = tanks;
+    let mut dispatcher = game::build_dispatcher();
+    let mut world = recording.create_world(&mut dispatcher);
+    let mut navigator = Navigator::new(&world, game::TANK_COLLIDER);
+
     let mut i = 0;
     'running: loop {
-        // None - no change, Some(MovementCommand) - perform movement
-        let mut movement_command_one = None;
-        let mut rotation_command = None;
-        let mut fire_command = None;
+        let tick = world.read_resource::<Tick>().0;
+        let mut local_commands = PlayerCommands::default();
 
         // Handle events
         for event in event_pump.poll_iter() {
@@ -204,133 +447,229 @@ fn main() -> Result<(), String> {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                event => handle_keyboard_event(&event, &mut local_commands),
+            }
+        }
 
-                // move
-                Event::KeyDown {
-                    keycode: Some(Keycode::Left),
-                    repeat: false,
-                    ..
-                } => {
-                    movement_command_one = Some(MovementCommand::Move(Angle { angle: 180.0 }));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Right),
-                    repeat: false,
-                    ..
-                } => {
-                    movement_command_one = Some(MovementCommand::Move(Angle { angle: 0.0 }));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Up),
-                    repeat: false,
-                    ..
-                } => {
-                    movement_command_one = Some(MovementCommand::Move(Angle { angle: 270.0 }));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Down),
-                    repeat: false,
-                    ..
-                } => {
-                    movement_command_one = Some(MovementCommand::Move(Angle { angle: 90.0 }));
-                }
-
-                // rotate
-                Event::KeyDown {
-                    keycode: Some(Keycode::Q),
-                    repeat: false,
-                    ..
-                } => {
-                    rotation_command = Some(RotationCommand::Move(Rotation::CounterClockwise));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::E),
-                    repeat: false,
-                    ..
-                } => {
-                    rotation_command = Some(RotationCommand::Move(Rotation::Clockwise));
-                }
-
-                // fire
-                Event::KeyDown {
-                    keycode: Some(Keycode::Space),
-                    repeat: false,
-                    ..
-                } => {
-                    fire_command = Some(FireCommand::Fire);
-                }
-
-                // stop move
-                Event::KeyUp {
-                    keycode: Some(Keycode::Left),
-                    repeat: false,
-                    ..
-                }
-                | Event::KeyUp {
-                    keycode: Some(Keycode::Right),
-                    repeat: false,
-                    ..
-                }
-                | Event::KeyUp {
-                    keycode: Some(Keycode::Up),
-                    repeat: false,
-                    ..
-                }
-                | Event::KeyUp {
-                    keycode: Some(Keycode::Down),
-                    repeat: false,
-                    ..
-                } => {
-                    movement_command_one = Some(MovementCommand::Stop);
-                }
-
-                // stop rotate
-                Event::KeyUp {
-                    keycode: Some(Keycode::Q),
-                    repeat: false,
-                    ..
-                }
-                | Event::KeyUp {
-                    keycode: Some(Keycode::E),
-                    repeat: false,
-                    ..
-                } => {
-                    rotation_command = Some(RotationCommand::Stop);
-                }
-
-                // stop fire
-                Event::KeyUp {
-                    keycode: Some(Keycode::Space),
-                    repeat: false,
+        // Once a match is over its last moment stays on screen until the window is closed
+        let over = world
+            .try_fetch::<Match>()
+            .is_some_and(|game_match| game_match.outcome.is_some());
+        if !over {
+            let mut commands = Commands::default();
+            commands.0.insert(game::LOCAL_PLAYER, local_commands);
+            for bot in &mut bots {
+                commands
+                    .0
+                    .insert(bot.player, bot.think(&world, &mut navigator));
+            }
+            recording.record(tick, &commands);
+
+            // Update
+            game::step(&mut world, &mut dispatcher, commands);
+            particles.update(&world);
+            fog.update(&world);
+        }
+
+        // Render
+        i = (i + 1) % 255;
+        renderer::draw(
+            canvas,
+            Color::RGB(i, 64, 255 - i),
+            textures,
+            world.system_data(),
+            &particles,
+            Some(&fog),
+        )?;
+        renderer::draw_laser_sight(canvas, &world, game::LOCAL_PLAYER, Some(&fog))?;
+        canvas.present();
+        let mut title = game::status(&world, game::LOCAL_PLAYER);
+        if let Some(status) = mode::status(&world, game::LOCAL_PLAYER) {
+            title = format!("{} - {}", title, status);
+        }
+        canvas
+            .window_mut()
+            .set_title(&title)
+            .map_err(|e| e.to_string())?;
+
+        // Time management!
+        ::std::thread::sleep(game::TICK_DURATION);
+    }
+
+    if let Some(status) = mode::status(&world, game::LOCAL_PLAYER) {
+        println!("{}", status);
+    }
+    if let Some(path) = &options.record {
+        recording.save(path)?;
+    }
+
+    Ok(())
+}
+
+/// Play on a server, drawing what it tells us
+fn run_client(
+    addr: &str,
+    seat: Option<PlayerId>,
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    event_pump: &mut EventPump,
+) -> Result<(), String> {
+    let mut client = Client::connect(addr, seat)?;
+    println!(
+        "connected to {} as player {} in a {}",
+        addr, client.player, client.archetype
+    );
+    let mut particles = Particles::new();
+
+    'running: loop {
+        let mut local_commands = PlayerCommands::default();
+
+        // Handle events
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
                     ..
-                } => {
-                    fire_command = Some(FireCommand::Stop);
-                }
+                } => break 'running,
+                event => handle_keyboard_event(&event, &mut local_commands),
+            }
+        }
+
+        // Update
+        client.send_input(local_commands);
+        client.receive()?;
+        client.interpolate();
+        particles.update(client.world());
+
+        // Render
+        renderer::render(
+            canvas,
+            Color::RGB(64, 64, 64),
+            textures,
+            client.world().system_data(),
+            &particles,
+            Some(client.fog()),
+        )?;
+
+        // Time management!
+        ::std::thread::sleep(game::TICK_DURATION);
+    }
+
+    client.disconnect();
+    Ok(())
+}
+
+/// Play a rollback match against a single peer, both sides must use the same seed
+fn run_peer(
+    options: &Options,
+    peer: &str,
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    event_pump: &mut EventPump,
+) -> Result<(), String> {
+    let transport = UdpTransport::connect(options.port, peer)?;
+    let remote = if options.player == 0 { 1 } else { 0 };
+    let mut session = RollbackSession::new(transport, seed(options), options.player, remote, 2);
+    let mut particles = Particles::new();
+    let mut fog = Fog::new(options.player);
 
-                _ => {}
+    'running: loop {
+        let mut local_commands = PlayerCommands::default();
+
+        // Handle events
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                event => handle_keyboard_event(&event, &mut local_commands),
             }
         }
 
-        *world.write_resource() = movement_command_one;
-        // println!("{:?}", rotation_command);
-        *world.write_resource() = rotation_command;
-        *world.write_resource() = fire_command;
+        // Update
+        session.advance(local_commands);
+        particles.update(&session.world);
+        fog.update(&session.world);
+
+        // Render
+        renderer::draw(
+            canvas,
+            Color::RGB(64, 64, 64),
+            textures,
+            session.world.system_data(),
+            &particles,
+            Some(&fog),
+        )?;
+        renderer::draw_laser_sight(canvas, &session.world, options.player, Some(&fog))?;
+        canvas.present();
+        canvas
+            .window_mut()
+            .set_title(&game::status(&session.world, options.player))
+            .map_err(|e| e.to_string())?;
+
+        // Time management!
+        ::std::thread::sleep(game::TICK_DURATION);
+    }
+
+    Ok(())
+}
+
+/// Review a recorded match: Space pauses, '.' steps, Up/Down change speed,
+/// Left/Right/Home/End or clicking the timeline seek
+fn run_replay_viewer(
+    replay: Replay,
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    event_pump: &mut EventPump,
+) -> Result<(), String> {
+    let mut playback = Playback::new(replay);
+    let mut particles = Particles::new();
+
+    'running: loop {
+        let timeline = renderer::timeline_rect(canvas)?;
+
+        // Handle events
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                event => viewer::handle_event(&mut playback, &event, timeline),
+            }
+        }
 
         // Update
-        dispatcher.dispatch(&mut world);
-        world.maintain();
+        playback.update();
+        particles.update(&playback.world);
 
         // Render
-        i = (i + 1) % 255;
-        renderer::render(
-            &mut canvas,
-            Color::RGB(i, 64, 255 - i),
-            &textures,
-            world.system_data(),
+        renderer::draw(
+            canvas,
+            Color::RGB(64, 64, 64),
+            textures,
+            playback.world.system_data(),
+            &particles,
+            None,
+        )?;
+        renderer::draw_timeline(
+            canvas,
+            playback.tick(),
+            playback.length(),
+            &playback.snapshot_ticks(),
         )?;
+        canvas.present();
+        canvas
+            .window_mut()
+            .set_title(&playback.status())
+            .map_err(|e| e.to_string())?;
 
         // Time management!
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 20));
+        ::std::thread::sleep(game::TICK_DURATION);
     }
 
     Ok(())
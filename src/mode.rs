@@ -0,0 +1,553 @@
+use crate::archetype::TankArchetype;
+use crate::commands::PlayerId;
+use crate::components::{Flag, Health, KeyboardControlled, Position, Team};
+use crate::damage;
+use crate::flag;
+use crate::game;
+use crate::geometry::Point;
+use crate::resources::Tick;
+use crate::score::Scoreboard;
+use specs::join::Join;
+use specs::prelude::{Read, ReadStorage, System, World, WorldExt, Write};
+use specs::LazyUpdate;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Ticks a destroyed tank waits before coming back, in the modes that bring it back
+const RESPAWN_DELAY: u64 = 3 * 20;
+
+/// The ways a match can be played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    /// Every tank for itself, destroyed tanks come back and the most kills wins
+    Deathmatch,
+    /// As deathmatch, with the kills of everyone on a team counting for it
+    TeamDeathmatch,
+    /// Destroyed tanks stay destroyed, the last tank or team left wins
+    Elimination,
+    /// Two teams, each scoring by carrying the other's flag home to its own
+    CaptureTheFlag,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 4] = [
+        GameMode::Deathmatch,
+        GameMode::TeamDeathmatch,
+        GameMode::Elimination,
+        GameMode::CaptureTheFlag,
+    ];
+
+    /// Whether the players are split into teams
+    pub fn teams(self) -> bool {
+        self == GameMode::TeamDeathmatch || self == GameMode::CaptureTheFlag
+    }
+
+    /// Whether destroyed tanks come back
+    pub fn respawns(self) -> bool {
+        self != GameMode::Elimination
+    }
+
+    /// Ticks a match lasts unless it is told otherwise
+    pub fn time_limit(self) -> u64 {
+        match self {
+            GameMode::Deathmatch | GameMode::TeamDeathmatch => 5 * 60 * 20,
+            GameMode::Elimination => 3 * 60 * 20,
+            GameMode::CaptureTheFlag => 10 * 60 * 20,
+        }
+    }
+
+    /// The score that wins a match outright unless it is told otherwise
+    pub fn score_limit(self) -> Option<u32> {
+        match self {
+            GameMode::Deathmatch => Some(10),
+            GameMode::TeamDeathmatch => Some(20),
+            GameMode::Elimination => None,
+            GameMode::CaptureTheFlag => Some(3),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::Deathmatch => "deathmatch",
+            GameMode::TeamDeathmatch => "team-deathmatch",
+            GameMode::Elimination => "elimination",
+            GameMode::CaptureTheFlag => "capture-the-flag",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<GameMode> {
+        GameMode::ALL
+            .iter()
+            .cloned()
+            .find(|mode| mode.name() == name)
+    }
+}
+
+impl fmt::Display for GameMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The rules a match is played to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    pub mode: GameMode,
+    /// The match ends after this many ticks, won by whoever is ahead
+    pub time_limit: Option<u64>,
+    /// The first side to reach this score wins, in the modes with respawning
+    pub score_limit: Option<u32>,
+}
+
+impl Rules {
+    /// `mode` with its usual limits
+    pub fn new(mode: GameMode) -> Rules {
+        Rules {
+            mode,
+            time_limit: Some(mode.time_limit()),
+            score_limit: mode.score_limit(),
+        }
+    }
+}
+
+/// Who a player fights for: its team if it has one, otherwise itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Side {
+    Player(PlayerId),
+    Team(u8),
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Side::Player(player) => write!(f, "player {}", player),
+            Side::Team(team) => write!(f, "team {}", team),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Won(Side),
+    Draw,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Won(side) => write!(f, "{} won", side),
+            Outcome::Draw => f.write_str("draw"),
+        }
+    }
+}
+
+/// A player taking part in a match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entrant {
+    pub archetype: TankArchetype,
+    pub team: Option<Team>,
+    /// Whether it has a tank in one piece
+    pub standing: bool,
+    /// The tick it gets a new tank on, while it is waiting for one
+    pub respawn_at: Option<u64>,
+}
+
+impl Entrant {
+    pub fn side(&self, player: PlayerId) -> Side {
+        match self.team {
+            Some(Team(team)) => Side::Team(team),
+            None => Side::Player(player),
+        }
+    }
+}
+
+/// A match being played to a set of rules. It is part of the simulation, so it is rolled back along
+/// with everything else. Worlds without one have no rules and go on until the window is closed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub rules: Rules,
+    pub entrants: BTreeMap<PlayerId, Entrant>,
+    /// How it ended, once it has
+    pub outcome: Option<Outcome>,
+}
+
+impl Match {
+    /// A match between `entrants`, whose tanks are all standing to begin with
+    pub fn new(rules: Rules, entrants: &[(PlayerId, TankArchetype, Option<Team>)]) -> Match {
+        Match {
+            rules,
+            entrants: entrants
+                .iter()
+                .map(|&(player, archetype, team)| {
+                    let entrant = Entrant {
+                        archetype,
+                        team,
+                        standing: true,
+                        respawn_at: None,
+                    };
+                    (player, entrant)
+                })
+                .collect(),
+            outcome: None,
+        }
+    }
+
+    /// Each side's score: its kills in the deathmatches, its tanks left in elimination
+    /// and the flags it has captured in capture the flag
+    pub fn scores(&self, scoreboard: &Scoreboard) -> BTreeMap<Side, u32> {
+        let mut scores = BTreeMap::new();
+        for (&player, entrant) in &self.entrants {
+            let score = match self.rules.mode {
+                GameMode::Deathmatch | GameMode::TeamDeathmatch => scoreboard.of(player).kills,
+                GameMode::Elimination => u32::from(entrant.standing),
+                GameMode::CaptureTheFlag => scoreboard.of(player).captures,
+            };
+            *scores.entry(entrant.side(player)).or_default() += score;
+        }
+        scores
+    }
+
+    /// How the match stands after `tick`, if it is over
+    fn judge(&self, scoreboard: &Scoreboard, tick: u64) -> Option<Outcome> {
+        let scores = self.scores(scoreboard);
+        let best = scores.values().cloned().max().unwrap_or(0);
+        let leaders: Vec<Side> = scores
+            .iter()
+            .filter(|(_, &score)| score == best)
+            .map(|(&side, _)| side)
+            .collect();
+        let leader = match leaders.as_slice() {
+            [side] => Outcome::Won(*side),
+            _ => Outcome::Draw,
+        };
+
+        if self.rules.mode.respawns() {
+            // Sides tied on the limit play on until one of them pulls ahead
+            let limit = self.rules.score_limit;
+            if limit.is_some_and(|limit| best >= limit) && leader != Outcome::Draw {
+                return Some(leader);
+            }
+        } else if scores.len() > 1 && scores.values().filter(|&&left| left > 0).count() <= 1 {
+            return Some(if best > 0 { leader } else { Outcome::Draw });
+        }
+        if self.rules.time_limit.is_some_and(|limit| tick + 1 >= limit) {
+            return Some(leader);
+        }
+        None
+    }
+
+    /// A line on how the match is going for `player` at the start of `tick`, for the window title
+    pub fn summary(&self, scoreboard: &Scoreboard, tick: u64, player: PlayerId) -> String {
+        let mut parts = vec![self.rules.mode.to_string()];
+        if self.rules.mode.respawns() {
+            let mut scores: Vec<(Side, u32)> = self.scores(scoreboard).into_iter().collect();
+            scores.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            let scores: Vec<String> = scores
+                .iter()
+                .map(|(side, score)| format!("{} {}", side, score))
+                .collect();
+            parts.push(scores.join(", "));
+        } else {
+            let left = self.entrants.values().filter(|entrant| entrant.standing);
+            parts.push(format!(
+                "{} of {} tanks left",
+                left.count(),
+                self.entrants.len()
+            ));
+        }
+        match self.outcome {
+            Some(outcome) => parts.push(outcome.to_string()),
+            None => {
+                let entrant = self.entrants.get(&player);
+                if let Some(at) = entrant.and_then(|entrant| entrant.respawn_at) {
+                    parts.push(format!("back in {}s", seconds(at.saturating_sub(tick))));
+                }
+                if let Some(limit) = self.rules.time_limit {
+                    let left = seconds(limit.saturating_sub(tick));
+                    parts.push(format!("{}:{:02} left", left / 60, left % 60));
+                }
+            }
+        }
+        parts.join(" - ")
+    }
+}
+
+/// How the match in `world` is going for `player`, if it has one
+pub fn status(world: &World, player: PlayerId) -> Option<String> {
+    let game_match = world.try_fetch::<Match>()?;
+    let tick = world.read_resource::<Tick>().0;
+    Some(game_match.summary(&world.read_resource(), tick, player))
+}
+
+/// `ticks` in whole seconds, rounded up
+fn seconds(ticks: u64) -> u64 {
+    (ticks as f64 * game::TICK_DURATION.as_secs_f64()).ceil() as u64
+}
+
+/// Keeps a match to its rules: brings destroyed tanks back where the mode allows it and decides when it is over
+pub struct MatchRules;
+
+impl<'a> System<'a> for MatchRules {
+    type SystemData = (
+        Option<Write<'a, Match>>,
+        Read<'a, Tick>,
+        Read<'a, Scoreboard>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Health>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Team>,
+        ReadStorage<'a, Flag>,
+        Read<'a, LazyUpdate>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (state, tick, scoreboard, controlled, health, positions, teams, flags, updater) = data;
+        let mut state = match state {
+            Some(state) => state,
+            None => return,
+        };
+        if state.outcome.is_some() {
+            return;
+        }
+        let tick = tick.0;
+
+        // Tanks destroyed this tick are only removed once the world is maintained, but they are out of health
+        let tanks: Vec<(PlayerId, Point, Option<Team>)> =
+            (&controlled, &health, &positions, teams.maybe())
+                .join()
+                .filter(|(_, health, _, _)| health.current > 0)
+                .map(|(control, _, pos, team)| (control.player, pos.0, team.cloned()))
+                .collect();
+        let bases: BTreeMap<u8, Point> =
+            flags.join().map(|flag| (flag.team.0, flag.base)).collect();
+        let respawns = state.rules.mode.respawns();
+        // The tanks coming back this tick aren't in the world yet, so they are kept apart here
+        let mut taken: Vec<Point> = Vec::new();
+        for (&player, entrant) in state.entrants.iter_mut() {
+            entrant.standing = tanks.iter().any(|&(owner, _, _)| owner == player);
+            if entrant.standing || !respawns {
+                continue;
+            }
+            match entrant.respawn_at {
+                None => entrant.respawn_at = Some(tick + RESPAWN_DELAY),
+                Some(at) if tick >= at => {
+                    entrant.respawn_at = None;
+                    // Teams with a flag come back at their base
+                    let candidates = match entrant.team.and_then(|team| bases.get(&team.0)) {
+                        Some(&base) => flag::spawn_points(base),
+                        None => {
+                            let mut points = vec![game::spawn_point(player)];
+                            points
+                                .extend(game::SPAWN_POINTS.iter().map(|&(x, y)| Point::new(x, y)));
+                            points
+                        }
+                    };
+                    let free: Vec<Point> = candidates
+                        .iter()
+                        .cloned()
+                        .filter(|point| !taken.contains(point))
+                        .collect();
+                    let free = if free.is_empty() { candidates } else { free };
+                    let position = safest_spawn(player, entrant.team, &tanks, &free);
+                    taken.push(position);
+                    let (archetype, team) = (entrant.archetype, entrant.team);
+                    updater.exec_mut(move |world| {
+                        game::spawn_player_tank(world, player, position, archetype, team)
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        state.outcome = state.judge(&scoreboard, tick);
+    }
+}
+
+/// Whichever of `candidates` is furthest from the nearest of `player`'s enemies, the first if it has none left
+fn safest_spawn(
+    player: PlayerId,
+    team: Option<Team>,
+    tanks: &[(PlayerId, Point, Option<Team>)],
+    candidates: &[Point],
+) -> Point {
+    let enemies: Vec<Point> = tanks
+        .iter()
+        .filter(|(owner, _, other)| {
+            *owner != player && !damage::friendly(team.as_ref(), other.as_ref())
+        })
+        .map(|&(_, position, _)| position)
+        .collect();
+    let clearance = |point: Point| {
+        enemies
+            .iter()
+            .map(|&enemy| {
+                let offset = enemy - point;
+                offset.x().pow(2) + offset.y().pow(2)
+            })
+            .min()
+    };
+    candidates
+        .iter()
+        .cloned()
+        .fold(candidates[0], |best, point| {
+            if clearance(point) > clearance(best) {
+                point
+            } else {
+                best
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Commands;
+
+    fn game(rules: Rules, teams: &[Option<u8>]) -> Match {
+        let entrants: Vec<(PlayerId, TankArchetype, Option<Team>)> = teams
+            .iter()
+            .enumerate()
+            .map(|(i, &team)| (i as PlayerId + 1, TankArchetype::default(), team.map(Team)))
+            .collect();
+        Match::new(rules, &entrants)
+    }
+
+    fn scoreboard(kills: &[u32]) -> Scoreboard {
+        let mut scoreboard = Scoreboard::default();
+        for (i, &kills) in kills.iter().enumerate() {
+            scoreboard
+                .players
+                .entry(i as PlayerId + 1)
+                .or_default()
+                .kills = kills;
+        }
+        scoreboard
+    }
+
+    #[test]
+    fn the_first_side_to_the_score_limit_wins() {
+        let game = game(Rules::new(GameMode::Deathmatch), &[None, None]);
+        assert_eq!(game.judge(&scoreboard(&[9, 3]), 0), None);
+        assert_eq!(
+            game.judge(&scoreboard(&[3, 10]), 0),
+            Some(Outcome::Won(Side::Player(2)))
+        );
+        // Tied on the limit, they play on
+        assert_eq!(game.judge(&scoreboard(&[10, 10]), 0), None);
+    }
+
+    #[test]
+    fn the_leader_wins_when_time_runs_out() {
+        let rules = Rules {
+            time_limit: Some(100),
+            ..Rules::new(GameMode::Deathmatch)
+        };
+        let game = game(rules, &[None, None]);
+        assert_eq!(game.judge(&scoreboard(&[2, 1]), 98), None);
+        assert_eq!(
+            game.judge(&scoreboard(&[2, 1]), 99),
+            Some(Outcome::Won(Side::Player(1)))
+        );
+        assert_eq!(game.judge(&scoreboard(&[2, 2]), 99), Some(Outcome::Draw));
+    }
+
+    #[test]
+    fn team_mates_score_together() {
+        let game = game(
+            Rules::new(GameMode::TeamDeathmatch),
+            &[Some(0), Some(1), Some(0)],
+        );
+        assert_eq!(game.judge(&scoreboard(&[12, 15, 7]), 0), None);
+        assert_eq!(
+            game.judge(&scoreboard(&[12, 15, 8]), 0),
+            Some(Outcome::Won(Side::Team(0)))
+        );
+    }
+
+    #[test]
+    fn elimination_ends_with_one_side_standing() {
+        let mut game = game(Rules::new(GameMode::Elimination), &[None, None, None]);
+        let scoreboard = Scoreboard::default();
+        assert_eq!(game.judge(&scoreboard, 0), None);
+        game.entrants.get_mut(&1).expect("entered").standing = false;
+        assert_eq!(game.judge(&scoreboard, 0), None);
+        game.entrants.get_mut(&3).expect("entered").standing = false;
+        assert_eq!(
+            game.judge(&scoreboard, 0),
+            Some(Outcome::Won(Side::Player(2)))
+        );
+        game.entrants.get_mut(&2).expect("entered").standing = false;
+        assert_eq!(game.judge(&scoreboard, 0), Some(Outcome::Draw));
+    }
+
+    #[test]
+    fn a_team_is_eliminated_once_all_its_tanks_are() {
+        let mut game = game(
+            Rules::new(GameMode::Elimination),
+            &[Some(0), Some(0), Some(1), Some(1)],
+        );
+        let scoreboard = Scoreboard::default();
+        for player in [1, 3] {
+            game.entrants.get_mut(&player).expect("entered").standing = false;
+        }
+        assert_eq!(game.judge(&scoreboard, 0), None);
+        game.entrants.get_mut(&4).expect("entered").standing = false;
+        assert_eq!(
+            game.judge(&scoreboard, 0),
+            Some(Outcome::Won(Side::Team(0)))
+        );
+    }
+
+    #[test]
+    fn tanks_come_back_furthest_from_their_enemies() {
+        let candidates = [Point::new(-300, 0), Point::new(300, 0), Point::new(0, 200)];
+        let tanks = [
+            (2, Point::new(250, 0), Some(Team(1))),
+            (3, Point::new(0, 100), Some(Team(1))),
+            (4, Point::new(-250, 0), Some(Team(0))),
+        ];
+        // The team mate next to the first point doesn't keep it away from there
+        let position = safest_spawn(1, Some(Team(0)), &tanks, &candidates);
+        assert_eq!(position, Point::new(-300, 0));
+        let position = safest_spawn(1, Some(Team(2)), &tanks, &candidates);
+        assert_eq!(position, Point::new(0, 200));
+        assert_eq!(safest_spawn(1, None, &[], &candidates), candidates[0]);
+    }
+
+    #[test]
+    fn tanks_coming_back_together_get_a_point_each() {
+        let rules = Rules::new(GameMode::Deathmatch);
+        let entrants: Vec<(PlayerId, TankArchetype, Option<Team>)> = (1..=4)
+            .map(|player| (player, TankArchetype::default(), None))
+            .collect();
+        let mut dispatcher = game::build_dispatcher();
+        let mut world = game::create_game(&mut dispatcher, 1, rules, &entrants);
+        {
+            let controlled = world.read_storage::<KeyboardControlled>();
+            let mut healths = world.write_storage::<Health>();
+            for (control, health) in (&controlled, &mut healths).join() {
+                if control.player <= 2 {
+                    health.current = 0;
+                }
+            }
+        }
+        // Check where they come back before they have had a tick to push each other apart
+        let back = |world: &World| -> Vec<Point> {
+            let controlled = world.read_storage::<KeyboardControlled>();
+            let positions = world.read_storage::<Position>();
+            let healths = world.read_storage::<Health>();
+            (&controlled, &positions, &healths)
+                .join()
+                .filter(|(control, _, _)| control.player <= 2)
+                .map(|(_, pos, _)| pos.0)
+                .collect()
+        };
+        game::step(&mut world, &mut dispatcher, Commands::default());
+        assert!(back(&world).is_empty());
+        while back(&world).is_empty() {
+            game::step(&mut world, &mut dispatcher, Commands::default());
+        }
+        let positions = back(&world);
+        assert_eq!(positions.len(), 2);
+        assert_ne!(positions[0], positions[1]);
+    }
+}
@@ -0,0 +1,569 @@
+use crate::commands::MovementCommand;
+use crate::components::Angle;
+use crate::components::Collider;
+use crate::components::Engine;
+use crate::components::Position;
+use crate::components::Velocity;
+use crate::components::Wall;
+use crate::geometry::{Point, Rect};
+use crate::ricochet::overlap;
+use crate::terrain::Terrain;
+use specs::join::Join;
+use specs::prelude::{World, WorldExt};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::rc::Rc;
+
+/// The side of a square of the navigation grid, half a wall tile so paths can hug obstacles
+const NAV_CELL: i32 = 16;
+
+/// What a step to a neighbouring square costs on plain ground, straight and diagonally
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+/// The cheapest a step can ever be, on the fastest ground, which keeps the A* estimate from overshooting
+const CHEAPEST_STRAIGHT: u32 = 7;
+const CHEAPEST_DIAGONAL: u32 = 10;
+
+/// Paths found are kept for asking again until there are this many, then they are all forgotten
+const MAX_CACHED_PATHS: usize = 256;
+
+/// A waypoint counts as reached this close, or closer than the tank moves in a tick
+const WAYPOINT_RADIUS: f32 = 12.0;
+
+type Node = (i32, i32);
+
+/// Where a tank of a given size can drive, as a grid of squares it can be centred on
+pub struct NavGrid {
+    bounds: Rect,
+    columns: i32,
+    rows: i32,
+    /// What a step onto each square costs, nothing for those a tank can't be on
+    costs: Vec<Option<u32>>,
+}
+
+impl NavGrid {
+    /// The grid over `world`'s terrain for a tank with `clearance`, avoiding its walls and water
+    pub fn build(world: &World, clearance: &Collider) -> Self {
+        let terrain = world.read_resource::<Terrain>();
+        let positions = world.read_storage::<Position>();
+        let colliders = world.read_storage::<Collider>();
+        let walls = world.read_storage::<Wall>();
+        let solids: Vec<Rect> = (&positions, &colliders, &walls)
+            .join()
+            .map(|(pos, collider, _)| collider.rect(pos.0))
+            .collect();
+
+        let bounds = terrain.bounds();
+        let columns = bounds.width() as i32 / NAV_CELL;
+        let rows = bounds.height() as i32 / NAV_CELL;
+        let mut grid = NavGrid {
+            bounds,
+            columns,
+            rows,
+            costs: Vec::with_capacity((columns * rows) as usize),
+        };
+        for row in 0..rows {
+            for column in 0..columns {
+                let centre = grid.centre((column, row));
+                let ground = terrain.at(centre);
+                let tank = clearance.rect(centre);
+                let clear =
+                    ground.passable() && solids.iter().all(|&solid| overlap(solid, tank).is_none());
+                // Slow ground costs more to cross
+                grid.costs.push(
+                    Some(STRAIGHT_COST)
+                        .filter(|_| clear)
+                        .map(|cost| (cost as f32 / ground.speed()).round() as u32),
+                );
+            }
+        }
+        grid
+    }
+
+    fn centre(&self, (column, row): Node) -> Point {
+        Point::new(
+            self.bounds.left() + column * NAV_CELL + NAV_CELL / 2,
+            self.bounds.top() + row * NAV_CELL + NAV_CELL / 2,
+        )
+    }
+
+    fn node_at(&self, point: Point) -> Node {
+        (
+            (point.x() - self.bounds.left()).div_euclid(NAV_CELL),
+            (point.y() - self.bounds.top()).div_euclid(NAV_CELL),
+        )
+    }
+
+    /// What a straight step onto `node` costs, if a tank can be there
+    fn cost(&self, (column, row): Node) -> Option<u32> {
+        if column < 0 || row < 0 || column >= self.columns || row >= self.rows {
+            return None;
+        }
+        self.costs[(row * self.columns + column) as usize]
+    }
+
+    /// The squares a tank can step to from `node` and what each step costs.
+    /// Diagonal steps can't cut the corner of something in the way.
+    fn neighbours(&self, (column, row): Node) -> impl Iterator<Item = (Node, u32)> + '_ {
+        const STEPS: [(i32, i32); 8] = [
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (1, -1),
+            (-1, 1),
+            (-1, -1),
+        ];
+        STEPS.iter().filter_map(move |&(dx, dy)| {
+            let next = (column + dx, row + dy);
+            let cost = self.cost(next)?;
+            if dx != 0 && dy != 0 {
+                self.cost((column + dx, row))?;
+                self.cost((column, row + dy))?;
+                Some((next, cost * DIAGONAL_COST / STRAIGHT_COST))
+            } else {
+                Some((next, cost))
+            }
+        })
+    }
+
+    /// The nearest square to `point` a tank can be on, going out a ring at a time
+    fn nearest_open(&self, point: Point) -> Option<Node> {
+        let (column, row) = self.node_at(point);
+        (0..self.columns.max(self.rows)).find_map(|ring| {
+            let mut ring_nodes: Vec<Node> = (-ring..=ring)
+                .flat_map(|dy| (-ring..=ring).map(move |dx| (dx, dy)))
+                .filter(|&(dx, dy)| dx.abs() == ring || dy.abs() == ring)
+                .map(|(dx, dy)| (column + dx, row + dy))
+                .filter(|&node| self.cost(node).is_some())
+                .collect();
+            ring_nodes.sort_by_key(|&node| {
+                let offset = self.centre(node) - point;
+                (offset.x().pow(2) + offset.y().pow(2), node)
+            });
+            ring_nodes.first().cloned()
+        })
+    }
+
+    /// The cheapest path between two squares, both ends included
+    fn search(&self, start: Node, goal: Node) -> Option<Vec<Node>> {
+        let estimate = |(column, row): Node| {
+            let (dx, dy) = ((goal.0 - column).abs(), (goal.1 - row).abs());
+            let (long, short) = (dx.max(dy) as u32, dx.min(dy) as u32);
+            CHEAPEST_STRAIGHT * (long - short) + CHEAPEST_DIAGONAL * short
+        };
+        let mut open = BinaryHeap::new();
+        let mut spent: HashMap<Node, u32> = HashMap::new();
+        let mut came_from: HashMap<Node, Node> = HashMap::new();
+        // Ties go to the square nearer the goal, then on position, so the same question
+        // always gets the same path
+        open.push(Reverse((estimate(start), estimate(start), start)));
+        spent.insert(start, 0);
+        while let Some(Reverse((_, _, node))) = open.pop() {
+            if node == goal {
+                let mut path = vec![goal];
+                while let Some(&previous) = came_from.get(path.last().expect("path is not empty")) {
+                    path.push(previous);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let so_far = spent[&node];
+            for (next, cost) in self.neighbours(node) {
+                let total = so_far + cost;
+                if spent.get(&next).is_some_and(|&known| known <= total) {
+                    continue;
+                }
+                spent.insert(next, total);
+                came_from.insert(next, node);
+                open.push(Reverse((total + estimate(next), estimate(next), next)));
+            }
+        }
+        None
+    }
+
+    /// Whether a tank can drive straight from `from` to `to` without leaving the open squares
+    /// or crossing slower ground than it starts on
+    fn clear_line(&self, from: Point, to: Point) -> bool {
+        let start = self.cost(self.node_at(from));
+        let offset = to - from;
+        let length = (offset.x() as f32).hypot(offset.y() as f32);
+        let samples = (length / (NAV_CELL / 2) as f32).ceil().max(1.0) as i32;
+        (0..=samples).all(|i| {
+            let t = i as f32 / samples as f32;
+            let point = from
+                + Point::new(
+                    (offset.x() as f32 * t).round() as i32,
+                    (offset.y() as f32 * t).round() as i32,
+                );
+            let cost = self.cost(self.node_at(point));
+            cost.is_some() && cost <= start
+        })
+    }
+
+    /// The waypoints of the cheapest path from `from` to `to`, leaving out those that can be driven
+    /// straight past. The path ends as near to `to` as a tank can get.
+    pub fn find_path(&self, from: Point, to: Point) -> Option<Vec<Point>> {
+        let start = self.nearest_open(from)?;
+        let goal = self.nearest_open(to)?;
+        let nodes = self.search(start, goal)?;
+        let mut waypoints = vec![self.centre(start)];
+        let mut i = 0;
+        while i + 1 < nodes.len() {
+            // The furthest square still in a straight line from the last waypoint, never
+            // smoothing over slow ground as that was worth going round
+            let mut next = i + 1;
+            while next + 1 < nodes.len()
+                && self.clear_line(self.centre(nodes[i]), self.centre(nodes[next + 1]))
+            {
+                next += 1;
+            }
+            waypoints.push(self.centre(nodes[next]));
+            i = next;
+        }
+        Some(waypoints)
+    }
+}
+
+/// Finds paths for tanks the size of `clearance`, remembering the answers until the walls change
+pub struct Navigator {
+    clearance: Collider,
+    grid: NavGrid,
+    /// How many walls were standing when the grid was built, barricades can be shot down
+    walls: usize,
+    cache: HashMap<(Node, Node), Option<Rc<Vec<Point>>>>,
+    /// Questions answered, and how many of those from the cache
+    pub asked: usize,
+    pub cached: usize,
+}
+
+impl Navigator {
+    pub fn new(world: &World, clearance: Collider) -> Self {
+        Navigator {
+            grid: NavGrid::build(world, &clearance),
+            walls: world.read_storage::<Wall>().join().count(),
+            clearance,
+            cache: HashMap::new(),
+            asked: 0,
+            cached: 0,
+        }
+    }
+
+    /// The waypoints from `from` to `to` in `world`, see `NavGrid::find_path`
+    pub fn path(&mut self, world: &World, from: Point, to: Point) -> Option<Rc<Vec<Point>>> {
+        let walls = world.read_storage::<Wall>().join().count();
+        if walls != self.walls {
+            self.grid = NavGrid::build(world, &self.clearance);
+            self.walls = walls;
+            self.cache.clear();
+        }
+        self.asked += 1;
+        // Asked from and to anywhere in the same squares, the answer is the same
+        let key = (self.grid.node_at(from), self.grid.node_at(to));
+        if let Some(path) = self.cache.get(&key) {
+            self.cached += 1;
+            return path.clone();
+        }
+        if self.cache.len() >= MAX_CACHED_PATHS {
+            self.cache.clear();
+        }
+        let path = self.grid.find_path(from, to).map(Rc::new);
+        self.cache.insert(key, path.clone());
+        path
+    }
+}
+
+/// Steers a tank along a path's waypoints, one after the other
+pub struct PathFollower {
+    waypoints: Rc<Vec<Point>>,
+    next: usize,
+}
+
+impl PathFollower {
+    pub fn new(waypoints: Rc<Vec<Point>>) -> Self {
+        PathFollower { waypoints, next: 0 }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.waypoints.len()
+    }
+
+    /// Where the tank at `position` moving at `vel` with `engine` should drive this tick to carry on
+    /// along the path. It lets go of the throttle in time to coast to a stop on the last waypoint.
+    pub fn steer(&mut self, position: Point, vel: &Velocity, engine: &Engine) -> MovementCommand {
+        let distance = |waypoint: Point| {
+            let offset = waypoint - position;
+            (offset.x() as f32).hypot(offset.y() as f32)
+        };
+        let reached = WAYPOINT_RADIUS.max(vel.speed);
+        while !self.finished() && distance(self.waypoints[self.next]) <= reached {
+            self.next += 1;
+        }
+        let waypoint = match self.waypoints.get(self.next) {
+            Some(&waypoint) => waypoint,
+            None => return MovementCommand::Stop,
+        };
+
+        let last = self.next + 1 == self.waypoints.len();
+        let stopping = if engine.friction > 0.0 {
+            vel.speed * vel.speed / (2.0 * engine.friction)
+        } else {
+            0.0
+        };
+        if last && distance(waypoint) <= stopping {
+            return MovementCommand::Stop;
+        }
+        let offset = waypoint - position;
+        MovementCommand::Move(Angle {
+            angle: (offset.y() as f32).atan2(offset.x() as f32).to_degrees(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{Commands, PlayerCommands, PlayerId};
+    use crate::components::KeyboardControlled;
+    use crate::game;
+    use crate::terrain::TerrainKind;
+    use specs::prelude::Builder;
+
+    /// An empty grass arena from -176 to 176 both ways
+    fn arena() -> World {
+        let mut world = World::new();
+        world.register::<Position>();
+        world.register::<Collider>();
+        world.register::<Wall>();
+        world.insert(Terrain::new(32, 320, 320));
+        world
+    }
+
+    /// A wall with its top left corner at `x` and `y`
+    fn wall(world: &mut World, x: i32, y: i32, width: u32, height: u32) -> specs::Entity {
+        let rect = Rect::new(x, y, width, height);
+        world
+            .create_entity()
+            .with(Position(rect.center()))
+            .with(Collider { width, height })
+            .with(Wall)
+            .build()
+    }
+
+    fn grid(world: &World) -> NavGrid {
+        NavGrid::build(world, &game::TANK_COLLIDER)
+    }
+
+    fn open(grid: &NavGrid, point: Point) -> bool {
+        grid.cost(grid.node_at(point)).is_some()
+    }
+
+    /// Whether a tank driven straight along each leg of `path` stays clear of `solid`
+    fn misses(path: &[Point], solid: Rect) -> bool {
+        path.windows(2).all(|leg| {
+            let offset = leg[1] - leg[0];
+            (0..=32).all(|i| {
+                let at = leg[0] + offset * i / 32;
+                !game::TANK_COLLIDER.rect(at).has_intersection(solid)
+            })
+        })
+    }
+
+    #[test]
+    fn paths_go_round_a_wall() {
+        let mut world = arena();
+        // From the top of the arena down to near the bottom, leaving a gap to go through
+        wall(&mut world, -16, -176, 32, 256);
+        let path = grid(&world)
+            .find_path(Point::new(-100, -100), Point::new(100, -100))
+            .expect("there is a way round");
+
+        assert_eq!(path.first(), Some(&Point::new(-104, -104)));
+        assert_eq!(path.last(), Some(&Point::new(104, -104)));
+        assert!(misses(&path, Rect::new(-16, -176, 32, 256)));
+        assert!(path.iter().any(|point| point.y() > 80));
+    }
+
+    #[test]
+    fn paths_go_straight_across_open_ground() {
+        let world = arena();
+        let path = grid(&world)
+            .find_path(Point::new(-100, -100), Point::new(100, 100))
+            .expect("nothing is in the way");
+        assert_eq!(path, vec![Point::new(-104, -104), Point::new(104, 104)]);
+    }
+
+    #[test]
+    fn there_is_no_path_into_an_enclosure() {
+        let mut world = arena();
+        // A 64 square pen at the bottom right with 16 thick walls
+        wall(&mut world, 48, 48, 96, 16);
+        wall(&mut world, 48, 128, 96, 16);
+        wall(&mut world, 48, 64, 16, 64);
+        wall(&mut world, 128, 64, 16, 64);
+        let grid = grid(&world);
+
+        assert!(open(&grid, Point::new(96, 96)));
+        assert_eq!(
+            grid.find_path(Point::new(-100, -100), Point::new(96, 96)),
+            None
+        );
+        assert_eq!(
+            grid.find_path(Point::new(96, 96), Point::new(-100, -100)),
+            None
+        );
+    }
+
+    #[test]
+    fn gaps_narrower_than_a_tank_are_closed() {
+        let mut world = arena();
+        // Across the middle with a 20 wide gap on the left and a 48 wide one on the right
+        wall(&mut world, -176, -4, 76, 8);
+        wall(&mut world, -80, -4, 128, 8);
+        wall(&mut world, 96, -4, 80, 8);
+        let grid = grid(&world);
+
+        assert!(!open(&grid, Point::new(-88, 0)));
+        assert!(open(&grid, Point::new(72, 0)));
+        // Nor can a tank be centred any closer to a wall than half its width
+        assert!(!open(&grid, Point::new(-120, 8)));
+        assert!(open(&grid, Point::new(-120, 24)));
+
+        let path = grid
+            .find_path(Point::new(-88, -100), Point::new(-88, 100))
+            .expect("there is a way through the wide gap");
+        assert!(path.iter().any(|point| point.x() > 48));
+    }
+
+    #[test]
+    fn water_is_closed_and_mud_is_gone_round() {
+        let world = arena();
+        {
+            let mut terrain = world.write_resource::<Terrain>();
+            terrain.paint(TerrainKind::Water, (-5, 3), (5, 3));
+            terrain.paint(TerrainKind::Mud, (0, -5), (3, -1));
+        }
+        let grid = grid(&world);
+        assert!(!open(&grid, Point::new(0, 96)));
+        assert_eq!(grid.find_path(Point::new(0, 0), Point::new(0, 150)), None);
+
+        // Straight through the mud is shorter, round the bottom of it is quicker
+        let path = grid
+            .find_path(Point::new(-112, -72), Point::new(144, -72))
+            .expect("the mud can be crossed");
+        assert!(path.iter().any(|point| point.y() > -16));
+    }
+
+    #[test]
+    fn asking_again_gets_the_cached_path() {
+        let mut world = arena();
+        let barricade = wall(&mut world, -16, -176, 32, 256);
+        let mut navigator = Navigator::new(&world, game::TANK_COLLIDER);
+
+        let path = navigator
+            .path(&world, Point::new(-100, -100), Point::new(100, -100))
+            .expect("there is a way round");
+        // Anywhere in the same squares is the same question
+        let again = navigator
+            .path(&world, Point::new(-97, -110), Point::new(99, -98))
+            .expect("there is still a way round");
+        assert!(Rc::ptr_eq(&path, &again));
+        assert_eq!((navigator.asked, navigator.cached), (2, 1));
+
+        // Once the barricade comes down the way is straight across
+        world.delete_entity(barricade).expect("barricade is alive");
+        world.maintain();
+        let straight = navigator
+            .path(&world, Point::new(-100, -100), Point::new(100, -100))
+            .expect("nothing is in the way");
+        assert_eq!(
+            *straight,
+            vec![Point::new(-104, -104), Point::new(104, -104)]
+        );
+        assert_eq!((navigator.asked, navigator.cached), (3, 1));
+    }
+
+    /// Drive the lone tank in `world` along `path` with a `PathFollower` until it comes to a stop,
+    /// returning where it stopped, the waypoints it passed, and how long it took
+    fn follow(
+        world: &mut World,
+        dispatcher: &mut specs::Dispatcher,
+        player: PlayerId,
+        path: Rc<Vec<Point>>,
+    ) -> (Point, usize, u64) {
+        let hull = |world: &World| {
+            let positions = world.read_storage::<Position>();
+            let velocities = world.read_storage::<Velocity>();
+            let engines = world.read_storage::<Engine>();
+            let controlled = world.read_storage::<KeyboardControlled>();
+            (&positions, &velocities, &engines, &controlled)
+                .join()
+                .find(|(_, _, _, control)| control.player == player)
+                .map(|(pos, vel, engine, _)| (pos.0, vel.clone(), engine.clone()))
+                .expect("the tank is there")
+        };
+        let mut follower = PathFollower::new(path.clone());
+        let mut passed = 0;
+        for ticks in 0..400 {
+            let (pos, vel, engine) = hull(world);
+            // Every waypoint is driven close by on the way
+            while passed < path.len() && {
+                let offset = path[passed] - pos;
+                offset.x().abs() <= 2 * NAV_CELL && offset.y().abs() <= 2 * NAV_CELL
+            } {
+                passed += 1;
+            }
+            if follower.finished() && vel.speed == 0.0 {
+                return (pos, passed, ticks);
+            }
+            let mut commands = Commands::default();
+            commands.0.insert(
+                player,
+                PlayerCommands {
+                    movement: Some(follower.steer(pos, &vel, &engine)),
+                    ..PlayerCommands::default()
+                },
+            );
+            game::step(world, dispatcher, commands);
+        }
+        panic!("still going along {:?} after 400 ticks", path);
+    }
+
+    #[test]
+    fn tanks_follow_paths_round_the_arena() {
+        const PLAYER: PlayerId = 0;
+        // Behind the north barricade, past the pillars, the far side of the water and back
+        const GOALS: [(i32, i32); 6] = [
+            (0, -200),
+            (300, -220),
+            (300, 200),
+            (-300, 220),
+            (-300, 0),
+            (0, -200),
+        ];
+        let mut dispatcher = game::build_dispatcher();
+        let mut world = game::create_match(&mut dispatcher, 1, &[PLAYER]);
+        let mut navigator = Navigator::new(&world, game::TANK_COLLIDER);
+        let mut start = game::spawn_point(PLAYER);
+
+        for &(x, y) in GOALS.iter() {
+            let goal = Point::new(x, y);
+            let path = navigator
+                .path(&world, start, goal)
+                .unwrap_or_else(|| panic!("no path from {:?} to {:?}", start, goal));
+            let (stopped, passed, _) = follow(&mut world, &mut dispatcher, PLAYER, path.clone());
+            assert_eq!(passed, path.len(), "missed a waypoint of {:?}", path);
+            // As near as a tank can get may be a little way off
+            let offset = goal - stopped;
+            assert!(
+                offset.x().abs() <= 32 && offset.y().abs() <= 32,
+                "stopped at {:?} short of {:?}",
+                stopped,
+                goal
+            );
+            start = stopped;
+        }
+    }
+}
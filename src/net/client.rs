@@ -0,0 +1,570 @@
+use crate::archetype::TankArchetype;
+use crate::commands::{Commands, PlayerCommands, PlayerId};
+use crate::components::{
+    Angle, AngularVelocity, Emitter, Engine, Health, KeyboardControlled, Position, Sprite, Team,
+    Velocity,
+};
+use crate::damage::EXPLOSION_SPRITE;
+use crate::fog::Fog;
+use crate::game;
+use crate::geometry::Point;
+use crate::net::protocol::{
+    ClientMessage, Controls, EntityState, NetId, ServerMessage, WorldState, MAX_PACKET_SIZE,
+};
+use crate::particles::{EffectKind, Effects};
+use crate::pickup::PICKUP_SPRITE;
+use crate::resources::Tick;
+use specs::prelude::{Component, Dispatcher, Entity, SystemData, World, WorldExt, WriteStorage};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How far behind the newest snapshot remote entities are drawn, in ticks.
+/// Two ticks leaves room for one lost or late snapshot without stuttering.
+const INTERPOLATION_DELAY: f32 = 2.0;
+
+/// If the render clock drifts this far from where it should be it is reset
+const MAX_CLOCK_DRIFT: f32 = 5.0;
+
+/// How many received snapshots are kept for interpolation and as delta baselines
+const SNAPSHOT_BUFFER: usize = 32;
+
+/// Unacknowledged inputs beyond this are dropped rather than resent
+const MAX_PENDING_INPUTS: usize = 32;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const HELLO_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A connection to a server plus a world, used only for rendering, holding what it last told us.
+/// Our own tank is predicted from our inputs rather than waiting for the server to confirm them.
+pub struct Client<'a, 'b> {
+    socket: UdpSocket,
+    pub player: PlayerId,
+    pub archetype: TankArchetype,
+    next_sequence: u32,
+    /// Inputs sent but not yet applied by the server, oldest first
+    pending: VecDeque<(u32, PlayerCommands)>,
+    snapshots: BTreeMap<u32, WorldState>,
+    /// The tick currently drawn, fractional between two snapshots
+    render_tick: Option<f32>,
+    world: World,
+    entities: HashMap<NetId, Entity>,
+    /// A world holding only our own tank, simulated ahead of the server
+    predicted: World,
+    prediction: Dispatcher<'a, 'b>,
+    predicted_entities: HashMap<NetId, Entity>,
+    /// What our tank can see, the server only sends the enemies in it
+    fog: Fog,
+}
+
+impl<'a, 'b> Client<'a, 'b> {
+    /// Join the server at `addr`, waiting until it welcomes us. A lobby tells us which seat to ask for.
+    pub fn connect(addr: &str, seat: Option<PlayerId>) -> Result<Self, String> {
+        let server: SocketAddr = addr
+            .to_socket_addrs()
+            .map_err(|e| format!("bad server address {}: {}", addr, e))?
+            .next()
+            .ok_or(format!("bad server address {}", addr))?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|e| e.to_string())?;
+        socket.connect(server).map_err(|e| e.to_string())?;
+        socket
+            .set_read_timeout(Some(HELLO_INTERVAL))
+            .map_err(|e| e.to_string())?;
+
+        let started = Instant::now();
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let (player, archetype) = loop {
+            if started.elapsed() > CONNECT_TIMEOUT {
+                return Err(format!("no answer from {}", server));
+            }
+            socket
+                .send(&ClientMessage::Hello { seat }.encode())
+                .map_err(|e| e.to_string())?;
+            match socket.recv(&mut buffer) {
+                Ok(size) => {
+                    if let Some(ServerMessage::Welcome { player, archetype }) =
+                        ServerMessage::decode(&buffer[..size])
+                    {
+                        break (player, archetype);
+                    }
+                }
+                Err(ref e)
+                    if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                // The server isn't up yet
+                Err(ref e) if e.kind() == ErrorKind::ConnectionRefused => {
+                    ::std::thread::sleep(HELLO_INTERVAL)
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        };
+        Self::welcomed(socket, player, archetype)
+    }
+
+    /// Start playing as `player` once the server at the other end of `socket` has welcomed us
+    fn welcomed(
+        socket: UdpSocket,
+        player: PlayerId,
+        archetype: TankArchetype,
+    ) -> Result<Self, String> {
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        let mut world = World::new();
+        game::DrawData::setup(&mut world);
+        world.register::<Velocity>();
+        world.register::<Emitter>();
+        world.insert(Tick::default());
+        world.insert(Effects::default());
+        world.insert(game::arena_terrain());
+        let mut prediction = game::build_prediction_dispatcher();
+        let mut predicted = game::create_arena(&mut prediction, 0);
+        // Our tank slides along the walls as it will on the server, though it can't know
+        // which barricades have been shot down
+        predicted.register::<Health>();
+        predicted.register::<Team>();
+        game::spawn_walls(&mut predicted);
+        Ok(Client {
+            socket,
+            player,
+            archetype,
+            next_sequence: 0,
+            pending: VecDeque::new(),
+            snapshots: BTreeMap::new(),
+            render_tick: None,
+            world,
+            entities: HashMap::new(),
+            predicted,
+            prediction,
+            predicted_entities: HashMap::new(),
+            fog: Fog::new(player),
+        })
+    }
+
+    /// The world as it should be drawn this frame
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// What we can see of the world, from where our tank is predicted to be
+    pub fn fog(&self) -> &Fog {
+        &self.fog
+    }
+
+    /// Send this tick's commands along with every earlier one the server hasn't applied yet
+    pub fn send_input(&mut self, commands: PlayerCommands) {
+        self.pending.push_back((self.next_sequence, commands));
+        self.next_sequence += 1;
+        while self.pending.len() > MAX_PENDING_INPUTS {
+            self.pending.pop_front();
+        }
+
+        let message = ClientMessage::Input {
+            ack: self.snapshots.keys().next_back().cloned(),
+            first_sequence: self.pending.front().map_or(0, |(sequence, _)| *sequence),
+            commands: self.pending.iter().map(|(_, commands)| *commands).collect(),
+        };
+        // UDP is unreliable anyway, and the input is resent until acknowledged
+        let _ = self.socket.send(&message.encode());
+    }
+
+    pub fn disconnect(&self) {
+        let _ = self.socket.send(&ClientMessage::Goodbye.encode());
+    }
+
+    /// Read every snapshot that has arrived
+    pub fn receive(&mut self) -> Result<(), String> {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let size = match self.socket.recv(&mut buffer) {
+                Ok(size) => size,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(ref e) if e.kind() == ErrorKind::ConnectionRefused => {
+                    return Err("lost connection to the server".to_string())
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            if let Some(ServerMessage::Snapshot(snapshot)) = ServerMessage::decode(&buffer[..size])
+            {
+                if self.snapshots.contains_key(&snapshot.tick) {
+                    continue;
+                }
+                let state = match snapshot.baseline {
+                    Some(baseline) => match self.snapshots.get(&baseline) {
+                        Some(baseline) => snapshot.apply(Some(baseline)),
+                        // We no longer have the baseline, wait for a newer snapshot
+                        None => continue,
+                    },
+                    None => snapshot.apply(None),
+                };
+                self.snapshots.insert(snapshot.tick, state);
+                if let Some(last_input) = snapshot.last_input {
+                    while self
+                        .pending
+                        .front()
+                        .is_some_and(|(sequence, _)| *sequence <= last_input)
+                    {
+                        self.pending.pop_front();
+                    }
+                }
+            }
+            while self.snapshots.len() > SNAPSHOT_BUFFER {
+                let oldest = *self.snapshots.keys().next().expect("buffer is not empty");
+                self.snapshots.remove(&oldest);
+            }
+        }
+    }
+
+    /// Advance the render clock by one tick and update the world to the interpolated state
+    pub fn interpolate(&mut self) {
+        let latest = match self.snapshots.keys().next_back() {
+            Some(&latest) => latest as f32,
+            None => return,
+        };
+        let target = latest - INTERPOLATION_DELAY;
+        let render_tick = match self.render_tick {
+            Some(tick) if (tick + 1.0 - target).abs() <= MAX_CLOCK_DRIFT => tick + 1.0,
+            _ => target,
+        };
+        self.render_tick = Some(render_tick);
+
+        // Remote entities are drawn in the past, our own tank slightly in the future
+        let mut state = self.state_at(render_tick);
+        state.retain(|_, entity_state| entity_state.controller != Some(self.player));
+        state.extend(self.predict());
+        self.fog.look(&self.predicted);
+
+        // The render world counts frames rather than server ticks, that is all its particles need
+        let tick = self.world.read_resource::<Tick>().0;
+        self.world.write_resource::<Effects>().start_tick(tick);
+        self.sync_world(&state);
+        self.world.write_resource::<Tick>().0 += 1;
+    }
+
+    /// Rewind our own tank to the newest authoritative state, then replay every input
+    /// the server hasn't applied yet to find where it will be once they arrive
+    fn predict(&mut self) -> WorldState {
+        let player = self.player;
+        let archetype = self.archetype;
+        let authoritative: WorldState = match self.snapshots.values().next_back() {
+            Some(latest) => latest
+                .iter()
+                .filter(|(_, entity_state)| entity_state.controller == Some(player))
+                .map(|(&id, entity_state)| (id, entity_state.clone()))
+                .collect(),
+            None => return WorldState::new(),
+        };
+
+        // Rewind
+        let world = &mut self.predicted;
+        let predicted_entities = &mut self.predicted_entities;
+        predicted_entities.retain(|id, &mut entity| {
+            let keep = authoritative.contains_key(id);
+            if !keep {
+                world.delete_entity(entity).expect("entity is alive");
+            }
+            keep
+        });
+        {
+            let world_entities = world.entities();
+            let mut positions = world.write_storage::<Position>();
+            let mut angles = world.write_storage::<Angle>();
+            let mut velocities = world.write_storage::<Velocity>();
+            let mut angular_velocities = world.write_storage::<AngularVelocity>();
+            let mut controlled = world.write_storage::<KeyboardControlled>();
+            let mut engines = world.write_storage::<Engine>();
+            for (id, entity_state) in &authoritative {
+                let entity = *predicted_entities
+                    .entry(*id)
+                    .or_insert_with(|| world_entities.create());
+                set(
+                    &mut positions,
+                    entity,
+                    Some(Position(entity_state.position)),
+                );
+                set(
+                    &mut angles,
+                    entity,
+                    Some(Angle {
+                        angle: entity_state.angle,
+                    }),
+                );
+                set(&mut velocities, entity, entity_state.velocity.clone());
+                set(
+                    &mut angular_velocities,
+                    entity,
+                    entity_state.angular_velocity.clone(),
+                );
+                set(
+                    &mut controlled,
+                    entity,
+                    Some(KeyboardControlled {
+                        player,
+                        speed: archetype.speed(),
+                        rotation_speed: archetype.rotation_speed(),
+                    }),
+                );
+                // The replay starts from where the inputs the server has applied left the engine
+                let controls = entity_state.controls.unwrap_or(Controls {
+                    throttle: None,
+                    turn: None,
+                });
+                set(
+                    &mut engines,
+                    entity,
+                    Some(Engine {
+                        throttle: controls.throttle,
+                        turn: controls.turn,
+                        ..archetype.engine()
+                    }),
+                );
+            }
+        }
+        world.maintain();
+
+        // Replay
+        for (_, player_commands) in &self.pending {
+            let mut commands = Commands::default();
+            commands.0.insert(player, *player_commands);
+            game::step(world, &mut self.prediction, commands);
+        }
+
+        let positions = world.read_storage::<Position>();
+        let angles = world.read_storage::<Angle>();
+        authoritative
+            .into_iter()
+            .map(|(id, mut entity_state)| {
+                let entity = predicted_entities[&id];
+                if let Some(pos) = positions.get(entity) {
+                    entity_state.position = pos.0;
+                }
+                if let Some(angle) = angles.get(entity) {
+                    entity_state.angle = angle.angle;
+                }
+                (id, entity_state)
+            })
+            .collect()
+    }
+
+    /// Interpolate between the snapshots either side of `tick`
+    fn state_at(&self, tick: f32) -> WorldState {
+        let before = self.snapshots.range(..=tick.floor() as u32).next_back();
+        let after = self.snapshots.range(tick.floor() as u32 + 1..).next();
+        match (before, after) {
+            (Some((&from_tick, from)), Some((&to_tick, to))) => {
+                let t = (tick - from_tick as f32) / (to_tick - from_tick) as f32;
+                from.iter()
+                    .map(|(&id, from)| match to.get(&id) {
+                        Some(to) => (id, lerp(from, to, t)),
+                        None => (id, from.clone()),
+                    })
+                    .collect()
+            }
+            (Some((_, state)), None) | (None, Some((_, state))) => state.clone(),
+            (None, None) => WorldState::new(),
+        }
+    }
+
+    /// Make the render world's entities match `state`.
+    /// The server doesn't say why an entity went away, so what it looked like decides the particles left behind.
+    /// Enemies also go away when they go out of sight, those leave nothing behind.
+    fn sync_world(&mut self, state: &WorldState) {
+        let world = &mut self.world;
+        let entities = &mut self.entities;
+        let fog = &self.fog;
+
+        entities.retain(|id, &mut entity| {
+            let keep = state.contains_key(id);
+            if !keep {
+                let effect = {
+                    let positions = world.read_storage::<Position>();
+                    let sprites = world.read_storage::<Sprite>();
+                    positions
+                        .get(entity)
+                        .zip(sprites.get(entity))
+                        .filter(|(pos, _)| fog.in_view(pos.0))
+                        .and_then(|(pos, sprite)| match sprite.spritesheet {
+                            game::TANK_BASE_SPRITE | game::WALL_SPRITE => {
+                                Some((EffectKind::Debris, pos.0))
+                            }
+                            PICKUP_SPRITE | EXPLOSION_SPRITE | game::TANK_TURRET_SPRITE => None,
+                            _ => Some((EffectKind::Sparks, pos.0)),
+                        })
+                };
+                if let Some((kind, position)) = effect {
+                    world.write_resource::<Effects>().add(kind, position);
+                }
+                world.delete_entity(entity).expect("entity is alive");
+            }
+            keep
+        });
+
+        {
+            let world_entities = world.entities();
+            let mut positions = world.write_storage::<Position>();
+            let mut angles = world.write_storage::<Angle>();
+            let mut sprites = world.write_storage::<Sprite>();
+            let mut velocities = world.write_storage::<Velocity>();
+            let mut emitters = world.write_storage::<Emitter>();
+            for (id, entity_state) in state {
+                let entity = *entities
+                    .entry(*id)
+                    .or_insert_with(|| world_entities.create());
+                let _ = positions.insert(entity, Position(entity_state.position));
+                let _ = angles.insert(
+                    entity,
+                    Angle {
+                        angle: entity_state.angle,
+                    },
+                );
+                let _ = sprites.insert(entity, entity_state.sprite.clone());
+                set(&mut velocities, entity, entity_state.velocity.clone());
+                let exhaust = entity_state.sprite.spritesheet == game::TANK_BASE_SPRITE;
+                set(
+                    &mut emitters,
+                    entity,
+                    Some(Emitter::exhaust()).filter(|_| exhaust),
+                );
+            }
+        }
+        world.maintain();
+    }
+}
+
+fn lerp(from: &EntityState, to: &EntityState, t: f32) -> EntityState {
+    let x = from.position.x() as f32 + (to.position.x() - from.position.x()) as f32 * t;
+    let y = from.position.y() as f32 + (to.position.y() - from.position.y()) as f32 * t;
+    // Turn whichever way round is shorter
+    let mut turn = (to.angle - from.angle) % 360.0;
+    if turn > 180.0 {
+        turn -= 360.0;
+    } else if turn < -180.0 {
+        turn += 360.0;
+    }
+    EntityState {
+        position: Point::new(x.round() as i32, y.round() as i32),
+        angle: from.angle + turn * t,
+        sprite: to.sprite.clone(),
+        controller: to.controller,
+        velocity: to.velocity.clone(),
+        angular_velocity: to.angular_velocity.clone(),
+        controls: to.controls,
+    }
+}
+
+/// Insert `component` for `entity`, or remove it when there is none
+fn set<T: Component>(storage: &mut WriteStorage<T>, entity: Entity, component: Option<T>) {
+    match component {
+        Some(component) => {
+            storage.insert(entity, component).expect("entity is alive");
+        }
+        None => {
+            storage.remove(entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{MovementCommand, RotationCommand};
+    use crate::components::Rotation;
+    use crate::net::server::Server;
+
+    /// Say hello to `server` on the loopback and let it welcome us
+    fn join<'a, 'b>(server: &mut Server) -> Client<'a, 'b> {
+        let port = server.local_addr().expect("server is bound").port();
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).expect("could bind a free port");
+        socket.connect(("127.0.0.1", port)).expect("could connect");
+        socket
+            .set_read_timeout(Some(CONNECT_TIMEOUT))
+            .expect("could set a timeout");
+        socket
+            .send(&ClientMessage::Hello { seat: None }.encode())
+            .expect("could send hello");
+        server.tick().expect("server ticks");
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let size = socket.recv(&mut buffer).expect("server answers");
+        match ServerMessage::decode(&buffer[..size]) {
+            Some(ServerMessage::Welcome { player, archetype }) => {
+                Client::welcomed(socket, player, archetype).expect("client starts")
+            }
+            other => panic!("expected a welcome, got {:?}", other),
+        }
+    }
+
+    /// Where the server last said our tank is
+    fn authoritative(client: &Client) -> WorldState {
+        client
+            .snapshots
+            .values()
+            .next_back()
+            .expect("a snapshot arrived")
+            .iter()
+            .filter(|(_, state)| state.controller == Some(client.player))
+            .map(|(&id, state)| (id, state.clone()))
+            .collect()
+    }
+
+    /// Prediction only moves and turns the tank, the rest is copied from the server
+    fn poses(state: &WorldState) -> Vec<(NetId, Point, f32)> {
+        state
+            .iter()
+            .map(|(&id, state)| (id, state.position, state.angle))
+            .collect()
+    }
+
+    /// Drive away from the wall, turn while moving, then stop and coast, so the engine's throttle and turn
+    /// are set by inputs the replay starts after
+    fn input(sequence: u32) -> PlayerCommands {
+        PlayerCommands {
+            movement: match sequence {
+                0 => Some(MovementCommand::Move(Angle { angle: 180.0 })),
+                12 => Some(MovementCommand::Stop),
+                _ => None,
+            },
+            rotation: match sequence {
+                5 => Some(RotationCommand::Move(Rotation::Clockwise)),
+                9 => Some(RotationCommand::Stop),
+                _ => None,
+            },
+            ..PlayerCommands::default()
+        }
+    }
+
+    #[test]
+    fn predictions_come_true_once_the_server_applies_the_inputs() {
+        let mut server = Server::bind(0, 3).expect("could bind a free port");
+        let mut client = join(&mut server);
+        client.receive().expect("snapshot arrives");
+
+        // The server applies one input a tick, so keeping three ahead leaves three pending
+        let lead = 3;
+        let mut predictions = Vec::new();
+        for sequence in 0..lead {
+            client.send_input(input(sequence));
+            predictions.push(poses(&client.predict()));
+        }
+        for sequence in lead..30 {
+            client.send_input(input(sequence));
+            predictions.push(poses(&client.predict()));
+            assert_eq!(client.pending.len(), lead as usize + 1);
+
+            server.tick().expect("server ticks");
+            client.receive().expect("snapshot arrives");
+            let applied = (sequence - lead) as usize;
+            assert_eq!(poses(&authoritative(&client)), predictions[applied]);
+        }
+
+        // Once every input is applied there is nothing left to replay
+        for _ in 0..lead {
+            server.tick().expect("server ticks");
+        }
+        client.receive().expect("snapshot arrives");
+        assert!(client.pending.is_empty());
+        assert_eq!(client.predict(), authoritative(&client));
+        assert_eq!(
+            &poses(&client.predict()),
+            predictions.last().expect("predicted")
+        );
+    }
+}
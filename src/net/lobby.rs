@@ -0,0 +1,595 @@
+use crate::archetype::TankArchetype;
+use crate::commands::PlayerId;
+use crate::components::Team;
+use crate::net::protocol::{LobbyMessage, LobbyPlayer, MAX_PACKET_SIZE};
+use crate::net::server::{self, Server};
+use crate::net::DEFAULT_PORT;
+use std::collections::BTreeMap;
+use std::io::{BufRead, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// How many players a lobby takes
+const MAX_PLAYERS: u8 = 8;
+
+/// Lobbies are searched for on this many ports from the default one
+const DISCOVERY_PORTS: u16 = 8;
+
+/// How long to wait for hosts to answer a search
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often members and the host tell each other they are still there
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Members or hosts we have not heard from for this long are gone
+const LOBBY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The start message is sent this many times as it must not be lost
+const START_REPEATS: usize = 5;
+
+/// What a player chose while waiting in a lobby
+#[derive(Debug, Clone)]
+pub struct Choices {
+    pub name: String,
+    pub archetype: TankArchetype,
+    pub team: u8,
+    pub ready: bool,
+}
+
+/// A lobby that answered a search
+#[derive(Debug, Clone)]
+pub struct Advert {
+    pub addr: SocketAddr,
+    pub name: String,
+    pub players: u8,
+    pub max_players: u8,
+}
+
+struct Member {
+    addr: SocketAddr,
+    player: LobbyPlayer,
+    last_heard: Instant,
+}
+
+/// Host a lobby on `port` until at least `min_players` have joined and all are ready,
+/// then serve the match on the same port with everyone in the seat they were given
+pub fn run_host(port: u16, name: &str, seed: u64, min_players: u8) -> Result<(), String> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))
+        .map_err(|e| format!("could not bind port {}: {}", port, e))?;
+    socket
+        .set_read_timeout(Some(UPDATE_INTERVAL / 5))
+        .map_err(|e| e.to_string())?;
+    println!("lobby {} open on {}", name, port);
+
+    let mut members: BTreeMap<PlayerId, Member> = BTreeMap::new();
+    let mut last_update = Instant::now();
+    let mut buffer = [0; MAX_PACKET_SIZE];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((size, from)) => {
+                if let Some(message) = LobbyMessage::decode(&buffer[..size]) {
+                    if handle(&socket, name, &mut members, from, message) {
+                        print_members(&members);
+                    }
+                }
+            }
+            Err(ref e)
+                if e.kind() == ErrorKind::WouldBlock
+                    || e.kind() == ErrorKind::TimedOut
+                    || e.kind() == ErrorKind::ConnectionReset => {}
+            Err(e) => return Err(e.to_string()),
+        }
+
+        if last_update.elapsed() < UPDATE_INTERVAL {
+            continue;
+        }
+        last_update = Instant::now();
+
+        let before = members.len();
+        members.retain(|_, member| member.last_heard.elapsed() < LOBBY_TIMEOUT);
+        if members.len() != before {
+            print_members(&members);
+        }
+
+        let players: Vec<LobbyPlayer> = members.values().map(|m| m.player.clone()).collect();
+        for member in members.values() {
+            let state = LobbyMessage::State {
+                you: member.player.player,
+                players: players.clone(),
+            };
+            let _ = socket.send_to(&state.encode(), member.addr);
+        }
+
+        if members.len() >= min_players.max(1) as usize && players.iter().all(|p| p.ready) {
+            break;
+        }
+    }
+
+    println!("starting with {} players", members.len());
+    for _ in 0..START_REPEATS {
+        for member in members.values() {
+            let _ = socket.send_to(&LobbyMessage::Start { seed }.encode(), member.addr);
+        }
+        ::std::thread::sleep(UPDATE_INTERVAL / 2);
+    }
+    drop(socket);
+
+    let mut server = Server::bind(port, seed)?;
+    for (&player, member) in &members {
+        server.reserve(player, member.player.archetype, Team(member.player.team));
+    }
+    server::serve(server)
+}
+
+/// Apply a message to the lobby, true if the members changed
+fn handle(
+    socket: &UdpSocket,
+    name: &str,
+    members: &mut BTreeMap<PlayerId, Member>,
+    from: SocketAddr,
+    message: LobbyMessage,
+) -> bool {
+    match message {
+        LobbyMessage::Discover => {
+            let advert = LobbyMessage::Advertise {
+                name: name.to_string(),
+                players: members.len() as u8,
+                max_players: MAX_PLAYERS,
+            };
+            let _ = socket.send_to(&advert.encode(), from);
+            false
+        }
+        LobbyMessage::Update {
+            name,
+            archetype,
+            team,
+            ready,
+        } => {
+            let existing = members
+                .iter()
+                .find(|(_, member)| member.addr == from)
+                .map(|(&player, _)| player);
+            let player = match existing {
+                Some(player) => player,
+                None => match (1..=MAX_PLAYERS).find(|id| !members.contains_key(id)) {
+                    Some(player) => player,
+                    None => return false, // the lobby is full
+                },
+            };
+            let updated = LobbyPlayer {
+                player,
+                name,
+                archetype,
+                team,
+                ready,
+            };
+            let changed = members.get(&player).map(|m| &m.player) != Some(&updated);
+            members.insert(
+                player,
+                Member {
+                    addr: from,
+                    player: updated,
+                    last_heard: Instant::now(),
+                },
+            );
+            changed
+        }
+        LobbyMessage::Leave => {
+            let before = members.len();
+            members.retain(|_, member| member.addr != from);
+            members.len() != before
+        }
+        _ => false,
+    }
+}
+
+fn print_members(members: &BTreeMap<PlayerId, Member>) {
+    let players: Vec<LobbyPlayer> = members.values().map(|m| m.player.clone()).collect();
+    print_players(&players, None);
+}
+
+fn print_players(players: &[LobbyPlayer], you: Option<PlayerId>) {
+    println!("{} in the lobby:", players.len());
+    for player in players {
+        println!(
+            "  {} {:<16} {:<7} team {} {}{}",
+            player.player,
+            player.name,
+            player.archetype,
+            player.team,
+            if player.ready { "ready" } else { "not ready" },
+            if Some(player.player) == you {
+                " (you)"
+            } else {
+                ""
+            },
+        );
+    }
+}
+
+/// Find lobbies on the local network and on this machine
+pub fn discover() -> Result<Vec<Advert>, String> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|e| e.to_string())?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+    socket
+        .set_read_timeout(Some(DISCOVERY_TIMEOUT / 10))
+        .map_err(|e| e.to_string())?;
+
+    let discover = LobbyMessage::Discover.encode();
+    for port in DEFAULT_PORT..DEFAULT_PORT + DISCOVERY_PORTS {
+        // Either may fail, a machine without a network can still find local lobbies
+        let _ = socket.send_to(&discover, ("255.255.255.255", port));
+        let _ = socket.send_to(&discover, ("127.0.0.1", port));
+    }
+
+    let started = Instant::now();
+    let mut adverts: Vec<Advert> = Vec::new();
+    let mut buffer = [0; MAX_PACKET_SIZE];
+    while started.elapsed() < DISCOVERY_TIMEOUT {
+        match socket.recv_from(&mut buffer) {
+            Ok((size, from)) => {
+                if let Some(LobbyMessage::Advertise {
+                    name,
+                    players,
+                    max_players,
+                }) = LobbyMessage::decode(&buffer[..size])
+                {
+                    // A host may hear a probe more than once. Hosts on different machines can share
+                    // a name and port, so only the address tells them apart.
+                    let seen = adverts.iter().any(|advert| advert.addr == from);
+                    if !seen {
+                        adverts.push(Advert {
+                            addr: from,
+                            name,
+                            players,
+                            max_players,
+                        });
+                    }
+                }
+            }
+            Err(ref e)
+                if e.kind() == ErrorKind::WouldBlock
+                    || e.kind() == ErrorKind::TimedOut
+                    || e.kind() == ErrorKind::ConnectionReset => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(adverts)
+}
+
+/// Search for lobbies and wait in one until its match starts, returning the address to play on
+/// and the seat we were given.
+/// `pick` chooses a lobby by its number in the list, otherwise the player is asked if there
+/// is more than one. While waiting, lines typed on stdin change our choices:
+/// `archetype <scout|medium|heavy>`, `team <n>`, `ready`, `unready` and `leave`.
+pub fn join(mut choices: Choices, pick: Option<usize>) -> Result<(SocketAddr, PlayerId), String> {
+    let lines = stdin_lines();
+
+    println!("searching for lobbies...");
+    let adverts = discover()?;
+    if adverts.is_empty() {
+        return Err("no lobbies found".to_string());
+    }
+    for (i, advert) in adverts.iter().enumerate() {
+        println!(
+            "{}: {} at {} ({}/{} players)",
+            i, advert.name, advert.addr, advert.players, advert.max_players
+        );
+    }
+    let index = match pick {
+        Some(index) => index,
+        None if adverts.len() == 1 => 0,
+        None => {
+            println!("pick a lobby:");
+            let line = lines.recv().map_err(|_| "no lobby picked".to_string())?;
+            line.trim()
+                .parse()
+                .map_err(|_| format!("bad lobby {}", line.trim()))?
+        }
+    };
+    let host = adverts
+        .get(index)
+        .ok_or(format!("there is no lobby {}", index))?
+        .addr;
+
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|e| e.to_string())?;
+    socket.connect(host).map_err(|e| e.to_string())?;
+    socket
+        .set_read_timeout(Some(UPDATE_INTERVAL / 5))
+        .map_err(|e| e.to_string())?;
+    println!(
+        "joined {}, type archetype, team, ready, unready or leave",
+        host
+    );
+
+    let mut last_heard = Instant::now();
+    let mut last_update: Option<Instant> = None;
+    let mut shown: Option<LobbyMessage> = None;
+    let mut buffer = [0; MAX_PACKET_SIZE];
+    loop {
+        match lines.try_recv() {
+            Ok(line) => {
+                if !apply_command(&mut choices, &line) {
+                    let _ = socket.send(&LobbyMessage::Leave.encode());
+                    return Err("left the lobby".to_string());
+                }
+                last_update = None;
+            }
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {}
+        }
+
+        if last_update.is_none_or(|last| last.elapsed() >= UPDATE_INTERVAL) {
+            last_update = Some(Instant::now());
+            let update = LobbyMessage::Update {
+                name: choices.name.clone(),
+                archetype: choices.archetype,
+                team: choices.team,
+                ready: choices.ready,
+            };
+            let _ = socket.send(&update.encode());
+        }
+
+        match socket.recv(&mut buffer) {
+            Ok(size) => match LobbyMessage::decode(&buffer[..size]) {
+                Some(LobbyMessage::Start { .. }) => {
+                    let seat = match shown {
+                        Some(LobbyMessage::State { you, .. }) => you,
+                        _ => return Err("the match started without us".to_string()),
+                    };
+                    return Ok((host, seat));
+                }
+                Some(state @ LobbyMessage::State { .. }) => {
+                    last_heard = Instant::now();
+                    if shown.as_ref() != Some(&state) {
+                        if let LobbyMessage::State { you, players } = &state {
+                            print_players(players, Some(*you));
+                        }
+                        shown = Some(state);
+                    }
+                }
+                _ => {}
+            },
+            Err(ref e)
+                if e.kind() == ErrorKind::WouldBlock
+                    || e.kind() == ErrorKind::TimedOut
+                    || e.kind() == ErrorKind::ConnectionRefused => {}
+            Err(e) => return Err(e.to_string()),
+        }
+
+        if last_heard.elapsed() > LOBBY_TIMEOUT {
+            return Err(format!("lost the lobby at {}", host));
+        }
+    }
+}
+
+/// Change our choices from a typed command, false if we want to leave
+fn apply_command(choices: &mut Choices, line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("archetype"), Some(name)) => match TankArchetype::from_name(name) {
+            Some(archetype) => choices.archetype = archetype,
+            None => println!("unknown archetype {}", name),
+        },
+        (Some("team"), Some(team)) => match team.parse() {
+            Ok(team) => choices.team = team,
+            Err(_) => println!("bad team {}", team),
+        },
+        (Some("ready"), None) => choices.ready = true,
+        (Some("unready"), None) => choices.ready = false,
+        (Some("leave"), None) => return false,
+        (None, _) => {}
+        _ => println!("unknown command {}", line.trim()),
+    }
+    true
+}
+
+/// Lines typed on stdin, read on their own thread so waiting for them doesn't block the lobby
+fn stdin_lines() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    ::std::thread::spawn(move || {
+        let stdin = ::std::io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn host() -> UdpSocket {
+        UdpSocket::bind(("127.0.0.1", 0)).expect("could bind a free port")
+    }
+
+    fn from(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+
+    fn update(name: &str, team: u8, ready: bool) -> LobbyMessage {
+        LobbyMessage::Update {
+            name: name.to_string(),
+            archetype: TankArchetype::Heavy,
+            team,
+            ready,
+        }
+    }
+
+    fn seats(members: &BTreeMap<PlayerId, Member>) -> Vec<(PlayerId, &str, bool)> {
+        members
+            .iter()
+            .map(|(&player, member)| (player, member.player.name.as_str(), member.player.ready))
+            .collect()
+    }
+
+    #[test]
+    fn players_join_change_their_choices_and_leave() {
+        let socket = host();
+        let mut members = BTreeMap::new();
+        assert!(handle(
+            &socket,
+            "den",
+            &mut members,
+            from(1),
+            update("ann", 0, false)
+        ));
+        assert!(handle(
+            &socket,
+            "den",
+            &mut members,
+            from(2),
+            update("bob", 1, false)
+        ));
+        assert_eq!(seats(&members), vec![(1, "ann", false), (2, "bob", false)]);
+
+        // The same update again changes nothing, a new choice does
+        assert!(!handle(
+            &socket,
+            "den",
+            &mut members,
+            from(1),
+            update("ann", 0, false)
+        ));
+        assert!(handle(
+            &socket,
+            "den",
+            &mut members,
+            from(1),
+            update("ann", 0, true)
+        ));
+        assert_eq!(seats(&members), vec![(1, "ann", true), (2, "bob", false)]);
+
+        assert!(handle(
+            &socket,
+            "den",
+            &mut members,
+            from(1),
+            LobbyMessage::Leave
+        ));
+        assert!(!handle(
+            &socket,
+            "den",
+            &mut members,
+            from(1),
+            LobbyMessage::Leave
+        ));
+        assert_eq!(seats(&members), vec![(2, "bob", false)]);
+
+        // A seat given up is given to the next to join
+        assert!(handle(
+            &socket,
+            "den",
+            &mut members,
+            from(3),
+            update("cat", 0, false)
+        ));
+        assert_eq!(seats(&members), vec![(1, "cat", false), (2, "bob", false)]);
+    }
+
+    #[test]
+    fn full_lobbies_turn_players_away() {
+        let socket = host();
+        let mut members = BTreeMap::new();
+        for port in 1..=u16::from(MAX_PLAYERS) {
+            assert!(handle(
+                &socket,
+                "den",
+                &mut members,
+                from(port),
+                update("p", 0, false)
+            ));
+        }
+        let late = from(u16::from(MAX_PLAYERS) + 1);
+        assert!(!handle(
+            &socket,
+            "den",
+            &mut members,
+            late,
+            update("late", 0, false)
+        ));
+        assert_eq!(members.len(), usize::from(MAX_PLAYERS));
+        assert!(members.values().all(|member| member.addr != late));
+    }
+
+    #[test]
+    fn searches_are_answered_with_the_lobby() {
+        let socket = host();
+        let player = host();
+        player
+            .set_read_timeout(Some(DISCOVERY_TIMEOUT))
+            .expect("could set a timeout");
+        let mut members = BTreeMap::new();
+        handle(
+            &socket,
+            "den",
+            &mut members,
+            from(1),
+            update("ann", 0, false),
+        );
+
+        let searcher = player.local_addr().expect("player is bound");
+        assert!(!handle(
+            &socket,
+            "den",
+            &mut members,
+            searcher,
+            LobbyMessage::Discover
+        ));
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let size = player.recv(&mut buffer).expect("host answers");
+        assert_eq!(
+            LobbyMessage::decode(&buffer[..size]),
+            Some(LobbyMessage::Advertise {
+                name: "den".to_string(),
+                players: 1,
+                max_players: MAX_PLAYERS,
+            })
+        );
+        // Searching doesn't join
+        assert_eq!(members.len(), 1);
+    }
+
+    #[test]
+    fn typed_commands_change_the_choices() {
+        let mut choices = Choices {
+            name: "ann".to_string(),
+            archetype: TankArchetype::Medium,
+            team: 0,
+            ready: false,
+        };
+        for line in ["archetype scout", "team 3", "ready", "", "  "] {
+            assert!(apply_command(&mut choices, line));
+        }
+        assert_eq!(choices.archetype, TankArchetype::Scout);
+        assert_eq!(choices.team, 3);
+        assert!(choices.ready);
+
+        // Mistakes are ignored
+        for line in [
+            "archetype tiger",
+            "team red",
+            "team 300",
+            "ready now",
+            "jump",
+        ] {
+            assert!(apply_command(&mut choices, line));
+        }
+        assert_eq!(choices.archetype, TankArchetype::Scout);
+        assert_eq!(choices.team, 3);
+        assert!(choices.ready);
+
+        assert!(apply_command(&mut choices, "unready"));
+        assert!(!choices.ready);
+        assert!(!apply_command(&mut choices, "leave"));
+    }
+}
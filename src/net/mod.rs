@@ -0,0 +1,27 @@
+//! Client-server multiplayer over UDP.
+//!
+//! The server runs the only authoritative simulation. Clients send their commands every tick
+//! and the server broadcasts delta compressed snapshots of the world, which clients
+//! interpolate between to draw remote entities smoothly.
+//!
+//! Matches can be gathered in a lobby first, found by broadcasting on the local network,
+//! where players pick their tank and team before the host starts serving.
+//!
+//! Two player matches can instead be played peer-to-peer with rollback, where both peers
+//! simulate everything and correct themselves when the other's inputs arrive.
+
+pub mod client;
+pub mod lobby;
+pub mod protocol;
+pub mod rollback;
+pub mod server;
+
+use crate::net::protocol::NetId;
+use specs::Entity;
+
+pub const DEFAULT_PORT: u16 = 7777;
+
+/// The wire id of a server entity; the generation makes ids of recycled entities distinct
+pub fn net_id(entity: Entity) -> NetId {
+    (u64::from(entity.gen().id() as u32) << 32) | u64::from(entity.id())
+}
@@ -0,0 +1,1038 @@
+use crate::archetype::TankArchetype;
+use crate::commands::{
+    FireCommand, MovementCommand, PlayerCommands, PlayerId, RotationCommand, WeaponCommand,
+};
+use crate::components::{Angle, AngularVelocity, Rotation, Sprite, Velocity};
+use crate::geometry::{Point, Rect};
+use std::collections::BTreeMap;
+
+/// Every datagram starts with these bytes so stray packets are ignored
+const MAGIC: [u8; 3] = *b"RT2";
+
+/// Lobby datagrams are kept apart from game ones, a lobby and a server may share a port
+const LOBBY_MAGIC: [u8; 3] = *b"RTL";
+
+/// Largest datagram we will send, comfortably below the UDP limit
+pub const MAX_PACKET_SIZE: usize = 60_000;
+
+/// Identifies an entity on the wire, built from the server's entity index and generation
+pub type NetId = u64;
+
+/// The replicated state of a single entity
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityState {
+    pub position: Point,
+    pub angle: f32,
+    pub sprite: Sprite,
+    /// The player driving this entity, if any
+    pub controller: Option<PlayerId>,
+    /// Needed by clients to predict their own tank's movement
+    pub velocity: Option<Velocity>,
+    pub angular_velocity: Option<AngularVelocity>,
+    /// Where the inputs the server has applied left the engine, only sent to its own driver
+    pub controls: Option<Controls>,
+}
+
+/// What a tank's driver is asking of its engine, kept from one input to the next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Controls {
+    pub throttle: Option<Angle>,
+    pub turn: Option<Rotation>,
+}
+
+/// The complete replicated state of the world on a given tick
+pub type WorldState = BTreeMap<NetId, EntityState>;
+
+// Bits of `EntityDelta::changed`
+const POSITION: u8 = 1;
+const ANGLE: u8 = 1 << 1;
+const SPRITE: u8 = 1 << 2;
+const CONTROLLER: u8 = 1 << 3;
+const VELOCITY: u8 = 1 << 4;
+const ANGULAR_VELOCITY: u8 = 1 << 5;
+const CONTROLS: u8 = 1 << 6;
+const ALL: u8 = POSITION | ANGLE | SPRITE | CONTROLLER | VELOCITY | ANGULAR_VELOCITY | CONTROLS;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientMessage {
+    /// Ask to join the match, taking the seat a lobby gave us if there was one
+    Hello {
+        seat: Option<PlayerId>,
+    },
+    /// Every input the server has not acknowledged yet, oldest first, so a lost packet costs nothing.
+    /// `ack` is the latest snapshot tick received, which the server uses as the delta baseline.
+    Input {
+        ack: Option<u32>,
+        first_sequence: u32,
+        commands: Vec<PlayerCommands>,
+    },
+    Goodbye,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+    /// Accepted, as this player driving this kind of tank
+    Welcome {
+        player: PlayerId,
+        archetype: TankArchetype,
+    },
+    Snapshot(Snapshot),
+}
+
+/// Sent between the two peers of a rollback match
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerMessage {
+    /// Every input the other peer has not acknowledged yet, for consecutive ticks from `first_tick`.
+    /// `ack` is the last tick up to which we hold all of the other peer's inputs.
+    Inputs {
+        ack: Option<u32>,
+        first_tick: u32,
+        commands: Vec<PlayerCommands>,
+    },
+}
+
+/// A player waiting in a lobby
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobbyPlayer {
+    pub player: PlayerId,
+    pub name: String,
+    pub archetype: TankArchetype,
+    pub team: u8,
+    pub ready: bool,
+}
+
+/// Sent between a lobby host and the players browsing or waiting in it
+#[derive(Debug, Clone, PartialEq)]
+pub enum LobbyMessage {
+    /// Broadcast by players looking for matches, hosts answer with `Advertise`
+    Discover,
+    Advertise {
+        name: String,
+        players: u8,
+        max_players: u8,
+    },
+    /// Join the lobby, or change our choices once in it. Resent until the match starts.
+    Update {
+        name: String,
+        archetype: TankArchetype,
+        team: u8,
+        ready: bool,
+    },
+    Leave,
+    /// Everyone in the lobby, and which of them the recipient is
+    State {
+        you: PlayerId,
+        players: Vec<LobbyPlayer>,
+    },
+    /// The match is starting on the host's port, take your seat
+    Start {
+        seed: u64,
+    },
+}
+
+/// A world state, delta compressed against an earlier snapshot the client acknowledged
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    /// The snapshot this one is relative to, None when it is a full snapshot
+    pub baseline: Option<u32>,
+    /// The sequence number of the last input the server applied for this client
+    pub last_input: Option<u32>,
+    pub changed: Vec<(NetId, EntityDelta)>,
+    pub removed: Vec<NetId>,
+}
+
+/// The fields of an entity that differ from the baseline
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityDelta {
+    changed: u8,
+    state: EntityState,
+}
+
+impl Snapshot {
+    /// Encode `state` relative to `baseline` (a full snapshot if there is none)
+    pub fn diff(
+        tick: u32,
+        last_input: Option<u32>,
+        state: &WorldState,
+        baseline: Option<(u32, &WorldState)>,
+    ) -> Snapshot {
+        let empty = WorldState::new();
+        let (baseline_tick, old) = match baseline {
+            Some((tick, old)) => (Some(tick), old),
+            None => (None, &empty),
+        };
+
+        let mut changed = Vec::new();
+        for (&id, new) in state {
+            let mut bits = 0;
+            match old.get(&id) {
+                Some(old) => {
+                    if old.position != new.position {
+                        bits |= POSITION;
+                    }
+                    if old.angle.to_bits() != new.angle.to_bits() {
+                        bits |= ANGLE;
+                    }
+                    if old.sprite != new.sprite {
+                        bits |= SPRITE;
+                    }
+                    if old.controller != new.controller {
+                        bits |= CONTROLLER;
+                    }
+                    if old.velocity != new.velocity {
+                        bits |= VELOCITY;
+                    }
+                    if old.angular_velocity != new.angular_velocity {
+                        bits |= ANGULAR_VELOCITY;
+                    }
+                    if old.controls != new.controls {
+                        bits |= CONTROLS;
+                    }
+                }
+                None => bits = ALL,
+            }
+            if bits != 0 {
+                changed.push((
+                    id,
+                    EntityDelta {
+                        changed: bits,
+                        state: new.clone(),
+                    },
+                ));
+            }
+        }
+        let removed = old
+            .keys()
+            .filter(|id| !state.contains_key(id))
+            .cloned()
+            .collect();
+
+        Snapshot {
+            tick,
+            baseline: baseline_tick,
+            last_input,
+            changed,
+            removed,
+        }
+    }
+
+    /// Rebuild the full world state, `baseline` must be the state of `self.baseline`
+    pub fn apply(&self, baseline: Option<&WorldState>) -> WorldState {
+        let mut state = baseline.cloned().unwrap_or_default();
+        for id in &self.removed {
+            state.remove(id);
+        }
+        for (id, delta) in &self.changed {
+            let entry = state.entry(*id).or_insert_with(|| delta.state.clone());
+            if delta.changed & POSITION != 0 {
+                entry.position = delta.state.position;
+            }
+            if delta.changed & ANGLE != 0 {
+                entry.angle = delta.state.angle;
+            }
+            if delta.changed & SPRITE != 0 {
+                entry.sprite = delta.state.sprite.clone();
+            }
+            if delta.changed & CONTROLLER != 0 {
+                entry.controller = delta.state.controller;
+            }
+            if delta.changed & VELOCITY != 0 {
+                entry.velocity = delta.state.velocity.clone();
+            }
+            if delta.changed & ANGULAR_VELOCITY != 0 {
+                entry.angular_velocity = delta.state.angular_velocity.clone();
+            }
+            if delta.changed & CONTROLS != 0 {
+                entry.controls = delta.state.controls;
+            }
+        }
+        state
+    }
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        match self {
+            ClientMessage::Hello { seat } => {
+                w.u8(0);
+                w.option_u8(*seat);
+            }
+            ClientMessage::Input {
+                ack,
+                first_sequence,
+                commands,
+            } => {
+                w.u8(1);
+                w.option_u32(*ack);
+                w.u32(*first_sequence);
+                w.u8(commands.len() as u8);
+                for command in commands {
+                    w.commands(command);
+                }
+            }
+            ClientMessage::Goodbye => w.u8(2),
+        }
+        w.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<ClientMessage> {
+        let mut r = Reader::new(bytes)?;
+        let message = match r.u8()? {
+            0 => ClientMessage::Hello {
+                seat: r.option_u8()?,
+            },
+            1 => {
+                let ack = r.option_u32()?;
+                let first_sequence = r.u32()?;
+                let count = r.u8()?;
+                let commands = (0..count)
+                    .map(|_| r.commands())
+                    .collect::<Option<Vec<_>>>()?;
+                ClientMessage::Input {
+                    ack,
+                    first_sequence,
+                    commands,
+                }
+            }
+            2 => ClientMessage::Goodbye,
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+impl ServerMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        match self {
+            ServerMessage::Welcome { player, archetype } => {
+                w.u8(0);
+                w.u8(*player);
+                w.u8(archetype.index());
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                w.u8(1);
+                w.u32(snapshot.tick);
+                w.option_u32(snapshot.baseline);
+                w.option_u32(snapshot.last_input);
+                w.u16(snapshot.changed.len() as u16);
+                for (id, delta) in &snapshot.changed {
+                    w.u64(*id);
+                    w.entity_delta(delta);
+                }
+                w.u16(snapshot.removed.len() as u16);
+                for id in &snapshot.removed {
+                    w.u64(*id);
+                }
+            }
+        }
+        w.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<ServerMessage> {
+        let mut r = Reader::new(bytes)?;
+        let message = match r.u8()? {
+            0 => ServerMessage::Welcome {
+                player: r.u8()?,
+                archetype: r.archetype()?,
+            },
+            1 => {
+                let tick = r.u32()?;
+                let baseline = r.option_u32()?;
+                let last_input = r.option_u32()?;
+                let changed_count = r.u16()?;
+                let changed = (0..changed_count)
+                    .map(|_| Some((r.u64()?, r.entity_delta()?)))
+                    .collect::<Option<Vec<_>>>()?;
+                let removed_count = r.u16()?;
+                let removed = (0..removed_count)
+                    .map(|_| r.u64())
+                    .collect::<Option<Vec<_>>>()?;
+                ServerMessage::Snapshot(Snapshot {
+                    tick,
+                    baseline,
+                    last_input,
+                    changed,
+                    removed,
+                })
+            }
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+impl PeerMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        match self {
+            PeerMessage::Inputs {
+                ack,
+                first_tick,
+                commands,
+            } => {
+                w.u8(0);
+                w.option_u32(*ack);
+                w.u32(*first_tick);
+                w.u8(commands.len() as u8);
+                for command in commands {
+                    w.commands(command);
+                }
+            }
+        }
+        w.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<PeerMessage> {
+        let mut r = Reader::new(bytes)?;
+        let message = match r.u8()? {
+            0 => {
+                let ack = r.option_u32()?;
+                let first_tick = r.u32()?;
+                let count = r.u8()?;
+                let commands = (0..count)
+                    .map(|_| r.commands())
+                    .collect::<Option<Vec<_>>>()?;
+                PeerMessage::Inputs {
+                    ack,
+                    first_tick,
+                    commands,
+                }
+            }
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+impl LobbyMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::with_magic(LOBBY_MAGIC);
+        match self {
+            LobbyMessage::Discover => w.u8(0),
+            LobbyMessage::Advertise {
+                name,
+                players,
+                max_players,
+            } => {
+                w.u8(1);
+                w.string(name);
+                w.u8(*players);
+                w.u8(*max_players);
+            }
+            LobbyMessage::Update {
+                name,
+                archetype,
+                team,
+                ready,
+            } => {
+                w.u8(2);
+                w.string(name);
+                w.u8(archetype.index());
+                w.u8(*team);
+                w.bool(*ready);
+            }
+            LobbyMessage::Leave => w.u8(3),
+            LobbyMessage::State { you, players } => {
+                w.u8(4);
+                w.u8(*you);
+                w.u8(players.len() as u8);
+                for player in players {
+                    w.u8(player.player);
+                    w.string(&player.name);
+                    w.u8(player.archetype.index());
+                    w.u8(player.team);
+                    w.bool(player.ready);
+                }
+            }
+            LobbyMessage::Start { seed } => {
+                w.u8(5);
+                w.u64(*seed);
+            }
+        }
+        w.finish()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<LobbyMessage> {
+        let mut r = Reader::with_magic(bytes, LOBBY_MAGIC)?;
+        let message = match r.u8()? {
+            0 => LobbyMessage::Discover,
+            1 => LobbyMessage::Advertise {
+                name: r.string()?,
+                players: r.u8()?,
+                max_players: r.u8()?,
+            },
+            2 => LobbyMessage::Update {
+                name: r.string()?,
+                archetype: r.archetype()?,
+                team: r.u8()?,
+                ready: r.bool()?,
+            },
+            3 => LobbyMessage::Leave,
+            4 => {
+                let you = r.u8()?;
+                let count = r.u8()?;
+                let players = (0..count)
+                    .map(|_| {
+                        Some(LobbyPlayer {
+                            player: r.u8()?,
+                            name: r.string()?,
+                            archetype: r.archetype()?,
+                            team: r.u8()?,
+                            ready: r.bool()?,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                LobbyMessage::State { you, players }
+            }
+            5 => LobbyMessage::Start { seed: r.u64()? },
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+/// Little endian encoder for the wire format
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Writer::with_magic(MAGIC)
+    }
+
+    fn with_magic(magic: [u8; 3]) -> Self {
+        Writer {
+            bytes: magic.to_vec(),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn option_u32(&mut self, value: Option<u32>) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                self.u32(value);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn option_u8(&mut self, value: Option<u8>) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                self.u8(value);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    /// At most 255 bytes of it, cut at a character boundary
+    fn string(&mut self, value: &str) {
+        let mut end = value.len().min(255);
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.u8(end as u8);
+        self.bytes.extend_from_slice(&value.as_bytes()[..end]);
+    }
+
+    fn commands(&mut self, commands: &PlayerCommands) {
+        match commands.movement {
+            None => self.u8(0),
+            Some(MovementCommand::Stop) => self.u8(1),
+            Some(MovementCommand::Move(direction)) => {
+                self.u8(2);
+                self.f32(direction.angle);
+            }
+        }
+        self.u8(match commands.rotation {
+            None => 0,
+            Some(RotationCommand::Stop) => 1,
+            Some(RotationCommand::Move(Rotation::Clockwise)) => 2,
+            Some(RotationCommand::Move(Rotation::CounterClockwise)) => 3,
+        });
+        self.u8(match commands.fire {
+            None => 0,
+            Some(FireCommand::Stop) => 1,
+            Some(FireCommand::Fire) => 2,
+        });
+        match commands.weapon {
+            None => self.u8(0),
+            Some(WeaponCommand::Next) => self.u8(1),
+            Some(WeaponCommand::Select(slot)) => {
+                self.u8(2);
+                self.u8(slot);
+            }
+        }
+    }
+
+    fn entity_delta(&mut self, delta: &EntityDelta) {
+        let state = &delta.state;
+        self.u8(delta.changed);
+        if delta.changed & POSITION != 0 {
+            self.i32(state.position.x());
+            self.i32(state.position.y());
+        }
+        if delta.changed & ANGLE != 0 {
+            self.f32(state.angle);
+        }
+        if delta.changed & SPRITE != 0 {
+            let region = state.sprite.region;
+            self.u8(state.sprite.spritesheet as u8);
+            self.i32(region.x());
+            self.i32(region.y());
+            self.u32(region.width());
+            self.u32(region.height());
+        }
+        if delta.changed & CONTROLLER != 0 {
+            match state.controller {
+                Some(player) => {
+                    self.u8(1);
+                    self.u8(player);
+                }
+                None => self.u8(0),
+            }
+        }
+        if delta.changed & VELOCITY != 0 {
+            match &state.velocity {
+                Some(velocity) => {
+                    self.u8(1);
+                    self.f32(velocity.speed);
+                    self.f32(velocity.direction.angle);
+                }
+                None => self.u8(0),
+            }
+        }
+        if delta.changed & ANGULAR_VELOCITY != 0 {
+            match &state.angular_velocity {
+                Some(angular_velocity) => {
+                    self.u8(1);
+                    self.f32(angular_velocity.speed);
+                    self.rotation(angular_velocity.rotation);
+                }
+                None => self.u8(0),
+            }
+        }
+        if delta.changed & CONTROLS != 0 {
+            match &state.controls {
+                Some(controls) => {
+                    self.u8(1);
+                    match controls.throttle {
+                        Some(direction) => {
+                            self.u8(1);
+                            self.f32(direction.angle);
+                        }
+                        None => self.u8(0),
+                    }
+                    match controls.turn {
+                        Some(rotation) => {
+                            self.u8(1);
+                            self.rotation(rotation);
+                        }
+                        None => self.u8(0),
+                    }
+                }
+                None => self.u8(0),
+            }
+        }
+    }
+
+    fn rotation(&mut self, rotation: Rotation) {
+        self.u8(match rotation {
+            Rotation::Clockwise => 0,
+            Rotation::CounterClockwise => 1,
+        });
+    }
+}
+
+/// Decoder for the wire format, every read returns None on truncated or malformed input
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Option<Self> {
+        Reader::with_magic(bytes, MAGIC)
+    }
+
+    fn with_magic(bytes: &'a [u8], magic: [u8; 3]) -> Option<Self> {
+        if bytes.len() < magic.len() || bytes[..magic.len()] != magic {
+            return None;
+        }
+        Some(Reader {
+            bytes: &bytes[magic.len()..],
+        })
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.bytes.len() < N {
+            return None;
+        }
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        let mut out = [0; N];
+        out.copy_from_slice(head);
+        Some(out)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        self.take().map(i32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+
+    fn option_u32(&mut self) -> Option<Option<u32>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => self.u32().map(Some),
+            _ => None,
+        }
+    }
+
+    fn option_u8(&mut self) -> Option<Option<u8>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => self.u8().map(Some),
+            _ => None,
+        }
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (text, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        String::from_utf8(text.to_vec()).ok()
+    }
+
+    fn archetype(&mut self) -> Option<TankArchetype> {
+        TankArchetype::from_index(self.u8()?)
+    }
+
+    fn rotation(&mut self) -> Option<Rotation> {
+        match self.u8()? {
+            0 => Some(Rotation::Clockwise),
+            1 => Some(Rotation::CounterClockwise),
+            _ => None,
+        }
+    }
+
+    fn commands(&mut self) -> Option<PlayerCommands> {
+        let movement = match self.u8()? {
+            0 => None,
+            1 => Some(MovementCommand::Stop),
+            2 => Some(MovementCommand::Move(Angle { angle: self.f32()? })),
+            _ => return None,
+        };
+        let rotation = match self.u8()? {
+            0 => None,
+            1 => Some(RotationCommand::Stop),
+            2 => Some(RotationCommand::Move(Rotation::Clockwise)),
+            3 => Some(RotationCommand::Move(Rotation::CounterClockwise)),
+            _ => return None,
+        };
+        let fire = match self.u8()? {
+            0 => None,
+            1 => Some(FireCommand::Stop),
+            2 => Some(FireCommand::Fire),
+            _ => return None,
+        };
+        let weapon = match self.u8()? {
+            0 => None,
+            1 => Some(WeaponCommand::Next),
+            2 => Some(WeaponCommand::Select(self.u8()?)),
+            _ => return None,
+        };
+        Some(PlayerCommands {
+            movement,
+            rotation,
+            fire,
+            weapon,
+        })
+    }
+
+    fn entity_delta(&mut self) -> Option<EntityDelta> {
+        let changed = self.u8()?;
+        let mut state = EntityState {
+            position: Point::new(0, 0),
+            angle: 0.0,
+            sprite: Sprite {
+                spritesheet: 0,
+                region: Rect::new(0, 0, 1, 1),
+            },
+            controller: None,
+            velocity: None,
+            angular_velocity: None,
+            controls: None,
+        };
+        if changed & POSITION != 0 {
+            state.position = Point::new(self.i32()?, self.i32()?);
+        }
+        if changed & ANGLE != 0 {
+            state.angle = self.f32()?;
+        }
+        if changed & SPRITE != 0 {
+            state.sprite = Sprite {
+                spritesheet: self.u8()? as usize,
+                region: Rect::new(self.i32()?, self.i32()?, self.u32()?, self.u32()?),
+            };
+        }
+        if changed & CONTROLLER != 0 {
+            state.controller = match self.u8()? {
+                0 => None,
+                1 => Some(self.u8()?),
+                _ => return None,
+            };
+        }
+        if changed & VELOCITY != 0 {
+            state.velocity = match self.u8()? {
+                0 => None,
+                1 => Some(Velocity {
+                    speed: self.f32()?,
+                    direction: Angle { angle: self.f32()? },
+                }),
+                _ => return None,
+            };
+        }
+        if changed & ANGULAR_VELOCITY != 0 {
+            state.angular_velocity = match self.u8()? {
+                0 => None,
+                1 => Some(AngularVelocity {
+                    speed: self.f32()?,
+                    rotation: self.rotation()?,
+                }),
+                _ => return None,
+            };
+        }
+        if changed & CONTROLS != 0 {
+            state.controls = match self.u8()? {
+                0 => None,
+                1 => Some(Controls {
+                    throttle: match self.u8()? {
+                        0 => None,
+                        1 => Some(Angle { angle: self.f32()? }),
+                        _ => return None,
+                    },
+                    turn: match self.u8()? {
+                        0 => None,
+                        1 => Some(self.rotation()?),
+                        _ => return None,
+                    },
+                }),
+                _ => return None,
+            };
+        }
+        Some(EntityDelta { changed, state })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tank(x: i32, player: PlayerId) -> EntityState {
+        EntityState {
+            position: Point::new(x, -40),
+            angle: 90.0,
+            sprite: Sprite {
+                spritesheet: 1,
+                region: Rect::new(0, 0, 32, 32),
+            },
+            controller: Some(player),
+            velocity: Some(Velocity {
+                speed: 2.5,
+                direction: Angle { angle: 180.0 },
+            }),
+            angular_velocity: Some(AngularVelocity {
+                speed: 0.0,
+                rotation: Rotation::Clockwise,
+            }),
+            controls: Some(Controls {
+                throttle: Some(Angle { angle: 270.0 }),
+                turn: None,
+            }),
+        }
+    }
+
+    fn bullet(x: i32) -> EntityState {
+        EntityState {
+            position: Point::new(x, 12),
+            angle: 0.0,
+            sprite: Sprite {
+                spritesheet: 0,
+                region: Rect::new(0, 0, 8, 8),
+            },
+            controller: None,
+            velocity: None,
+            angular_velocity: None,
+            controls: None,
+        }
+    }
+
+    /// A delta sent over the wire and applied to its baseline gives back the state it was made from
+    fn round_trip(state: &WorldState, baseline: Option<(u32, &WorldState)>) -> WorldState {
+        let snapshot = Snapshot::diff(7, Some(3), state, baseline);
+        let bytes = ServerMessage::Snapshot(snapshot).encode();
+        match ServerMessage::decode(&bytes) {
+            Some(ServerMessage::Snapshot(snapshot)) => {
+                snapshot.apply(baseline.map(|(_, baseline)| baseline))
+            }
+            other => panic!("decoded {:?}", other),
+        }
+    }
+
+    #[test]
+    fn full_snapshot_round_trips() {
+        let state: WorldState = vec![(1, tank(-100, 0)), (2, tank(100, 1)), (3, bullet(0))]
+            .into_iter()
+            .collect();
+        assert_eq!(round_trip(&state, None), state);
+    }
+
+    #[test]
+    fn delta_round_trips() {
+        let old: WorldState = vec![(1, tank(-100, 0)), (2, tank(100, 1)), (3, bullet(0))]
+            .into_iter()
+            .collect();
+        let mut new = old.clone();
+        // One tank moved and turned, the other stopped and started turning its turret,
+        // the bullet is gone and another was fired
+        let moved = new.get_mut(&1).unwrap();
+        moved.position = moved.position.offset(3, 0);
+        moved.angle = 95.0;
+        let stopped = new.get_mut(&2).unwrap();
+        stopped.velocity = None;
+        stopped.controls = Some(Controls {
+            throttle: None,
+            turn: Some(Rotation::CounterClockwise),
+        });
+        new.remove(&3);
+        new.insert(4, bullet(40));
+
+        let snapshot = Snapshot::diff(8, None, &new, Some((7, &old)));
+        assert_eq!(snapshot.baseline, Some(7));
+        assert_eq!(snapshot.removed, vec![3]);
+        assert_eq!(snapshot.changed.len(), 3);
+        assert_eq!(round_trip(&new, Some((7, &old))), new);
+    }
+
+    #[test]
+    fn unchanged_entities_are_left_out() {
+        let state: WorldState = vec![(1, tank(0, 0))].into_iter().collect();
+        let snapshot = Snapshot::diff(8, None, &state, Some((7, &state)));
+        assert!(snapshot.changed.is_empty());
+        assert!(snapshot.removed.is_empty());
+        assert_eq!(snapshot.apply(Some(&state)), state);
+    }
+
+    #[test]
+    fn truncated_client_messages_are_rejected() {
+        let commands = PlayerCommands {
+            movement: Some(MovementCommand::Move(Angle { angle: 270.0 })),
+            rotation: Some(RotationCommand::Stop),
+            fire: Some(FireCommand::Fire),
+            weapon: Some(WeaponCommand::Select(2)),
+        };
+        let messages = [
+            ClientMessage::Hello { seat: Some(3) },
+            ClientMessage::Input {
+                ack: Some(40),
+                first_sequence: 12,
+                commands: vec![commands, PlayerCommands::default()],
+            },
+            ClientMessage::Goodbye,
+        ];
+        for message in &messages {
+            let bytes = message.encode();
+            assert_eq!(ClientMessage::decode(&bytes).as_ref(), Some(message));
+            for length in 0..bytes.len() {
+                assert_eq!(ClientMessage::decode(&bytes[..length]), None);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_server_messages_are_rejected() {
+        let state: WorldState = vec![(1, tank(-100, 0)), (3, bullet(0))]
+            .into_iter()
+            .collect();
+        let messages = [
+            ServerMessage::Welcome {
+                player: 2,
+                archetype: TankArchetype::default(),
+            },
+            ServerMessage::Snapshot(Snapshot::diff(5, Some(1), &state, None)),
+        ];
+        for message in &messages {
+            let bytes = message.encode();
+            assert_eq!(ServerMessage::decode(&bytes).as_ref(), Some(message));
+            for length in 0..bytes.len() {
+                assert_eq!(ServerMessage::decode(&bytes[..length]), None);
+            }
+        }
+    }
+}
@@ -0,0 +1,427 @@
+use crate::commands::{Commands, PlayerCommands, PlayerId};
+use crate::game;
+use crate::net::protocol::{PeerMessage, MAX_PACKET_SIZE};
+use crate::resources::Tick;
+use crate::snapshot::WorldSnapshot;
+use specs::prelude::{Dispatcher, World, WorldExt};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{ToSocketAddrs, UdpSocket};
+
+/// How many ticks we may run ahead of the other peer's confirmed inputs before waiting for them
+const MAX_PREDICTION: u32 = 8;
+
+/// Unacknowledged inputs beyond this are not resent, the other peer will have stalled long before
+const MAX_RESENT_INPUTS: usize = 64;
+
+/// Moves datagrams between the two peers of a rollback match
+pub trait Transport {
+    fn send(&mut self, bytes: &[u8]);
+    /// The next datagram that has arrived, if any
+    fn receive(&mut self) -> Option<Vec<u8>>;
+}
+
+/// A transport over a real UDP socket
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    pub fn connect(port: u16, peer: &str) -> Result<Self, String> {
+        let peer = peer
+            .to_socket_addrs()
+            .map_err(|e| format!("bad peer address {}: {}", peer, e))?
+            .next()
+            .ok_or(format!("bad peer address {}", peer))?;
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .map_err(|e| format!("could not bind port {}: {}", port, e))?;
+        socket.connect(peer).map_err(|e| e.to_string())?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(UdpTransport { socket })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, bytes: &[u8]) {
+        // UDP is unreliable anyway, and inputs are resent until acknowledged
+        let _ = self.socket.send(bytes);
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(size) => return Some(buffer[..size].to_vec()),
+                // The peer isn't up yet, or went away; either way there is nothing to read
+                Err(ref e) if e.kind() == ErrorKind::ConnectionRefused => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// One peer of a rollback match.
+///
+/// Every tick is simulated straight away using the other peer's inputs where we have them and a
+/// prediction (no change) where we don't. The world is saved before every tick, so when the other
+/// peer's real inputs turn out to differ from the prediction we restore the tick they apply to and
+/// re-simulate from there.
+pub struct RollbackSession<'a, 'b, T: Transport> {
+    transport: T,
+    dispatcher: Dispatcher<'a, 'b>,
+    pub world: World,
+    local: PlayerId,
+    remote: PlayerId,
+    /// Local inputs are applied this many ticks after they are given, hiding some latency
+    input_delay: u32,
+    /// Input given while stalled, applied on the next tick we simulate
+    held_input: PlayerCommands,
+    local_inputs: BTreeMap<u32, PlayerCommands>,
+    remote_inputs: BTreeMap<u32, PlayerCommands>,
+    /// Every remote input for ticks before this has arrived
+    remote_confirmed: u32,
+    /// Every local input for ticks before this has been acknowledged by the other peer
+    local_acked: u32,
+    /// The world as it was at the start of each tick that may still be rolled back
+    snapshots: BTreeMap<u32, WorldSnapshot>,
+    /// The earliest tick simulated with a remote input that turned out to be mispredicted
+    rollback_from: Option<u32>,
+    /// How many ticks have been re-simulated because of mispredictions
+    pub resimulated: u64,
+}
+
+impl<'a, 'b, T: Transport> RollbackSession<'a, 'b, T> {
+    /// Both peers must use the same seed and agree on who is who
+    pub fn new(
+        transport: T,
+        seed: u64,
+        local: PlayerId,
+        remote: PlayerId,
+        input_delay: u32,
+    ) -> Self {
+        let mut dispatcher = game::build_dispatcher();
+        let mut players = [local, remote];
+        players.sort_unstable();
+        let world = game::create_match(&mut dispatcher, seed, &players);
+        RollbackSession {
+            transport,
+            dispatcher,
+            world,
+            local,
+            remote,
+            input_delay,
+            held_input: PlayerCommands::default(),
+            // Nothing is pressed during the ticks covered by the delay
+            local_inputs: (0..input_delay)
+                .map(|tick| (tick, PlayerCommands::default()))
+                .collect(),
+            remote_inputs: BTreeMap::new(),
+            remote_confirmed: 0,
+            local_acked: 0,
+            snapshots: BTreeMap::new(),
+            rollback_from: None,
+            resimulated: 0,
+        }
+    }
+
+    /// The next tick to be simulated
+    pub fn tick(&self) -> u32 {
+        self.world.read_resource::<Tick>().0 as u32
+    }
+
+    /// Every tick before this has been simulated with both peers' real inputs
+    pub fn confirmed_tick(&self) -> u32 {
+        self.remote_confirmed.min(self.tick())
+    }
+
+    /// A checksum of the world at the start of `tick`, if it is the current tick or still kept
+    pub fn checksum_at(&self, tick: u32) -> Option<u64> {
+        if tick == self.tick() {
+            return Some(WorldSnapshot::capture(&self.world).checksum());
+        }
+        self.snapshots.get(&tick).map(WorldSnapshot::checksum)
+    }
+
+    /// Give this tick's local input and simulate a tick, unless we are too far ahead of the
+    /// other peer, in which case the input is kept and false is returned.
+    pub fn advance(&mut self, input: PlayerCommands) -> bool {
+        let tick = self.tick();
+        // Don't lose what was pressed while we wait
+        self.held_input.merge(&input);
+        let stalled = tick >= self.remote_confirmed + MAX_PREDICTION;
+        if !stalled {
+            let input = std::mem::take(&mut self.held_input);
+            self.local_inputs.insert(tick + self.input_delay, input);
+        }
+
+        self.send_inputs();
+        self.receive_inputs();
+        self.roll_back();
+        if stalled {
+            return false;
+        }
+
+        self.simulate(tick);
+        self.prune();
+        true
+    }
+
+    /// Restore and re-simulate from the earliest mispredicted tick
+    fn roll_back(&mut self) {
+        let from = match self.rollback_from.take() {
+            Some(from) => from,
+            None => return,
+        };
+        let to = self.tick();
+        self.snapshots[&from].restore(&mut self.world);
+        for tick in from..to {
+            self.simulate(tick);
+            self.resimulated += 1;
+        }
+    }
+
+    fn simulate(&mut self, tick: u32) {
+        self.snapshots
+            .insert(tick, WorldSnapshot::capture(&self.world));
+
+        let mut commands = Commands::default();
+        commands.0.insert(
+            self.local,
+            self.local_inputs.get(&tick).cloned().unwrap_or_default(),
+        );
+        // The remote prediction is "no change", tanks keep doing what they were last told to
+        commands.0.insert(
+            self.remote,
+            self.remote_inputs.get(&tick).cloned().unwrap_or_default(),
+        );
+        game::step(&mut self.world, &mut self.dispatcher, commands);
+    }
+
+    fn send_inputs(&mut self) {
+        let commands: Vec<PlayerCommands> = self
+            .local_inputs
+            .range(self.local_acked..)
+            .map(|(_, commands)| *commands)
+            .take(MAX_RESENT_INPUTS)
+            .collect();
+        let message = PeerMessage::Inputs {
+            ack: self.remote_confirmed.checked_sub(1),
+            first_tick: self.local_acked,
+            commands,
+        };
+        self.transport.send(&message.encode());
+    }
+
+    fn receive_inputs(&mut self) {
+        while let Some(bytes) = self.transport.receive() {
+            let (ack, first_tick, commands) = match PeerMessage::decode(&bytes) {
+                Some(PeerMessage::Inputs {
+                    ack,
+                    first_tick,
+                    commands,
+                }) => (ack, first_tick, commands),
+                None => continue,
+            };
+            if let Some(ack) = ack {
+                self.local_acked = self.local_acked.max(ack + 1);
+            }
+            for (tick, input) in (first_tick..).zip(commands) {
+                if tick < self.remote_confirmed || self.remote_inputs.contains_key(&tick) {
+                    continue;
+                }
+                self.remote_inputs.insert(tick, input);
+                if tick < self.tick() && !input.is_empty() {
+                    self.rollback_from = Some(self.rollback_from.map_or(tick, |t| t.min(tick)));
+                }
+            }
+            while self.remote_inputs.contains_key(&self.remote_confirmed) {
+                self.remote_confirmed += 1;
+            }
+        }
+    }
+
+    /// Forget snapshots and inputs that can no longer be rolled back to
+    fn prune(&mut self) {
+        let keep_from = self.confirmed_tick();
+        self.snapshots = self.snapshots.split_off(&keep_from);
+        self.remote_inputs = self.remote_inputs.split_off(&keep_from);
+        let acked = self.local_acked.min(keep_from);
+        self.local_inputs = self.local_inputs.split_off(&acked);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{FireCommand, MovementCommand, RotationCommand, WeaponCommand};
+    use crate::components::{Angle, Rotation};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// One direction of a simulated network link with latency and packet loss
+    struct Channel {
+        /// Datagrams in flight and the tick they arrive on
+        in_flight: Vec<(u64, Vec<u8>)>,
+    }
+
+    /// A transport for testing two peers in one process, over a link that delays and drops packets
+    pub struct SimulatedLink {
+        outgoing: Rc<RefCell<Channel>>,
+        incoming: Rc<RefCell<Channel>>,
+        clock: Rc<RefCell<u64>>,
+        latency: u64,
+        jitter: u64,
+        loss: f64,
+        rng: StdRng,
+    }
+
+    impl SimulatedLink {
+        /// A connected pair of transports sharing `clock`, which counts ticks
+        pub fn pair(
+            clock: Rc<RefCell<u64>>,
+            latency: u64,
+            jitter: u64,
+            loss: f64,
+            seed: u64,
+        ) -> (SimulatedLink, SimulatedLink) {
+            let a_to_b = Rc::new(RefCell::new(Channel {
+                in_flight: Vec::new(),
+            }));
+            let b_to_a = Rc::new(RefCell::new(Channel {
+                in_flight: Vec::new(),
+            }));
+            let end = |outgoing: &Rc<RefCell<Channel>>, incoming: &Rc<RefCell<Channel>>, seed| {
+                SimulatedLink {
+                    outgoing: outgoing.clone(),
+                    incoming: incoming.clone(),
+                    clock: clock.clone(),
+                    latency,
+                    jitter,
+                    loss,
+                    rng: StdRng::seed_from_u64(seed),
+                }
+            };
+            (end(&a_to_b, &b_to_a, seed), end(&b_to_a, &a_to_b, seed + 1))
+        }
+    }
+
+    impl Transport for SimulatedLink {
+        fn send(&mut self, bytes: &[u8]) {
+            if self.rng.gen_bool(self.loss) {
+                return;
+            }
+            let arrives =
+                *self.clock.borrow() + self.latency + self.rng.gen_range(0, self.jitter + 1);
+            self.outgoing
+                .borrow_mut()
+                .in_flight
+                .push((arrives, bytes.to_vec()));
+        }
+
+        fn receive(&mut self) -> Option<Vec<u8>> {
+            let now = *self.clock.borrow();
+            let mut incoming = self.incoming.borrow_mut();
+            let index = incoming
+                .in_flight
+                .iter()
+                .position(|(arrives, _)| *arrives <= now)?;
+            Some(incoming.in_flight.remove(index).1)
+        }
+    }
+
+    /// Play two peers against each other over a simulated link, with both fed random inputs,
+    /// checking that they agree on every tick both have confirmed up to the last one played.
+    /// Returns how many ticks each re-simulated.
+    /// `latency` and `jitter` are in ticks, `loss` is the fraction of packets dropped.
+    fn play(seed: u64, ticks: u32, latency: u64, jitter: u64, loss: f64) -> [u64; 2] {
+        let clock = Rc::new(RefCell::new(0));
+        let (link_a, link_b) = SimulatedLink::pair(clock.clone(), latency, jitter, loss, seed);
+        let mut peers = [
+            RollbackSession::new(link_a, seed, 0, 1, 2),
+            RollbackSession::new(link_b, seed, 1, 0, 2),
+        ];
+        let mut input_rng = StdRng::seed_from_u64(seed);
+
+        // The last tick both peers had confirmed and still had the world for
+        let mut compared = None;
+        let mut step = 0;
+        // Keep going without input after the last tick until both peers have confirmed everything
+        while compared.is_none_or(|tick| tick < ticks) {
+            for peer in peers.iter_mut() {
+                let input = if peer.tick() < ticks {
+                    random_commands(&mut input_rng)
+                } else {
+                    PlayerCommands::default()
+                };
+                peer.advance(input);
+            }
+            *clock.borrow_mut() += 1;
+
+            // Once both peers have every input before a tick, their state at its start is final
+            let tick = peers[0].confirmed_tick().min(peers[1].confirmed_tick());
+            if let (Some(a), Some(b)) = (peers[0].checksum_at(tick), peers[1].checksum_at(tick)) {
+                assert_eq!(a, b, "peers desynchronised at tick {}", tick);
+                compared = Some(tick);
+            }
+
+            step += 1;
+            assert!(step <= ticks * 10 + 1000, "peers stopped making progress");
+        }
+
+        [peers[0].resimulated, peers[1].resimulated]
+    }
+
+    #[test]
+    fn peers_agree_over_a_perfect_link() {
+        play(1, 300, 0, 0, 0.0);
+    }
+
+    #[test]
+    fn peers_converge_after_rolling_back_over_a_lossy_link() {
+        let resimulated = play(7, 1000, 3, 2, 0.1);
+        assert!(resimulated.iter().all(|&ticks| ticks > 0));
+    }
+
+    #[test]
+    fn peers_converge_with_latency_beyond_the_prediction_window() {
+        let resimulated = play(42, 600, 20, 4, 0.2);
+        assert!(resimulated.iter().all(|&ticks| ticks > 0));
+    }
+
+    fn random_commands(rng: &mut StdRng) -> PlayerCommands {
+        let mut commands = PlayerCommands::default();
+        // Players change what they are doing every few ticks at most
+        if rng.gen_bool(0.1) {
+            commands.movement = Some(match rng.gen_range(0, 5) {
+                0 => MovementCommand::Stop,
+                direction => MovementCommand::Move(Angle {
+                    angle: 90.0 * direction as f32,
+                }),
+            });
+        }
+        if rng.gen_bool(0.1) {
+            commands.rotation = Some(match rng.gen_range(0, 3) {
+                0 => RotationCommand::Stop,
+                1 => RotationCommand::Move(Rotation::Clockwise),
+                _ => RotationCommand::Move(Rotation::CounterClockwise),
+            });
+        }
+        if rng.gen_bool(0.1) {
+            commands.fire = Some(if rng.gen() {
+                FireCommand::Fire
+            } else {
+                FireCommand::Stop
+            });
+        }
+        if rng.gen_bool(0.02) {
+            commands.weapon = Some(if rng.gen() {
+                WeaponCommand::Next
+            } else {
+                WeaponCommand::Select(rng.gen_range(0, 4))
+            });
+        }
+        commands
+    }
+}
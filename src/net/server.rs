@@ -0,0 +1,383 @@
+use crate::archetype::TankArchetype;
+use crate::commands::{Commands, PlayerCommands, PlayerId};
+use crate::components::{
+    Angle, AngularVelocity, Engine, KeyboardControlled, Position, Sprite, Team, Velocity,
+};
+use crate::fog::Sight;
+use crate::game;
+use crate::geometry::Rect;
+use crate::net::net_id;
+use crate::net::protocol::{
+    ClientMessage, Controls, EntityState, ServerMessage, Snapshot, WorldState, MAX_PACKET_SIZE,
+};
+use crate::resources::Tick;
+use specs::join::Join;
+use specs::prelude::{Dispatcher, World, WorldExt};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Clients we have not heard from for this long are dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many past world states are kept as delta baselines
+const SNAPSHOT_HISTORY: usize = 64;
+
+/// Inputs beyond this many queued are merged into the current tick so a client can't lag behind
+const MAX_INPUT_BACKLOG: usize = 4;
+
+/// Only entities inside this area are replicated, bullets that fly off are of no interest
+const REPLICATION_BOUNDS: (i32, i32, u32, u32) = (-600, -500, 1200, 1000);
+
+struct Connection {
+    player: PlayerId,
+    archetype: TankArchetype,
+    last_heard: Instant,
+    /// Received inputs waiting to be applied, with their sequence numbers
+    pending: VecDeque<(u32, PlayerCommands)>,
+    last_received: Option<u32>,
+    last_applied: Option<u32>,
+    /// The latest snapshot the client has acknowledged
+    acked: Option<u32>,
+    /// What it has been sent lately, the baselines for its deltas. Each client is only sent
+    /// the enemies it can see, so they all differ.
+    history: BTreeMap<u32, WorldState>,
+}
+
+/// The authoritative simulation, serving any number of clients
+pub struct Server<'a, 'b> {
+    socket: UdpSocket,
+    dispatcher: Dispatcher<'a, 'b>,
+    world: World,
+    connections: HashMap<SocketAddr, Connection>,
+    /// Players a lobby has promised a place to, with the tank and team they chose
+    seats: HashMap<PlayerId, (TankArchetype, Team)>,
+}
+
+impl<'a, 'b> Server<'a, 'b> {
+    pub fn bind(port: u16, seed: u64) -> Result<Self, String> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .map_err(|e| format!("could not bind port {}: {}", port, e))?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        let mut dispatcher = game::build_dispatcher();
+        let mut world = game::create_arena(&mut dispatcher, seed);
+        game::spawn_scenery(&mut world);
+        Ok(Server {
+            socket,
+            dispatcher,
+            world,
+            connections: HashMap::new(),
+            seats: HashMap::new(),
+        })
+    }
+
+    /// Keep `player` for whoever asks for it in their hello
+    pub fn reserve(&mut self, player: PlayerId, archetype: TankArchetype, team: Team) {
+        self.seats.insert(player, (archetype, team));
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.socket.local_addr().map_err(|e| e.to_string())
+    }
+
+    /// Handle incoming packets, simulate one tick and send every client a snapshot
+    pub fn tick(&mut self) -> Result<(), String> {
+        self.receive()?;
+        self.drop_timed_out();
+
+        let mut commands = Commands::default();
+        for connection in self.connections.values_mut() {
+            let mut player_commands = PlayerCommands::default();
+            let mut budget = connection.pending.len().saturating_sub(MAX_INPUT_BACKLOG) + 1;
+            while budget > 0 {
+                match connection.pending.pop_front() {
+                    Some((sequence, input)) => {
+                        player_commands.merge(&input);
+                        connection.last_applied = Some(sequence);
+                    }
+                    None => break,
+                }
+                budget -= 1;
+            }
+            commands.0.insert(connection.player, player_commands);
+        }
+        game::step(&mut self.world, &mut self.dispatcher, commands);
+
+        self.broadcast()
+    }
+
+    fn receive(&mut self) -> Result<(), String> {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let (size, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                // A previous send to a client that went away, nothing to do
+                Err(ref e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e.to_string()),
+            };
+            if let Some(message) = ClientMessage::decode(&buffer[..size]) {
+                self.handle(from, message);
+            }
+        }
+    }
+
+    fn handle(&mut self, from: SocketAddr, message: ClientMessage) {
+        match message {
+            ClientMessage::Hello { seat } => {
+                let (player, archetype) = match self.connections.get(&from) {
+                    Some(connection) => (connection.player, connection.archetype),
+                    None => match self.join(from, seat) {
+                        Some(joined) => joined,
+                        None => return, // the server is full
+                    },
+                };
+                self.send(from, &ServerMessage::Welcome { player, archetype });
+            }
+            ClientMessage::Input {
+                ack,
+                first_sequence,
+                commands,
+            } => {
+                let connection = match self.connections.get_mut(&from) {
+                    Some(connection) => connection,
+                    None => return,
+                };
+                connection.last_heard = Instant::now();
+                connection.acked = connection.acked.max(ack);
+                for (sequence, input) in (first_sequence..).zip(commands) {
+                    if connection.last_received.is_none_or(|last| sequence > last) {
+                        connection.pending.push_back((sequence, input));
+                        connection.last_received = Some(sequence);
+                    }
+                }
+            }
+            ClientMessage::Goodbye => self.leave(from),
+        }
+    }
+
+    /// Give a new client a player id and a tank, the reserved ones if it asked for a free seat
+    fn join(
+        &mut self,
+        from: SocketAddr,
+        seat: Option<PlayerId>,
+    ) -> Option<(PlayerId, TankArchetype)> {
+        let taken = |id: PlayerId| self.connections.values().any(|c| c.player == id);
+        let player = match seat {
+            Some(seat) if self.seats.contains_key(&seat) && !taken(seat) => seat,
+            // Only as many as can start apart from each other
+            _ => (1..game::MAX_PLAYERS as PlayerId)
+                .find(|&id| !taken(id) && !self.seats.contains_key(&id))?,
+        };
+        let (archetype, team) = match self.seats.get(&player) {
+            Some(&(archetype, team)) => (archetype, Some(team)),
+            None => (TankArchetype::default(), None),
+        };
+
+        // The spawn points are clear of the walls, barricades and water
+        let position = game::spawn_point(player);
+        game::spawn_player_tank(&mut self.world, player, position, archetype, team);
+        println!("player {} joined from {} as a {}", player, from, archetype);
+
+        self.connections.insert(
+            from,
+            Connection {
+                player,
+                archetype,
+                last_heard: Instant::now(),
+                pending: VecDeque::new(),
+                last_received: None,
+                last_applied: None,
+                acked: None,
+                history: BTreeMap::new(),
+            },
+        );
+        Some((player, archetype))
+    }
+
+    fn leave(&mut self, from: SocketAddr) {
+        if let Some(connection) = self.connections.remove(&from) {
+            println!("player {} left", connection.player);
+            let entities = self.world.entities();
+            let controlled = self.world.read_storage::<KeyboardControlled>();
+            for (entity, control) in (&entities, &controlled).join() {
+                if control.player == connection.player {
+                    entities.delete(entity).expect("entity is alive");
+                }
+            }
+        }
+        self.world.maintain();
+    }
+
+    fn drop_timed_out(&mut self) {
+        let timed_out: Vec<SocketAddr> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.last_heard.elapsed() > CLIENT_TIMEOUT)
+            .map(|(&addr, _)| addr)
+            .collect();
+        for addr in timed_out {
+            self.leave(addr);
+        }
+    }
+
+    fn broadcast(&mut self) -> Result<(), String> {
+        let tick = self.world.read_resource::<Tick>().0 as u32;
+
+        for (&addr, connection) in &mut self.connections {
+            let state = world_state(&self.world, connection.player);
+            let baseline = connection
+                .acked
+                .and_then(|acked| connection.history.get(&acked).map(|old| (acked, old)));
+            let snapshot = Snapshot::diff(tick, connection.last_applied, &state, baseline);
+            connection.history.insert(tick, state);
+            while connection.history.len() > SNAPSHOT_HISTORY {
+                let oldest = *connection
+                    .history
+                    .keys()
+                    .next()
+                    .expect("history is not empty");
+                connection.history.remove(&oldest);
+            }
+
+            let bytes = ServerMessage::Snapshot(snapshot).encode();
+            if bytes.len() > MAX_PACKET_SIZE {
+                eprintln!("snapshot of {} bytes is too large to send", bytes.len());
+                continue;
+            }
+            // UDP is unreliable anyway, a failed send is just a lost packet
+            let _ = self.socket.send_to(&bytes, addr);
+        }
+        Ok(())
+    }
+
+    fn send(&self, to: SocketAddr, message: &ServerMessage) {
+        let _ = self.socket.send_to(&message.encode(), to);
+    }
+}
+
+/// The replicated state of every entity inside the replication bounds that `viewer` can see.
+/// Enemies out of sight are left out altogether, so a client can't be made to show them.
+fn world_state(world: &World, viewer: PlayerId) -> WorldState {
+    let (x, y, width, height) = REPLICATION_BOUNDS;
+    let bounds = Rect::new(x, y, width, height);
+    let hidden = Sight::of(world, viewer).hidden(world);
+    let entities = world.entities();
+    let positions = world.read_storage::<Position>();
+    let angles = world.read_storage::<Angle>();
+    let sprites = world.read_storage::<Sprite>();
+    let controlled = world.read_storage::<KeyboardControlled>();
+    let velocities = world.read_storage::<Velocity>();
+    let angular_velocities = world.read_storage::<AngularVelocity>();
+    let engines = world.read_storage::<Engine>();
+    (
+        &entities,
+        &positions,
+        &angles,
+        &sprites,
+        controlled.maybe(),
+        velocities.maybe(),
+        angular_velocities.maybe(),
+        engines.maybe(),
+    )
+        .join()
+        .filter(|(entity, pos, _, _, _, _, _, _)| {
+            bounds.contains_point(pos.0) && !hidden.contains(entity)
+        })
+        .map(
+            |(entity, pos, angle, sprite, control, vel, angular_vel, engine)| {
+                // Other players' engines are left for their own clients to predict
+                let own = control.is_some_and(|control| control.player == viewer);
+                (
+                    net_id(entity),
+                    EntityState {
+                        position: pos.0,
+                        angle: angle.angle,
+                        sprite: sprite.clone(),
+                        controller: control.map(|control| control.player),
+                        velocity: vel.cloned(),
+                        angular_velocity: angular_vel.cloned(),
+                        controls: engine.filter(|_| own).map(|engine| Controls {
+                            throttle: engine.throttle,
+                            turn: engine.turn,
+                        }),
+                    },
+                )
+            },
+        )
+        .collect()
+}
+
+/// Run a headless server until the process is killed
+pub fn run(port: u16, seed: u64) -> Result<(), String> {
+    serve(Server::bind(port, seed)?)
+}
+
+/// Tick `server` forever at the game's tick rate
+pub fn serve(mut server: Server) -> Result<(), String> {
+    println!("serving on {}", server.local_addr()?);
+
+    let mut next_tick = Instant::now();
+    loop {
+        server.tick()?;
+
+        // Time management!
+        next_tick += game::TICK_DURATION;
+        let now = Instant::now();
+        if next_tick > now {
+            ::std::thread::sleep(next_tick - now);
+        } else {
+            next_tick = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Collider, Wall};
+    use crate::geometry::Point;
+    use crate::terrain::Terrain;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn joining_tanks_are_clear_of_the_scenery() {
+        let mut server = Server::bind(0, 5).expect("could bind a free port");
+        let from = |port| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+        // Player 0 is never given out, so one less than there are places to start
+        let players = game::MAX_PLAYERS as u16 - 1;
+        for port in 1..=players {
+            assert!(server.join(from(port), None).is_some());
+        }
+        assert_eq!(server.join(from(players + 1), None), None);
+
+        let world = &server.world;
+        let positions = world.read_storage::<Position>();
+        let colliders = world.read_storage::<Collider>();
+        let walls = world.read_storage::<Wall>();
+        let controlled = world.read_storage::<KeyboardControlled>();
+        let terrain = world.read_resource::<Terrain>();
+        let tanks: Vec<Rect> = (&positions, &colliders, &controlled)
+            .join()
+            .map(|(pos, collider, _)| collider.rect(pos.0))
+            .collect();
+        assert_eq!(tanks.len(), usize::from(players));
+        for (i, tank) in tanks.iter().enumerate() {
+            for (pos, collider, _) in (&positions, &colliders, &walls).join() {
+                assert!(!tank.has_intersection(collider.rect(pos.0)));
+            }
+            let corners = [
+                tank.top_left(),
+                Point::new(tank.right() - 1, tank.top()),
+                Point::new(tank.left(), tank.bottom() - 1),
+                Point::new(tank.right() - 1, tank.bottom() - 1),
+            ];
+            assert!(corners.iter().all(|&corner| terrain.at(corner).passable()));
+            assert!(tanks[i + 1..]
+                .iter()
+                .all(|other| !tank.has_intersection(*other)));
+        }
+    }
+}
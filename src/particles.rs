@@ -0,0 +1,258 @@
+use crate::components::Emitter;
+use crate::components::Position;
+use crate::components::Velocity;
+use crate::geometry::Point;
+use crate::resources::Tick;
+use core::f32::consts::PI;
+use rand::rngs::SmallRng;
+use rand::{FromEntropy, Rng};
+use specs::join::Join;
+use specs::prelude::{World, WorldExt};
+
+/// The most particles alive at once, no more are spawned until some have died
+const MAX_PARTICLES: usize = 2048;
+
+/// Effects are kept this many ticks for whoever draws them
+const EFFECT_HISTORY: u64 = 20;
+
+/// Effects this many ticks old by the time they are drawn are too late to bother with
+const MAX_EFFECT_AGE: u64 = 5;
+
+/// The gap between the particles drawing a beam
+const BEAM_SPACING: f32 = 4.0;
+
+/// How far behind a moving tank its exhaust comes out
+const EXHAUST_OFFSET: f32 = 14.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EffectKind {
+    /// Exhaust drifting up from a moving tank
+    Smoke,
+    /// A shot hitting something
+    Sparks,
+    /// What is left of something destroyed
+    Debris,
+    /// A laser fired from where the effect is to `to`
+    Beam { to: Point },
+}
+
+impl EffectKind {
+    /// How many particles a single burst of the effect gives off
+    fn count(self) -> usize {
+        match self {
+            EffectKind::Smoke => 1,
+            EffectKind::Sparks => 10,
+            EffectKind::Debris => 24,
+            EffectKind::Beam { .. } => 1,
+        }
+    }
+
+    /// Spawn one particle of the effect at `position`
+    fn particle(self, rng: &mut SmallRng, position: (f32, f32)) -> Particle {
+        let (speed, lifetime, size, colour, drag) = match self {
+            EffectKind::Smoke => (
+                rng.gen_range(0.2, 0.8),
+                rng.gen_range(20, 30),
+                (4.0, 14.0),
+                (120, 120, 120, 140),
+                0.95,
+            ),
+            EffectKind::Sparks => (
+                rng.gen_range(3.0, 7.0),
+                rng.gen_range(6, 12),
+                (3.0, 1.0),
+                (255, 200, 80, 255),
+                0.8,
+            ),
+            EffectKind::Debris => (
+                rng.gen_range(1.5, 5.0),
+                rng.gen_range(25, 45),
+                (6.0, 3.0),
+                (80, 60, 40, 255),
+                0.9,
+            ),
+            EffectKind::Beam { .. } => (
+                0.0,
+                rng.gen_range(3, 6),
+                (3.0, 1.0),
+                (255, 60, 60, 255),
+                0.0,
+            ),
+        };
+        let direction = rng.gen_range(0.0, 2.0 * PI);
+        Particle {
+            position,
+            velocity: (speed * direction.cos(), speed * direction.sin()),
+            drag,
+            age: 0,
+            lifetime,
+            size,
+            colour,
+        }
+    }
+}
+
+/// Things that happened in the simulation worth drawing particles for, with the tick they happened on.
+/// They are only for show, nothing in the simulation reads them and snapshots leave them out.
+#[derive(Debug, Default)]
+pub struct Effects {
+    tick: u64,
+    happened: Vec<(u64, EffectKind, Point)>,
+}
+
+impl Effects {
+    /// Start recording the effects of `tick`, forgetting those too old for anyone to still draw
+    pub fn start_tick(&mut self, tick: u64) {
+        self.tick = tick;
+        self.happened
+            .retain(|&(happened, _, _)| happened + EFFECT_HISTORY > tick);
+    }
+
+    pub fn add(&mut self, kind: EffectKind, position: Point) {
+        self.happened.push((self.tick, kind, position));
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Particle {
+    position: (f32, f32),
+    velocity: (f32, f32),
+    /// The fraction of its speed kept from one frame to the next
+    drag: f32,
+    age: u32,
+    lifetime: u32,
+    /// The size it is born with and the size it has faded away at
+    size: (f32, f32),
+    /// Red, green, blue and alpha
+    colour: (u8, u8, u8, u8),
+}
+
+/// Smoke, sparks and debris, kept apart from the simulation in a fixed size pool.
+/// Bursts come from the world's `Effects`, a steady trickle from entities with an `Emitter`.
+pub struct Particles {
+    pool: Vec<Particle>,
+    /// The tick the particles were last updated for
+    tick: Option<u64>,
+    rng: SmallRng,
+}
+
+impl Default for Particles {
+    fn default() -> Self {
+        Particles::new()
+    }
+}
+
+impl Particles {
+    pub fn new() -> Self {
+        Particles {
+            pool: Vec::with_capacity(MAX_PARTICLES),
+            tick: None,
+            rng: SmallRng::from_entropy(),
+        }
+    }
+
+    /// Age every particle and spawn the new ones for whatever `world` has done since the last update.
+    /// Nothing moves while the world's tick stays the same, and going back in time clears the lot.
+    pub fn update(&mut self, world: &World) {
+        let tick = world.read_resource::<Tick>().0;
+        let seen = match self.tick {
+            Some(seen) if seen == tick => return,
+            Some(seen) if seen > tick => {
+                self.pool.clear();
+                None
+            }
+            seen => seen,
+        };
+        self.tick = Some(tick);
+
+        let mut i = 0;
+        while i < self.pool.len() {
+            let particle = &mut self.pool[i];
+            particle.age += 1;
+            if particle.age >= particle.lifetime {
+                self.pool.swap_remove(i);
+                continue;
+            }
+            particle.position.0 += particle.velocity.0;
+            particle.position.1 += particle.velocity.1;
+            particle.velocity.0 *= particle.drag;
+            particle.velocity.1 *= particle.drag;
+            i += 1;
+        }
+
+        // Effects from ticks already seen were simulated again after a rollback, they've been drawn
+        let effects = world.read_resource::<Effects>();
+        for &(happened, kind, position) in &effects.happened {
+            if seen.is_none_or(|seen| happened >= seen) && happened + MAX_EFFECT_AGE >= tick {
+                match kind {
+                    EffectKind::Beam { to } => self.beam(position, to),
+                    _ => self.burst(kind, (position.x() as f32, position.y() as f32)),
+                }
+            }
+        }
+
+        let positions = world.read_storage::<Position>();
+        let velocities = world.read_storage::<Velocity>();
+        let emitters = world.read_storage::<Emitter>();
+        for (pos, vel, emitter) in (&positions, &velocities, &emitters).join() {
+            if vel.speed == 0.0 || tick % u64::from(emitter.interval) != 0 {
+                continue;
+            }
+            // Out of the back of whichever way it is going
+            let radians = vel.direction.angle * (PI / 180.0);
+            let behind = if vel.speed > 0.0 {
+                -EXHAUST_OFFSET
+            } else {
+                EXHAUST_OFFSET
+            };
+            let position = (
+                pos.0.x() as f32 + behind * radians.cos(),
+                pos.0.y() as f32 + behind * radians.sin(),
+            );
+            self.burst(emitter.kind, position);
+        }
+    }
+
+    fn burst(&mut self, kind: EffectKind, position: (f32, f32)) {
+        for _ in 0..kind.count() {
+            if self.pool.len() >= MAX_PARTICLES {
+                return;
+            }
+            let particle = kind.particle(&mut self.rng, position);
+            self.pool.push(particle);
+        }
+    }
+
+    /// A line of particles from `from` to `to`
+    fn beam(&mut self, from: Point, to: Point) {
+        let offset = to - from;
+        let length = (offset.x() as f32).hypot(offset.y() as f32);
+        let count = (length / BEAM_SPACING).ceil().max(1.0) as i32;
+        for i in 0..=count {
+            let t = i as f32 / count as f32;
+            let position = (
+                from.x() as f32 + offset.x() as f32 * t,
+                from.y() as f32 + offset.y() as f32 * t,
+            );
+            self.burst(EffectKind::Beam { to }, position);
+        }
+    }
+
+    /// Where each particle is, how big it is and its red, green, blue and alpha, faded by its age
+    pub fn iter(&self) -> impl Iterator<Item = (Point, u32, (u8, u8, u8, u8))> + '_ {
+        self.pool.iter().map(|particle| {
+            let t = particle.age as f32 / particle.lifetime as f32;
+            let size = particle.size.0 + (particle.size.1 - particle.size.0) * t;
+            let (red, green, blue, alpha) = particle.colour;
+            let alpha = (f32::from(alpha) * (1.0 - t)) as u8;
+            (
+                Point::new(
+                    particle.position.0.round() as i32,
+                    particle.position.1.round() as i32,
+                ),
+                size.round().max(1.0) as u32,
+                (red, green, blue, alpha),
+            )
+        })
+    }
+}
@@ -1,43 +1,378 @@
+use crate::collision;
+use crate::collision::Obstacles;
 use crate::components::Angle;
 use crate::components::AngularVelocity;
+use crate::components::BulletSpawner;
+use crate::components::Collider;
+use crate::components::Engine;
+use crate::components::Health;
+use crate::components::KeyboardControlled;
+use crate::components::Knockback;
 use crate::components::Position;
+use crate::components::Projectile;
 use crate::components::Rotation;
 use crate::components::Velocity;
+use crate::components::Wall;
+use crate::geometry::Point;
+use crate::ricochet::PROJECTILE_SIZE;
+use crate::spatial;
+use crate::spatial::SpatialIndex;
+use crate::terrain::Terrain;
 use core::f32::consts::PI;
 use specs::storage::WriteStorage;
+use std::collections::BTreeMap;
 
+use specs::Entities;
+use specs::Read;
 use specs::ReadStorage;
 use specs::System;
+use specs::Write;
 
 use specs::join::Join;
+
+/// The fraction of its speed a knockback keeps from one tick to the next
+const KNOCKBACK_DECAY: f32 = 0.75;
+
+/// Knockbacks slower than this have died away
+const KNOCKBACK_MIN_SPEED: f32 = 0.5;
+
+/// Tanks going slower than this after braking have stopped, rather than creeping on from rounding
+const STOPPED_SPEED: f32 = 0.01;
+
+/// How far `vel` moves something in a tick
+pub fn step(vel: &Velocity) -> Point {
+    let x = vel.speed * (vel.direction.angle * (PI / 180.0)).cos();
+    let y = vel.speed * (vel.direction.angle * (PI / 180.0)).sin();
+    Point::new(x as i32, y as i32)
+}
+
 pub struct Physics;
 
 impl<'a> System<'a> for Physics {
     type SystemData = (
         WriteStorage<'a, Position>,
-        ReadStorage<'a, Velocity>,
+        WriteStorage<'a, Velocity>,
         WriteStorage<'a, Angle>,
         ReadStorage<'a, AngularVelocity>,
+        WriteStorage<'a, Knockback>,
+        Entities<'a>,
+        Read<'a, Terrain>,
+        ReadStorage<'a, Collider>,
+        ReadStorage<'a, Wall>,
+        ReadStorage<'a, Projectile>,
+        Write<'a, SpatialIndex>,
     );
 
     fn run(&mut self, mut data: Self::SystemData) {
-        //TODO: This code can be made nicer and more idiomatic using more pattern matching.
-        // Look up "rust irrefutable patterns" and use them here.
-        for (pos, vel) in (&mut data.0, &data.1).join() {
-            let x = vel.speed as f32 * (vel.direction.angle * (PI / 180.0)).cos();
-            let y = vel.speed as f32 * (vel.direction.angle * (PI / 180.0)).sin();
-            pos.0 = pos.0.offset(x as i32, y as i32);
+        let entities = &data.5;
+        let terrain = &data.6;
+        let colliders = &data.7;
+        let walls = &data.8;
+        let index = &mut data.10;
+
+        // Tanks slide along walls and water
+        for (entity, pos, vel, collider) in (entities, &mut data.0, &mut data.1, colliders).join() {
+            let step = step(vel);
+            let area = collision::swept(collider.rect(pos.0), step);
+            let ground = Obstacles::walls(index, walls, area, terrain);
+            let (moved, stopped) = ground.slide(collider, pos.0, step);
+            pos.0 = moved;
+            collision::stop_along(vel, stopped);
+            index.insert(entity, collider.rect(pos.0));
+        }
+
+        // Shots stop at the first solid thing they reach, for the systems after to deal with
+        for (entity, pos, vel, _) in (entities, &mut data.0, &data.1, &data.9).join() {
+            let step = step(vel);
+            let shot = spatial::bounds(pos.0, None, true);
+            let solids = Obstacles::colliders(index, colliders, collision::swept(shot, step));
+            pos.0 = solids
+                .sweep(pos.0, step, PROJECTILE_SIZE, PROJECTILE_SIZE)
+                .0;
+            index.insert(entity, spatial::bounds(pos.0, None, true));
+        }
+        for (entity, pos, vel, _, _) in
+            (entities, &mut data.0, &data.1, !colliders, !&data.9).join()
+        {
+            pos.0 += step(vel);
+            index.insert(entity, spatial::bounds(pos.0, None, false));
+        }
+
+        let mut settled = Vec::new();
+        for (entity, pos, knockback, collider) in
+            (entities, &mut data.0, &mut data.4, colliders.maybe()).join()
+        {
+            let push = &mut knockback.0;
+            let step = step(push);
+            match collider {
+                Some(collider) => {
+                    let area = collision::swept(collider.rect(pos.0), step);
+                    let ground = Obstacles::walls(index, walls, area, terrain);
+                    let (moved, stopped) = ground.slide(collider, pos.0, step);
+                    pos.0 = moved;
+                    collision::stop_along(push, stopped);
+                }
+                None => pos.0 += step,
+            }
+            index.insert(entity, spatial::bounds(pos.0, collider, false));
+            push.speed *= KNOCKBACK_DECAY;
+            if push.speed < KNOCKBACK_MIN_SPEED {
+                settled.push(entity);
+            }
+        }
+        for entity in settled {
+            data.4.remove(entity);
         }
 
         for (angle, angular_vel) in (&mut data.2, &data.3).join() {
             match angular_vel.rotation {
                 Rotation::Clockwise => {
-                    angle.angle = (angle.angle + angular_vel.speed as f32) % 360.0;
+                    angle.angle = (angle.angle + angular_vel.speed) % 360.0;
                 }
                 Rotation::CounterClockwise => {
-                    angle.angle = (angle.angle - angular_vel.speed as f32) % 360.0;
+                    angle.angle = (angle.angle - angular_vel.speed) % 360.0;
                 }
             }
         }
     }
 }
+
+/// Speeds tanks up towards where their drivers are heading and lets them coast to a stop otherwise
+pub struct Drive;
+
+impl<'a> System<'a> for Drive {
+    type SystemData = (
+        ReadStorage<'a, Engine>,
+        ReadStorage<'a, KeyboardControlled>,
+        WriteStorage<'a, Velocity>,
+        WriteStorage<'a, AngularVelocity>,
+        ReadStorage<'a, Position>,
+        Read<'a, Terrain>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for (engine, control, vel, pos) in (&data.0, &data.1, &mut data.2, &data.4).join() {
+            let ground = data.5.at(pos.0);
+            let radians = vel.direction.angle * (PI / 180.0);
+            let (x, y) = (vel.speed * radians.cos(), vel.speed * radians.sin());
+            let (x, y) = match engine.throttle {
+                Some(direction) => {
+                    let radians = direction.angle * (PI / 180.0);
+                    let top_speed = control.speed * ground.speed();
+                    let (target_x, target_y) =
+                        (top_speed * radians.cos(), top_speed * radians.sin());
+                    // Turning back against the way it is going takes the brakes
+                    let force = if x * target_x + y * target_y < 0.0 {
+                        engine.braking * ground.grip()
+                    } else {
+                        engine.acceleration
+                    };
+                    let (dx, dy) = (target_x - x, target_y - y);
+                    let change = (dx * dx + dy * dy).sqrt();
+                    let step = (force / engine.mass).min(change);
+                    if change > 0.0 {
+                        (x + dx / change * step, y + dy / change * step)
+                    } else {
+                        (x, y)
+                    }
+                }
+                None => {
+                    let slowed = (vel.speed - engine.friction * ground.grip()).max(0.0);
+                    let scale = if vel.speed > 0.0 {
+                        slowed / vel.speed
+                    } else {
+                        0.0
+                    };
+                    (x * scale, y * scale)
+                }
+            };
+            vel.speed = (x * x + y * y).sqrt();
+            // A stopped tank keeps the direction it was last going in
+            if vel.speed < STOPPED_SPEED {
+                vel.speed = 0.0;
+            } else {
+                vel.direction.angle = y.atan2(x) * (180.0 / PI);
+            }
+        }
+
+        for (engine, control, angular_vel) in (&data.0, &data.1, &mut data.3).join() {
+            match engine.turn {
+                Some(rotation) if rotation == angular_vel.rotation || angular_vel.speed == 0.0 => {
+                    angular_vel.rotation = rotation;
+                    angular_vel.speed =
+                        (angular_vel.speed + engine.turn_acceleration).min(control.rotation_speed);
+                }
+                // Turning the other way, it has to slow down first
+                Some(rotation) => {
+                    angular_vel.speed -= engine.turn_acceleration;
+                    if angular_vel.speed < 0.0 {
+                        angular_vel.rotation = rotation;
+                        angular_vel.speed = (-angular_vel.speed).min(control.rotation_speed);
+                    }
+                }
+                None => {
+                    angular_vel.speed = (angular_vel.speed - engine.turn_acceleration).max(0.0);
+                }
+            }
+        }
+    }
+}
+
+/// Keeps each tank's turret on its hull, which can be pushed about without it
+pub struct TurretMount;
+
+impl<'a> System<'a> for TurretMount {
+    type SystemData = (
+        WriteStorage<'a, Position>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Health>,
+        ReadStorage<'a, BulletSpawner>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let hulls: BTreeMap<_, _> = (&data.0, &data.1, &data.2)
+            .join()
+            .map(|(pos, control, _)| (control.player, pos.0))
+            .collect();
+        for (pos, control, _) in (&mut data.0, &data.1, &data.3).join() {
+            if let Some(&hull) = hulls.get(&control.player) {
+                pos.0 = hull;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::prelude::{Builder, RunNow, World, WorldExt};
+    use specs::Entity;
+
+    const TOP_SPEED: f32 = 4.0;
+    const TURN_SPEED: f32 = 3.0;
+
+    /// Gains 0.5 a tick driving, loses 2 braking and 0.25 coasting
+    fn engine() -> Engine {
+        Engine {
+            mass: 2.0,
+            acceleration: 1.0,
+            braking: 4.0,
+            friction: 0.25,
+            turn_acceleration: 1.0,
+            throttle: None,
+            turn: None,
+        }
+    }
+
+    fn arena() -> World {
+        let mut world = World::new();
+        System::setup(&mut Drive, &mut world);
+        world
+    }
+
+    /// A tank going `speed` to the right
+    fn tank(world: &mut World, speed: f32) -> Entity {
+        world
+            .create_entity()
+            .with(engine())
+            .with(KeyboardControlled {
+                player: 1,
+                speed: TOP_SPEED,
+                rotation_speed: TURN_SPEED,
+            })
+            .with(Velocity {
+                speed,
+                direction: Angle { angle: 0.0 },
+            })
+            .with(AngularVelocity {
+                speed: 0.0,
+                rotation: Rotation::Clockwise,
+            })
+            .with(Position(Point::new(0, 0)))
+            .build()
+    }
+
+    fn control(world: &mut World, tank: Entity, throttle: Option<f32>, turn: Option<Rotation>) {
+        let mut engines = world.write_storage::<Engine>();
+        let engine = engines.get_mut(tank).expect("tank is alive");
+        engine.throttle = throttle.map(|angle| Angle { angle });
+        engine.turn = turn;
+    }
+
+    /// Runs a tick and returns the tank's speed and direction after it
+    fn run(world: &mut World, tank: Entity) -> (f32, f32) {
+        Drive.run_now(world);
+        world.maintain();
+        let vel = world.read_storage::<Velocity>().get(tank).cloned();
+        let vel = vel.expect("tank is alive");
+        (vel.speed, vel.direction.angle)
+    }
+
+    fn turning(world: &World, tank: Entity) -> (f32, Rotation) {
+        let angular = world.read_storage::<AngularVelocity>();
+        let angular = angular.get(tank).expect("tank is alive");
+        (angular.speed, angular.rotation)
+    }
+
+    #[test]
+    fn tanks_speed_up_to_their_top_speed() {
+        let mut world = arena();
+        let tank = tank(&mut world, 0.0);
+        control(&mut world, tank, Some(90.0), None);
+        let speeds: Vec<f32> = (0..10).map(|_| run(&mut world, tank).0).collect();
+        assert_eq!(
+            speeds,
+            vec![0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.0, 4.0, 4.0]
+        );
+        assert!((run(&mut world, tank).1 - 90.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn driving_back_the_way_it_came_takes_the_brakes() {
+        let mut world = arena();
+        let tank = tank(&mut world, TOP_SPEED);
+        control(&mut world, tank, Some(180.0), None);
+        // Braking, then speeding up the other way once stopped
+        let (speed, direction) = run(&mut world, tank);
+        assert!((speed - 2.0).abs() < 1e-4);
+        assert!(direction.abs() < 1e-4);
+        let (speed, _) = run(&mut world, tank);
+        assert!(speed < 1e-4);
+        let (speed, direction) = run(&mut world, tank);
+        assert!((speed - 0.5).abs() < 1e-4);
+        assert!((direction.abs() - 180.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn tanks_coast_to_a_stop_facing_the_way_they_went() {
+        let mut world = arena();
+        let tank = tank(&mut world, 1.0);
+        let speeds: Vec<f32> = (0..6).map(|_| run(&mut world, tank).0).collect();
+        assert_eq!(speeds, vec![0.75, 0.5, 0.25, 0.0, 0.0, 0.0]);
+        assert_eq!(run(&mut world, tank).1, 0.0);
+    }
+
+    #[test]
+    fn turrets_slow_down_before_turning_the_other_way() {
+        let mut world = arena();
+        let tank = tank(&mut world, 0.0);
+        control(&mut world, tank, None, Some(Rotation::Clockwise));
+        for _ in 0..5 {
+            run(&mut world, tank);
+        }
+        assert_eq!(turning(&world, tank), (TURN_SPEED, Rotation::Clockwise));
+
+        control(&mut world, tank, None, Some(Rotation::CounterClockwise));
+        run(&mut world, tank);
+        assert_eq!(turning(&world, tank), (2.0, Rotation::Clockwise));
+        for _ in 0..3 {
+            run(&mut world, tank);
+        }
+        assert_eq!(turning(&world, tank), (1.0, Rotation::CounterClockwise));
+
+        control(&mut world, tank, None, None);
+        run(&mut world, tank);
+        run(&mut world, tank);
+        assert_eq!(turning(&world, tank).0, 0.0);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::commands::PlayerId;
+use crate::components::Ammo;
+use crate::components::Health;
+use crate::components::KeyboardControlled;
+use crate::components::Pickup;
+use crate::components::Position;
+use crate::components::Sprite;
+use crate::components::StatusEffects;
+use crate::geometry::Rect;
+use crate::status;
+use crate::status::StatusKind;
+use specs::join::Join;
+use specs::storage::WriteStorage;
+use specs::Entities;
+use specs::ReadStorage;
+use specs::System;
+
+/// Index of the pickup spritesheet loaded by the renderer
+pub const PICKUP_SPRITE: usize = 4;
+
+/// How close a tank has to drive to a pickup to take it
+const PICKUP_RADIUS: i32 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickupKind {
+    /// Refills every weapon's spare rounds
+    Ammo,
+    /// Restores the tank to full health
+    Repair,
+    SpeedBoost,
+    Shield,
+    RapidFire,
+}
+
+impl PickupKind {
+    /// Ticks before it reappears once taken
+    pub fn respawn_time(self) -> u32 {
+        match self {
+            PickupKind::Ammo => 600,
+            PickupKind::Repair => 500,
+            PickupKind::SpeedBoost | PickupKind::Shield | PickupKind::RapidFire => 700,
+        }
+    }
+
+    /// Ticks into a match before it first appears
+    pub fn first_spawn(self) -> u32 {
+        match self {
+            PickupKind::Ammo => 0,
+            PickupKind::Repair => 200,
+            PickupKind::SpeedBoost | PickupKind::Shield | PickupKind::RapidFire => 300,
+        }
+    }
+
+    /// The status effect it grants, if it is a power-up
+    fn status(self) -> Option<StatusKind> {
+        match self {
+            PickupKind::Ammo | PickupKind::Repair => None,
+            PickupKind::SpeedBoost => Some(StatusKind::Speed),
+            PickupKind::Shield => Some(StatusKind::Shield),
+            PickupKind::RapidFire => Some(StatusKind::RapidFire),
+        }
+    }
+
+    pub fn sprite(self) -> Sprite {
+        let frame = match self {
+            PickupKind::Ammo => 0,
+            PickupKind::Repair => 1,
+            PickupKind::SpeedBoost => 2,
+            PickupKind::Shield => 3,
+            PickupKind::RapidFire => 4,
+        };
+        Sprite {
+            spritesheet: PICKUP_SPRITE,
+            region: Rect::new(frame * 32, 0, 32, 32),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PickupKind::Ammo => "ammo",
+            PickupKind::Repair => "repair",
+            PickupKind::SpeedBoost => "speedboost",
+            PickupKind::Shield => "shield",
+            PickupKind::RapidFire => "rapidfire",
+        }
+    }
+}
+
+/// Hands out pickups to the tanks that drive over them and brings them back once taken
+pub struct PickupSystem;
+
+impl<'a> System<'a> for PickupSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Pickup>,
+        WriteStorage<'a, Sprite>,
+        WriteStorage<'a, Ammo>,
+        WriteStorage<'a, Health>,
+        WriteStorage<'a, KeyboardControlled>,
+        WriteStorage<'a, StatusEffects>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let entities = data.0;
+        for (entity, pos, pickup) in (&entities, &data.1, &mut data.2).join() {
+            if pickup.respawn_rem > 0 {
+                pickup.respawn_rem -= 1;
+                if pickup.respawn_rem == 0 {
+                    data.3
+                        .insert(entity, pickup.kind.sprite())
+                        .expect("entity is alive");
+                }
+                continue;
+            }
+
+            // Tanks are picked up by their hull. Players with no use for it leave it be.
+            let ammo = &data.4;
+            let controlled = &data.6;
+            let wants = |player: PlayerId, health: &Health| match pickup.kind {
+                PickupKind::Ammo => (controlled, ammo).join().any(|(control, ammo)| {
+                    control.player == player
+                        && ammo
+                            .magazines
+                            .iter()
+                            .any(|magazine| magazine.reserve < magazine.max_reserve)
+                }),
+                PickupKind::Repair => health.current < health.max,
+                _ => true,
+            };
+            // The closest tank that wants it, ties broken on position so the
+            // choice doesn't depend on storage order
+            let taker = (&entities, &data.1, controlled, &data.5)
+                .join()
+                .map(|(hull, tank, control, health)| (hull, tank.0 - pos.0, control.player, health))
+                .filter(|(_, offset, player, health)| {
+                    offset.x().abs() <= PICKUP_RADIUS
+                        && offset.y().abs() <= PICKUP_RADIUS
+                        && wants(*player, health)
+                })
+                .min_by_key(|(_, offset, _, _)| {
+                    (
+                        offset.x().pow(2) + offset.y().pow(2),
+                        offset.x(),
+                        offset.y(),
+                    )
+                })
+                .map(|(hull, _, player, _)| (hull, player));
+            let (hull, player) = match taker {
+                Some(taker) => taker,
+                None => continue,
+            };
+
+            match pickup.kind {
+                PickupKind::Ammo => {
+                    for (control, ammo) in (&data.6, &mut data.4).join() {
+                        if control.player == player {
+                            for magazine in &mut ammo.magazines {
+                                magazine.reserve = magazine.max_reserve;
+                            }
+                        }
+                    }
+                }
+                PickupKind::Repair => {
+                    let health = data.5.get_mut(hull).expect("the hull has health");
+                    health.current = health.max;
+                }
+                kind => {
+                    let status = kind.status().expect("every other pickup is a power-up");
+                    for (tank_entity, control) in (&entities, &mut data.6).join() {
+                        if control.player != player {
+                            continue;
+                        }
+                        let effects = data
+                            .7
+                            .entry(tank_entity)
+                            .expect("entity is alive")
+                            .or_insert_with(StatusEffects::default);
+                        if effects.grant(status) {
+                            status::start(status, control);
+                        }
+                    }
+                }
+            }
+            pickup.respawn_rem = pickup.kind.respawn_time();
+            // Without a sprite it is neither drawn nor sent to clients until it is back
+            data.3.remove(entity);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use crate::game;
+use crate::mode;
+use crate::replay::Replay;
+use crate::resources::Tick;
+use crate::snapshot::WorldSnapshot;
+use specs::prelude::{Dispatcher, World, WorldExt};
+use std::collections::BTreeMap;
+
+/// How often (in ticks) playback keeps a snapshot to seek from
+pub const SNAPSHOT_INTERVAL: u64 = 100;
+
+/// Selectable playback speeds, in ticks per rendered frame
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+
+/// Plays a recorded match with pause, single-step, variable speed and seeking.
+/// Seeking restores the nearest earlier snapshot and re-simulates from there.
+pub struct Playback<'a, 'b> {
+    replay: Replay,
+    dispatcher: Dispatcher<'a, 'b>,
+    pub world: World,
+    snapshots: BTreeMap<u64, WorldSnapshot>,
+    paused: bool,
+    speed: usize,
+    /// Fraction of a tick carried over between frames at speeds below 1x
+    progress: f32,
+}
+
+impl<'a, 'b> Playback<'a, 'b> {
+    pub fn new(replay: Replay) -> Self {
+        let mut dispatcher = game::build_dispatcher();
+        let world = replay.create_world(&mut dispatcher);
+        let mut snapshots = BTreeMap::new();
+        snapshots.insert(0, WorldSnapshot::capture(&world));
+        Playback {
+            replay,
+            dispatcher,
+            world,
+            snapshots,
+            paused: false,
+            speed: NORMAL_SPEED,
+            progress: 0.0,
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.world.read_resource::<Tick>().0
+    }
+
+    pub fn length(&self) -> u64 {
+        self.replay.length
+    }
+
+    pub fn snapshot_ticks(&self) -> Vec<u64> {
+        self.snapshots.keys().cloned().collect()
+    }
+
+    /// Advance playback by one rendered frame
+    pub fn update(&mut self) {
+        if self.paused {
+            return;
+        }
+        self.progress += SPEEDS[self.speed];
+        while self.progress >= 1.0 {
+            self.progress -= 1.0;
+            if !self.step() {
+                self.paused = true;
+                self.progress = 0.0;
+            }
+        }
+    }
+
+    /// Simulate a single tick, returning false once the end of the replay is reached
+    fn step(&mut self) -> bool {
+        let tick = self.tick();
+        if tick >= self.replay.length {
+            return false;
+        }
+        if tick.is_multiple_of(SNAPSHOT_INTERVAL) && !self.snapshots.contains_key(&tick) {
+            self.snapshots
+                .insert(tick, WorldSnapshot::capture(&self.world));
+        }
+        let commands = self.replay.commands_at(tick);
+        game::step(&mut self.world, &mut self.dispatcher, commands);
+        true
+    }
+
+    /// Jump to `target`, restoring the closest snapshot at or before it when it is not ahead of us
+    pub fn seek(&mut self, target: u64) {
+        let target = target.min(self.replay.length);
+        let tick = self.tick();
+        if let Some((&snapshot_tick, snapshot)) = self.snapshots.range(..=target).next_back() {
+            if target < tick || snapshot_tick > tick {
+                snapshot.restore(&mut self.world);
+            }
+        }
+        while self.tick() < target && self.step() {}
+        self.progress = 0.0;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Pause and advance exactly one tick
+    pub fn single_step(&mut self) {
+        self.paused = true;
+        self.step();
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    /// A one line summary of the playback state, and of the match if it was played to rules
+    pub fn status(&self) -> String {
+        let mut status = format!(
+            "rusty-tanks replay - tick {}/{} - {}x{}",
+            self.tick(),
+            self.replay.length,
+            SPEEDS[self.speed],
+            if self.paused { " - paused" } else { "" },
+        );
+        if let Some(game_match) = mode::status(&self.world, game::LOCAL_PLAYER) {
+            status.push_str(" - ");
+            status.push_str(&game_match);
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archetype::TankArchetype;
+    use crate::commands::{Commands, FireCommand, MovementCommand, PlayerCommands};
+    use crate::components::Angle;
+    use crate::mode::{GameMode, Rules};
+
+    /// A deathmatch between two tanks driving about and firing at each other
+    fn replay(length: u64) -> Replay {
+        let mut replay = Replay::new(5);
+        replay.rules = Some(Rules::new(GameMode::Deathmatch));
+        replay.tanks = vec![
+            (1, TankArchetype::Scout, None),
+            (2, TankArchetype::Heavy, None),
+        ];
+        for tick in 0..length {
+            let mut commands = Commands::default();
+            for player in 1..=2 {
+                let phase = tick + u64::from(player) * 13;
+                commands.0.insert(
+                    player,
+                    PlayerCommands {
+                        movement: Some(MovementCommand::Move(Angle {
+                            angle: (phase / 30 * 90 % 360) as f32,
+                        })),
+                        fire: Some(FireCommand::Fire),
+                        ..PlayerCommands::default()
+                    },
+                );
+            }
+            replay.record(tick, &commands);
+        }
+        replay
+    }
+
+    fn checksum(playback: &Playback) -> u64 {
+        WorldSnapshot::capture(&playback.world).checksum()
+    }
+
+    /// Where playing `replay` through from the start is after each tick up to `ticks`
+    fn played_through(replay: &Replay, ticks: u64) -> Vec<u64> {
+        let mut playback = Playback::new(replay.clone());
+        let mut checksums = vec![checksum(&playback)];
+        for _ in 0..ticks {
+            playback.single_step();
+            checksums.push(checksum(&playback));
+        }
+        checksums
+    }
+
+    #[test]
+    fn seeking_lands_where_playing_through_does() {
+        let replay = replay(350);
+        let through = played_through(&replay, 350);
+
+        let mut playback = Playback::new(replay);
+        for target in [320, 250, 100, 0, 99, 201, 350] {
+            playback.seek(target);
+            assert_eq!(playback.tick(), target);
+            assert_eq!(
+                checksum(&playback),
+                through[target as usize],
+                "at {}",
+                target
+            );
+        }
+        // Past the end stays at the end
+        playback.seek(1000);
+        assert_eq!(playback.tick(), 350);
+        assert_eq!(checksum(&playback), through[350]);
+    }
+
+    #[test]
+    fn snapshots_are_kept_every_interval() {
+        let mut playback = Playback::new(replay(350));
+        assert_eq!(playback.snapshot_ticks(), vec![0]);
+        playback.seek(320);
+        assert_eq!(playback.snapshot_ticks(), vec![0, 100, 200, 300]);
+        // Going back over them doesn't add any
+        playback.seek(50);
+        playback.seek(250);
+        assert_eq!(playback.snapshot_ticks(), vec![0, 100, 200, 300]);
+    }
+
+    #[test]
+    fn speed_is_how_many_ticks_a_frame_plays() {
+        let mut playback = Playback::new(replay(350));
+        playback.update();
+        assert_eq!(playback.tick(), 1);
+
+        for _ in 0..SPEEDS.len() {
+            playback.slower();
+        }
+        // A quarter of a tick a frame
+        for _ in 0..3 {
+            playback.update();
+        }
+        assert_eq!(playback.tick(), 1);
+        playback.update();
+        assert_eq!(playback.tick(), 2);
+
+        for _ in 0..SPEEDS.len() {
+            playback.faster();
+        }
+        playback.update();
+        assert_eq!(playback.tick(), 10);
+
+        playback.toggle_pause();
+        playback.update();
+        assert_eq!(playback.tick(), 10);
+        playback.toggle_pause();
+
+        // It pauses at the end
+        playback.seek(345);
+        playback.update();
+        assert_eq!(playback.tick(), 350);
+        assert!(playback.status().contains("paused"));
+    }
+}
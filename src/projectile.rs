@@ -0,0 +1,118 @@
+use crate::commands::PlayerId;
+use crate::components::Angle;
+use crate::components::KeyboardControlled;
+use crate::components::Position;
+use crate::components::Projectile;
+use crate::components::Team;
+use crate::components::Velocity;
+use crate::components::Wall;
+use crate::damage;
+use crate::geometry::Point;
+use crate::raycast;
+use crate::spatial::SpatialIndex;
+use specs::join::Join;
+use specs::storage::WriteStorage;
+use specs::Entities;
+use specs::Entity;
+use specs::LazyUpdate;
+use specs::Read;
+use specs::ReadStorage;
+use specs::System;
+use std::collections::HashMap;
+
+/// Expires projectiles at the end of their range, explosive ones going off where they are,
+/// and steers homing ones towards their target
+pub struct ProjectileSystem;
+
+impl<'a> System<'a> for ProjectileSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Velocity>,
+        WriteStorage<'a, Angle>,
+        WriteStorage<'a, Projectile>,
+        ReadStorage<'a, KeyboardControlled>,
+        ReadStorage<'a, Team>,
+        Read<'a, LazyUpdate>,
+        Read<'a, SpatialIndex>,
+        ReadStorage<'a, Wall>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let entities = data.0;
+
+        // Anything that moves and isn't a shot itself can be homed in on
+        let targets: HashMap<Entity, (Point, Option<PlayerId>, Option<Team>)> = (
+            &entities,
+            &data.1,
+            &data.2,
+            !&data.4,
+            data.5.maybe(),
+            data.6.maybe(),
+        )
+            .join()
+            .map(|(entity, pos, _, _, control, team)| {
+                let player = control.map(|control| control.player);
+                (entity, (pos.0, player, team.cloned()))
+            })
+            .collect();
+
+        let (index, walls) = (&data.8, &data.9);
+        let mut expired = Vec::new();
+        for (entity, pos, vel, angle, projectile, team) in (
+            &entities,
+            &data.1,
+            &mut data.2,
+            &mut data.3,
+            &mut data.4,
+            data.6.maybe(),
+        )
+            .join()
+        {
+            if projectile.ticks_left == 0 {
+                damage::detonate(&entities, &data.7, entity, pos.0, projectile, team.cloned());
+                expired.push(entity);
+                continue;
+            }
+            projectile.ticks_left -= 1;
+
+            let turn_rate = projectile.weapon.homing();
+            if turn_rate <= 0.0 {
+                continue;
+            }
+            // Only what it can see and could still reach before it expires. Ties are broken
+            // on position so the choice doesn't depend on storage order.
+            let reach = (vel.speed * (projectile.ticks_left + 1) as f32).ceil() as i32;
+            let target = index
+                .in_radius(pos.0, reach)
+                .iter()
+                .filter_map(|entry| targets.get(&entry.entity))
+                .filter(|(target, player, target_team)| {
+                    let own = player.is_some() && *player == projectile.owner;
+                    let friendly = team.is_some() && target_team.as_ref() == team;
+                    !own && !friendly && raycast::line_of_sight(index, walls, pos.0, *target)
+                })
+                .map(|(target, _, _)| *target - pos.0)
+                .min_by_key(|offset| {
+                    (
+                        i64::from(offset.x()).pow(2) + i64::from(offset.y()).pow(2),
+                        offset.x(),
+                        offset.y(),
+                    )
+                });
+            if let Some(offset) = target {
+                let wanted = (offset.y() as f32).atan2(offset.x() as f32).to_degrees();
+                let mut turn = (wanted - vel.direction.angle).rem_euclid(360.0);
+                if turn > 180.0 {
+                    turn -= 360.0;
+                }
+                vel.direction.angle += turn.max(-turn_rate).min(turn_rate);
+                angle.angle = vel.direction.angle;
+            }
+        }
+        // Later systems this tick must not see them
+        for entity in expired {
+            data.4.remove(entity);
+        }
+    }
+}
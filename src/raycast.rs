@@ -0,0 +1,176 @@
+use crate::components::Collider;
+use crate::components::Wall;
+use crate::geometry::Point;
+use crate::spatial::SpatialIndex;
+use specs::Entity;
+use specs::ReadStorage;
+
+/// The first solid thing a ray ran into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub entity: Entity,
+    /// Where the ray first touched it
+    pub point: Point,
+    /// The outward facing normal of the side it was touched on, nothing if the ray started inside it
+    pub normal: Point,
+}
+
+/// Where a ray `length` long from `from` heading at `angle` degrees ends
+pub fn ray_end(from: Point, angle: f32, length: f32) -> Point {
+    let radians = angle.to_radians();
+    from + Point::new(
+        (length * radians.cos()).round() as i32,
+        (length * radians.sin()).round() as i32,
+    )
+}
+
+/// The first thing with a collider on the line from `from` to `to` that `hits` doesn't pass through
+pub fn raycast(
+    index: &SpatialIndex,
+    colliders: &ReadStorage<Collider>,
+    from: Point,
+    to: Point,
+    hits: impl Fn(Entity) -> bool,
+) -> Option<RayHit> {
+    let offset = to - from;
+    index
+        .along(from, to)
+        .into_iter()
+        .find(|(_, _, entry)| colliders.contains(entry.entity) && hits(entry.entity))
+        .map(|(t, normal, entry)| RayHit {
+            entity: entry.entity,
+            point: from
+                + Point::new(
+                    (offset.x() as f32 * t).round() as i32,
+                    (offset.y() as f32 * t).round() as i32,
+                ),
+            normal,
+        })
+}
+
+/// Whether `to` can be seen from `from`. Only walls get in the way, and a wall doesn't hide itself.
+pub fn line_of_sight(
+    index: &SpatialIndex,
+    walls: &ReadStorage<Wall>,
+    from: Point,
+    to: Point,
+) -> bool {
+    index
+        .along(from, to)
+        .iter()
+        .all(|(_, _, entry)| !walls.contains(entry.entity) || entry.bounds.contains_point(to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Position;
+    use crate::spatial::Indexing;
+    use specs::prelude::{Builder, RunNow, System, World, WorldExt};
+
+    /// A world with a 32 by 32 wall centred on the origin, and a crate the same size that isn't a wall
+    fn arena() -> (World, Entity, Entity) {
+        let mut world = World::new();
+        System::setup(&mut Indexing, &mut world);
+        world.register::<Wall>();
+        let collider = Collider {
+            width: 32,
+            height: 32,
+        };
+        let wall = world
+            .create_entity()
+            .with(Position(Point::new(0, 0)))
+            .with(collider)
+            .with(Wall)
+            .build();
+        let block = world
+            .create_entity()
+            .with(Position(Point::new(200, 0)))
+            .with(collider)
+            .build();
+        Indexing.run_now(&world);
+        (world, wall, block)
+    }
+
+    fn cast(world: &World, from: (i32, i32), to: (i32, i32)) -> Option<RayHit> {
+        raycast(
+            &world.read_resource(),
+            &world.read_storage(),
+            Point::new(from.0, from.1),
+            Point::new(to.0, to.1),
+            |_| true,
+        )
+    }
+
+    fn sees(world: &World, from: (i32, i32), to: (i32, i32)) -> bool {
+        line_of_sight(
+            &world.read_resource(),
+            &world.read_storage(),
+            Point::new(from.0, from.1),
+            Point::new(to.0, to.1),
+        )
+    }
+
+    #[test]
+    fn rays_stop_at_whichever_side_they_reach_first() {
+        let (world, wall, _) = arena();
+        // The wall covers -16 to 15 either way, the hit is on its outermost pixel
+        for (from, point, normal) in [
+            ((-100, 0), (-16, 0), (-1, 0)),
+            ((100, 0), (15, 0), (1, 0)),
+            ((0, -100), (0, -16), (0, -1)),
+            ((0, 100), (0, 15), (0, 1)),
+        ] {
+            let hit = cast(&world, from, (0, 0)).expect("the ray hits the wall");
+            assert_eq!(hit.entity, wall);
+            assert_eq!(hit.point, Point::new(point.0, point.1), "from {:?}", from);
+            assert_eq!(
+                hit.normal,
+                Point::new(normal.0, normal.1),
+                "from {:?}",
+                from
+            );
+        }
+    }
+
+    #[test]
+    fn rays_starting_inside_have_no_normal() {
+        let (world, wall, _) = arena();
+        let hit = cast(&world, (5, 5), (100, 5)).expect("the ray hits the wall");
+        assert_eq!(hit.entity, wall);
+        assert_eq!(hit.point, Point::new(5, 5));
+        assert_eq!(hit.normal, Point::new(0, 0));
+    }
+
+    #[test]
+    fn rays_pass_what_they_miss_or_are_told_to_ignore() {
+        let (world, wall, block) = arena();
+        assert_eq!(cast(&world, (-100, 50), (300, 50)), None);
+        let hit = raycast(
+            &world.read_resource(),
+            &world.read_storage(),
+            Point::new(-100, 0),
+            Point::new(300, 0),
+            |entity| entity != wall,
+        );
+        assert_eq!(hit.map(|hit| hit.entity), Some(block));
+    }
+
+    #[test]
+    fn only_walls_block_the_view() {
+        let (world, _, _) = arena();
+        assert!(!sees(&world, (-100, 0), (100, 0)));
+        assert!(sees(&world, (-100, 50), (100, 50)));
+        // Nor does a wall hide itself
+        assert!(sees(&world, (-100, 0), (0, 0)));
+        assert!(sees(&world, (100, 0), (300, 0)));
+    }
+
+    #[test]
+    fn rays_end_their_length_away() {
+        assert_eq!(ray_end(Point::new(10, 10), 0.0, 5.0), Point::new(15, 10));
+        // Down the screen is towards positive y
+        assert_eq!(ray_end(Point::new(10, 10), 90.0, 5.0), Point::new(10, 15));
+        assert_eq!(ray_end(Point::new(0, 0), 225.0, 10.0), Point::new(-7, -7));
+    }
+}
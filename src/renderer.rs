@@ -1,18 +1,34 @@
-use crate::components::Angle;
-use crate::components::Position;
-use crate::components::Sprite;
+use rusty_tanks::commands::PlayerId;
+use rusty_tanks::components::Angle;
+use rusty_tanks::components::BulletSpawner;
+use rusty_tanks::components::Collider;
+use rusty_tanks::components::KeyboardControlled;
+use rusty_tanks::components::Position;
+use rusty_tanks::components::Sprite;
+use rusty_tanks::fog::{Fog, FOG_CELL};
+use rusty_tanks::game::DrawData;
+use rusty_tanks::geometry;
+use rusty_tanks::particles::Particles;
+use rusty_tanks::raycast;
+use rusty_tanks::spatial::SpatialIndex;
 use sdl2::pixels::Color;
 use sdl2::rect::{Point, Rect};
-use sdl2::render::{Texture, WindowCanvas};
+use sdl2::render::{BlendMode, Texture, WindowCanvas};
 use specs::join::Join;
-use specs::ReadStorage;
+use specs::prelude::{World, WorldExt};
 
-// Type alias for the data needed by the renderer
-pub type SystemData<'a> = (
-    ReadStorage<'a, Position>,
-    ReadStorage<'a, Sprite>,
-    ReadStorage<'a, Angle>,
-);
+/// How far the laser sight reaches when nothing is in the way
+const SIGHT_LENGTH: f32 = 600.0;
+
+/// Private fn to turn a point in the world into one SDL can draw at
+fn sdl_point(point: geometry::Point) -> Point {
+    Point::new(point.x(), point.y())
+}
+
+/// Private fn to turn a rectangle in the world into one SDL can draw
+fn sdl_rect(rect: geometry::Rect) -> Rect {
+    Rect::new(rect.x(), rect.y(), rect.width(), rect.height())
+}
 
 /// Private fn to render a sprite
 fn render_sprite(
@@ -24,9 +40,9 @@ fn render_sprite(
 ) -> Result<(), String> {
     let (width, height) = canvas.output_size()?;
 
-    let current_frame = sprite.region;
+    let current_frame = sdl_rect(sprite.region);
     // Treat the center of the screen as the (0, 0) coordinate
-    let screen_position = pos.0 + Point::new(width as i32 / 2, height as i32 / 2);
+    let screen_position = sdl_point(pos.0) + Point::new(width as i32 / 2, height as i32 / 2);
     let screen_rect = Rect::from_center(
         screen_position,
         current_frame.width(),
@@ -49,16 +65,175 @@ pub fn render(
     canvas: &mut WindowCanvas,
     background: Color,
     textures: &[Texture],
-    data: SystemData,
+    data: DrawData,
+    particles: &Particles,
+    fog: Option<&Fog>,
+) -> Result<(), String> {
+    draw(canvas, background, textures, data, particles, fog)?;
+
+    canvas.present();
+
+    Ok(())
+}
+
+/// Draw the world without presenting it, so overlays can be drawn on top.
+/// With `fog`, only what its player can see is drawn.
+pub fn draw(
+    canvas: &mut WindowCanvas,
+    background: Color,
+    textures: &[Texture],
+    data: DrawData,
+    particles: &Particles,
+    fog: Option<&Fog>,
 ) -> Result<(), String> {
     canvas.set_draw_color(background);
     canvas.clear();
 
-    for (pos, sprite, angle) in (&data.0, &data.1, &data.2).join() {
+    // The ground goes under everything else
+    for (centre, sprite) in data.3.sprites() {
+        render_sprite(canvas, &Position(centre), 0.0, &sprite, textures)?;
+    }
+    for (entity, pos, sprite, angle) in (&data.4, &data.0, &data.1, &data.2).join() {
+        if fog.is_some_and(|fog| fog.hides(entity)) {
+            continue;
+        }
         render_sprite(canvas, pos, angle.angle, sprite, textures)?;
     }
+    draw_particles(canvas, particles)?;
+    if let Some(fog) = fog {
+        draw_fog(canvas, fog)?;
+    }
 
-    canvas.present();
+    Ok(())
+}
+
+/// Private fn to draw every particle as a square, blended over what is already drawn
+fn draw_particles(canvas: &mut WindowCanvas, particles: &Particles) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let centre = Point::new(width as i32 / 2, height as i32 / 2);
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    for (position, size, (red, green, blue, alpha)) in particles.iter() {
+        canvas.set_draw_color(Color::RGBA(red, green, blue, alpha));
+        canvas.fill_rect(Rect::from_center(sdl_point(position) + centre, size, size))?;
+    }
+    canvas.set_blend_mode(BlendMode::None);
+
+    Ok(())
+}
+
+/// Private fn to black out where the player has never seen and darken where they can't see now
+fn draw_fog(canvas: &mut WindowCanvas, fog: &Fog) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let centre = Point::new(width as i32 / 2, height as i32 / 2);
+    let cell = FOG_CELL as u32;
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    // Squares line up with the fog's grid in the world, not with the screen
+    let first = (-centre.x()).div_euclid(FOG_CELL) * FOG_CELL;
+    let top = (-centre.y()).div_euclid(FOG_CELL) * FOG_CELL;
+    for y in (top..height as i32 - centre.y()).step_by(cell as usize) {
+        for x in (first..width as i32 - centre.x()).step_by(cell as usize) {
+            let square = geometry::Rect::new(x, y, cell, cell);
+            let alpha = if !fog.explored(square.center()) {
+                255
+            } else if !fog.in_view(square.center()) {
+                150
+            } else {
+                continue;
+            };
+            canvas.set_draw_color(Color::RGBA(0, 0, 0, alpha));
+            canvas.fill_rect(Rect::new(x + centre.x(), y + centre.y(), cell, cell))?;
+        }
+    }
+    canvas.set_blend_mode(BlendMode::None);
+
+    Ok(())
+}
+
+/// Draw a laser sight from `player`'s turret to the first solid thing it is aimed at,
+/// passing through whatever `fog` hides
+pub fn draw_laser_sight(
+    canvas: &mut WindowCanvas,
+    world: &World,
+    player: PlayerId,
+    fog: Option<&Fog>,
+) -> Result<(), String> {
+    let positions = world.read_storage::<Position>();
+    let angles = world.read_storage::<Angle>();
+    let controlled = world.read_storage::<KeyboardControlled>();
+    let spawners = world.read_storage::<BulletSpawner>();
+    let turret = (&positions, &angles, &controlled, &spawners)
+        .join()
+        .find(|(_, _, control, _)| control.player == player);
+    let (from, angle) = match turret {
+        Some((pos, angle, _, _)) => (pos.0, angle.angle),
+        None => return Ok(()),
+    };
+
+    let end = raycast::ray_end(from, angle, SIGHT_LENGTH);
+    let hit = raycast::raycast(
+        &world.read_resource::<SpatialIndex>(),
+        &world.read_storage::<Collider>(),
+        from,
+        end,
+        |target| {
+            controlled
+                .get(target)
+                .is_none_or(|control| control.player != player)
+                && !fog.is_some_and(|fog| fog.hides(target))
+        },
+    );
+    let to = hit.map_or(end, |hit| hit.point);
+
+    let (width, height) = canvas.output_size()?;
+    let centre = Point::new(width as i32 / 2, height as i32 / 2);
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(255, 40, 40, 120));
+    canvas.draw_line(sdl_point(from) + centre, sdl_point(to) + centre)?;
+    if hit.is_some() {
+        canvas.set_draw_color(Color::RGBA(255, 40, 40, 220));
+        canvas.fill_rect(Rect::from_center(sdl_point(to) + centre, 3, 3))?;
+    }
+    canvas.set_blend_mode(BlendMode::None);
+
+    Ok(())
+}
+
+/// The on-screen area the replay timeline occupies
+pub fn timeline_rect(canvas: &WindowCanvas) -> Result<Rect, String> {
+    let (width, height) = canvas.output_size()?;
+    Ok(Rect::new(10, height as i32 - 20, width - 20, 10))
+}
+
+/// Draw a replay timeline: the played portion, a marker for every snapshot and the play head
+pub fn draw_timeline(
+    canvas: &mut WindowCanvas,
+    tick: u64,
+    length: u64,
+    snapshot_ticks: &[u64],
+) -> Result<(), String> {
+    let bar = timeline_rect(canvas)?;
+    let length = length.max(1);
+    let x_at = |tick: u64| bar.x() + (bar.width() as u64 * tick.min(length) / length) as i32;
+
+    canvas.set_draw_color(Color::RGB(40, 40, 40));
+    canvas.fill_rect(bar)?;
+
+    let played = (x_at(tick) - bar.x()) as u32;
+    if played > 0 {
+        canvas.set_draw_color(Color::RGB(200, 200, 200));
+        canvas.fill_rect(Rect::new(bar.x(), bar.y(), played, bar.height()))?;
+    }
+
+    canvas.set_draw_color(Color::RGB(90, 160, 255));
+    for &snapshot_tick in snapshot_ticks {
+        let x = x_at(snapshot_tick);
+        canvas.draw_line(Point::new(x, bar.bottom()), Point::new(x, bar.bottom() + 4))?;
+    }
+
+    canvas.set_draw_color(Color::RGB(255, 60, 60));
+    canvas.fill_rect(Rect::new(x_at(tick) - 1, bar.y() - 3, 3, bar.height() + 6))?;
 
     Ok(())
 }
@@ -0,0 +1,432 @@
+use crate::archetype::TankArchetype;
+use crate::commands::Commands;
+use crate::commands::FireCommand;
+use crate::commands::MovementCommand;
+use crate::commands::PlayerCommands;
+use crate::commands::PlayerId;
+use crate::commands::RotationCommand;
+use crate::commands::WeaponCommand;
+use crate::components::Angle;
+use crate::components::Rotation;
+use crate::components::Team;
+use crate::game;
+use crate::mode::{GameMode, Rules};
+use specs::prelude::{Dispatcher, World};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const HEADER: &str = "rusty-tanks-replay 5";
+
+/// Replays recorded before the simulation had its own random number generator, they no longer play back the same
+const OLD_HEADERS: [&str; 4] = [
+    "rusty-tanks-replay 1",
+    "rusty-tanks-replay 2",
+    "rusty-tanks-replay 3",
+    "rusty-tanks-replay 4",
+];
+
+/// Every player's commands for every tick of a match, plus the seed the world was created with,
+/// the rules it was played to and the tanks added to it. Feeding these back into a world created
+/// the same way reproduces the match exactly.
+///
+/// The file format is line based:
+/// ```text
+/// rusty-tanks-replay 5
+/// seed <u64>
+/// length <ticks>
+/// mode <mode> <time limit> <score limit>
+/// tank <player> <archetype> <team>
+/// <tick> <player> <movement> <rotation> <fire> <weapon>
+/// ```
+/// where `-` means "no command", or no limit or team, the mode line is only there for matches
+/// played to rules and only ticks with at least one command are written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    /// The number of ticks recorded
+    pub length: u64,
+    /// The rules the match was played to, if it had any
+    pub rules: Option<Rules>,
+    /// The tanks added to the world besides the usual ones, in the order they were spawned.
+    /// In a match played to rules these are all of them.
+    pub tanks: Vec<(PlayerId, TankArchetype, Option<Team>)>,
+    frames: BTreeMap<u64, Commands>,
+}
+
+impl Replay {
+    pub fn new(seed: u64) -> Self {
+        Replay {
+            seed,
+            length: 0,
+            rules: None,
+            tanks: Vec::new(),
+            frames: BTreeMap::new(),
+        }
+    }
+
+    /// Record the commands that were applied on `tick`
+    pub fn record(&mut self, tick: u64, commands: &Commands) {
+        let commands = Commands(
+            commands
+                .0
+                .iter()
+                .filter(|(_, player_commands)| !player_commands.is_empty())
+                .map(|(&player, &player_commands)| (player, player_commands))
+                .collect(),
+        );
+        if !commands.0.is_empty() {
+            self.frames.insert(tick, commands);
+        }
+        self.length = self.length.max(tick + 1);
+    }
+
+    /// The world the match started from, created the way it was when it was recorded
+    pub fn create_world(&self, dispatcher: &mut Dispatcher) -> World {
+        match self.rules {
+            Some(rules) => game::create_game(dispatcher, self.seed, rules, &self.tanks),
+            None => {
+                let mut world = game::create_world(dispatcher, self.seed);
+                game::spawn_tanks(&mut world, &self.tanks);
+                world
+            }
+        }
+    }
+
+    /// The commands to apply on `tick`
+    pub fn commands_at(&self, tick: u64) -> Commands {
+        self.frames.get(&tick).cloned().unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut out = format!("{}\nseed {}\nlength {}\n", HEADER, self.seed, self.length);
+        if let Some(rules) = &self.rules {
+            out.push_str(&format!(
+                "mode {} {} {}\n",
+                rules.mode,
+                format_optional(rules.time_limit),
+                format_optional(rules.score_limit)
+            ));
+        }
+        for (player, archetype, team) in &self.tanks {
+            out.push_str(&format!(
+                "tank {} {} {}\n",
+                player,
+                archetype,
+                format_optional(team.map(|team| team.0))
+            ));
+        }
+        for (tick, commands) in &self.frames {
+            for (player, player_commands) in &commands.0 {
+                out.push_str(&format!(
+                    "{} {} {} {} {} {}\n",
+                    tick,
+                    player,
+                    format_movement(player_commands.movement),
+                    format_rotation(player_commands.rotation),
+                    format_fire(player_commands.fire),
+                    format_weapon(player_commands.weapon),
+                ));
+            }
+        }
+        fs::write(path, out).map_err(|e| format!("could not write replay {:?}: {}", path, e))
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read replay {:?}: {}", path, e))?;
+        Replay::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Replay, String> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            Some((_, header)) if OLD_HEADERS.contains(&header) => {
+                return Err("replay was recorded by an older version of the game".to_string())
+            }
+            _ => return Err("not a rusty-tanks replay".to_string()),
+        }
+
+        let mut seed = None;
+        let mut length = None;
+        let mut rules = None;
+        let mut tanks = Vec::new();
+        let mut frames: BTreeMap<u64, Commands> = BTreeMap::new();
+        for (number, line) in lines {
+            let error = |what: &str| format!("replay line {}: {}", number + 1, what);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                ["seed", value] => seed = Some(value.parse().map_err(|_| error("bad seed"))?),
+                ["length", value] => length = Some(value.parse().map_err(|_| error("bad length"))?),
+                ["mode", mode, time_limit, score_limit] => {
+                    rules = Some(Rules {
+                        mode: GameMode::from_name(mode).ok_or_else(|| error("bad mode"))?,
+                        time_limit: parse_optional(time_limit)
+                            .ok_or_else(|| error("bad time limit"))?,
+                        score_limit: parse_optional(score_limit)
+                            .ok_or_else(|| error("bad score limit"))?,
+                    })
+                }
+                ["tank", player, archetype, team] => tanks.push((
+                    player.parse().map_err(|_| error("bad player"))?,
+                    TankArchetype::from_name(archetype).ok_or_else(|| error("bad archetype"))?,
+                    parse_optional(team)
+                        .ok_or_else(|| error("bad team"))?
+                        .map(Team),
+                )),
+                [tick, player, movement, rotation, fire, weapon] => {
+                    let tick: u64 = tick.parse().map_err(|_| error("bad tick"))?;
+                    let player = player.parse().map_err(|_| error("bad player"))?;
+                    let player_commands = PlayerCommands {
+                        movement: parse_movement(movement).ok_or_else(|| error("bad movement"))?,
+                        rotation: parse_rotation(rotation).ok_or_else(|| error("bad rotation"))?,
+                        fire: parse_fire(fire).ok_or_else(|| error("bad fire"))?,
+                        weapon: parse_weapon(weapon).ok_or_else(|| error("bad weapon"))?,
+                    };
+                    frames
+                        .entry(tick)
+                        .or_default()
+                        .0
+                        .insert(player, player_commands);
+                }
+                _ => return Err(error("unrecognised line")),
+            }
+        }
+
+        Ok(Replay {
+            seed: seed.ok_or("replay is missing its seed")?,
+            length: length.ok_or("replay is missing its length")?,
+            rules,
+            tanks,
+            frames,
+        })
+    }
+}
+
+/// A value that may be left out, written as `-` when it is
+fn format_optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
+
+fn parse_optional<T: std::str::FromStr>(text: &str) -> Option<Option<T>> {
+    match text {
+        "-" => Some(None),
+        value => value.parse().ok().map(Some),
+    }
+}
+
+fn format_movement(command: Option<MovementCommand>) -> String {
+    match command {
+        None => "-".to_string(),
+        Some(MovementCommand::Stop) => "stop".to_string(),
+        // f32's Display is the shortest representation that round trips exactly
+        Some(MovementCommand::Move(angle)) => angle.angle.to_string(),
+    }
+}
+
+fn parse_movement(text: &str) -> Option<Option<MovementCommand>> {
+    match text {
+        "-" => Some(None),
+        "stop" => Some(Some(MovementCommand::Stop)),
+        angle => angle
+            .parse()
+            .ok()
+            .map(|angle| Some(MovementCommand::Move(Angle { angle }))),
+    }
+}
+
+fn format_rotation(command: Option<RotationCommand>) -> &'static str {
+    match command {
+        None => "-",
+        Some(RotationCommand::Stop) => "stop",
+        Some(RotationCommand::Move(Rotation::Clockwise)) => "cw",
+        Some(RotationCommand::Move(Rotation::CounterClockwise)) => "ccw",
+    }
+}
+
+fn parse_rotation(text: &str) -> Option<Option<RotationCommand>> {
+    match text {
+        "-" => Some(None),
+        "stop" => Some(Some(RotationCommand::Stop)),
+        "cw" => Some(Some(RotationCommand::Move(Rotation::Clockwise))),
+        "ccw" => Some(Some(RotationCommand::Move(Rotation::CounterClockwise))),
+        _ => None,
+    }
+}
+
+fn format_fire(command: Option<FireCommand>) -> &'static str {
+    match command {
+        None => "-",
+        Some(FireCommand::Stop) => "stop",
+        Some(FireCommand::Fire) => "fire",
+    }
+}
+
+fn parse_fire(text: &str) -> Option<Option<FireCommand>> {
+    match text {
+        "-" => Some(None),
+        "stop" => Some(Some(FireCommand::Stop)),
+        "fire" => Some(Some(FireCommand::Fire)),
+        _ => None,
+    }
+}
+
+fn format_weapon(command: Option<WeaponCommand>) -> String {
+    match command {
+        None => "-".to_string(),
+        Some(WeaponCommand::Next) => "next".to_string(),
+        Some(WeaponCommand::Select(slot)) => slot.to_string(),
+    }
+}
+
+fn parse_weapon(text: &str) -> Option<Option<WeaponCommand>> {
+    match text {
+        "-" => Some(None),
+        "next" => Some(Some(WeaponCommand::Next)),
+        slot => slot
+            .parse()
+            .ok()
+            .map(|slot| Some(WeaponCommand::Select(slot))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::Tick;
+    use crate::snapshot::WorldSnapshot;
+    use specs::prelude::WorldExt;
+    use std::path::PathBuf;
+
+    const TANKS: [(PlayerId, TankArchetype, Option<Team>); 3] = [
+        (0, TankArchetype::Scout, Some(Team(0))),
+        (1, TankArchetype::Heavy, Some(Team(1))),
+        (2, TankArchetype::Medium, Some(Team(1))),
+    ];
+
+    /// Each tank driving, turning, firing and switching weapons now and then
+    fn commands(tick: u64) -> Commands {
+        let mut commands = Commands::default();
+        for &(player, _, _) in &TANKS {
+            let phase = tick + u64::from(player) * 7;
+            let player_commands = PlayerCommands {
+                movement: match phase % 30 {
+                    0 => Some(MovementCommand::Move(Angle {
+                        angle: (phase * 37 % 360) as f32 + 0.5,
+                    })),
+                    20 => Some(MovementCommand::Stop),
+                    _ => None,
+                },
+                rotation: match phase % 25 {
+                    0 => Some(RotationCommand::Move(Rotation::Clockwise)),
+                    10 => Some(RotationCommand::Move(Rotation::CounterClockwise)),
+                    20 => Some(RotationCommand::Stop),
+                    _ => None,
+                },
+                fire: match phase % 15 {
+                    0 => Some(FireCommand::Fire),
+                    5 => Some(FireCommand::Stop),
+                    _ => None,
+                },
+                weapon: match phase % 50 {
+                    0 => Some(WeaponCommand::Next),
+                    25 => Some(WeaponCommand::Select(0)),
+                    _ => None,
+                },
+            };
+            commands.0.insert(player, player_commands);
+        }
+        commands
+    }
+
+    /// Play `ticks` of a match to `rules`, recording it, and how the world was left
+    fn record(rules: Option<Rules>, ticks: u64) -> (Replay, u64) {
+        let mut replay = Replay::new(7);
+        replay.rules = rules;
+        replay.tanks = TANKS.to_vec();
+        let mut dispatcher = game::build_dispatcher();
+        let mut world = replay.create_world(&mut dispatcher);
+        for tick in 0..ticks {
+            let commands = commands(tick);
+            replay.record(tick, &commands);
+            game::step(&mut world, &mut dispatcher, commands);
+        }
+        (replay, WorldSnapshot::capture(&world).checksum())
+    }
+
+    /// A file of its own in the temporary directory, removed once the test is done with it
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> TempFile {
+            let file = format!("rusty-tanks-{}-{}.replay", name, std::process::id());
+            TempFile(std::env::temp_dir().join(file))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn saved_replays_load_the_same() {
+        for rules in [None, Some(Rules::new(GameMode::CaptureTheFlag))] {
+            let (replay, _) = record(rules, 120);
+            let file = TempFile::new("round-trip");
+            replay.save(&file.0).expect("replay saves");
+            assert_eq!(Replay::load(&file.0), Ok(replay));
+        }
+
+        let mut replay = Replay::new(u64::MAX);
+        replay.rules = Some(Rules {
+            mode: GameMode::Elimination,
+            time_limit: None,
+            score_limit: None,
+        });
+        replay.tanks = vec![(5, TankArchetype::Scout, None)];
+        let file = TempFile::new("limits");
+        replay.save(&file.0).expect("replay saves");
+        assert_eq!(Replay::load(&file.0), Ok(replay));
+    }
+
+    #[test]
+    fn playing_a_replay_back_ends_in_the_same_world() {
+        for rules in [None, Some(Rules::new(GameMode::TeamDeathmatch))] {
+            let (replay, checksum) = record(rules, 400);
+            let file = TempFile::new("playback");
+            replay.save(&file.0).expect("replay saves");
+            let replay = Replay::load(&file.0).expect("replay loads");
+
+            let mut dispatcher = game::build_dispatcher();
+            let mut world = replay.create_world(&mut dispatcher);
+            while world.read_resource::<Tick>().0 < replay.length {
+                let tick = world.read_resource::<Tick>().0;
+                game::step(&mut world, &mut dispatcher, replay.commands_at(tick));
+            }
+            assert_eq!(WorldSnapshot::capture(&world).checksum(), checksum);
+        }
+    }
+
+    #[test]
+    fn malformed_replays_are_rejected() {
+        let header = format!("{}\nseed 1\nlength 2\n", HEADER);
+        assert!(Replay::parse(&header).is_ok());
+        for text in [
+            "".to_string(),
+            "seed 1\nlength 2\n".to_string(),
+            "rusty-tanks-replay 4\nseed 1\nlength 2\n".to_string(),
+            format!("{}\nlength 2\n", HEADER),
+            format!("{}\nseed 1\n", HEADER),
+            format!("{}mode chess - -\n", header),
+            format!("{}tank 1 tiger -\n", header),
+            format!("{}0 1 north - - -\n", header),
+            format!("{}0 1 - - - -\n0 1 - -\n", header),
+        ] {
+            assert!(Replay::parse(&text).is_err(), "{:?} was accepted", text);
+        }
+    }
+}
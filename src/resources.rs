@@ -0,0 +1,24 @@
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+
+/// The number of simulation steps that have been run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tick(pub u64);
+
+/// The only source of randomness the simulation may use.
+/// Seeding it explicitly keeps every run (and replay) reproducible. It is a named generator, as
+/// `StdRng` is free to change between versions of rand and give old replays different numbers.
+#[derive(Debug, Clone)]
+pub struct GameRng {
+    pub seed: u64,
+    pub rng: ChaChaRng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng {
+            seed,
+            rng: ChaChaRng::seed_from_u64(seed),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::collision;
+use crate::components::Angle;
+use crate::components::Collider;
+use crate::components::Position;
+use crate::components::Projectile;
+use crate::components::Team;
+use crate::components::Velocity;
+use crate::components::Wall;
+use crate::damage;
+use crate::geometry::{Point, Rect};
+use crate::particles::{EffectKind, Effects};
+use crate::physics;
+use crate::spatial;
+use crate::spatial::SpatialIndex;
+use core::f32::consts::PI;
+use specs::join::Join;
+use specs::storage::WriteStorage;
+use specs::Entities;
+use specs::LazyUpdate;
+use specs::Read;
+use specs::ReadStorage;
+use specs::System;
+use specs::Write;
+
+/// The size of the box projectiles are treated as when hitting things
+pub const PROJECTILE_SIZE: u32 = 8;
+
+/// Bounces projectiles off the walls they have flown into, destroying those with no bounces left
+pub struct Ricochet;
+
+impl<'a> System<'a> for Ricochet {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Velocity>,
+        WriteStorage<'a, Angle>,
+        WriteStorage<'a, Projectile>,
+        ReadStorage<'a, Collider>,
+        ReadStorage<'a, Wall>,
+        ReadStorage<'a, Team>,
+        Read<'a, LazyUpdate>,
+        Write<'a, Effects>,
+        Write<'a, SpatialIndex>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let entities = data.0;
+        let index = &mut data.10;
+
+        for (entity, pos, vel, angle, projectile, team) in (
+            &entities,
+            &mut data.1,
+            &mut data.2,
+            &mut data.3,
+            &mut data.4,
+            data.7.maybe(),
+        )
+            .join()
+        {
+            let walls = &data.6;
+            let touches_wall = |centre: Point| {
+                let shot = Rect::from_center(centre, PROJECTILE_SIZE, PROJECTILE_SIZE);
+                index
+                    .in_rect(shot)
+                    .iter()
+                    .any(|entry| walls.contains(entry.entity))
+            };
+            if !touches_wall(pos.0) {
+                continue;
+            }
+            data.9.add(EffectKind::Sparks, pos.0);
+
+            if projectile.bounces_rem == 0 {
+                damage::detonate(&entities, &data.8, entity, pos.0, projectile, team.cloned());
+                continue;
+            }
+            projectile.bounces_rem -= 1;
+
+            // Walls are axis aligned, so the surface normal is along whichever axis of this tick's
+            // move took the shot into the wall, or both when undoing either alone doesn't get it
+            // out again, as when it flies into a corner. Reflecting around the normal flips the
+            // direction along that axis. Every wall is checked, as the face of a wall is made up
+            // of many tiles.
+            let radians = vel.direction.angle * (PI / 180.0);
+            let (mut dx, mut dy) = (radians.cos(), radians.sin());
+            // The last substep Physics took, the one that brought it up against the wall
+            let step = collision::substeps(physics::step(vel))
+                .last()
+                .expect("there is always a substep");
+            let entered_x = !touches_wall(pos.0.offset(-step.x(), 0));
+            let entered_y = !touches_wall(pos.0.offset(0, -step.y()));
+            if entered_x || !entered_y {
+                dx = -dx;
+            }
+            if entered_y || !entered_x {
+                dy = -dy;
+            }
+            pos.0 -= step;
+            index.insert(entity, spatial::bounds(pos.0, None, true));
+
+            vel.direction.angle = dy.atan2(dx) * (180.0 / PI);
+            if projectile.weapon.spin() == 0.0 {
+                angle.angle = vel.direction.angle;
+            }
+        }
+    }
+}
+
+/// The width and height of the area two boxes share, if they overlap
+pub fn overlap(a: Rect, b: Rect) -> Option<(i32, i32)> {
+    let width = a.right().min(b.right()) - a.left().max(b.left());
+    let height = a.bottom().min(b.bottom()) - a.top().max(b.top());
+    if width > 0 && height > 0 {
+        Some((width, height))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,84 @@
+use crate::commands::PlayerId;
+use crate::components::Health;
+use std::collections::BTreeMap;
+
+/// What a player has done in a match so far
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerScore {
+    pub kills: u32,
+    pub deaths: u32,
+    /// Damage done to enemy tanks by every hit in full, even past their last point of health
+    pub damage_dealt: u32,
+    pub damage_taken: u32,
+    /// Enemy flags brought home
+    pub captures: u32,
+}
+
+/// Every player's score, along with who has been hurting each tank so kills can be put down to someone.
+/// It is part of the simulation, so it is rolled back along with everything else.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Scoreboard {
+    pub players: BTreeMap<PlayerId, PlayerScore>,
+    /// For each tank, the player credited with destroying it if it were destroyed now
+    last_hit: BTreeMap<PlayerId, PlayerId>,
+    /// This tick's damage to each tank by each player
+    hits: BTreeMap<PlayerId, BTreeMap<PlayerId, u32>>,
+}
+
+impl Scoreboard {
+    /// Take `damage` off `health`, putting it down to `attacker` if it belongs to `victim`'s tank.
+    /// Hurting yourself is only counted against you.
+    pub fn damage(
+        &mut self,
+        health: &mut Health,
+        attacker: Option<PlayerId>,
+        victim: Option<PlayerId>,
+        damage: u32,
+    ) {
+        health.current = health.current.saturating_sub(damage);
+        let victim = match victim {
+            Some(victim) => victim,
+            None => return,
+        };
+        self.players.entry(victim).or_default().damage_taken += damage;
+        if let Some(attacker) = attacker.filter(|&attacker| attacker != victim) {
+            self.players.entry(attacker).or_default().damage_dealt += damage;
+            *self
+                .hits
+                .entry(victim)
+                .or_default()
+                .entry(attacker)
+                .or_default() += damage;
+        }
+    }
+
+    /// Settle who is credited with each tank hit this tick: whoever did it the most damage,
+    /// the lowest player on a tie, so the order the hits landed in doesn't matter
+    pub fn end_tick(&mut self) {
+        for (victim, hits) in std::mem::take(&mut self.hits) {
+            let attacker = hits
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+                .map(|(attacker, _)| attacker);
+            if let Some(attacker) = attacker {
+                self.last_hit.insert(victim, attacker);
+            }
+        }
+    }
+
+    /// Count `victim`'s tank as destroyed, giving the kill to whoever last hurt it
+    pub fn destroyed(&mut self, victim: PlayerId) {
+        self.players.entry(victim).or_default().deaths += 1;
+        if let Some(attacker) = self.last_hit.remove(&victim) {
+            self.players.entry(attacker).or_default().kills += 1;
+        }
+    }
+
+    pub fn captured(&mut self, player: PlayerId) {
+        self.players.entry(player).or_default().captures += 1;
+    }
+
+    pub fn of(&self, player: PlayerId) -> PlayerScore {
+        self.players.get(&player).cloned().unwrap_or_default()
+    }
+}
@@ -0,0 +1,252 @@
+use crate::components::{
+    Ammo, Angle, AngularVelocity, BulletSpawner, Collider, Emitter, Engine, Explosion, Flag,
+    Health, KeyboardControlled, Knockback, Pickup, Position, Projectile, Sprite, StatusEffects,
+    Team, Velocity, Wall,
+};
+use crate::mode::Match;
+use crate::resources::GameRng;
+use crate::resources::Tick;
+use crate::score::Scoreboard;
+use specs::join::Join;
+use specs::prelude::{Entity, World, WorldExt};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Declares the per-entity snapshot along with how to capture and restore it.
+/// Every component that takes part in the simulation must be listed here,
+/// otherwise restoring a snapshot would silently drop it.
+macro_rules! snapshot_components {
+    ($($field:ident: $component:ty),* $(,)?) => {
+        #[derive(Debug, Clone, Default)]
+        struct EntitySnapshot {
+            $($field: Option<$component>,)*
+        }
+
+        fn capture_entities(world: &World) -> Vec<EntitySnapshot> {
+            $(let $field = world.read_storage::<$component>();)*
+            world
+                .entities()
+                .join()
+                .map(|entity| EntitySnapshot {
+                    $($field: $field.get(entity).cloned(),)*
+                })
+                .collect()
+        }
+
+        fn restore_entities(world: &mut World, snapshots: &[EntitySnapshot]) {
+            let entities = world.entities();
+            $(let mut $field = world.write_storage::<$component>();)*
+            let mut created: Vec<Entity> = snapshots.iter().map(|_| entities.create()).collect();
+            // Recycled ids are handed back newest first, sorting them keeps the snapshot's join order
+            created.sort_by_key(|entity| entity.id());
+            for (&entity, snapshot) in created.iter().zip(snapshots) {
+                $(
+                    if let Some(component) = &snapshot.$field {
+                        $field
+                            .insert(entity, component.clone())
+                            .expect("entity was just created");
+                    }
+                )*
+            }
+        }
+    };
+}
+
+snapshot_components! {
+    position: Position,
+    angle: Angle,
+    sprite: Sprite,
+    keyboard_controlled: KeyboardControlled,
+    velocity: Velocity,
+    angular_velocity: AngularVelocity,
+    bullet_spawner: BulletSpawner,
+    team: Team,
+    projectile: Projectile,
+    ammo: Ammo,
+    pickup: Pickup,
+    flag: Flag,
+    collider: Collider,
+    wall: Wall,
+    health: Health,
+    knockback: Knockback,
+    explosion: Explosion,
+    status_effects: StatusEffects,
+    engine: Engine,
+    emitter: Emitter,
+}
+
+/// A copy of the complete simulation state at the start of a tick
+#[derive(Debug, Clone)]
+pub struct WorldSnapshot {
+    pub tick: Tick,
+    rng: GameRng,
+    scoreboard: Scoreboard,
+    /// The match being played, if the world has one
+    game_match: Option<Match>,
+    entities: Vec<EntitySnapshot>,
+}
+
+impl WorldSnapshot {
+    pub fn capture(world: &World) -> Self {
+        WorldSnapshot {
+            tick: *world.read_resource::<Tick>(),
+            rng: (*world.read_resource::<GameRng>()).clone(),
+            scoreboard: (*world.read_resource::<Scoreboard>()).clone(),
+            game_match: world
+                .try_fetch::<Match>()
+                .map(|game_match| (*game_match).clone()),
+            entities: capture_entities(world),
+        }
+    }
+
+    /// A hash of the snapshot's entities, score and match, which ignores the order the entities are in.
+    /// Used to check that two simulations which should agree actually do.
+    pub fn checksum(&self) -> u64 {
+        // Debug formatting writes every float exactly, so it is as good as hashing the bits
+        let mut entities: Vec<String> = self
+            .entities
+            .iter()
+            .map(|entity| format!("{:?}", entity))
+            .collect();
+        entities.sort_unstable();
+
+        let mut hasher = DefaultHasher::new();
+        self.tick.0.hash(&mut hasher);
+        entities.hash(&mut hasher);
+        format!("{:?}", self.scoreboard).hash(&mut hasher);
+        format!("{:?}", self.game_match).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Replace every entity and simulation resource in `world` with the snapshot's.
+    /// Entities are recreated in their original relative order, so joins visit them in the same order.
+    /// Entities spawned afterwards may be given different ids than they were the first time round,
+    /// so systems must not let the order of a join decide the outcome of a tick.
+    pub fn restore(&self, world: &mut World) {
+        world.delete_all();
+        world.maintain();
+
+        restore_entities(world, &self.entities);
+        world.maintain();
+
+        *world.write_resource::<Tick>() = self.tick;
+        *world.write_resource::<GameRng>() = self.rng.clone();
+        *world.write_resource::<Scoreboard>() = self.scoreboard.clone();
+        match &self.game_match {
+            Some(game_match) => world.insert(game_match.clone()),
+            None => {
+                world.remove::<Match>();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archetype::TankArchetype;
+    use crate::commands::{
+        Commands, FireCommand, MovementCommand, PlayerCommands, PlayerId, RotationCommand,
+    };
+    use crate::components::Rotation;
+    use crate::game;
+    use crate::mode::{GameMode, Rules};
+    use specs::prelude::Dispatcher;
+
+    const PLAYERS: [(PlayerId, TankArchetype, Option<Team>); 4] = [
+        (0, TankArchetype::Scout, Some(Team(0))),
+        (1, TankArchetype::Medium, Some(Team(1))),
+        (2, TankArchetype::Heavy, Some(Team(0))),
+        (3, TankArchetype::Medium, Some(Team(1))),
+    ];
+
+    /// Every tank firing, turning its turret and changing direction every couple of seconds
+    fn commands(tick: u64) -> Commands {
+        let mut commands = Commands::default();
+        for &(player, _, _) in &PLAYERS {
+            let mut player_commands = PlayerCommands::default();
+            if tick % 40 == u64::from(player) {
+                let heading = (tick / 40 + u64::from(player)) % 4;
+                player_commands.movement = Some(MovementCommand::Move(Angle {
+                    angle: 90.0 * heading as f32,
+                }));
+                player_commands.rotation =
+                    Some(RotationCommand::Move(if heading.is_multiple_of(2) {
+                        Rotation::Clockwise
+                    } else {
+                        Rotation::CounterClockwise
+                    }));
+                player_commands.fire = Some(FireCommand::Fire);
+            }
+            commands.0.insert(player, player_commands);
+        }
+        commands
+    }
+
+    fn play(world: &mut World, dispatcher: &mut Dispatcher, ticks: u64) {
+        for _ in 0..ticks {
+            let tick = world.read_resource::<Tick>().0;
+            game::step(world, dispatcher, commands(tick));
+        }
+    }
+
+    /// A capture the flag match, which has every kind of entity in it once it has gone on a while
+    fn game(seed: u64) -> (Dispatcher<'static, 'static>, World) {
+        let mut dispatcher = game::build_dispatcher();
+        let rules = Rules::new(GameMode::CaptureTheFlag);
+        let world = game::create_game(&mut dispatcher, seed, rules, &PLAYERS);
+        (dispatcher, world)
+    }
+
+    #[test]
+    fn restoring_a_snapshot_gives_back_the_same_world() {
+        let (mut dispatcher, mut world) = game(3);
+        play(&mut world, &mut dispatcher, 300);
+        let snapshot = WorldSnapshot::capture(&world);
+
+        play(&mut world, &mut dispatcher, 50);
+        assert_ne!(
+            WorldSnapshot::capture(&world).checksum(),
+            snapshot.checksum()
+        );
+
+        snapshot.restore(&mut world);
+        let restored = WorldSnapshot::capture(&world);
+        assert_eq!(restored.tick, snapshot.tick);
+        assert_eq!(restored.checksum(), snapshot.checksum());
+    }
+
+    #[test]
+    fn a_restored_world_plays_on_the_same() {
+        let (mut dispatcher, mut world) = game(3);
+        play(&mut world, &mut dispatcher, 300);
+        let snapshot = WorldSnapshot::capture(&world);
+        play(&mut world, &mut dispatcher, 200);
+
+        // Restored over a different match, anything left out of the snapshot would make it play out differently
+        let (mut other_dispatcher, mut other) = game(99);
+        play(&mut other, &mut other_dispatcher, 120);
+        snapshot.restore(&mut other);
+        play(&mut other, &mut other_dispatcher, 200);
+
+        assert_eq!(
+            WorldSnapshot::capture(&other).checksum(),
+            WorldSnapshot::capture(&world).checksum()
+        );
+    }
+
+    #[test]
+    fn restoring_a_world_without_a_match_removes_it() {
+        let mut dispatcher = game::build_dispatcher();
+        let world = game::create_world(&mut dispatcher, 3);
+        let snapshot = WorldSnapshot::capture(&world);
+
+        let (_, mut other) = game(3);
+        snapshot.restore(&mut other);
+        assert!(other.try_fetch::<Match>().is_none());
+        assert_eq!(
+            WorldSnapshot::capture(&other).checksum(),
+            snapshot.checksum()
+        );
+    }
+}
@@ -0,0 +1,479 @@
+use crate::components::Collider;
+use crate::components::Position;
+use crate::components::Projectile;
+use crate::geometry::{Point, Rect};
+use crate::ricochet::{overlap, PROJECTILE_SIZE};
+use specs::join::Join;
+use specs::Entities;
+use specs::Entity;
+use specs::ReadStorage;
+use specs::System;
+use specs::Write;
+use std::collections::HashMap;
+
+/// The side of a grid cell, a little over twice the size of a tank
+const CELL_SIZE: i32 = 64;
+
+/// Something in the index and the box it takes up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    pub entity: Entity,
+    pub bounds: Rect,
+}
+
+/// Where everything is, in a uniform grid of cells each listing what overlaps it,
+/// so what is near somewhere can be found without going through the whole world.
+/// Queries return what they find in no particular order.
+#[derive(Debug, Default)]
+pub struct SpatialIndex {
+    cells: HashMap<(i32, i32), Vec<Entry>>,
+    bounds: HashMap<Entity, Rect>,
+}
+
+impl SpatialIndex {
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.bounds.clear();
+    }
+
+    /// Put `entity` in the index taking up `bounds`, or move it there if it is already in it
+    pub fn insert(&mut self, entity: Entity, bounds: Rect) {
+        if let Some(old) = self.bounds.insert(entity, bounds) {
+            if cells_under(old).eq(cells_under(bounds)) {
+                for cell in cells_under(bounds) {
+                    for entry in self.cells.get_mut(&cell).into_iter().flatten() {
+                        if entry.entity == entity {
+                            entry.bounds = bounds;
+                        }
+                    }
+                }
+                return;
+            }
+            self.remove_from_cells(entity, old);
+        }
+        for cell in cells_under(bounds) {
+            self.cells
+                .entry(cell)
+                .or_default()
+                .push(Entry { entity, bounds });
+        }
+    }
+
+    fn remove_from_cells(&mut self, entity: Entity, bounds: Rect) {
+        for cell in cells_under(bounds) {
+            if let Some(entries) = self.cells.get_mut(&cell) {
+                entries.retain(|entry| entry.entity != entity);
+                if entries.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    /// Everything overlapping `area`
+    pub fn in_rect(&self, area: Rect) -> Vec<Entry> {
+        let mut found: Vec<Entry> = cells_under(area)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(|entry| overlap(entry.bounds, area).is_some())
+            .cloned()
+            .collect();
+        found.sort_by_key(|entry| entry.entity);
+        found.dedup_by_key(|entry| entry.entity);
+        found
+    }
+
+    /// Everything centred within `radius` of `centre`
+    pub fn in_radius(&self, centre: Point, radius: i32) -> Vec<Entry> {
+        let radius = radius.max(0);
+        let square = Rect::from_center(centre, 2 * radius as u32 + 1, 2 * radius as u32 + 1);
+        let mut found: Vec<Entry> = cells_under(square)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(|entry| {
+                let offset = entry.bounds.center() - centre;
+                i64::from(offset.x()).pow(2) + i64::from(offset.y()).pow(2)
+                    <= i64::from(radius).pow(2)
+            })
+            .cloned()
+            .collect();
+        found.sort_by_key(|entry| entry.entity);
+        found.dedup_by_key(|entry| entry.entity);
+        found
+    }
+
+    /// Everything the line from `from` to `to` passes through, nearest first, with how far
+    /// along the line it is first touched from 0 at `from` to 1 at `to` and the side it is touched on
+    pub fn along(&self, from: Point, to: Point) -> Vec<(f32, Point, Entry)> {
+        let mut found: Vec<(f32, Point, Entry)> = cells_along(from, to)
+            .into_iter()
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter_map(|entry| {
+                segment_enters(entry.bounds, from, to).map(|(t, normal)| (t, normal, *entry))
+            })
+            .collect();
+        found.sort_by_key(|(_, _, entry)| entry.entity);
+        found.dedup_by_key(|(_, _, entry)| entry.entity);
+        // Ties are broken on position so the order doesn't depend on the order things were indexed in
+        found.sort_by(|(a, _, a_entry), (b, _, b_entry)| {
+            let a_corner = (a_entry.bounds.x(), a_entry.bounds.y());
+            let b_corner = (b_entry.bounds.x(), b_entry.bounds.y());
+            a.partial_cmp(b)
+                .expect("distances are never NaN")
+                .then(a_corner.cmp(&b_corner))
+        });
+        found
+    }
+}
+
+/// The box an entity takes up in the index: its collider, the size shots hit things with, or just its position
+pub fn bounds(position: Point, collider: Option<&Collider>, projectile: bool) -> Rect {
+    match collider {
+        Some(collider) => collider.rect(position),
+        None if projectile => Rect::from_center(position, PROJECTILE_SIZE, PROJECTILE_SIZE),
+        None => Rect::from_center(position, 1, 1),
+    }
+}
+
+fn cell_of(point: Point) -> (i32, i32) {
+    (
+        point.x().div_euclid(CELL_SIZE),
+        point.y().div_euclid(CELL_SIZE),
+    )
+}
+
+fn cells_under(area: Rect) -> impl Iterator<Item = (i32, i32)> {
+    let (left, top) = cell_of(area.top_left());
+    let (right, bottom) = cell_of(Point::new(area.right() - 1, area.bottom() - 1));
+    (top..=bottom).flat_map(move |y| (left..=right).map(move |x| (x, y)))
+}
+
+/// Every cell the line from `from` to `to` passes through, in order
+fn cells_along(from: Point, to: Point) -> Vec<(i32, i32)> {
+    let (mut x, mut y) = cell_of(from);
+    let end = cell_of(to);
+    let (dx, dy) = ((to.x() - from.x()) as f32, (to.y() - from.y()) as f32);
+    let (step_x, step_y) = (dx.signum() as i32, dy.signum() as i32);
+    // How far along the line the next cell boundary is crossed on each axis, and the distance between them
+    let crossing = |cell: i32, step: i32, start: i32, delta: f32| {
+        if delta == 0.0 {
+            return (f32::INFINITY, f32::INFINITY);
+        }
+        let boundary = (cell + if step > 0 { 1 } else { 0 }) * CELL_SIZE;
+        (
+            (boundary - start) as f32 / delta,
+            CELL_SIZE as f32 / delta.abs(),
+        )
+    };
+    let (mut next_x, delta_x) = crossing(x, step_x, from.x(), dx);
+    let (mut next_y, delta_y) = crossing(y, step_y, from.y(), dy);
+
+    let mut cells = vec![(x, y)];
+    let count = (end.0 - x).abs() + (end.1 - y).abs();
+    for _ in 0..count {
+        if next_x < next_y {
+            x += step_x;
+            next_x += delta_x;
+        } else {
+            y += step_y;
+            next_y += delta_y;
+        }
+        cells.push((x, y));
+    }
+    cells
+}
+
+/// How far along the line from `from` to `to` it first touches `rect` and the outward facing
+/// normal of the side it touches, which is nothing if the line starts inside it
+fn segment_enters(rect: Rect, from: Point, to: Point) -> Option<(f32, Point)> {
+    let (mut enter, mut exit) = (0.0f32, 1.0f32);
+    let mut normal = Point::new(0, 0);
+    // The last pixel covered on each axis is one before its right and bottom edges
+    let axes = [
+        (from.x(), to.x(), rect.left(), rect.right() - 1),
+        (from.y(), to.y(), rect.top(), rect.bottom() - 1),
+    ];
+    for (axis, &(start, end, low, high)) in axes.iter().enumerate() {
+        let delta = (end - start) as f32;
+        if delta == 0.0 {
+            if start < low || start > high {
+                return None;
+            }
+            continue;
+        }
+        let a = (low - start) as f32 / delta;
+        let b = (high - start) as f32 / delta;
+        if a.min(b) > enter {
+            enter = a.min(b);
+            // The side facing back along the line
+            let side = if delta > 0.0 { -1 } else { 1 };
+            normal = if axis == 0 {
+                Point::new(side, 0)
+            } else {
+                Point::new(0, side)
+            };
+        }
+        exit = exit.min(a.max(b));
+        if enter > exit {
+            return None;
+        }
+    }
+    Some((enter, normal))
+}
+
+/// Rebuilds the spatial index from where everything is at the start of a tick.
+/// Systems moving things after update it as they go.
+pub struct Indexing;
+
+impl<'a> System<'a> for Indexing {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Collider>,
+        ReadStorage<'a, Projectile>,
+        Write<'a, SpatialIndex>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, positions, colliders, projectiles, mut index) = data;
+        index.clear();
+        for (entity, pos, collider, projectile) in (
+            &entities,
+            &positions,
+            colliders.maybe(),
+            projectiles.maybe(),
+        )
+            .join()
+        {
+            index.insert(entity, bounds(pos.0, collider, projectile.is_some()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use specs::prelude::{Builder, World, WorldExt};
+    use std::time::Instant;
+
+    const HALF_ARENA: (i32, i32) = (400, 300);
+
+    fn random_point(rng: &mut StdRng) -> Point {
+        Point::new(
+            rng.gen_range(-HALF_ARENA.0, HALF_ARENA.0),
+            rng.gen_range(-HALF_ARENA.1, HALF_ARENA.1),
+        )
+    }
+
+    /// An index of `count` boxes of all sizes about the arena, many of them across cell boundaries,
+    /// along with a list of the same boxes to go through one by one
+    fn population(seed: u64, count: usize) -> (SpatialIndex, Vec<Entry>) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut world = World::new();
+        let mut index = SpatialIndex::default();
+        let mut entries = Vec::new();
+        let mut add = |bounds: Rect| {
+            let entity = world.create_entity().build();
+            index.insert(entity, bounds);
+            entries.push(Entry { entity, bounds });
+        };
+        // On and either side of the cell corners around the origin
+        for &(x, y) in &[
+            (0, 0),
+            (-1, -1),
+            (CELL_SIZE, -CELL_SIZE),
+            (-CELL_SIZE - 1, 0),
+        ] {
+            add(Rect::from_center(Point::new(x, y), 28, 28));
+            add(Rect::new(x, y, 1, 1));
+        }
+        for _ in 0..count {
+            let size = rng.gen_range(1, 3 * CELL_SIZE as u32);
+            add(Rect::from_center(
+                random_point(&mut rng),
+                size,
+                rng.gen_range(1, 40),
+            ));
+        }
+        // Move some of them, some within their cells and some across into others
+        let moved: Vec<usize> = (0..entries.len()).step_by(7).collect();
+        for i in moved {
+            let Entry { entity, bounds } = entries[i];
+            let step = rng.gen_range(-CELL_SIZE, CELL_SIZE);
+            let bounds = Rect::new(
+                bounds.x() + step,
+                bounds.y() - step,
+                bounds.width(),
+                bounds.height(),
+            );
+            index.insert(entity, bounds);
+            entries[i].bounds = bounds;
+        }
+        (index, entries)
+    }
+
+    fn entities(found: impl Iterator<Item = Entry>) -> Vec<Entity> {
+        let mut entities: Vec<Entity> = found.map(|entry| entry.entity).collect();
+        entities.sort();
+        entities
+    }
+
+    #[test]
+    fn boxes_find_what_going_through_everything_does() {
+        let (index, entries) = population(1, 300);
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..500 {
+            let size = rng.gen_range(1, 2 * CELL_SIZE as u32);
+            let area = Rect::from_center(random_point(&mut rng), size, size);
+            let expected = entries
+                .iter()
+                .filter(|entry| overlap(entry.bounds, area).is_some())
+                .cloned();
+            assert_eq!(
+                entities(index.in_rect(area).into_iter()),
+                entities(expected),
+                "in {:?}",
+                area
+            );
+        }
+    }
+
+    #[test]
+    fn circles_find_what_going_through_everything_does() {
+        let (index, entries) = population(3, 300);
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut centres: Vec<Point> = (0..500).map(|_| random_point(&mut rng)).collect();
+        centres.push(Point::new(0, 0));
+        centres.push(Point::new(-CELL_SIZE, CELL_SIZE));
+        for centre in centres {
+            let radius = rng.gen_range(0, 2 * CELL_SIZE);
+            let expected = entries
+                .iter()
+                .filter(|entry| {
+                    let offset = entry.bounds.center() - centre;
+                    offset.x().pow(2) + offset.y().pow(2) <= radius.pow(2)
+                })
+                .cloned();
+            assert_eq!(
+                entities(index.in_radius(centre, radius).into_iter()),
+                entities(expected),
+                "within {} of {:?}",
+                radius,
+                centre
+            );
+        }
+    }
+
+    #[test]
+    fn lines_find_what_going_through_everything_does() {
+        let (index, entries) = population(5, 300);
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut lines: Vec<(Point, Point)> = (0..500)
+            .map(|_| (random_point(&mut rng), random_point(&mut rng)))
+            .collect();
+        // Straight along and across cell boundaries, and a line that is only a point
+        lines.push((Point::new(-300, 0), Point::new(300, 0)));
+        lines.push((Point::new(CELL_SIZE, -250), Point::new(CELL_SIZE, 250)));
+        lines.push((
+            Point::new(-CELL_SIZE, -CELL_SIZE),
+            Point::new(CELL_SIZE, CELL_SIZE),
+        ));
+        lines.push((Point::new(0, 0), Point::new(0, 0)));
+        for (from, to) in lines {
+            let found = index.along(from, to);
+            let mut expected: Vec<(Entity, f32)> = entries
+                .iter()
+                .filter_map(|entry| {
+                    segment_enters(entry.bounds, from, to).map(|(t, _)| (entry.entity, t))
+                })
+                .collect();
+            expected.sort_by_key(|&(entity, _)| entity);
+            let mut found_at: Vec<(Entity, f32)> = found
+                .iter()
+                .map(|&(t, _, entry)| (entry.entity, t))
+                .collect();
+            found_at.sort_by_key(|&(entity, _)| entity);
+            assert_eq!(found_at, expected, "from {:?} to {:?}", from, to);
+            // Nearest first
+            assert!(found.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        }
+    }
+
+    /// Time queries against the index among 500 shots, and the same done by going through every one.
+    /// Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark_against_brute_force() {
+        const SHOTS: usize = 500;
+        const QUERIES: usize = 2000;
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut world = World::new();
+        let mut index = SpatialIndex::default();
+        let mut entries = Vec::with_capacity(SHOTS);
+        for _ in 0..SHOTS {
+            let entity = world.create_entity().build();
+            let bounds = bounds(random_point(&mut rng), None, true);
+            index.insert(entity, bounds);
+            entries.push(Entry { entity, bounds });
+        }
+        let areas: Vec<Rect> = (0..QUERIES)
+            .map(|_| Rect::from_center(random_point(&mut rng), 32, 32))
+            .collect();
+        let lines: Vec<(Point, Point)> = (0..QUERIES)
+            .map(|_| (random_point(&mut rng), random_point(&mut rng)))
+            .collect();
+
+        let time = |name: &str, indexed: &dyn Fn() -> usize, brute: &dyn Fn() -> usize| {
+            let started = Instant::now();
+            let found = indexed();
+            let indexed_time = started.elapsed();
+            let started = Instant::now();
+            let expected = brute();
+            let brute_time = started.elapsed();
+            assert_eq!(found, expected);
+            println!(
+                "{} queries among {} shots: index {:?}, brute force {:?}",
+                name, SHOTS, indexed_time, brute_time
+            );
+        };
+        time(
+            "box",
+            &|| areas.iter().map(|&area| index.in_rect(area).len()).sum(),
+            &|| {
+                areas
+                    .iter()
+                    .map(|&area| {
+                        entries
+                            .iter()
+                            .filter(|entry| overlap(entry.bounds, area).is_some())
+                            .count()
+                    })
+                    .sum()
+            },
+        );
+        time(
+            "line",
+            &|| {
+                lines
+                    .iter()
+                    .map(|&(from, to)| index.along(from, to).len())
+                    .sum()
+            },
+            &|| {
+                lines
+                    .iter()
+                    .map(|&(from, to)| {
+                        entries
+                            .iter()
+                            .filter(|entry| segment_enters(entry.bounds, from, to).is_some())
+                            .count()
+                    })
+                    .sum()
+            },
+        );
+    }
+}
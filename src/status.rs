@@ -0,0 +1,225 @@
+use crate::components::KeyboardControlled;
+use crate::components::StatusEffects;
+use specs::join::Join;
+use specs::storage::WriteStorage;
+use specs::Entities;
+use specs::System;
+use std::fmt;
+
+/// How much faster a speed boost makes a tank
+const SPEED_BOOST: f32 = 1.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusKind {
+    /// Drives faster
+    Speed,
+    /// Takes half damage
+    Shield,
+    /// Fires twice as often
+    RapidFire,
+}
+
+impl StatusKind {
+    /// Ticks it lasts for
+    pub fn duration(self) -> u32 {
+        match self {
+            StatusKind::Speed => 200,
+            StatusKind::Shield => 160,
+            StatusKind::RapidFire => 160,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StatusKind::Speed => "speed",
+            StatusKind::Shield => "shield",
+            StatusKind::RapidFire => "rapid fire",
+        }
+    }
+}
+
+impl fmt::Display for StatusKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Apply what `kind` does to an entity the moment it is granted.
+/// Its engine takes a tank up to or back down from boosted speeds.
+pub fn start(kind: StatusKind, control: &mut KeyboardControlled) {
+    if kind == StatusKind::Speed {
+        control.speed *= SPEED_BOOST;
+    }
+}
+
+/// Undo what `start` did once `kind` wears off
+fn end(kind: StatusKind, control: &mut KeyboardControlled) {
+    if kind == StatusKind::Speed {
+        control.speed /= SPEED_BOOST;
+    }
+}
+
+/// The damage an entity under `effects` takes from a hit of `damage`
+pub fn damage_taken(effects: Option<&StatusEffects>, damage: u32) -> u32 {
+    match effects {
+        Some(effects) if effects.has(StatusKind::Shield) => damage / 2,
+        _ => damage,
+    }
+}
+
+/// The ticks a weapon waits between shots when its usual cooldown is `cooldown`
+pub fn cooldown(effects: Option<&StatusEffects>, cooldown: u32) -> u32 {
+    match effects {
+        Some(effects) if effects.has(StatusKind::RapidFire) => cooldown / 2,
+        _ => cooldown,
+    }
+}
+
+/// Counts status effects down, undoing them as they wear off
+pub struct StatusSystem;
+
+impl<'a> System<'a> for StatusSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, StatusEffects>,
+        WriteStorage<'a, KeyboardControlled>,
+    );
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let mut finished = Vec::new();
+        for (entity, effects, control) in (&data.0, &mut data.1, &mut data.2).join() {
+            for effect in &mut effects.active {
+                effect.ticks_left -= 1;
+                if effect.ticks_left == 0 {
+                    end(effect.kind, control);
+                }
+            }
+            effects.active.retain(|effect| effect.ticks_left > 0);
+            if effects.active.is_empty() {
+                finished.push(entity);
+            }
+        }
+        for entity in finished {
+            data.1.remove(entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::prelude::{Builder, RunNow, World, WorldExt};
+    use specs::Entity;
+
+    const SPEED: f32 = 4.0;
+
+    fn tank(world: &mut World) -> Entity {
+        world
+            .create_entity()
+            .with(KeyboardControlled {
+                player: 1,
+                speed: SPEED,
+                rotation_speed: 1.0,
+            })
+            .build()
+    }
+
+    /// Grants `kind` the way picking it up does
+    fn grant(world: &mut World, tank: Entity, kind: StatusKind) {
+        let mut effects = world.write_storage::<StatusEffects>();
+        let mut controls = world.write_storage::<KeyboardControlled>();
+        let effects = effects
+            .entry(tank)
+            .expect("tank is alive")
+            .or_insert_with(StatusEffects::default);
+        if effects.grant(kind) {
+            start(kind, controls.get_mut(tank).expect("tank is alive"));
+        }
+    }
+
+    fn run(world: &mut World, ticks: u32) {
+        for _ in 0..ticks {
+            StatusSystem.run_now(world);
+            world.maintain();
+        }
+    }
+
+    fn speed(world: &World, tank: Entity) -> f32 {
+        world
+            .read_storage::<KeyboardControlled>()
+            .get(tank)
+            .expect("tank is alive")
+            .speed
+    }
+
+    fn arena() -> World {
+        let mut world = World::new();
+        System::setup(&mut StatusSystem, &mut world);
+        world
+    }
+
+    #[test]
+    fn speed_goes_back_to_normal_when_the_boost_wears_off() {
+        let mut world = arena();
+        let tank = tank(&mut world);
+        grant(&mut world, tank, StatusKind::Speed);
+        assert_eq!(speed(&world, tank), SPEED * SPEED_BOOST);
+
+        run(&mut world, StatusKind::Speed.duration() - 1);
+        assert_eq!(speed(&world, tank), SPEED * SPEED_BOOST);
+        run(&mut world, 1);
+        assert_eq!(speed(&world, tank), SPEED);
+    }
+
+    #[test]
+    fn picking_up_a_boost_again_only_makes_it_last_longer() {
+        let mut world = arena();
+        let tank = tank(&mut world);
+        grant(&mut world, tank, StatusKind::Speed);
+        run(&mut world, 50);
+        grant(&mut world, tank, StatusKind::Speed);
+        assert_eq!(speed(&world, tank), SPEED * SPEED_BOOST);
+
+        run(&mut world, StatusKind::Speed.duration() - 1);
+        assert_eq!(speed(&world, tank), SPEED * SPEED_BOOST);
+        run(&mut world, 1);
+        assert_eq!(speed(&world, tank), SPEED);
+    }
+
+    #[test]
+    fn shields_halve_damage_until_they_wear_off() {
+        let mut world = arena();
+        let tank = tank(&mut world);
+        grant(&mut world, tank, StatusKind::Shield);
+        let damage =
+            |world: &World| damage_taken(world.read_storage::<StatusEffects>().get(tank), 10);
+        assert_eq!(damage(&world), 5);
+
+        run(&mut world, StatusKind::Shield.duration());
+        assert_eq!(damage(&world), 10);
+        assert_eq!(speed(&world, tank), SPEED);
+    }
+
+    #[test]
+    fn effects_wear_off_on_their_own() {
+        let mut world = arena();
+        let tank = tank(&mut world);
+        grant(&mut world, tank, StatusKind::Speed);
+        grant(&mut world, tank, StatusKind::RapidFire);
+        assert_eq!(
+            cooldown(world.read_storage::<StatusEffects>().get(tank), 8),
+            4
+        );
+
+        run(&mut world, StatusKind::RapidFire.duration());
+        {
+            let effects = world.read_storage::<StatusEffects>();
+            let effects = effects.get(tank).expect("speed is still running");
+            assert!(effects.has(StatusKind::Speed));
+            assert!(!effects.has(StatusKind::RapidFire));
+        }
+
+        run(&mut world, StatusKind::Speed.duration());
+        assert!(world.read_storage::<StatusEffects>().get(tank).is_none());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::components::Sprite;
+use crate::geometry::{Point, Rect};
+
+/// Index of the terrain spritesheet loaded by the renderer
+pub const TERRAIN_SPRITE: usize = 7;
+
+/// The ground a tile is covered in, tanks drive differently over each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerrainKind {
+    Grass,
+    Mud,
+    Ice,
+    Water,
+    Road,
+}
+
+impl TerrainKind {
+    /// How much of its top speed a tank reaches on it
+    pub fn speed(self) -> f32 {
+        match self {
+            TerrainKind::Mud => 0.5,
+            TerrainKind::Road => 1.3,
+            _ => 1.0,
+        }
+    }
+
+    /// How much of its usual friction and braking a tank gets on it
+    pub fn grip(self) -> f32 {
+        match self {
+            TerrainKind::Ice => 0.15,
+            _ => 1.0,
+        }
+    }
+
+    /// Whether tanks can drive onto it, shots fly over anything
+    pub fn passable(self) -> bool {
+        self != TerrainKind::Water
+    }
+
+    /// Grass is left to the background colour
+    fn sprite(self) -> Option<Sprite> {
+        let frame = match self {
+            TerrainKind::Grass => return None,
+            TerrainKind::Mud => 0,
+            TerrainKind::Ice => 1,
+            TerrainKind::Water => 2,
+            TerrainKind::Road => 3,
+        };
+        Some(Sprite {
+            spritesheet: TERRAIN_SPRITE,
+            region: Rect::new(frame * 32, 0, 32, 32),
+        })
+    }
+}
+
+/// The ground of the arena as a grid of square tiles centred on multiples of the tile size,
+/// with the middle tile on the origin. Everything off the grid is grass.
+#[derive(Debug, Clone, Default)]
+pub struct Terrain {
+    tile: i32,
+    /// How many tiles there are either side of the middle one
+    half_columns: i32,
+    half_rows: i32,
+    tiles: Vec<TerrainKind>,
+}
+
+impl Terrain {
+    /// An all grass grid covering `width` by `height`
+    pub fn new(tile: u32, width: u32, height: u32) -> Self {
+        let tile = tile as i32;
+        let half_columns = width as i32 / tile / 2;
+        let half_rows = height as i32 / tile / 2;
+        let count = (2 * half_columns + 1) * (2 * half_rows + 1);
+        Terrain {
+            tile,
+            half_columns,
+            half_rows,
+            tiles: vec![TerrainKind::Grass; count as usize],
+        }
+    }
+
+    /// Cover the tiles from `from` to `to` inclusive, in tiles from the middle, with `kind`
+    pub fn paint(&mut self, kind: TerrainKind, from: (i32, i32), to: (i32, i32)) {
+        for row in from.1.min(to.1)..=from.1.max(to.1) {
+            for column in from.0.min(to.0)..=from.0.max(to.0) {
+                if let Some(index) = self.index(column, row) {
+                    self.tiles[index] = kind;
+                }
+            }
+        }
+    }
+
+    /// The ground under `point`
+    pub fn at(&self, point: Point) -> TerrainKind {
+        if self.tile == 0 {
+            return TerrainKind::Grass;
+        }
+        let column = (point.x() + self.tile / 2).div_euclid(self.tile);
+        let row = (point.y() + self.tile / 2).div_euclid(self.tile);
+        self.index(column, row)
+            .map_or(TerrainKind::Grass, |index| self.tiles[index])
+    }
+
+    /// The area the grid covers
+    pub fn bounds(&self) -> Rect {
+        let columns = 2 * self.half_columns + 1;
+        let rows = 2 * self.half_rows + 1;
+        Rect::new(
+            -self.half_columns * self.tile - self.tile / 2,
+            -self.half_rows * self.tile - self.tile / 2,
+            (columns * self.tile) as u32,
+            (rows * self.tile) as u32,
+        )
+    }
+
+    /// The sprite to draw centred on each tile that isn't grass
+    pub fn sprites(&self) -> impl Iterator<Item = (Point, Sprite)> + '_ {
+        self.centres()
+            .filter_map(|(centre, kind)| kind.sprite().map(|sprite| (centre, sprite)))
+    }
+
+    fn centres(&self) -> impl Iterator<Item = (Point, TerrainKind)> + '_ {
+        let columns = 2 * self.half_columns + 1;
+        self.tiles.iter().enumerate().map(move |(index, &kind)| {
+            let column = index as i32 % columns - self.half_columns;
+            let row = index as i32 / columns - self.half_rows;
+            (Point::new(column * self.tile, row * self.tile), kind)
+        })
+    }
+
+    fn index(&self, column: i32, row: i32) -> Option<usize> {
+        if column.abs() > self.half_columns || row.abs() > self.half_rows {
+            return None;
+        }
+        let columns = 2 * self.half_columns + 1;
+        Some(((row + self.half_rows) * columns + column + self.half_columns) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::Obstacles;
+    use crate::components::{Angle, Collider, Engine, KeyboardControlled, Position, Velocity};
+    use crate::physics::Drive;
+    use specs::prelude::{Builder, RunNow, World, WorldExt};
+    use specs::System;
+
+    const TOP_SPEED: f32 = 4.0;
+
+    /// Grass in the middle column, then mud, road, ice and water columns to its right
+    fn terrain() -> Terrain {
+        let mut terrain = Terrain::new(32, 320, 320);
+        terrain.paint(TerrainKind::Mud, (1, -5), (1, 5));
+        terrain.paint(TerrainKind::Road, (2, -5), (2, 5));
+        terrain.paint(TerrainKind::Ice, (3, -5), (3, 5));
+        terrain.paint(TerrainKind::Water, (4, -5), (4, 5));
+        terrain
+    }
+
+    /// The speed a tank at `x` ends up at after `ticks` of driving, or of coasting from top speed
+    fn speed_after(x: i32, throttle: bool, ticks: u32) -> f32 {
+        let mut world = World::new();
+        System::setup(&mut Drive, &mut world);
+        world.insert(terrain());
+        let tank = world
+            .create_entity()
+            .with(Engine {
+                mass: 1.0,
+                acceleration: 1.0,
+                braking: 2.0,
+                friction: 1.0,
+                turn_acceleration: 1.0,
+                throttle: if throttle {
+                    Some(Angle { angle: 90.0 })
+                } else {
+                    None
+                },
+                turn: None,
+            })
+            .with(KeyboardControlled {
+                player: 1,
+                speed: TOP_SPEED,
+                rotation_speed: 1.0,
+            })
+            .with(Velocity {
+                speed: if throttle { 0.0 } else { TOP_SPEED },
+                direction: Angle { angle: 90.0 },
+            })
+            .with(Position(Point::new(x, 0)))
+            .build();
+        for _ in 0..ticks {
+            Drive.run_now(&world);
+        }
+        let speed = world
+            .read_storage::<Velocity>()
+            .get(tank)
+            .expect("tank is alive")
+            .speed;
+        speed
+    }
+
+    #[test]
+    fn points_are_on_the_tile_they_are_nearest_the_centre_of() {
+        let terrain = terrain();
+        assert_eq!(terrain.at(Point::new(15, 0)), TerrainKind::Grass);
+        assert_eq!(terrain.at(Point::new(16, 0)), TerrainKind::Mud);
+        assert_eq!(terrain.at(Point::new(47, -170)), TerrainKind::Mud);
+        assert_eq!(terrain.at(Point::new(128, 100)), TerrainKind::Water);
+        // Off the grid
+        assert_eq!(terrain.at(Point::new(32, 176)), TerrainKind::Grass);
+        assert_eq!(terrain.at(Point::new(500, 0)), TerrainKind::Grass);
+        assert_eq!(terrain.bounds(), Rect::new(-176, -176, 352, 352));
+    }
+
+    #[test]
+    fn tanks_are_slower_on_mud_and_faster_on_roads() {
+        assert_eq!(speed_after(0, true, 10), TOP_SPEED);
+        assert_eq!(speed_after(32, true, 10), TOP_SPEED * 0.5);
+        assert_eq!(speed_after(64, true, 10), TOP_SPEED * 1.3);
+    }
+
+    #[test]
+    fn tanks_slide_on_for_longer_on_ice() {
+        assert_eq!(speed_after(0, false, 2), TOP_SPEED - 2.0);
+        assert!((speed_after(96, false, 2) - (TOP_SPEED - 0.3)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn tanks_cant_drive_into_water() {
+        let terrain = terrain();
+        let ground = Obstacles::new(vec![], Some(&terrain));
+        let collider = Collider {
+            width: 32,
+            height: 32,
+        };
+        // Stopped on the ice at the edge of the water, still sliding along it
+        let (moved, stopped) = ground.slide(&collider, Point::new(80, 0), Point::new(40, 16));
+        assert_eq!(moved, Point::new(104, 16));
+        assert_eq!(stopped, (true, false));
+    }
+
+    #[test]
+    fn tanks_in_water_can_drive_out_of_it() {
+        let terrain = terrain();
+        let ground = Obstacles::new(vec![], Some(&terrain));
+        let collider = Collider {
+            width: 32,
+            height: 32,
+        };
+        let (moved, stopped) = ground.slide(&collider, Point::new(128, 0), Point::new(-32, 0));
+        assert_eq!(moved, Point::new(96, 0));
+        assert_eq!(stopped, (false, false));
+    }
+}
@@ -0,0 +1,480 @@
+use crate::ai::senses;
+use crate::ai::Bot;
+use crate::commands::{Commands, PlayerId};
+use crate::game;
+use crate::json;
+use crate::mode::{GameMode, Match, Outcome, Rules, Side};
+use crate::navigation::Navigator;
+use crate::score::{PlayerScore, Scoreboard};
+use specs::prelude::WorldExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The players the two bots in a match drive, swapped every other round so each gets both spawn points
+const SIDES: [PlayerId; 2] = [0, 1];
+
+/// How one bot got on in one match
+#[derive(Debug, Clone)]
+struct Entrant {
+    /// Which of the bots it was, by its place in the list
+    bot: usize,
+    player: PlayerId,
+    score: PlayerScore,
+    /// Ticks its tank lasted, the whole match if it was never destroyed
+    survived: u64,
+    overruns: u32,
+}
+
+#[derive(Debug, Clone)]
+struct MatchResult {
+    seed: u64,
+    ticks: u64,
+    entrants: [Entrant; 2],
+    /// The bot left standing, nobody if both were destroyed or time ran out
+    winner: Option<usize>,
+}
+
+/// A bot's results over the whole tournament
+#[derive(Debug, Clone, Default)]
+struct Standing {
+    name: String,
+    file: String,
+    matches: u32,
+    wins: u32,
+    draws: u32,
+    losses: u32,
+    kills: u32,
+    deaths: u32,
+    damage_dealt: u32,
+    damage_taken: u32,
+    survived: u64,
+    overruns: u32,
+}
+
+impl Standing {
+    fn average_survival_seconds(&self) -> f64 {
+        if self.matches == 0 {
+            return 0.0;
+        }
+        self.survived as f64 * game::TICK_DURATION.as_secs_f64() / f64::from(self.matches)
+    }
+}
+
+/// Play every one of `bots` against every other `rounds` times as fast as the simulation goes,
+/// with the seeds counting up from `seed`, and write the standings to `results`: JSON if it ends
+/// in `.json`, CSV otherwise. Matches are played as elimination, a draw if both tanks are still
+/// standing after `max_ticks`.
+pub fn run(
+    bots: &[PathBuf],
+    rounds: u32,
+    seed: u64,
+    max_ticks: u64,
+    budget: u64,
+    results: Option<&Path>,
+) -> Result<(), String> {
+    if bots.len() < 2 {
+        return Err("a tournament needs at least two bots".to_string());
+    }
+    // Load them all up front, so a broken one is found before hours of matches rather than after
+    let mut standings = Vec::new();
+    for path in bots {
+        let bot = Bot::load(0, path, seed, budget)?;
+        standings.push(Standing {
+            name: bot.name().to_string(),
+            file: path.to_string_lossy().into_owned(),
+            ..Standing::default()
+        });
+    }
+
+    let pairings: Vec<(usize, usize)> = (0..bots.len())
+        .flat_map(|a| (a + 1..bots.len()).map(move |b| (a, b)))
+        .collect();
+    let total = pairings.len() as u64 * u64::from(rounds);
+    let mut matches = Vec::new();
+    for round in 0..rounds {
+        for &(a, b) in &pairings {
+            let number = matches.len() as u64;
+            let pair = if round % 2 == 0 { [a, b] } else { [b, a] };
+            let result = play(bots, pair, seed.wrapping_add(number), max_ticks, budget)?;
+            let outcome = match result.winner {
+                Some(winner) => format!("{} won", standings[winner].name),
+                None => "draw".to_string(),
+            };
+            println!(
+                "match {}/{} (seed {}): {} v {}, {} after {} ticks",
+                number + 1,
+                total,
+                result.seed,
+                standings[pair[0]].name,
+                standings[pair[1]].name,
+                outcome,
+                result.ticks
+            );
+            matches.push(result);
+        }
+    }
+
+    for result in &matches {
+        for entrant in &result.entrants {
+            let standing = &mut standings[entrant.bot];
+            standing.matches += 1;
+            match result.winner {
+                Some(winner) if winner == entrant.bot => standing.wins += 1,
+                Some(_) => standing.losses += 1,
+                None => standing.draws += 1,
+            }
+            standing.kills += entrant.score.kills;
+            standing.deaths += entrant.score.deaths;
+            standing.damage_dealt += entrant.score.damage_dealt;
+            standing.damage_taken += entrant.score.damage_taken;
+            standing.survived += entrant.survived;
+            standing.overruns += entrant.overruns;
+        }
+    }
+    let mut order: Vec<usize> = (0..standings.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&standings[a], &standings[b]);
+        (b.wins, b.draws, b.damage_dealt)
+            .cmp(&(a.wins, a.draws, a.damage_dealt))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    println!();
+    println!(
+        "{:<16} {:>7} {:>5} {:>5} {:>6} {:>5} {:>6} {:>7} {:>7} {:>9}",
+        "bot",
+        "matches",
+        "wins",
+        "draws",
+        "losses",
+        "kills",
+        "deaths",
+        "dealt",
+        "taken",
+        "survival"
+    );
+    for &i in &order {
+        let standing = &standings[i];
+        println!(
+            "{:<16} {:>7} {:>5} {:>5} {:>6} {:>5} {:>6} {:>7} {:>7} {:>8.1}s",
+            standing.name,
+            standing.matches,
+            standing.wins,
+            standing.draws,
+            standing.losses,
+            standing.kills,
+            standing.deaths,
+            standing.damage_dealt,
+            standing.damage_taken,
+            standing.average_survival_seconds()
+        );
+    }
+
+    if let Some(path) = results {
+        let ranked: Vec<&Standing> = order.iter().map(|&i| &standings[i]).collect();
+        let out = if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            to_json(&ranked, &matches, &standings)
+        } else {
+            to_csv(&ranked)
+        };
+        fs::write(path, out).map_err(|e| format!("could not write results {:?}: {}", path, e))?;
+        println!("results written to {:?}", path);
+    }
+    Ok(())
+}
+
+/// One headless match between the bots at `pair`, the first driving the first of the `SIDES`
+fn play(
+    bots: &[PathBuf],
+    pair: [usize; 2],
+    seed: u64,
+    max_ticks: u64,
+    budget: u64,
+) -> Result<MatchResult, String> {
+    let mut players = Vec::new();
+    for (&bot, &player) in pair.iter().zip(&SIDES) {
+        players.push(Bot::load(player, &bots[bot], seed, budget)?);
+    }
+    let tanks: Vec<_> = players
+        .iter()
+        .map(|bot| (bot.player, bot.archetype(), None))
+        .collect();
+    let rules = Rules {
+        mode: GameMode::Elimination,
+        time_limit: Some(max_ticks),
+        score_limit: None,
+    };
+
+    let mut dispatcher = game::build_dispatcher();
+    let mut world = game::create_game(&mut dispatcher, seed, rules, &tanks);
+    let mut navigator = Navigator::new(&world, game::TANK_COLLIDER);
+
+    let mut survived = [None; 2];
+    let mut ticks = 0;
+    let outcome = loop {
+        if let Some(outcome) = world.read_resource::<Match>().outcome {
+            break outcome;
+        }
+        let mut commands = Commands::default();
+        for bot in &mut players {
+            commands
+                .0
+                .insert(bot.player, bot.think(&world, &mut navigator));
+        }
+        game::step(&mut world, &mut dispatcher, commands);
+        ticks += 1;
+        for (lasted, bot) in survived.iter_mut().zip(&players) {
+            if lasted.is_none() && senses::own_tank(&world, bot.player).is_none() {
+                *lasted = Some(ticks);
+            }
+        }
+    };
+
+    let scoreboard = world.read_resource::<Scoreboard>();
+    let entrant = |i: usize| Entrant {
+        bot: pair[i],
+        player: players[i].player,
+        score: scoreboard.of(players[i].player),
+        survived: survived[i].unwrap_or(ticks),
+        overruns: players[i].overruns(),
+    };
+    Ok(MatchResult {
+        seed,
+        ticks,
+        entrants: [entrant(0), entrant(1)],
+        winner: match outcome {
+            Outcome::Won(Side::Player(winner)) => (0..2)
+                .find(|&i| players[i].player == winner)
+                .map(|i| pair[i]),
+            _ => None,
+        },
+    })
+}
+
+fn to_csv(ranked: &[&Standing]) -> String {
+    let mut out = "bot,file,matches,wins,draws,losses,kills,deaths,damage_dealt,damage_taken,average_survival_seconds,overruns\n"
+        .to_string();
+    for standing in ranked {
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{:.2},{}\n",
+            csv_field(&standing.name),
+            csv_field(&standing.file),
+            standing.matches,
+            standing.wins,
+            standing.draws,
+            standing.losses,
+            standing.kills,
+            standing.deaths,
+            standing.damage_dealt,
+            standing.damage_taken,
+            standing.average_survival_seconds(),
+            standing.overruns
+        ));
+    }
+    out
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// The standings in order, then every match with the bots named as in the standings
+fn to_json(ranked: &[&Standing], matches: &[MatchResult], standings: &[Standing]) -> String {
+    let standing_entries: Vec<String> = ranked
+        .iter()
+        .map(|standing| {
+            format!(
+                "    {{\"bot\": {}, \"file\": {}, \"matches\": {}, \"wins\": {}, \"draws\": {}, \"losses\": {}, \"kills\": {}, \"deaths\": {}, \"damage_dealt\": {}, \"damage_taken\": {}, \"average_survival_seconds\": {:.2}, \"overruns\": {}}}",
+                json::string(&standing.name),
+                json::string(&standing.file),
+                standing.matches,
+                standing.wins,
+                standing.draws,
+                standing.losses,
+                standing.kills,
+                standing.deaths,
+                standing.damage_dealt,
+                standing.damage_taken,
+                standing.average_survival_seconds(),
+                standing.overruns
+            )
+        })
+        .collect();
+    let match_entries: Vec<String> = matches
+        .iter()
+        .map(|result| {
+            let entrants: Vec<String> = result
+                .entrants
+                .iter()
+                .map(|entrant| {
+                    format!(
+                        "{{\"bot\": {}, \"player\": {}, \"kills\": {}, \"deaths\": {}, \"damage_dealt\": {}, \"damage_taken\": {}, \"survived_ticks\": {}, \"overruns\": {}}}",
+                        json::string(&standings[entrant.bot].name),
+                        entrant.player,
+                        entrant.score.kills,
+                        entrant.score.deaths,
+                        entrant.score.damage_dealt,
+                        entrant.score.damage_taken,
+                        entrant.survived,
+                        entrant.overruns
+                    )
+                })
+                .collect();
+            format!(
+                "    {{\"seed\": {}, \"ticks\": {}, \"winner\": {}, \"entrants\": [{}]}}",
+                result.seed,
+                result.ticks,
+                result
+                    .winner
+                    .map_or("null".to_string(), |winner| json::string(&standings[winner].name)),
+                entrants.join(", ")
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"standings\": [\n{}\n  ],\n  \"matches\": [\n{}\n  ]\n}}\n",
+        standing_entries.join(",\n"),
+        match_entries.join(",\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::script;
+    use crate::json::Json;
+
+    /// Drives straight at whatever it sees, firing once the turret is on it
+    const CHARGER: &str = r#"
+fn tank() {
+    "heavy"
+}
+
+fn think(me, enemies) {
+    if enemies.is_empty() {
+        return #{ movement: "stop", rotation: "cw", fire: false };
+    }
+    let enemy = enemies[0];
+    let aim = heading(enemy.x - me.x, enemy.y - me.y);
+    let turn = angle_diff(me.turret, aim);
+    #{
+        movement: aim,
+        rotation: if turn > 4.0 { "cw" } else if turn < -4.0 { "ccw" } else { "stop" },
+        fire: turn.abs() < 8.0,
+    }
+}
+"#;
+
+    /// Wanders about, shooting at whatever it sees
+    const WANDERER: &str = "tank scout
+selector
+    sequence
+        enemy-visible
+        aim
+        clear-shot
+        fire
+    wander
+";
+
+    const ROUNDS: u32 = 2;
+
+    /// A fresh directory holding the two bots, and the paths of the bots in it
+    fn bots(test: &str) -> (PathBuf, Vec<PathBuf>) {
+        let dir = std::env::temp_dir().join(format!(
+            "rusty-tanks-tournament-{}-{}",
+            test,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).expect("temp dir is writable");
+        let bots = vec![dir.join("charger.rhai"), dir.join("wanderer.bt")];
+        fs::write(&bots[0], CHARGER).expect("temp dir is writable");
+        fs::write(&bots[1], WANDERER).expect("temp dir is writable");
+        (dir, bots)
+    }
+
+    /// Play the two bots against each other and read back the results written to `file`
+    fn results(bots: &[PathBuf], seed: u64, file: &Path) -> String {
+        run(bots, ROUNDS, seed, 600, script::DEFAULT_BUDGET, Some(file))
+            .expect("the tournament is played");
+        fs::read_to_string(file).expect("results are written")
+    }
+
+    #[test]
+    fn the_same_seeds_give_the_same_results() {
+        let (dir, bots) = bots("seeds");
+        let first = results(&bots, 5, &dir.join("first.json"));
+        let second = results(&bots, 5, &dir.join("second.json"));
+        let other = results(&bots, 9, &dir.join("other.json"));
+        fs::remove_dir_all(&dir).ok();
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn results_are_well_formed() {
+        let (dir, bots) = bots("formats");
+        let json = results(&bots, 9, &dir.join("results.json"));
+        let csv = results(&bots, 9, &dir.join("results.csv"));
+        fs::remove_dir_all(&dir).ok();
+
+        let json = Json::parse(&json).expect("results are JSON");
+        let standings = json
+            .get("standings")
+            .and_then(Json::as_array)
+            .expect("there are standings");
+        let matches = json
+            .get("matches")
+            .and_then(Json::as_array)
+            .expect("there are matches");
+        let names = ["charger", "wanderer"];
+        assert_eq!(standings.len(), 2);
+        assert_eq!(matches.len(), ROUNDS as usize);
+        for (i, result) in matches.iter().enumerate() {
+            let number = |key| result.get(key).and_then(Json::as_f64);
+            assert_eq!(number("seed"), Some(9.0 + i as f64));
+            assert!(number("ticks").is_some_and(|ticks| ticks > 0.0 && ticks <= 600.0));
+            let winner = result.get("winner").expect("there is a winner or null");
+            assert!(
+                *winner == Json::Null || winner.as_str().is_some_and(|name| names.contains(&name))
+            );
+            let entrants = result
+                .get("entrants")
+                .and_then(Json::as_array)
+                .expect("there are entrants");
+            assert_eq!(entrants.len(), 2);
+        }
+
+        // A row a bot under the header, every row as long as it, agreeing with the JSON
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0][0], "bot");
+        assert!(rows.iter().all(|row| row.len() == rows[0].len()));
+        for (row, standing) in rows[1..].iter().zip(standings) {
+            let field =
+                |column: &str| row[rows[0].iter().position(|&name| name == column).unwrap()];
+            let count = |column: &str| field(column).parse::<u32>().expect("counts are numbers");
+            assert_eq!(
+                Some(field("bot")),
+                standing.get("bot").and_then(Json::as_str)
+            );
+            assert_eq!(
+                count("matches"),
+                count("wins") + count("draws") + count("losses")
+            );
+            assert_eq!(count("matches"), ROUNDS);
+            for column in ["wins", "kills", "damage_dealt", "damage_taken"] {
+                assert_eq!(
+                    Some(f64::from(count(column))),
+                    standing.get(column).and_then(Json::as_f64)
+                );
+            }
+        }
+    }
+}
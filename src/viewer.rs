@@ -0,0 +1,71 @@
+use rusty_tanks::playback::Playback;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::rect::{Point, Rect};
+
+/// How far the arrow keys seek, five seconds of play
+const SEEK_STEP: u64 = 100;
+
+/// Apply a viewer control to `playback`. `timeline` is the on-screen timeline, clicking it seeks.
+pub fn handle_event(playback: &mut Playback, event: &Event, timeline: Rect) {
+    match event {
+        Event::KeyDown {
+            keycode: Some(Keycode::Space),
+            repeat: false,
+            ..
+        } => playback.toggle_pause(),
+        Event::KeyDown {
+            keycode: Some(Keycode::Period),
+            ..
+        } => playback.single_step(),
+        Event::KeyDown {
+            keycode: Some(Keycode::Up),
+            repeat: false,
+            ..
+        }
+        | Event::KeyDown {
+            keycode: Some(Keycode::Equals),
+            repeat: false,
+            ..
+        } => playback.faster(),
+        Event::KeyDown {
+            keycode: Some(Keycode::Down),
+            repeat: false,
+            ..
+        }
+        | Event::KeyDown {
+            keycode: Some(Keycode::Minus),
+            repeat: false,
+            ..
+        } => playback.slower(),
+        Event::KeyDown {
+            keycode: Some(Keycode::Left),
+            ..
+        } => playback.seek(playback.tick().saturating_sub(SEEK_STEP)),
+        Event::KeyDown {
+            keycode: Some(Keycode::Right),
+            ..
+        } => playback.seek(playback.tick() + SEEK_STEP),
+        Event::KeyDown {
+            keycode: Some(Keycode::Home),
+            repeat: false,
+            ..
+        } => playback.seek(0),
+        Event::KeyDown {
+            keycode: Some(Keycode::End),
+            repeat: false,
+            ..
+        } => playback.seek(playback.length()),
+        Event::MouseButtonDown {
+            mouse_btn: MouseButton::Left,
+            x,
+            y,
+            ..
+        } if timeline.contains_point(Point::new(*x, *y)) => {
+            let offset = (*x - timeline.x()) as u64;
+            playback.seek(offset * playback.length() / timeline.width() as u64);
+        }
+        _ => {}
+    }
+}
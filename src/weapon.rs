@@ -0,0 +1,219 @@
+use crate::components::Sprite;
+use crate::geometry::Rect;
+use std::fmt;
+
+/// Index of the projectile spritesheet loaded by the renderer, the cannon uses the plain bullet
+pub const PROJECTILE_SPRITE: usize = 3;
+
+/// The kinds of gun a turret can carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weapon {
+    /// A single straight shot
+    Cannon,
+    /// A fan of pellets
+    Spread,
+    /// A few quick shots per pull of the trigger
+    Burst,
+    /// Weak rounds fired every tick
+    MachineGun,
+    /// A slow shell that explodes where it lands
+    Shell,
+    /// A missile that turns towards the nearest enemy
+    Missile,
+    /// A beam hitting the first thing in its way the moment it is fired
+    Laser,
+}
+
+impl Weapon {
+    /// Ticks between pulls of the trigger
+    pub fn cooldown(self) -> u32 {
+        match self {
+            Weapon::Cannon => 2,
+            Weapon::Spread => 6,
+            Weapon::Burst => 8,
+            Weapon::MachineGun => 0,
+            Weapon::Shell => 12,
+            Weapon::Missile => 16,
+            Weapon::Laser => 10,
+        }
+    }
+
+    /// How many shots a pull of the trigger fires, one after the other
+    pub fn burst(self) -> u32 {
+        match self {
+            Weapon::Burst => 3,
+            _ => 1,
+        }
+    }
+
+    /// Ticks between the shots of a burst
+    pub fn burst_interval(self) -> u32 {
+        1
+    }
+
+    /// How many projectiles a single shot fires
+    pub fn pellets(self) -> u32 {
+        match self {
+            Weapon::Spread => 5,
+            _ => 1,
+        }
+    }
+
+    /// The angle in degrees the pellets of a shot are spread across
+    pub fn spread(self) -> f32 {
+        match self {
+            Weapon::Spread => 40.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Rounds a magazine holds, a spread shot takes a single round
+    pub fn magazine_size(self) -> u32 {
+        match self {
+            Weapon::Cannon => 5,
+            Weapon::Spread => 4,
+            Weapon::Burst => 12,
+            Weapon::MachineGun => 40,
+            Weapon::Shell => 1,
+            Weapon::Missile => 2,
+            Weapon::Laser => 3,
+        }
+    }
+
+    /// The most rounds that can be carried besides the loaded magazine
+    pub fn max_reserve(self) -> u32 {
+        self.magazine_size()
+            * match self {
+                Weapon::Shell => 12,
+                Weapon::Missile => 4,
+                _ => 6,
+            }
+    }
+
+    /// Ticks it takes to load a fresh magazine
+    pub fn reload_time(self) -> u32 {
+        match self {
+            Weapon::Cannon => 30,
+            Weapon::Spread => 40,
+            Weapon::Burst => 35,
+            Weapon::MachineGun => 50,
+            Weapon::Shell => 30,
+            Weapon::Missile => 60,
+            Weapon::Laser => 45,
+        }
+    }
+
+    /// How fast its projectiles fly, beams have none
+    pub fn projectile_speed(self) -> f32 {
+        match self {
+            Weapon::Cannon => 15.0,
+            Weapon::Spread => 13.0,
+            Weapon::Burst => 16.0,
+            Weapon::MachineGun => 20.0,
+            Weapon::Shell => 7.0,
+            Weapon::Missile => 10.0,
+            Weapon::Laser => 0.0,
+        }
+    }
+
+    pub fn damage(self) -> u32 {
+        match self {
+            Weapon::Cannon => 20,
+            Weapon::Spread => 8,
+            Weapon::Burst => 12,
+            Weapon::MachineGun => 4,
+            Weapon::Shell => 40,
+            Weapon::Missile => 30,
+            Weapon::Laser => 15,
+        }
+    }
+
+    /// The radius explosive projectiles damage everything within, 0 for those that only hit what they touch
+    pub fn blast_radius(self) -> i32 {
+        match self {
+            Weapon::Shell => 64,
+            Weapon::Missile => 40,
+            _ => 0,
+        }
+    }
+
+    /// How many degrees a homing projectile may turn in a tick, 0 for those that fly straight
+    pub fn homing(self) -> f32 {
+        match self {
+            Weapon::Missile => 6.0,
+            _ => 0.0,
+        }
+    }
+
+    /// How far a beam reaches, 0 for weapons firing projectiles
+    pub fn beam_length(self) -> i32 {
+        match self {
+            Weapon::Laser => 480,
+            _ => 0,
+        }
+    }
+
+    /// Ticks a projectile flies before it expires
+    pub fn range(self) -> u32 {
+        match self {
+            Weapon::MachineGun => 30,
+            Weapon::Spread => 25,
+            Weapon::Shell => 50,
+            Weapon::Missile => 80,
+            _ => 60,
+        }
+    }
+
+    /// How many walls a projectile bounces off before the next one destroys it
+    pub fn bounces(self) -> u32 {
+        match self {
+            Weapon::Cannon => 2,
+            Weapon::Burst => 1,
+            _ => 0,
+        }
+    }
+
+    /// How fast the projectile spins as it flies, in degrees a tick
+    pub fn spin(self) -> f32 {
+        match self {
+            Weapon::Cannon => 2.0,
+            _ => 0.0,
+        }
+    }
+
+    pub fn sprite(self) -> Sprite {
+        let frame = |i: i32| Sprite {
+            spritesheet: PROJECTILE_SPRITE,
+            region: Rect::new(i * 16, 0, 16, 16),
+        };
+        match self {
+            Weapon::Cannon => Sprite {
+                spritesheet: 0,
+                region: Rect::new(0, 0, 32, 32),
+            },
+            Weapon::Spread => frame(0),
+            Weapon::Burst => frame(1),
+            Weapon::MachineGun | Weapon::Laser => frame(2),
+            Weapon::Shell => frame(3),
+            Weapon::Missile => frame(4),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Weapon::Cannon => "cannon",
+            Weapon::Spread => "spread",
+            Weapon::Burst => "burst",
+            Weapon::MachineGun => "machinegun",
+            Weapon::Shell => "shell",
+            Weapon::Missile => "missile",
+            Weapon::Laser => "laser",
+        }
+    }
+}
+
+impl fmt::Display for Weapon {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}